@@ -1,31 +1,123 @@
+use std::collections::VecDeque;
+
 use tracing::debug;
+use tracing::error;
+use tracing::info;
 
+use super::to_bcd;
 use crate::consts::SECTOR_SIZE;
+use crate::region::Region;
+use crate::savestate::impl_state;
+
+/// Sectors kept around after being read, enough to cover the drive re-reading around a seek
+const CACHED_SECTORS: usize = 32;
+
+/// Sector holding the license text, 4 sectors into the data track
+const LICENSE_SECTOR: usize = 150 + 4;
+
+/// What the drive makes of a disc when asked for its id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum License {
+    Licensed(Region),
+    /// Data disc without the license text, `audio` if it also has audio tracks
+    Unlicensed {
+        mode2: bool,
+        audio: bool,
+    },
+    /// Audio CD
+    Audio,
+}
 
 pub struct Image {
     read_head: usize,
-    data: Box<[u8]>,
+    source: Box<dyn cue::DiscSource>,
+    cache: SectorCache,
     tracks: Box<[cue::Track]>,
+    license: License,
+    subchannel: cue::Subchannel,
+    /// Sector whose subchannel Q the drive last saw, the one just read or seeked to
+    q_sector: usize,
 }
 
+// Disc contents come from the loaded image, only the position is saved
+impl_state!(Image {
+    read_head,
+    q_sector
+});
+
 impl Image {
     pub fn from_bytes(bytes: Vec<u8>) -> Self {
         // Add 2 seconds of zero padding to disk image (missing in rips)
         let mut data = vec![0u8; 2 * 75 * SECTOR_SIZE];
         data.extend(bytes);
 
-        Self {
-            read_head: SECTOR_SIZE * 75 * 2, // 2 seconds,
-            data: data.into_boxed_slice(),
-            tracks: Box::new([cue::Track::single()]),
-        }
+        Self::new(
+            Box::new(data.into_boxed_slice()),
+            Box::new([cue::Track::single()]),
+            cue::Subchannel::default(),
+        )
     }
 
     pub fn from_disc(disc: cue::Disc) -> Self {
-        Self {
+        if !disc.subchannel.is_empty() {
+            info!(sectors = disc.subchannel.len(), "loaded subchannel patches");
+        }
+
+        Self::new(disc.source, disc.tracks, disc.subchannel)
+    }
+
+    fn new(
+        source: Box<dyn cue::DiscSource>,
+        tracks: Box<[cue::Track]>,
+        subchannel: cue::Subchannel,
+    ) -> Self {
+        let mut image = Self {
             read_head: SECTOR_SIZE * 75 * 2, // 2 seconds,
-            data: disc.sectors,
-            tracks: disc.tracks,
+            source,
+            cache: SectorCache::default(),
+            tracks,
+            license: License::Audio,
+            subchannel,
+            q_sector: 75 * 2,
+        };
+
+        image.license = image.detect_license();
+        image
+    }
+
+    fn detect_license(&mut self) -> License {
+        let audio = self
+            .tracks
+            .iter()
+            .any(|t| t.track_type == cue::TrackType::Audio);
+
+        let Some(data_track) = self
+            .tracks
+            .iter()
+            .find(|t| t.track_type != cue::TrackType::Audio)
+        else {
+            return License::Audio;
+        };
+
+        let mode2 = matches!(
+            data_track.track_type,
+            cue::TrackType::Mode2_2048 | cue::TrackType::Mode2_2336 | cue::TrackType::Mode2_2352
+        );
+
+        let sector = self.cache.read(self.source.as_mut(), LICENSE_SECTOR);
+        Region::from_license_sector(sector)
+            .map_or(License::Unlicensed { mode2, audio }, License::Licensed)
+    }
+
+    pub const fn license(&self) -> License {
+        self.license
+    }
+
+    /// Region the disc is licensed for, unlicensed discs have none
+    pub const fn region(&self) -> Option<Region> {
+        match self.license {
+            License::Licensed(region) => Some(region),
+            _ => None,
         }
     }
 
@@ -37,24 +129,55 @@ impl Image {
         self.tracks.last().expect("last track id").id
     }
 
-    pub fn track_mm_ss_ff(&self, track_id: u8) -> (u8, u8, u8) {
+    fn track_start(&self, track_id: u8) -> usize {
         let track = &self.tracks[track_id as usize - 1];
 
-        let start = if track.indexes[0].id == 1 {
+        if track.indexes[0].id == 1 {
             track.indexes[0].lba
         } else {
             track.indexes[1].lba
-        };
+        }
+    }
+
+    pub fn track_mm_ss_ff(&self, track_id: u8) -> (u8, u8, u8) {
+        mm_ss_ff(self.track_start(track_id))
+    }
+
+    /// Move the read head to index 1 of a track
+    pub fn seek_track(&mut self, track_id: u8) {
+        self.read_head = self.track_start(track_id);
+        self.q_sector = self.read_head / SECTOR_SIZE;
+    }
+
+    /// Sector under the read head
+    pub const fn lba(&self) -> usize {
+        self.read_head / SECTOR_SIZE
+    }
+
+    pub fn current_track_id(&self) -> u8 {
+        self.current_position_info()[0]
+    }
 
-        mm_ss_ff(start)
+    pub fn is_at_end(&self) -> bool {
+        self.read_head + SECTOR_SIZE > self.len()
+    }
+
+    pub fn track_is_audio(&self, track_id: u8) -> bool {
+        self.tracks[track_id as usize - 1].track_type == cue::TrackType::Audio
     }
 
     pub fn last_track_end(&self) -> (u8, u8, u8) {
-        mm_ss_ff(self.data.len()) // total length of the disk
+        mm_ss_ff(self.len()) // total length of the disk
+    }
+
+    /// Size of the disc in bytes
+    fn len(&self) -> usize {
+        self.source.sector_count() * SECTOR_SIZE
     }
 
     pub const fn reset_read_head(&mut self) {
         self.read_head = SECTOR_SIZE * 75 * 2; // 2 seconds
+        self.q_sector = 75 * 2;
     }
 
     pub fn current_position_info(&self) -> [u8; 8] {
@@ -87,8 +210,66 @@ impl Image {
         ]
     }
 
-    pub fn current_header_info(&self) -> [u8; 8] {
-        let current_sector = &self.data[self.read_head..self.read_head + SECTOR_SIZE];
+    /// Subchannel Q of the last sector the head passed over. Images don't store it, so it's
+    /// built from the track layout unless the disc's copy protection changed that sector.
+    pub fn current_subq(&self) -> cue::SubQ {
+        if let Some(q) = self.subchannel.patch(self.q_sector) {
+            return *q;
+        }
+
+        let pos = self.q_sector * SECTOR_SIZE;
+        let track = self
+            .tracks
+            .partition_point(|t| t.indexes[0].lba <= pos)
+            .saturating_sub(1);
+        let track = &self.tracks[track];
+
+        let index = track
+            .indexes
+            .partition_point(|i| i.lba <= pos)
+            .saturating_sub(1);
+        let index = &track.indexes[index];
+
+        // Relative time counts down to index 1 through the pregap, which for the first track is
+        // the 2 seconds before it even when the image has no index 0
+        let start = self.track_start(track.id);
+        let (index, relative) = if pos < start {
+            (0, mm_ss_ff(start - pos))
+        } else {
+            (index.id, mm_ss_ff(pos - start))
+        };
+        let absolute = mm_ss_ff(pos);
+
+        let control = if track.track_type == cue::TrackType::Audio {
+            0x01
+        } else {
+            0x41
+        };
+
+        let mut q = [
+            control,
+            to_bcd(track.id),
+            to_bcd(index),
+            to_bcd(relative.0),
+            to_bcd(relative.1),
+            to_bcd(relative.2),
+            0,
+            to_bcd(absolute.0),
+            to_bcd(absolute.1),
+            to_bcd(absolute.2),
+            0,
+            0,
+        ];
+
+        let crc = cue::q_crc(&q);
+        q[10..].copy_from_slice(&crc.to_be_bytes());
+        q
+    }
+
+    pub fn current_header_info(&mut self) -> [u8; 8] {
+        let current_sector = self
+            .cache
+            .read(self.source.as_mut(), self.read_head / SECTOR_SIZE);
 
         // Copy of header and subheader
         current_sector[0xC..0xC + 8]
@@ -99,6 +280,17 @@ impl Image {
     pub const fn seek_location(&mut self, mins: u8, secs: u8, sect: u8) {
         let total_sectors = ((mins as usize) * 60 * 75) + ((secs as usize) * 75) + (sect as usize);
         self.read_head = total_sectors * SECTOR_SIZE;
+        self.q_sector = total_sectors;
+    }
+
+    /// Move the read head by a number of sectors, staying inside the disc
+    pub fn skip_sectors(&mut self, sectors: isize) {
+        let last_sector = self.len().saturating_sub(SECTOR_SIZE);
+        self.read_head = self
+            .read_head
+            .saturating_add_signed(sectors * SECTOR_SIZE as isize)
+            .min(last_sector);
+        self.q_sector = self.read_head / SECTOR_SIZE;
     }
 
     pub fn advance_sector(&mut self) -> Vec<u8> {
@@ -109,9 +301,39 @@ impl Image {
             "reading sector"
         );
 
-        let start = self.read_head;
+        let lba = self.read_head / SECTOR_SIZE;
         self.read_head += SECTOR_SIZE;
-        self.data[start..self.read_head].to_vec()
+        self.q_sector = lba;
+        self.cache.read(self.source.as_mut(), lba).to_vec()
+    }
+}
+
+/// Most recently read sectors, oldest first
+#[derive(Default)]
+struct SectorCache {
+    sectors: VecDeque<(usize, Box<[u8]>)>,
+}
+
+impl SectorCache {
+    fn read(&mut self, source: &mut dyn cue::DiscSource, lba: usize) -> &[u8] {
+        if let Some(i) = self.sectors.iter().position(|(cached, _)| *cached == lba) {
+            return &self.sectors[i].1;
+        }
+
+        let mut sector = if self.sectors.len() == CACHED_SECTORS {
+            self.sectors.pop_front().expect("full sector cache").1
+        } else {
+            vec![0; SECTOR_SIZE].into_boxed_slice()
+        };
+
+        // A bad read shows up to the game as an unreadable sector rather than stopping the emulator
+        if let Err(err) = source.read_sector(lba, &mut sector) {
+            error!(target: "cdrom", lba, %err, "failed to read sector");
+            sector.fill(0);
+        }
+
+        self.sectors.push_back((lba, sector));
+        &self.sectors.back().expect("sector was just cached").1
     }
 }
 
@@ -129,3 +351,71 @@ fn mm_ss_ff_str(read_head: usize) -> String {
     let i = mm_ss_ff(read_head);
     format!("{:02}:{:02}:{:02}", i.0, i.1, i.2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn index(id: u8, sector: usize) -> cue::TrackIndex {
+        cue::TrackIndex {
+            id,
+            lba: sector * SECTOR_SIZE,
+        }
+    }
+
+    /// A data track, then an audio track with a 2 second pregap from sector 300
+    fn two_tracks() -> Image {
+        let tracks = [
+            cue::Track::single(),
+            cue::Track {
+                id: 2,
+                indexes: vec![index(0, 300), index(1, 450)],
+                track_type: cue::TrackType::Audio,
+            },
+        ];
+
+        let source: Box<[u8]> = vec![0; 600 * SECTOR_SIZE].into_boxed_slice();
+        Image::new(Box::new(source), tracks.into(), cue::Subchannel::default())
+    }
+
+    /// Control, track, index, relative and absolute time of the Q sector at `sector`
+    fn subq_at(image: &mut Image, sector: usize) -> [u8; 10] {
+        let (mm, ss, ff) = mm_ss_ff(sector * SECTOR_SIZE);
+        image.seek_location(mm, ss, ff);
+
+        let q = image.current_subq();
+        assert_eq!(cue::q_crc(&q), u16::from_be_bytes([q[10], q[11]]));
+        q[..10].try_into().expect("10 bytes")
+    }
+
+    #[test]
+    fn subq_counts_down_through_the_lead_in() {
+        let mut image = Image::from_bytes(vec![0; 16 * SECTOR_SIZE]);
+
+        let q = subq_at(&mut image, 0);
+        assert_eq!(q, [0x41, 0x01, 0x00, 0x00, 0x02, 0x00, 0, 0x00, 0x00, 0x00]);
+
+        let q = subq_at(&mut image, 149);
+        assert_eq!(q, [0x41, 0x01, 0x00, 0x00, 0x00, 0x01, 0, 0x00, 0x01, 0x74]);
+
+        let q = subq_at(&mut image, 150);
+        assert_eq!(q, [0x41, 0x01, 0x01, 0x00, 0x00, 0x00, 0, 0x00, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn subq_counts_down_through_a_pregap() {
+        let mut image = two_tracks();
+
+        let q = subq_at(&mut image, 299);
+        assert_eq!(q, [0x41, 0x01, 0x01, 0x00, 0x01, 0x74, 0, 0x00, 0x03, 0x74]);
+
+        let q = subq_at(&mut image, 300);
+        assert_eq!(q, [0x01, 0x02, 0x00, 0x00, 0x02, 0x00, 0, 0x00, 0x04, 0x00]);
+
+        let q = subq_at(&mut image, 449);
+        assert_eq!(q, [0x01, 0x02, 0x00, 0x00, 0x00, 0x01, 0, 0x00, 0x05, 0x74]);
+
+        let q = subq_at(&mut image, 525);
+        assert_eq!(q, [0x01, 0x02, 0x01, 0x00, 0x01, 0x00, 0, 0x00, 0x07, 0x00]);
+    }
+}
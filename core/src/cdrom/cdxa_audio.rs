@@ -3,6 +3,7 @@ use num_enum::FromPrimitive;
 
 use crate::consts::NEG_ADPCM_TABLE;
 use crate::consts::POS_ADPCM_TABLE;
+use crate::savestate::impl_state;
 use crate::spu::signed4bit;
 
 #[derive(Default)]
@@ -11,6 +12,8 @@ pub struct AdpcmHistory {
     older: i16,
 }
 
+impl_state!(AdpcmHistory { old, older });
+
 // Resamples to 44.1Khz
 pub trait Resampler {
     fn process_sample(&mut self, sample: i16) -> Option<[i16; 7]>;
@@ -149,6 +152,12 @@ pub struct HighResResampler {
     sixstep: usize,
 }
 
+impl_state!(HighResResampler {
+    ringbuf,
+    p,
+    sixstep,
+});
+
 impl Default for HighResResampler {
     fn default() -> Self {
         Self {
@@ -201,6 +210,13 @@ pub struct LowResResampler {
     input_buf: ArrayVec<i16, 7>,
 }
 
+impl_state!(LowResResampler {
+    ringbuf,
+    p,
+    sixstep,
+    input_buf,
+});
+
 impl Default for LowResResampler {
     fn default() -> Self {
         Self {
@@ -1,16 +1,28 @@
+use anyhow::bail;
 use arrayvec::ArrayVec;
 use tracing::debug;
 use tracing::error;
 
 use super::CdRom;
+use super::Image;
+use super::License;
+use super::Mode;
+use super::ScanDirection;
 use super::Speed;
 use super::Status;
+use super::from_bcd;
+use super::to_bcd;
 use crate::consts::AVG_1ST_RESP_GENERIC;
 use crate::consts::AVG_1ST_RESP_INIT;
 use crate::consts::AVG_2ND_RESP_GET_ID;
 use crate::consts::AVG_2ND_RESP_PAUSE;
-use crate::consts::AVG_2ND_RESP_SEEKL;
-use crate::consts::AVG_RATE_INT1;
+use crate::consts::AVG_2ND_RESP_READ_TOC;
+use crate::consts::SECTOR_READ_CYCLES;
+use crate::consts::SEEK_MIN_CYCLES;
+use crate::consts::SLED_FULL_STROKE_CYCLES;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
 
 impl CdRom {
     pub fn test(&self) -> CommandResponse {
@@ -42,14 +54,29 @@ impl CdRom {
         error_response(&self.status, 0x40, "invalid command")
     }
 
-    pub fn nop(&self) -> CommandResponse {
+    pub fn door_open(&self) -> CommandResponse {
+        error_response(&self.status, 0x08, "drive door is open")
+    }
+
+    pub fn no_disc(&self) -> CommandResponse {
+        error_response(&self.status, 0x80, "no disc in the drive")
+    }
+
+    pub fn nop(&mut self) -> CommandResponse {
         if !self.parameters.is_empty() {
             return error_response(&self.status, 0x20, "nop takes no parameters");
         }
 
         debug!(target: "cdrom", status=?self.status.0, "cdrom nop command");
 
-        CommandResponse::new().int3([self.status.0], AVG_1ST_RESP_GENERIC)
+        let status = self.status.0;
+
+        // Reading the status is what clears the shell open bit once the lid is closed
+        if !self.shell_open {
+            self.status.set_shell_open(false);
+        }
+
+        CommandResponse::new().int3([status], AVG_1ST_RESP_GENERIC)
     }
 
     pub fn get_id(&self) -> CommandResponse {
@@ -57,14 +84,43 @@ impl CdRom {
             return error_response(&self.status, 0x20, "get_id takes no parameters");
         }
 
-        debug!(target: "cdrom", "cdrom get id");
-
-        CommandResponse::new()
-            .int3([self.status.0], AVG_1ST_RESP_GENERIC)
-            .int2(
-                [0x02, 0x00, 0x20, 0x00, b'S', b'C', b'E', b'A'],
-                AVG_1ST_RESP_GENERIC + AVG_2ND_RESP_GET_ID,
-            )
+        let license = self.disk.as_ref().map(Image::license);
+        debug!(target: "cdrom", ?license, "cdrom get id");
+
+        let response = CommandResponse::new().int3([self.status.0], AVG_1ST_RESP_GENERIC);
+        let delay = AVG_1ST_RESP_GENERIC + AVG_2ND_RESP_GET_ID;
+
+        // Flags, disc type, then the license string for licensed discs
+        match license {
+            Some(License::Licensed(region)) => response.int2(
+                [
+                    0x02,
+                    0x00,
+                    0x20,
+                    0x00,
+                    b'S',
+                    b'C',
+                    b'E',
+                    region.license_letter(),
+                ],
+                delay,
+            ),
+            Some(License::Unlicensed { mode2, audio }) => response.int5(
+                [
+                    0x0A,
+                    if audio { 0x90 } else { 0x80 },
+                    if mode2 { 0x20 } else { 0x00 },
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                ],
+                delay,
+            ),
+            Some(License::Audio) => response.int5([0x0A, 0x90, 0, 0, 0, 0, 0, 0], delay),
+            None => response.int5([0x08, 0x40, 0, 0, 0, 0, 0, 0], delay),
+        }
     }
 
     pub fn set_loc(&mut self) -> CommandResponse {
@@ -84,6 +140,7 @@ impl CdRom {
                 .int5([self.status.with_error(), 0x10], AVG_1ST_RESP_GENERIC);
         };
 
+        self.start_seek();
         self.disk
             .as_mut()
             .expect("set_loc inserted disk")
@@ -92,37 +149,39 @@ impl CdRom {
         CommandResponse::new().int3([self.status.0], AVG_1ST_RESP_GENERIC)
     }
 
-    pub fn seekl(&mut self) -> CommandResponse {
+    pub fn seekl(&mut self, now: u64) -> CommandResponse {
         if !self.parameters.is_empty() {
             return error_response(&self.status, 0x20, "seekl takes no parameters");
         }
 
         debug!(target: "cdrom", "cdrom seekl");
 
+        let seek = self.seek_time(now).max(SEEK_MIN_CYCLES);
         self.status.set_seeking(true);
         let seeking_status = self.status.set_seeking(false);
 
         CommandResponse::new()
             .int3([seeking_status], AVG_1ST_RESP_GENERIC)
-            .int2([self.status.0], AVG_1ST_RESP_GENERIC + AVG_2ND_RESP_SEEKL)
+            .int2([self.status.0], AVG_1ST_RESP_GENERIC + seek)
     }
 
-    pub fn seekp(&mut self) -> CommandResponse {
+    pub fn seekp(&mut self, now: u64) -> CommandResponse {
         if !self.parameters.is_empty() {
             return error_response(&self.status, 0x20, "seekp takes no parameters");
         }
 
         debug!(target: "cdrom", "cdrom seekp");
 
+        let seek = self.seek_time(now).max(SEEK_MIN_CYCLES);
         self.status.set_seeking(true);
         let seeking_status = self.status.set_seeking(false);
 
         CommandResponse::new()
             .int3([seeking_status], AVG_1ST_RESP_GENERIC)
-            .int2([self.status.0], AVG_1ST_RESP_GENERIC + AVG_2ND_RESP_SEEKL)
+            .int2([self.status.0], AVG_1ST_RESP_GENERIC + seek)
     }
 
-    pub fn setmode(&mut self) -> CommandResponse {
+    pub fn setmode(&mut self, now: u64) -> CommandResponse {
         if self.parameters.len() != 1 {
             return error_response(&self.status, 0x20, "setmode expects 1 parameter");
         }
@@ -131,31 +190,37 @@ impl CdRom {
 
         self.mode.set_value(self.parameters[0]);
 
+        // A speed switch starts right away, reads wait for the disc to settle
+        if self.status.motor_on() {
+            self.spindle.change_speed(now, self.mode.speed);
+        }
+
         CommandResponse::new().int3([self.status.0], AVG_1ST_RESP_GENERIC)
     }
 
-    pub fn reads(&mut self) -> CommandResponse {
+    pub fn reads(&mut self, now: u64) -> CommandResponse {
         if !self.parameters.is_empty() {
             return error_response(&self.status, 0x20, "reads takes no parameters");
         }
 
         debug!(target: "cdrom", "cdrom reads");
 
-        self.readn()
+        self.readn(now)
     }
 
-    pub fn readn(&mut self) -> CommandResponse {
+    pub fn readn(&mut self, now: u64) -> CommandResponse {
         if !self.parameters.is_empty() {
             return error_response(&self.status, 0x20, "readn takes no parameters");
         }
 
         debug!(target: "cdrom", "cdrom readn");
 
+        let seek = self.seek_time(now);
         self.status.set_reading(true);
 
         CommandResponse::new()
             .int3([self.status.0], AVG_1ST_RESP_GENERIC)
-            .int1(AVG_1ST_RESP_GENERIC + self.mode.speed.transform(AVG_RATE_INT1))
+            .int1(AVG_1ST_RESP_GENERIC + seek + self.mode.speed.transform(SECTOR_READ_CYCLES))
     }
 
     pub fn pause(&mut self) -> CommandResponse {
@@ -166,6 +231,7 @@ impl CdRom {
         debug!(target: "cdrom", "cdrom pause");
 
         let before = self.status.0;
+        self.scan = None;
         self.status.set_reading(false);
         self.status.set_playing(false);
 
@@ -177,7 +243,7 @@ impl CdRom {
             )
     }
 
-    pub fn init(&mut self) -> CommandResponse {
+    pub fn init(&mut self, now: u64) -> CommandResponse {
         if !self.parameters.is_empty() {
             return error_response(&self.status, 0x20, "init takes no parameters");
         }
@@ -186,15 +252,17 @@ impl CdRom {
 
         self.mode.set_value(0x20);
 
+        self.start_seek();
         if let Some(disk) = self.disk.as_mut() {
             disk.reset_read_head();
         }
 
-        let before = self.status.enable_motor();
+        let before = self.status.0;
+        let seek = self.seek_time(now).max(SEEK_MIN_CYCLES);
 
         CommandResponse::new()
             .int3([before], AVG_1ST_RESP_INIT)
-            .int2([self.status.0], AVG_1ST_RESP_GENERIC + AVG_2ND_RESP_SEEKL)
+            .int2([self.status.0], AVG_1ST_RESP_GENERIC + seek)
     }
 
     pub fn set_filter(&mut self) -> CommandResponse {
@@ -210,16 +278,39 @@ impl CdRom {
         CommandResponse::new().int3([self.status.0], AVG_1ST_RESP_INIT)
     }
 
-    pub fn play(&mut self) -> CommandResponse {
+    pub fn play(&mut self, now: u64) -> CommandResponse {
+        if self.parameters.len() > 1 {
+            return error_response(&self.status, 0x20, "play expects at most 1 parameter");
+        }
+
         debug!(target: "cdrom", params=?self.parameters, "cdrom play");
 
-        let _track = self.parameters.first(); // Optional
+        let Some(disk) = self.disk.as_mut() else {
+            return self.no_disc();
+        };
+
+        // Without a track (or track 0) playback starts from the SetLoc position
+        if let Some(&track) = self.parameters.first()
+            && track != 0
+        {
+            let Some(track) = from_bcd(track)
+                .filter(|t| (disk.first_track_id()..=disk.last_track_id()).contains(t))
+            else {
+                return error_response(&self.status, 0x10, "play to invalid track");
+            };
+
+            // Seek timing starts from wherever the head was before
+            self.seek_from.get_or_insert_with(|| disk.lba());
+            disk.seek_track(track);
+        }
 
+        let seek = self.seek_time(now);
+        self.scan = None;
         self.status.set_playing(true);
 
         CommandResponse::new()
             .int3([self.status.0], AVG_1ST_RESP_INIT)
-            .int1(AVG_RATE_INT1)
+            .int1(seek + SECTOR_READ_CYCLES)
     }
 
     // stubbed audio command
@@ -244,8 +335,8 @@ impl CdRom {
 
         let disk = &self.disk.as_ref().expect("get_tn inserted disk");
 
-        let first_track = to_bcd(disk.first_track_id()).expect("first track id is valid bcd");
-        let last_track = to_bcd(disk.last_track_id()).expect("last track id is valid bcd");
+        let first_track = to_bcd(disk.first_track_id());
+        let last_track = to_bcd(disk.last_track_id());
 
         CommandResponse::new().int3([self.status.0, first_track, last_track], AVG_1ST_RESP_INIT)
     }
@@ -271,14 +362,7 @@ impl CdRom {
             disk.last_track_end()
         };
 
-        CommandResponse::new().int3(
-            [
-                self.status.0,
-                to_bcd(mm).expect("valid bcd minutes"),
-                to_bcd(ss).expect("valid bcd seconds"),
-            ],
-            AVG_1ST_RESP_INIT,
-        )
+        CommandResponse::new().int3([self.status.0, to_bcd(mm), to_bcd(ss)], AVG_1ST_RESP_INIT)
     }
 
     pub fn stop(&mut self) -> CommandResponse {
@@ -288,9 +372,13 @@ impl CdRom {
 
         debug!(target: "cdrom", "cdrom stop");
 
+        self.scan = None;
         self.status.set_reading(false);
+        self.status.set_playing(false);
         let after_reading = self.status.0;
 
+        // Parking the head isn't timed, the next seek starts from there
+        self.seek_from = None;
         if let Some(cd) = self.disk.as_mut() {
             cd.seek_location(0, 2, 0);
         }
@@ -316,14 +404,240 @@ impl CdRom {
 
         let disk = self.disk.as_ref().expect("get_locp inserted disk");
 
+        // Track, index, relative and absolute time, straight from subchannel Q so protection
+        // checks see the sectors their discs modified
+        let q = disk.current_subq();
         CommandResponse::new().int3(
-            disk.current_position_info()
-                .map(|x| to_bcd(x).expect("track position is valid bcd")),
+            [q[1], q[2], q[3], q[4], q[5], q[7], q[8], q[9]],
             AVG_1ST_RESP_GENERIC,
         )
     }
 
-    pub fn get_locl(&self) -> CommandResponse {
+    pub fn forward(&mut self) -> CommandResponse {
+        self.start_scan(ScanDirection::Forward)
+    }
+
+    pub fn backward(&mut self) -> CommandResponse {
+        self.start_scan(ScanDirection::Backward)
+    }
+
+    fn start_scan(&mut self, direction: ScanDirection) -> CommandResponse {
+        if !self.parameters.is_empty() {
+            return error_response(&self.status, 0x20, "forward/backward take no parameters");
+        }
+
+        if !self.status.playing() {
+            return error_response(&self.status, 0x80, "forward/backward while not playing");
+        }
+
+        debug!(target: "cdrom", ?direction, "cdrom scan");
+
+        self.scan = Some(direction);
+
+        CommandResponse::new().int3([self.status.0], AVG_1ST_RESP_GENERIC)
+    }
+
+    pub fn motor_on(&mut self, now: u64) -> CommandResponse {
+        if !self.parameters.is_empty() {
+            return error_response(&self.status, 0x20, "motor_on takes no parameters");
+        }
+
+        debug!(target: "cdrom", "cdrom motor on");
+
+        if !self.status.motor_on() {
+            self.spindle.spin_up(now, self.mode.speed);
+        }
+
+        let before = self.status.enable_motor();
+        let wait = self.spindle.wait(now).max(SEEK_MIN_CYCLES);
+
+        CommandResponse::new()
+            .int3([before], AVG_1ST_RESP_GENERIC)
+            .int2([self.status.0], AVG_1ST_RESP_GENERIC + wait)
+    }
+
+    pub fn mute(&mut self) -> CommandResponse {
+        if !self.parameters.is_empty() {
+            return error_response(&self.status, 0x20, "mute takes no parameters");
+        }
+
+        debug!(target: "cdrom", "cdrom mute");
+
+        self.audio_muted = true;
+
+        CommandResponse::new().int3([self.status.0], AVG_1ST_RESP_GENERIC)
+    }
+
+    pub fn get_param(&self) -> CommandResponse {
+        if !self.parameters.is_empty() {
+            return error_response(&self.status, 0x20, "get_param takes no parameters");
+        }
+
+        debug!(target: "cdrom", "cdrom get param");
+
+        CommandResponse::new().int3(
+            [
+                self.status.0,
+                self.mode.value(),
+                0x00,
+                self.filter_file,
+                self.filter_channel,
+            ],
+            AVG_1ST_RESP_GENERIC,
+        )
+    }
+
+    pub fn set_session(&mut self, now: u64) -> CommandResponse {
+        if self.parameters.len() != 1 {
+            return error_response(&self.status, 0x20, "set_session expects 1 parameter");
+        }
+
+        debug!(target: "cdrom", params=?self.parameters, "cdrom set session");
+
+        let session = self.parameters[0];
+        if session == 0 {
+            return error_response(&self.status, 0x10, "set_session to session 0");
+        }
+
+        self.status.set_seeking(true);
+        let seeking_status = self.status.set_seeking(false);
+
+        // Only single session discs exist here, later sessions are never found, after searching
+        // the whole disc
+        if session > 1 {
+            return CommandResponse::new()
+                .int3([seeking_status], AVG_1ST_RESP_GENERIC)
+                .int5([0x06, 0x40], AVG_1ST_RESP_GENERIC + SLED_FULL_STROKE_CYCLES);
+        }
+
+        self.start_seek();
+        if let Some(disk) = self.disk.as_mut() {
+            disk.reset_read_head();
+        }
+        let seek = self.seek_time(now).max(SEEK_MIN_CYCLES);
+
+        CommandResponse::new()
+            .int3([seeking_status], AVG_1ST_RESP_GENERIC)
+            .int2([self.status.0], AVG_1ST_RESP_GENERIC + seek)
+    }
+
+    // The real-time clock only exists on some drive revisions, this one rejects them
+    pub fn set_clock(&self) -> CommandResponse {
+        self.invalid()
+    }
+
+    pub fn get_clock(&self) -> CommandResponse {
+        self.invalid()
+    }
+
+    pub fn reset(&mut self) -> CommandResponse {
+        if !self.parameters.is_empty() {
+            return error_response(&self.status, 0x20, "reset takes no parameters");
+        }
+
+        debug!(target: "cdrom", "cdrom reset");
+
+        let before = self.status.0;
+
+        self.mode = Mode::default();
+        self.scan = None;
+        self.audio_muted = false;
+        self.data_buffer.clear();
+        self.audio_buffer.clear();
+        self.address.set_data_request(false);
+
+        self.status.set_reading(false);
+        self.status.set_seeking(false);
+        self.status.set_playing(false);
+
+        if let Some(disk) = self.disk.as_mut() {
+            disk.reset_read_head();
+        }
+
+        CommandResponse::new().int3([before], AVG_1ST_RESP_INIT)
+    }
+
+    pub fn get_q(&self) -> CommandResponse {
+        if self.parameters.len() != 2 {
+            return error_response(&self.status, 0x20, "get_q expects 2 parameters");
+        }
+
+        debug!(target: "cdrom", params=?self.parameters, "cdrom get q");
+
+        let Some(disk) = self.disk.as_ref() else {
+            return self.no_disc();
+        };
+        let (adr, point) = (self.parameters[0], self.parameters[1]);
+        let first_track = disk.first_track_id();
+        let last_track = disk.last_track_id();
+
+        let msf = |(m, s, f)| [to_bcd(m), to_bcd(s), to_bcd(f)];
+
+        // Lead-in TOC entries: A0 first track and disc type, A1 last track, A2 lead-out
+        let entry = match point {
+            _ if adr != 1 => None,
+            0xA0 => Some((first_track, [to_bcd(first_track), 0x20, 0x00])),
+            0xA1 => Some((last_track, [to_bcd(last_track), 0x00, 0x00])),
+            0xA2 => Some((last_track, msf(disk.last_track_end()))),
+            _ => from_bcd(point)
+                .filter(|track| (first_track..=last_track).contains(track))
+                .map(|track| (track, msf(disk.track_mm_ss_ff(track)))),
+        };
+
+        let Some((track, [pm, ps, pf])) = entry else {
+            return CommandResponse::new()
+                .int3([self.status.0], AVG_1ST_RESP_GENERIC)
+                .int5([self.status.with_error(), 0x10], AVG_1ST_RESP_GENERIC);
+        };
+
+        let control_adr = if disk.track_is_audio(track) {
+            0x01
+        } else {
+            0x41
+        };
+
+        // The 10 SubQ bytes without their CRC, then peak_lo, always 0
+        CommandResponse::new()
+            .int3([self.status.0], AVG_1ST_RESP_GENERIC)
+            .int2(
+                [
+                    control_adr,
+                    0x00,
+                    point,
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                    pm,
+                    ps,
+                    pf,
+                    0x00,
+                ],
+                AVG_1ST_RESP_GENERIC + AVG_2ND_RESP_GET_ID,
+            )
+    }
+
+    pub fn read_toc(&mut self) -> CommandResponse {
+        if !self.parameters.is_empty() {
+            return error_response(&self.status, 0x20, "read_toc takes no parameters");
+        }
+
+        debug!(target: "cdrom", "cdrom read toc");
+
+        self.scan = None;
+        self.status.set_reading(false);
+        self.status.set_playing(false);
+        let before = self.status.enable_motor();
+
+        CommandResponse::new()
+            .int3([before], AVG_1ST_RESP_INIT)
+            .int2(
+                [self.status.0],
+                AVG_1ST_RESP_INIT + self.mode.speed.transform(AVG_2ND_RESP_READ_TOC),
+            )
+    }
+
+    pub fn get_locl(&mut self) -> CommandResponse {
         if !self.parameters.is_empty() {
             return error_response(&self.status, 0x20, "get_locl takes no parameters");
         }
@@ -338,7 +652,7 @@ impl CdRom {
 
         debug!(target: "cdrom", "cdrom getlocl");
 
-        let disk = self.disk.as_ref().expect("get_locl inserted disk");
+        let disk = self.disk.as_mut().expect("get_locl inserted disk");
 
         CommandResponse::new().int3(disk.current_header_info(), AVG_1ST_RESP_GENERIC)
     }
@@ -349,33 +663,11 @@ fn error_response(stat: &Status, err_byte: u8, err: &str) -> CommandResponse {
     CommandResponse::new().int5([stat.with_error(), err_byte], AVG_1ST_RESP_INIT)
 }
 
-const fn from_bcd(bcd: u8) -> Option<u8> {
-    let tens = bcd >> 4;
-    let ones = bcd & 0x0F;
-
-    if tens <= 9 && ones <= 9 {
-        Some(tens * 10 + ones)
-    } else {
-        None
-    }
-}
-
-const fn to_bcd(val: u8) -> Option<u8> {
-    if val > 99 {
-        return None;
-    }
-
-    let tens = val / 10;
-    let ones = val % 10;
-
-    Some((tens << 4) | ones)
-}
-
 #[derive(PartialEq, Eq, Clone)]
 pub enum ResponseType {
-    INT3(ArrayVec<u8, 8>),
-    INT2(ArrayVec<u8, 8>),
-    INT5([u8; 2]),
+    INT3(ArrayVec<u8, 16>),
+    INT2(ArrayVec<u8, 16>),
+    INT5(ArrayVec<u8, 16>),
     INT1,
 }
 
@@ -390,6 +682,37 @@ impl From<&ResponseType> for u8 {
     }
 }
 
+impl State for ResponseType {
+    fn save(&self, w: &mut Writer) {
+        u8::from(self).save(w);
+        match self {
+            Self::INT3(x) | Self::INT2(x) | Self::INT5(x) => x.save(w),
+            Self::INT1 => (),
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let mut irq = 0u8;
+        irq.load(r)?;
+
+        *self = match irq {
+            1 => Self::INT1,
+            2 | 3 | 5 => {
+                let mut x = ArrayVec::new();
+                x.load(r)?;
+                match irq {
+                    2 => Self::INT2(x),
+                    3 => Self::INT3(x),
+                    _ => Self::INT5(x),
+                }
+            }
+            x => bail!("invalid cdrom response INT{x}"),
+        };
+
+        Ok(())
+    }
+}
+
 #[derive(Default)]
 pub struct CommandResponse {
     pub responses: ArrayVec<(ResponseType, u64), 2>,
@@ -412,8 +735,9 @@ impl CommandResponse {
         self
     }
 
-    pub fn int5(mut self, data: [u8; 2], delay: u64) -> Self {
-        self.responses.push((ResponseType::INT5(data), delay));
+    pub fn int5<const N: usize>(mut self, data: [u8; N], delay: u64) -> Self {
+        let arr = ArrayVec::from_iter(data);
+        self.responses.push((ResponseType::INT5(arr), delay));
         self
     }
 
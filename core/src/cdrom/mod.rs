@@ -1,14 +1,20 @@
 mod cd_image;
 mod cdxa_audio;
 mod commands;
+mod spindle;
 
 use std::collections::VecDeque;
 use std::ops::Div;
 
+use anyhow::bail;
 use arrayvec::ArrayVec;
 pub use cd_image::Image;
+pub use cd_image::License;
 pub use commands::ResponseType;
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
 use procmac::Boolable;
+use tracing::debug;
 use tracing::trace;
 use tracing::warn;
 
@@ -20,7 +26,12 @@ use crate::cdrom::cdxa_audio::HighResResampler;
 use crate::cdrom::cdxa_audio::LowResResampler;
 use crate::cdrom::cdxa_audio::SampleRate;
 use crate::cdrom::cdxa_audio::decode_audio_sector;
-use crate::consts::AVG_RATE_INT1;
+use crate::cdrom::spindle::Spindle;
+use crate::consts::SECTOR_READ_CYCLES;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
+use crate::savestate::impl_state_enum;
 use crate::sched::Event;
 
 pub const PADDR_START: u32 = 0x1F80_1800;
@@ -47,7 +58,19 @@ pub struct CdRom {
     filter_file: u8,
     filter_channel: u8,
 
+    /// Fast forward or rewind while playing audio
+    scan: Option<ScanDirection>,
+
     disk: Option<Image>,
+
+    spindle: Spindle,
+    /// Sector the head was over when `SetLoc` picked a new target, until a seek gets there
+    seek_from: Option<usize>,
+
+    /// Lid is physically open, the status bit stays set after closing until it's read
+    shell_open: bool,
+    /// Disc that goes in when the lid closes
+    next_disk: Option<Image>,
 }
 
 impl Default for CdRom {
@@ -73,8 +96,103 @@ impl Default for CdRom {
             filter_file: 0,
             filter_channel: 0,
 
+            scan: None,
+
             disk: None,
+
+            spindle: Spindle::default(),
+            seek_from: None,
+
+            shell_open: false,
+            next_disk: None,
+        }
+    }
+}
+
+impl State for CdRom {
+    fn save(&self, w: &mut Writer) {
+        self.status.0.save(w);
+        self.address.0.save(w);
+        self.hintsts.0.save(w);
+        self.hintmsk.0.save(w);
+        self.parameters.save(w);
+        self.results.save(w);
+
+        self.mode.save(w);
+        self.data_buffer.save(w);
+        self.audio_buffer.save(w);
+        self.audio_muted.save(w);
+
+        self.adpcm_history.save(w);
+        self.high_res_resamplers.save(w);
+        self.low_res_resamplers.save(w);
+
+        self.filter_file.save(w);
+        self.filter_channel.save(w);
+        self.scan.save(w);
+
+        self.spindle.save(w);
+        self.seek_from.save(w);
+
+        self.shell_open.save(w);
+        self.disk.is_some().save(w);
+        self.next_disk.is_some().save(w);
+        if let Some(disk) = &self.disk {
+            disk.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.status.0.load(r)?;
+        self.address.0.load(r)?;
+        self.hintsts.0.load(r)?;
+        self.hintmsk.0.load(r)?;
+        self.parameters.load(r)?;
+        self.results.load(r)?;
+
+        self.mode.load(r)?;
+        self.data_buffer.load(r)?;
+        self.audio_buffer.load(r)?;
+        self.audio_muted.load(r)?;
+
+        self.adpcm_history.load(r)?;
+        self.high_res_resamplers.load(r)?;
+        self.low_res_resamplers.load(r)?;
+
+        self.filter_file.load(r)?;
+        self.filter_channel.load(r)?;
+        self.scan.load(r)?;
+
+        self.spindle.load(r)?;
+        self.seek_from.load(r)?;
+
+        self.shell_open.load(r)?;
+        let mut has_disk = false;
+        has_disk.load(r)?;
+        let mut has_next_disk = false;
+        has_next_disk.load(r)?;
+
+        // Disc contents aren't saved, so the disc there is goes where the state had one: in
+        // the drive, or waiting for the lid of a disc swap to close
+        let image = self.disk.take().or_else(|| self.next_disk.take());
+        let inserted = image.is_some();
+        if has_disk {
+            self.disk = image;
+        } else {
+            self.next_disk = image;
+        }
+
+        match (has_disk || has_next_disk, inserted) {
+            (true, false) => bail!("save state was made with a disc inserted"),
+            (false, true) => bail!("save state was made without a disc inserted"),
+            _ => (),
+        }
+
+        if let Some(disk) = self.disk.as_mut() {
+            disk.load(r)?;
         }
+
+        Ok(())
     }
 }
 
@@ -174,14 +292,64 @@ impl CdRom {
         val
     }
 
-    /// Returns whether the sector was as adpcm or data
-    fn process_sector(&mut self, sector: Vec<u8>) -> bool {
-        if self.status.playing() {
-            assert!(self.mode.cdda_enabled); // CDDA bit should be on during play mode
-            self.push_to_audio_buffer(&sector);
-            return true;
+    /// Stream the next CD-DA sector, reporting position and end of track/disc
+    fn play_next_sector(&mut self) -> PlayEvent {
+        let disk = self.disk.as_mut().expect("play_next_sector inserted disk");
+
+        if disk.is_at_end() {
+            debug!(target: "cdrom", "cdrom play reached end of disc");
+            self.scan = None;
+            self.status.set_playing(false);
+            self.status.disable_motor();
+            return PlayEvent::End;
         }
 
+        let position = disk.current_position_info();
+        let sector = disk.advance_sector();
+        if let Some(direction) = self.scan {
+            disk.skip_sectors(direction.sectors_per_step());
+        }
+
+        let track_changed = disk.current_track_id() != position[0];
+        self.push_to_audio_buffer(&sector);
+
+        if self.mode.auto_pause && track_changed {
+            debug!(target: "cdrom", track = position[0], "cdrom play reached end of track");
+            self.scan = None;
+            self.status.set_playing(false);
+            return PlayEvent::End;
+        }
+
+        if self.mode.report_enabled && position[7].is_multiple_of(10) {
+            return PlayEvent::Report(self.play_report(position, &sector));
+        }
+
+        PlayEvent::None
+    }
+
+    /// Alternates between absolute and track relative time every 10 sectors
+    fn play_report(&self, position: [u8; 8], sector: &[u8]) -> [u8; 8] {
+        let [track, index, rel_mm, rel_ss, rel_ff, abs_mm, abs_ss, abs_ff] = position.map(to_bcd);
+
+        let (mm, ss, ff) = if (position[7] / 10).is_multiple_of(2) {
+            (abs_mm, abs_ss, abs_ff)
+        } else {
+            (rel_mm, rel_ss | 0x80, rel_ff)
+        };
+
+        let peak = sector
+            .chunks_exact(2)
+            .map(|c| i16::from_le_bytes([c[0], c[1]]).unsigned_abs())
+            .max()
+            .unwrap_or(0)
+            .min(0x7FFF)
+            .to_le_bytes();
+
+        [self.status.0, track, index, mm, ss, ff, peak[0], peak[1]]
+    }
+
+    /// Returns whether the sector was as adpcm or data
+    fn process_sector(&mut self, sector: Vec<u8>) -> bool {
         let sector_mode = sector[0xF];
         let file = sector[0x10];
         let channel = sector[0x11];
@@ -258,7 +426,33 @@ impl CdRom {
         false
     }
 
+    /// Cycles until the head is over the `SetLoc` target, spinning the disc up or to a new speed
+    /// first if needed
+    fn seek_time(&mut self, now: u64) -> u64 {
+        if self.status.motor_on() {
+            self.spindle.change_speed(now, self.mode.speed);
+        } else {
+            self.status.set_motor_on(true);
+            self.spindle.spin_up(now, self.mode.speed);
+        }
+
+        let seek = match (self.seek_from.take(), self.disk.as_ref()) {
+            (Some(from), Some(disk)) => self.spindle.seek_cycles(from, disk.lba()),
+            _ => 0,
+        };
+
+        self.spindle.wait(now) + seek
+    }
+
+    /// Remember where the head is before moving the target, the seek starts from there
+    fn start_seek(&mut self) {
+        if self.seek_from.is_none() {
+            self.seek_from = self.disk.as_ref().map(Image::lba);
+        }
+    }
+
     fn exec_command(system: &mut System, cmd: u8) {
+        let now = system.scheduler.sysclk();
         let cdrom = &mut system.cdrom;
 
         // Certain commands stop read responses
@@ -268,28 +462,49 @@ impl CdRom {
                 .unschedule(&Event::CdromResultIrq(ResponseType::INT1));
         }
 
+        // Reset aborts everything the drive was doing
+        if cmd == 0x1C {
+            system
+                .scheduler
+                .unschedule_matching(|e| matches!(e, Event::CdromResultIrq(_)));
+        }
+
         let response = match cmd {
-            0x00 => cdrom.invalid(),
+            _ if cdrom.shell_open && needs_disc(cmd) => cdrom.door_open(),
+            // GetID has its own answer for an empty drive
+            _ if cdrom.disk.is_none() && needs_disc(cmd) && cmd != 0x1A => cdrom.no_disc(),
+
             0x01 => cdrom.nop(),
             0x02 => cdrom.set_loc(),
-            0x03 => cdrom.play(),
-            0x06 => cdrom.readn(),
+            0x03 => cdrom.play(now),
+            0x04 => cdrom.forward(),
+            0x05 => cdrom.backward(),
+            0x06 => cdrom.readn(now),
+            0x07 => cdrom.motor_on(now),
             0x08 => cdrom.stop(),
             0x09 => cdrom.pause(),
-            0x0A => cdrom.init(),
+            0x0A => cdrom.init(now),
+            0x0B => cdrom.mute(),
             0x0C => cdrom.demute(),
             0x0D => cdrom.set_filter(),
-            0x0E => cdrom.setmode(),
+            0x0E => cdrom.setmode(now),
+            0x0F => cdrom.get_param(),
             0x10 => cdrom.get_locl(),
             0x11 => cdrom.get_locp(),
+            0x12 => cdrom.set_session(now),
             0x13 => cdrom.get_tn(),
             0x14 => cdrom.get_td(),
-            0x15 => cdrom.seekl(),
-            0x16 => cdrom.seekp(),
+            0x15 => cdrom.seekl(now),
+            0x16 => cdrom.seekp(now),
+            0x17 => cdrom.set_clock(),
+            0x18 => cdrom.get_clock(),
             0x19 => cdrom.test(),
             0x1A => cdrom.get_id(),
-            0x1B => cdrom.reads(),
-            _ => unimplemented!("cdrom command {cmd:02x}"),
+            0x1B => cdrom.reads(now),
+            0x1C => cdrom.reset(),
+            0x1D => cdrom.get_q(),
+            0x1E => cdrom.read_toc(),
+            _ => cdrom.invalid(),
         };
 
         cdrom.parameters.clear();
@@ -301,7 +516,7 @@ impl CdRom {
             .into_iter()
             .for_each(|(res_type, delay)| {
                 let repeat = match res_type {
-                    ResponseType::INT1 => Some(cdrom.mode.speed.transform(AVG_RATE_INT1)),
+                    ResponseType::INT1 => Some(cdrom.mode.speed.transform(SECTOR_READ_CYCLES)),
                     _ => None,
                 };
                 system
@@ -313,17 +528,29 @@ impl CdRom {
     pub fn handle_response(system: &mut System, response: ResponseType) {
         let cdrom = &mut system.cdrom;
 
-        let irq = u8::from(&response);
+        let mut irq = u8::from(&response);
         let mut results = Vec::new();
 
         match response {
-            ResponseType::INT5(response) => {
+            ResponseType::INT5(response)
+            | ResponseType::INT3(response)
+            | ResponseType::INT2(response) => {
                 results.extend(response);
             }
 
-            ResponseType::INT3(response) | ResponseType::INT2(response) => {
-                results.extend(response);
-            }
+            ResponseType::INT1 if cdrom.status.playing() => match cdrom.play_next_sector() {
+                PlayEvent::None => return,
+                PlayEvent::Report(report) => results.extend(report),
+                PlayEvent::End => {
+                    system
+                        .scheduler
+                        .unschedule(&Event::CdromResultIrq(ResponseType::INT1));
+
+                    // DataEnd
+                    irq = 4;
+                    results.push(cdrom.status.0);
+                }
+            },
 
             ResponseType::INT1 => {
                 let Some(inserted_disk) = cdrom.disk.as_mut() else {
@@ -359,9 +586,60 @@ impl CdRom {
         self.results.clear();
     }
 
+    /// Open the lid, which stops the drive and takes out the disc
     pub fn open_shell(&mut self) {
+        self.shell_open = true;
         self.status.set_shell_open(true);
+        self.status.set_reading(false);
+        self.status.set_seeking(false);
+        self.status.set_playing(false);
+        self.status.set_motor_on(false);
+        self.disk = None;
     }
+
+    /// Open the lid and keep `image` ready to go in once it closes
+    pub fn swap_disc(&mut self, image: Option<Image>) {
+        self.open_shell();
+        self.next_disk = image;
+    }
+
+    /// Close the lid on the disc from the last swap, an empty drive stays stopped
+    pub fn close_shell(&mut self, now: u64) {
+        self.shell_open = false;
+
+        // The game may be mid command, so unlike a boot insert nothing is reset
+        self.disk = self.next_disk.take();
+        self.seek_from = None;
+        self.status.set_motor_on(self.disk.is_some());
+        if self.disk.is_some() {
+            self.spindle.spin_up(now, self.mode.speed);
+        }
+    }
+}
+
+/// Packed BCD, how the drive reports times and track numbers. Those never reach 100.
+const fn to_bcd(val: u8) -> u8 {
+    debug_assert!(val < 100, "no bcd form");
+    ((val / 10) << 4) | (val % 10)
+}
+
+const fn from_bcd(bcd: u8) -> Option<u8> {
+    let tens = bcd >> 4;
+    let ones = bcd & 0x0F;
+
+    if tens <= 9 && ones <= 9 {
+        Some(tens * 10 + ones)
+    } else {
+        None
+    }
+}
+
+/// Commands that fail with the lid open
+const fn needs_disc(cmd: u8) -> bool {
+    matches!(
+        cmd,
+        0x02..=0x07 | 0x10..=0x16 | 0x1A | 0x1B | 0x1D | 0x1E
+    )
 }
 
 pub fn read<const WIDTH: usize>(system: &mut System, addr: u32) -> u32 {
@@ -448,7 +726,7 @@ bitfield::bitfield! {
     seeking, _: 6;
     reading, _: 5;
     _, set_shell_open: 4;
-    _, set_motor_on: 1;
+    motor_on, set_motor_on: 1;
     _, set_error: 0;
 }
 
@@ -506,7 +784,33 @@ impl Status {
     }
 }
 
-#[derive(Clone, Copy, Debug, Boolable)]
+enum PlayEvent {
+    None,
+    Report([u8; 8]),
+    End,
+}
+
+#[derive(Clone, Copy, Debug, Default, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+enum ScanDirection {
+    #[default]
+    Forward,
+    Backward,
+}
+
+impl_state_enum!(ScanDirection);
+
+impl ScanDirection {
+    /// Extra sectors skipped for every sector played
+    const fn sectors_per_step(self) -> isize {
+        match self {
+            Self::Forward => 8,
+            Self::Backward => -10, // Undo the sector that was just played too
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Boolable)]
 enum Speed {
     Normal = 0,
     Double = 1,
@@ -538,15 +842,55 @@ struct Mode {
     filter_enabled: bool,
     cdda_enabled: bool,
     auto_pause: bool,
+    report_enabled: bool,
+}
+
+impl State for Mode {
+    fn save(&self, w: &mut Writer) {
+        bool::from(self.speed).save(w);
+        bool::from(self.sector_size).save(w);
+        self.adpcm_enabled.save(w);
+        self.filter_enabled.save(w);
+        self.cdda_enabled.save(w);
+        self.auto_pause.save(w);
+        self.report_enabled.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let mut speed = false;
+        let mut sector_size = false;
+        speed.load(r)?;
+        sector_size.load(r)?;
+
+        self.speed = Speed::from(speed);
+        self.sector_size = SectorSize::from(sector_size);
+        self.adpcm_enabled.load(r)?;
+        self.filter_enabled.load(r)?;
+        self.cdda_enabled.load(r)?;
+        self.auto_pause.load(r)?;
+        self.report_enabled.load(r)
+    }
 }
 
 impl Mode {
+    // Bit 4 (ignore bit) is not kept around
+    fn value(&self) -> u8 {
+        u8::from(bool::from(self.speed)) << 7
+            | u8::from(self.adpcm_enabled) << 6
+            | u8::from(bool::from(self.sector_size)) << 5
+            | u8::from(self.filter_enabled) << 3
+            | u8::from(self.report_enabled) << 2
+            | u8::from(self.auto_pause) << 1
+            | u8::from(self.cdda_enabled)
+    }
+
     fn set_value(&mut self, data: u8) {
         self.speed = Speed::from(data & (1 << 7) != 0);
         self.adpcm_enabled = data & (1 << 6) != 0;
         self.filter_enabled = data & (1 << 3) != 0;
         self.cdda_enabled = data & 1 != 0;
         self.auto_pause = data & 2 != 0;
+        self.report_enabled = data & 4 != 0;
 
         // Set sector size only if ignore bit is 0
         if data & (1 << 4) == 0 {
@@ -564,6 +908,92 @@ impl Default for Mode {
             filter_enabled: false,
             cdda_enabled: false,
             auto_pause: false,
+            report_enabled: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::SECTOR_SIZE;
+
+    fn image() -> Image {
+        Image::from_bytes(vec![0; 16 * SECTOR_SIZE])
+    }
+
+    fn save(cdrom: &CdRom) -> Vec<u8> {
+        let mut w = Writer::default();
+        cdrom.save(&mut w);
+        w.into_inner()
+    }
+
+    #[test]
+    fn state_saved_during_a_swap() -> anyhow::Result<()> {
+        let mut cdrom = CdRom {
+            disk: Some(image()),
+            ..CdRom::default()
+        };
+        cdrom.swap_disc(Some(image()));
+        let state = save(&cdrom);
+
+        // The disc that went in since goes back to waiting for the lid
+        cdrom.close_shell(0);
+        cdrom.load(&mut Reader::new(&state))?;
+        assert!(cdrom.shell_open);
+        assert!(cdrom.disk.is_none());
+
+        cdrom.close_shell(0);
+        assert!(cdrom.disk.is_some());
+        assert!(cdrom.status.motor_on());
+
+        Ok(())
+    }
+
+    #[test]
+    fn state_saved_with_the_lid_closed() -> anyhow::Result<()> {
+        let mut cdrom = CdRom {
+            disk: Some(image()),
+            ..CdRom::default()
+        };
+        let state = save(&cdrom);
+
+        // Loaded mid swap, the waiting disc goes straight in
+        cdrom.swap_disc(Some(image()));
+        cdrom.load(&mut Reader::new(&state))?;
+        assert!(!cdrom.shell_open);
+        assert!(cdrom.disk.is_some());
+        assert!(cdrom.next_disk.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn state_with_another_disc_setup() {
+        let empty = save(&CdRom::default());
+        let mut cdrom = CdRom {
+            disk: Some(image()),
+            ..CdRom::default()
+        };
+        assert!(cdrom.load(&mut Reader::new(&empty)).is_err());
+
+        let inserted = save(&cdrom);
+        assert!(CdRom::default().load(&mut Reader::new(&inserted)).is_err());
+    }
+
+    #[test]
+    fn get_q_sends_subq_and_the_peak_level() {
+        let mut cdrom = CdRom {
+            disk: Some(image()),
+            ..CdRom::default()
+        };
+        cdrom.parameters.extend([1, 1]);
+
+        let response = cdrom.get_q();
+        let Some((ResponseType::INT2(data), _)) = response.responses.get(1) else {
+            panic!("get_q answers with INT3 then INT2");
+        };
+        assert_eq!(data.len(), 11);
+        assert_eq!(data[..3], [0x41, 0x00, 0x01]);
+    }
+}
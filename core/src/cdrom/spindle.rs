@@ -0,0 +1,86 @@
+//! Timing of the drive mechanics: spinning the disc up, switching speeds and moving the head
+
+use super::Speed;
+use crate::consts::SECTOR_READ_CYCLES;
+use crate::consts::SEEK_MIN_CYCLES;
+use crate::consts::SLED_FULL_STROKE_CYCLES;
+use crate::consts::SPEED_CHANGE_CYCLES;
+use crate::consts::SPIN_UP_CYCLES;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
+
+/// Seeks shorter than this are done by the lens alone, without moving the sled
+const TRACKING_SECTORS: usize = 32;
+
+/// Sectors on a full 80 minute disc, the length of a full stroke of the sled
+const MAX_SECTORS: usize = 80 * 60 * 75;
+
+pub struct Spindle {
+    /// Speed the disc is spinning at, or heading to
+    speed: Speed,
+    /// Cycle the disc is up to `speed`, reads and seeks wait for it
+    ready_at: u64,
+}
+
+impl Default for Spindle {
+    fn default() -> Self {
+        Self {
+            speed: Speed::Normal,
+            ready_at: 0,
+        }
+    }
+}
+
+impl State for Spindle {
+    fn save(&self, w: &mut Writer) {
+        bool::from(self.speed).save(w);
+        self.ready_at.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let mut speed = false;
+        speed.load(r)?;
+
+        self.speed = Speed::from(speed);
+        self.ready_at.load(r)
+    }
+}
+
+impl Spindle {
+    /// Start the motor from a stop
+    pub const fn spin_up(&mut self, now: u64, speed: Speed) {
+        self.speed = speed;
+        self.ready_at = now + SPIN_UP_CYCLES;
+    }
+
+    /// Change speed while spinning, the disc has to settle at the new speed before reading
+    pub fn change_speed(&mut self, now: u64, speed: Speed) {
+        if speed != self.speed {
+            self.speed = speed;
+            self.ready_at = self.ready_at.max(now) + SPEED_CHANGE_CYCLES;
+        }
+    }
+
+    /// Cycles until the disc is up to speed
+    pub const fn wait(&self, now: u64) -> u64 {
+        self.ready_at.saturating_sub(now)
+    }
+
+    /// Cycles to move the head between two sectors and find the target
+    pub fn seek_cycles(&self, from: usize, to: usize) -> u64 {
+        let distance = from.abs_diff(to);
+        let sector = self.speed.transform(SECTOR_READ_CYCLES);
+
+        // Short hops jump a few tracks and wait for the sector to come around
+        if distance < TRACKING_SECTORS {
+            return SEEK_MIN_CYCLES + sector * distance.min(4) as u64;
+        }
+
+        // The sled accelerates and brakes, so longer seeks cost less per sector
+        let sled = SLED_FULL_STROKE_CYCLES * distance.min(MAX_SECTORS).isqrt() as u64
+            / MAX_SECTORS.isqrt() as u64;
+
+        SEEK_MIN_CYCLES + sled + sector * 2
+    }
+}
@@ -1,18 +1,74 @@
-pub const LINE_DURATION: u64 = 2172;
-pub const HBLANK_DURATION: u64 = 390;
+/// Scanline timings of a video standard, durations are in CPU cycles
+pub struct VideoTiming {
+    pub line_cycles: u64,
+    pub hblank_cycles: u64,
+
+    /// GPU video clock ticks per scanline
+    pub line_dots: u64,
+
+    pub lines: u64,
+    pub vblank_start_line: u64,
+    pub refresh_rate: u32,
+}
+
+impl VideoTiming {
+    pub const fn frame_cycles(&self) -> u64 {
+        self.line_cycles * self.lines
+    }
+
+    /// Audio samples produced during one frame
+    pub const fn samples_per_frame(&self) -> usize {
+        (SAMPLE_RATE / self.refresh_rate) as usize
+    }
+}
+
+pub const NTSC_TIMING: VideoTiming = VideoTiming {
+    line_cycles: 2172,
+    hblank_cycles: 390,
+    line_dots: 3413,
+    lines: 263,
+    vblank_start_line: 240,
+    refresh_rate: 60,
+};
+
+pub const PAL_TIMING: VideoTiming = VideoTiming {
+    line_cycles: 2168,
+    hblank_cycles: 390,
+    line_dots: 3406,
+    lines: 314,
+    vblank_start_line: 288,
+    refresh_rate: 50,
+};
 
 pub const SECTOR_SIZE: usize = 0x930;
 
+/// How long the lid stays open while swapping discs, about a second so games polling the
+/// drive status notice it
+pub const LID_OPEN_CYCLES: u64 = 33_868_800;
+
 pub const AVG_1ST_RESP_GENERIC: u64 = 0xC4E1;
 pub const AVG_1ST_RESP_INIT: u64 = 0x13CCE;
 
 pub const AVG_2ND_RESP_GET_ID: u64 = 0x4A00;
 pub const AVG_2ND_RESP_PAUSE: u64 = 0x0021_181C;
-pub const AVG_2ND_RESP_SEEKL: u64 = 0x6E1CD;
+pub const AVG_2ND_RESP_READ_TOC: u64 = 0x0100_0000;
+
+/// One sector at single speed, 75 sectors a second
+pub const SECTOR_READ_CYCLES: u64 = 33_868_800 / 75;
+
+/// Bringing a stopped disc up to speed takes about half a second
+pub const SPIN_UP_CYCLES: u64 = 33_868_800 / 2;
+
+/// Switching between single and double speed takes about 0.3 seconds
+pub const SPEED_CHANGE_CYCLES: u64 = 33_868_800 * 3 / 10;
+
+/// Shortest seek, settling the lens on the target track
+pub const SEEK_MIN_CYCLES: u64 = 20_000;
 
-pub const AVG_RATE_INT1: u64 = 0x6E1CD;
+/// Sled seek from one edge of the disc to the other, close to a second
+pub const SLED_FULL_STROKE_CYCLES: u64 = 33_868_800 * 9 / 10;
 
 pub const POS_ADPCM_TABLE: [i32; 5] = [0, 60, 115, 98, 122];
 pub const NEG_ADPCM_TABLE: [i32; 5] = [0, 0, -52, -55, -60];
 
-pub const SAMPLES_PER_FRAME: usize = 735;
+pub const SAMPLE_RATE: u32 = 44100;
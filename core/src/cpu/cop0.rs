@@ -1,5 +1,6 @@
 use super::Instruction;
 use super::System;
+use crate::savestate::impl_state;
 
 #[derive(Default)]
 pub struct Cop0 {
@@ -16,6 +17,13 @@ pub struct Cop0 {
     pub baddr: u32,
 }
 
+impl_state!(Cop0 {
+    sr,
+    cause,
+    epc,
+    baddr,
+});
+
 impl Cop0 {
     pub const fn gte_enabled(&self) -> bool {
         (self.sr >> 30) & 1 != 0
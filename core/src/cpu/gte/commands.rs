@@ -1,3 +1,5 @@
+use starpsx_renderer::vec2::PreciseVertex;
+
 use super::CommandFields;
 use super::ControlVec;
 use super::GTEngine;
@@ -522,10 +524,31 @@ impl GTEngine {
         let sy2 = self.i32_to_i11::<1>((screen_y >> 16) as i32);
 
         self.sxy.push([sx2, sy2]);
+        self.precise_sxy
+            .push(self.precise_projection(temp, sf, [sx2, sy2]));
 
         projection_factor
     }
 
+    /// Screen position of a projected vertex without the rounding, saturation and division
+    /// approximation of the hardware. `None` when those changed the result by a pixel or more,
+    /// the vertex is off screen or too close to project.
+    fn precise_projection(&self, temp: [i64; 3], sf: u8, sxy: [i16; 2]) -> Option<PreciseVertex> {
+        let z = temp[2] as f32 / 4096.0;
+        if z <= f32::from(self.h) / 2.0 {
+            return None;
+        }
+
+        let view = [temp[0], temp[1]].map(|t| (t >> sf) as f32);
+        let h = f32::from(self.h);
+
+        let x = view[0] * h / z + self.of[0] as f32 / 65536.0;
+        let y = view[1] * h / z + self.of[1] as f32 / 65536.0;
+
+        let close = |precise: f32, whole: i16| (precise - f32::from(whole)).abs() < 1.0;
+        (close(x, sxy[0]) && close(y, sxy[1])).then_some(PreciseVertex { x, y, w: z })
+    }
+
     fn depth_queuing(&mut self, projection_factor: u32) {
         let (dqa, dqb) = (i64::from(self.dqa), i64::from(self.dqb));
         let factor = i64::from(projection_factor);
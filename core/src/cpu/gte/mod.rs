@@ -5,14 +5,20 @@ use std::ops::Index;
 use std::ops::IndexMut;
 
 use num_enum::FromPrimitive;
+use starpsx_renderer::vec2::PreciseVertex;
 use utils::matrix_reg_read;
 use utils::matrix_reg_write;
 use utils::vec_xy_read;
 use utils::vec_xy_write;
 
 use crate::System;
+use crate::cpu::timing::gte_cycles;
 use crate::cpu::utils::Exception;
 use crate::cpu::utils::Instruction;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
+use crate::savestate::impl_state;
 
 #[derive(Default)]
 pub struct GTEngine {
@@ -54,6 +60,10 @@ pub struct GTEngine {
     /// Screen XY coordinates (1, 15, 0)
     sxy: FixedFifo<[i16; 2], 3>,
 
+    /// Unrounded `sxy` entries from the last projections, for PGXP. Not part of the hardware
+    /// state, a loaded state just has none until the next projection.
+    precise_sxy: FixedFifo<Option<PreciseVertex>, 3>,
+
     /// Screen Z coordinates (0, 16, 0)
     sz: FixedFifo<u16, 4>,
 
@@ -82,17 +92,52 @@ pub struct GTEngine {
     flag: Flag,
 }
 
+impl_state!(GTEngine {
+    rtm,
+    llm,
+    lcm,
+    tr,
+    bk,
+    fc,
+    of,
+    h,
+    dqa,
+    dqb,
+    zsf3,
+    zsf4,
+    otz,
+    sxy,
+    sz,
+    rgbc,
+    res1,
+    colors,
+    v,
+    ir,
+    mac,
+    lzcs,
+    flag.0,
+});
+
 pub fn cop2(system: &mut System, instr: Instruction) -> Result<(), Exception> {
     check_valid_gte_access(system)?;
 
     if instr.is_gte_command() {
+        system.cpu.stall_gte();
+        system.cpu.start_gte(gte_cycles(instr.0 & 0x3F));
         system.cpu.gte.command(CommandFields(instr.0));
         return Ok(());
     }
 
+    // Reads wait for the running command, writes go through right away
     match instr.rs() {
-        0x00 => mfc2(system, instr),
-        0x02 => cfc2(system, instr),
+        0x00 => {
+            system.cpu.stall_gte();
+            mfc2(system, instr);
+        }
+        0x02 => {
+            system.cpu.stall_gte();
+            cfc2(system, instr);
+        }
         0x04 => mtc2(system, instr),
         0x06 => ctc2(system, instr),
         _ => unimplemented!("GTE instruction instr={:#08x} ", instr.0),
@@ -118,12 +163,16 @@ impl GTEngine {
 
             8..=11 => self.ir[r - 8] = data as i16,
 
-            12..=14 => vec_xy_write(&mut self.sxy[r - 12], data),
+            12..=14 => {
+                vec_xy_write(&mut self.sxy[r - 12], data);
+                self.precise_sxy[r - 12] = None;
+            }
             // SXYP is a SXY2 mirror with move-on-write
             15 => {
                 let mut v = [0; 2];
                 vec_xy_write(&mut v, data);
                 self.sxy.push(v);
+                self.precise_sxy.push(None);
             }
 
             16..=19 => self.sz[r - 16] = data as u16,
@@ -234,6 +283,14 @@ impl GTEngine {
         }
     }
 
+    /// Unrounded position of a projected vertex, if `word` is one of the SXY entries
+    pub fn precise_sxy(&self, word: u32) -> Option<PreciseVertex> {
+        (0..3)
+            .rev()
+            .find(|&i| vec_xy_read(&self.sxy[i]) == word)
+            .and_then(|i| self.precise_sxy[i])
+    }
+
     /// Counting leading bits result
     const fn lzcr(&self) -> u32 {
         if self.lzcs.is_negative() {
@@ -353,6 +410,7 @@ pub fn swc2(system: &mut System, instr: Instruction) -> Result<(), Exception> {
     let rt = instr.rt();
     let im = instr.imm16_se();
 
+    system.cpu.stall_gte();
     let addr = system.cpu.regs[rs].wrapping_add(im);
     let data = system.cpu.gte.read_reg(rt);
 
@@ -491,6 +549,19 @@ where
     }
 }
 
+impl<T, const LEN: usize> State for FixedFifo<T, LEN>
+where
+    T: Default + Copy + State,
+{
+    fn save(&self, w: &mut Writer) {
+        self.fifo.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.fifo.load(r)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum Matrix {
     Rotation = 0,
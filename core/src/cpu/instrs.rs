@@ -5,6 +5,7 @@ use super::Exception;
 use super::Instruction;
 use super::System;
 use super::error;
+use super::timing;
 
 impl Cpu {
     // Load and store instructions
@@ -530,6 +531,11 @@ impl Cpu {
         let rt = instr.rt();
         let rs = instr.rs();
 
+        system.cpu.stall_muldiv();
+        system
+            .cpu
+            .start_muldiv(timing::mult_cycles(system.cpu.regs[rs], true));
+
         let lhs = i64::from(system.cpu.regs[rs] as i32);
         let rhs = i64::from(system.cpu.regs[rt] as i32);
 
@@ -544,6 +550,11 @@ impl Cpu {
         let rt = instr.rt();
         let rs = instr.rs();
 
+        system.cpu.stall_muldiv();
+        system
+            .cpu
+            .start_muldiv(timing::mult_cycles(system.cpu.regs[rs], false));
+
         let lhs = u64::from(system.cpu.regs[rs]);
         let rhs = u64::from(system.cpu.regs[rt]);
 
@@ -558,6 +569,9 @@ impl Cpu {
         let rt = instr.rt();
         let rs = instr.rs();
 
+        system.cpu.stall_muldiv();
+        system.cpu.start_muldiv(timing::DIV_CYCLES);
+
         let lhs = system.cpu.regs[rs] as i32;
         let rhs = system.cpu.regs[rt] as i32;
 
@@ -576,6 +590,9 @@ impl Cpu {
         let rt = instr.rt();
         let rs = instr.rs();
 
+        system.cpu.stall_muldiv();
+        system.cpu.start_muldiv(timing::DIV_CYCLES);
+
         let lhs = system.cpu.regs[rs];
         let rhs = system.cpu.regs[rt];
 
@@ -591,12 +608,14 @@ impl Cpu {
     /// Move from hi
     pub fn mfhi(system: &mut System, instr: Instruction) {
         let rd = instr.rd();
+        system.cpu.stall_muldiv();
         system.cpu.regd[rd] = system.cpu.hi;
     }
 
     /// Move from lo
     pub fn mflo(system: &mut System, instr: Instruction) {
         let rd = instr.rd();
+        system.cpu.stall_muldiv();
         system.cpu.regd[rd] = system.cpu.lo;
     }
 
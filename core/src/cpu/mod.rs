@@ -1,15 +1,20 @@
 mod cop0;
 mod gte;
 mod instrs;
+pub mod timing;
 pub mod utils;
 
 use cop0::Cop0;
+use starpsx_renderer::vec2::PreciseVertex;
+use timing::CpuTiming;
+use timing::InstructionCache;
 use tracing::error;
 use utils::Exception;
 use utils::Instruction;
 
 use crate::System;
 use crate::cpu::gte::GTEngine;
+use crate::savestate::impl_state;
 
 pub struct Cpu {
     /// 32-bit general purpose registers, R0 is always zero
@@ -38,6 +43,21 @@ pub struct Cpu {
 
     /// Geometry Transformation Engine (Coprocessor 2)
     gte: GTEngine,
+
+    /// Cost model used for each instruction
+    pub timing: CpuTiming,
+
+    /// Cycles spent on the current instruction, only counted with accurate timing
+    cycles: u64,
+
+    /// Cycles until the MULT/DIV unit has its result in hi/lo
+    muldiv_busy: u64,
+
+    /// Cycles until the GTE finishes its current command
+    gte_busy: u64,
+
+    /// Instruction cache tags, decides which fetches hit
+    icache: InstructionCache,
 }
 
 impl Default for Cpu {
@@ -56,17 +76,34 @@ impl Default for Cpu {
             delayed_branch: None,
             cop0: Cop0::default(),
             gte: GTEngine::default(),
+            timing: CpuTiming::default(),
+            cycles: 0,
+            muldiv_busy: 0,
+            gte_busy: 0,
+            icache: InstructionCache::default(),
         }
     }
 }
 
 impl Cpu {
-    pub fn run_next_instruction(system: &mut System) {
-        let instr = Instruction(match system.read::<4>(system.cpu.pc) {
+    /// Run one instruction, returns the cycles it took
+    pub fn run_next_instruction(system: &mut System) -> u64 {
+        Self::execute_next(system);
+        system.cpu.take_cycles()
+    }
+
+    fn execute_next(system: &mut System) {
+        let pc = system.cpu.pc;
+        let instr = Instruction(match system.read::<4>(pc) {
             Ok(v) => v,
             Err(e) => return system.cpu.handle_exception(&e, false),
         });
 
+        // The fetch is charged through the instruction cache instead of as a data load
+        if system.cpu.timing == CpuTiming::Accurate {
+            system.cpu.cycles = system.cpu.icache.fetch_cycles(pc);
+        }
+
         let (next_pc, in_delay) = match system.cpu.delayed_branch.take() {
             Some(addr) => (addr, true),
             None => (system.cpu.pc.wrapping_add(4), false),
@@ -101,6 +138,56 @@ impl Cpu {
         }
     }
 
+    /// Cycles used by the instruction that just ran, busy units keep counting down meanwhile
+    const fn take_cycles(&mut self) -> u64 {
+        if matches!(self.timing, CpuTiming::Fast) {
+            return 2;
+        }
+
+        let cycles = std::mem::replace(&mut self.cycles, 0);
+        let cycles = if cycles == 0 { 1 } else { cycles };
+
+        self.muldiv_busy = self.muldiv_busy.saturating_sub(cycles);
+        self.gte_busy = self.gte_busy.saturating_sub(cycles);
+        cycles
+    }
+
+    /// Charge the wait states of a load or store to an already masked address
+    pub const fn charge_access<const WIDTH: usize>(&mut self, physical: u32, store: bool) {
+        if matches!(self.timing, CpuTiming::Accurate) {
+            self.cycles += timing::access_cycles::<WIDTH>(physical, store);
+        }
+    }
+
+    /// Wait for the MULT/DIV unit before touching hi/lo
+    const fn stall_muldiv(&mut self) {
+        self.cycles += self.muldiv_busy;
+        self.muldiv_busy = 0;
+    }
+
+    /// Wait for the GTE to finish its current command
+    const fn stall_gte(&mut self) {
+        self.cycles += self.gte_busy;
+        self.gte_busy = 0;
+    }
+
+    const fn start_muldiv(&mut self, cycles: u64) {
+        if matches!(self.timing, CpuTiming::Accurate) {
+            self.muldiv_busy = cycles;
+        }
+    }
+
+    const fn start_gte(&mut self, cycles: u64) {
+        if matches!(self.timing, CpuTiming::Accurate) {
+            self.gte_busy = cycles;
+        }
+    }
+
+    /// Unrounded position of a projected vertex the GTE holds as `word`, for PGXP
+    pub fn precise_sxy(&self, word: u32) -> Option<PreciseVertex> {
+        self.gte.precise_sxy(word)
+    }
+
     const fn pending_interrupts(system: &mut System) -> bool {
         let cpu = &mut system.cpu;
 
@@ -258,3 +345,18 @@ pub struct Snapshot {
 
     pub regs: [u32; 32],
 }
+
+impl_state!(Cpu {
+    regs,
+    regd,
+    pc,
+    delayed_branch,
+    hi,
+    lo,
+    load,
+    cop0,
+    gte,
+    muldiv_busy,
+    gte_busy,
+    icache,
+});
@@ -0,0 +1,112 @@
+use crate::cdrom;
+use crate::mem::bios;
+use crate::mem::ram;
+use crate::mem::scratch;
+use crate::savestate::impl_state;
+use crate::spu;
+
+/// How the CPU charges cycles for each instruction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CpuTiming {
+    /// Every instruction takes a flat 2 cycles, cheapest to emulate
+    #[default]
+    Fast,
+
+    /// Instruction cache misses, memory wait states, MULT/DIV and GTE stalls
+    Accurate,
+}
+
+const ICACHE_LINES: usize = 256;
+const INVALID_TAG: u32 = u32::MAX;
+
+/// Tags of the 4KiB instruction cache, only used to decide hits and misses as code is always
+/// fetched straight from memory
+pub struct InstructionCache {
+    tags: [u32; ICACHE_LINES],
+}
+
+impl_state!(InstructionCache { tags });
+
+impl Default for InstructionCache {
+    fn default() -> Self {
+        Self {
+            tags: [INVALID_TAG; ICACHE_LINES],
+        }
+    }
+}
+
+impl InstructionCache {
+    /// Cycles to fetch the instruction at `addr`, including the cycle it takes to execute
+    pub fn fetch_cycles(&mut self, addr: u32) -> u64 {
+        let physical = addr & 0x1FFF_FFFF;
+
+        // KSEG1 is never cached
+        if addr >> 29 == 0b101 {
+            return 1 + access_cycles::<4>(physical, false);
+        }
+
+        let line = ((addr >> 4) as usize) % ICACHE_LINES;
+        let tag = physical >> 12;
+        if self.tags[line] == tag {
+            return 1;
+        }
+
+        // A miss fills the line from the missing word to its end
+        self.tags[line] = tag;
+        let words = 4 - u64::from((addr >> 2) & 3);
+        1 + access_cycles::<4>(physical, false) + (words - 1)
+    }
+}
+
+/// Wait states of a CPU load or store, with the memory control delays the BIOS sets up
+pub const fn access_cycles<const WIDTH: usize>(physical: u32, store: bool) -> u64 {
+    let width = WIDTH as u64;
+    match physical {
+        // Stores to RAM go through the write queue
+        scratch::PADDR_START..scratch::PADDR_END | ram::PADDR_START..ram::PADDR_END if store => 0,
+        scratch::PADDR_START..scratch::PADDR_END => 0,
+        ram::PADDR_START..ram::PADDR_END => 4,
+
+        // 8 bit buses, every byte is its own access
+        bios::PADDR_START..bios::PADDR_END => 6 * width + 1,
+        cdrom::PADDR_START..cdrom::PADDR_END => 8 * width + 1,
+
+        // SPU is on a slow 16 bit bus
+        spu::PADDR_START..spu::PADDR_END => 17 * width.div_ceil(2) + 1,
+
+        _ if store => 1,
+        _ => 3,
+    }
+}
+
+/// MULT and MULTU finish early when the first operand is small
+pub const fn mult_cycles(rs: u32, signed: bool) -> u64 {
+    let magnitude = if signed && (rs as i32) < 0 { !rs } else { rs };
+    match magnitude {
+        0..0x800 => 6,
+        0x800..0x10_0000 => 9,
+        _ => 13,
+    }
+}
+
+pub const DIV_CYCLES: u64 = 36;
+
+/// Cycles until the result of a GTE command can be read
+pub const fn gte_cycles(opcode: u32) -> u64 {
+    match opcode {
+        0x01 => 15,
+        0x06 | 0x10 | 0x11 | 0x12 | 0x29 => 8,
+        0x0C | 0x2E => 6,
+        0x13 => 19,
+        0x14 => 13,
+        0x16 => 44,
+        0x1B | 0x2A => 17,
+        0x1C => 11,
+        0x1E => 14,
+        0x20 => 30,
+        0x28 | 0x2D | 0x3D | 0x3E => 5,
+        0x30 => 23,
+        0x3F => 39,
+        _ => 1,
+    }
+}
@@ -1,6 +1,7 @@
 use super::utils::Direction;
 use super::utils::Mode;
 use super::utils::Step;
+use crate::savestate::impl_state;
 
 bitfield::bitfield! {
     pub struct Control(u32);
@@ -14,21 +15,32 @@ bitfield::bitfield! {
 bitfield::bitfield! {
     pub struct Block(u32);
     block_size, _ : 15, 0;
-    block_count, _ : 31, 16;
+    block_count, set_block_count : 31, 16;
 }
 
 pub struct Channel {
     pub ctl: Control,
     pub base: u32,
     pub block_ctl: Block,
+
+    /// Linked list nodes walked by the current transfer
+    pub nodes: u32,
 }
 
+impl_state!(Channel {
+    ctl.0,
+    base,
+    block_ctl.0,
+    nodes,
+});
+
 impl Channel {
     pub const fn new() -> Self {
         Self {
             ctl: Control(0),
             block_ctl: Block(0),
             base: 0,
+            nodes: 0,
         }
     }
 
@@ -38,17 +50,40 @@ impl Channel {
 
     /// Get DMA transfer size in words
     pub fn transfer_size(&self) -> Option<u32> {
-        let bs = self.block_ctl.block_size();
-        let block_size = if bs == 0 { 0x10000 } else { bs };
         let block_count = self.block_ctl.block_count().max(1);
 
         match self.ctl.mode() {
-            Mode::Burst => Some(block_size),
-            Mode::Slice => Some(block_count * block_size),
+            Mode::Burst => Some(self.block_size()),
+            Mode::Slice => Some(block_count * self.block_size()),
             Mode::LinkedList => None,
         }
     }
 
+    pub fn block_size(&self) -> u32 {
+        match self.block_ctl.block_size() {
+            0 => 0x10000,
+            bs => bs,
+        }
+    }
+
+    pub fn step(&self) -> i32 {
+        match self.ctl.step() {
+            Step::Increment => 4,
+            Step::Decrement => -4,
+        }
+    }
+
+    /// Move past a finished slice block like hardware does with MADR and BCR,
+    /// returns the number of blocks left
+    pub fn advance_block(&mut self) -> u32 {
+        let words = self.block_size().cast_signed();
+        self.base = self.base.wrapping_add_signed(words * self.step()) & 0xFF_FFFF;
+
+        let remaining = self.block_ctl.block_count().saturating_sub(1);
+        self.block_ctl.set_block_count(remaining);
+        remaining
+    }
+
     /// Set the channel status to "completed" state
     pub fn done(&mut self) {
         self.ctl.set_enabled(false);
@@ -5,12 +5,14 @@ use std::array::from_fn;
 
 use channel::Channel;
 use tracing::debug;
+use tracing::warn;
 use utils::Direction;
 use utils::Mode;
 use utils::Port;
-use utils::Step;
 
 use crate::System;
+use crate::savestate::impl_state;
+use crate::sched::Event;
 
 bitfield::bitfield! {
     #[derive(Copy, Clone)]
@@ -22,7 +24,6 @@ bitfield::bitfield! {
     master_irq, set_master_irq : 31;
 }
 
-// Ignoring 0-6 channel irq setting, all irqs happen on completion for now
 impl Interrupt {
     fn master_flag(self) -> bool {
         let flags = (self.0 >> 24) & 0x7f;
@@ -46,6 +47,11 @@ impl Interrupt {
         self.update_irq()
     }
 
+    // Bits 0-6 request an irq after every slice block or linked list node
+    fn should_irq_on_chunk(&mut self, port: Port) -> bool {
+        self.0 & (1 << port as u32) != 0 && self.should_irq_on_channel_complete(port)
+    }
+
     const fn set_channel_irq_flag_bit(&mut self, port: Port) {
         self.0 |= 1 << ((port as u32) + 24);
     }
@@ -71,6 +77,12 @@ impl Default for DMAController {
     }
 }
 
+impl_state!(DMAController {
+    dpcr,
+    dicr.0,
+    channels,
+});
+
 pub const PADDR_START: u32 = 0x1F80_1080;
 pub const PADDR_END: u32 = 0x1F80_1100;
 
@@ -81,12 +93,58 @@ impl DMAController {
         self.dpcr & (1 << bit) != 0
     }
 
-    fn do_dma(system: &mut System, port: Port) {
-        match system.dma.channels[port as usize].ctl.mode() {
-            Mode::LinkedList => Self::do_dma_linked_list(system, port),
-            Mode::Burst | Mode::Slice => Self::do_dma_block(system, port),
+    /// Start a transfer, the first chunk moves right away and the rest is paced by the scheduler
+    fn start_dma(system: &mut System, port: Port) {
+        system.scheduler.unschedule(&Event::DmaComplete(port));
+        system.dma.channels[port as usize].nodes = 0;
+        Self::transfer_chunk(system, port);
+    }
+
+    /// Move the next chunk of a transfer: the whole block in burst mode, or the next few
+    /// blocks/nodes in slice and linked list mode
+    pub fn transfer_chunk(system: &mut System, port: Port) {
+        let channel = &system.dma.channels[port as usize];
+        if !channel.active() {
+            return;
+        }
+
+        let mode = channel.ctl.mode();
+
+        // Small blocks and nodes are grouped so a chunk is worth at least a few CPU instructions
+        let mut cycles = 0;
+        let finished = loop {
+            let (chunk_cycles, finished) = match mode {
+                Mode::Burst => Self::transfer_burst(system, port),
+                Mode::Slice => Self::transfer_block(system, port),
+                Mode::LinkedList => Self::transfer_node(system, port),
+            };
+
+            cycles += chunk_cycles;
+            if finished || cycles >= MIN_CHUNK_CYCLES {
+                break finished;
+            }
+        };
+
+        // Channels can also ask for an interrupt after every block or node
+        if !finished && system.dma.dicr.should_irq_on_chunk(port) {
+            system.irqctl.stat().set_dma(true);
         }
 
+        let event = if finished {
+            Event::DmaComplete(port)
+        } else {
+            Event::DmaChunk(port)
+        };
+
+        system.scheduler.schedule(event, cycles, None);
+    }
+
+    pub fn complete(system: &mut System, port: Port) {
+        let channel = &mut system.dma.channels[port as usize];
+        // Node count is only meaningful for linked list transfers
+        debug!(target: "dma", ?port, nodes = channel.nodes, "dma transfer complete");
+        channel.done();
+
         if system.dma.dicr.should_irq_on_channel_complete(port) {
             system.irqctl.stat().set_dma(true);
         }
@@ -110,16 +168,32 @@ impl DMAController {
         self.dicr.update_irq()
     }
 
-    fn do_dma_block(system: &mut System, port: Port) {
-        let (step, dir, base, size) = {
-            let channel = &mut system.dma.channels[port as usize];
-            let step: i32 = match channel.ctl.step() {
-                Step::Increment => 4,
-                Step::Decrement => -4,
-            };
-            let size = channel.transfer_size().expect("Should not be none!");
-            (step, channel.ctl.dir(), channel.base, size)
-        };
+    fn transfer_burst(system: &mut System, port: Port) -> (u64, bool) {
+        let channel = &system.dma.channels[port as usize];
+        let (base, size) = (channel.base, channel.transfer_size().expect("burst size"));
+
+        let draw_cycles = Self::transfer_words(system, port, base, size);
+        (u64::from(size) * cycles_per_word(port) + draw_cycles, true)
+    }
+
+    fn transfer_block(system: &mut System, port: Port) -> (u64, bool) {
+        let channel = &system.dma.channels[port as usize];
+        let (base, size) = (channel.base, channel.block_size());
+
+        let draw_cycles = Self::transfer_words(system, port, base, size);
+
+        let remaining = system.dma.channels[port as usize].advance_block();
+        (
+            u64::from(size) * cycles_per_word(port) + draw_cycles,
+            remaining == 0,
+        )
+    }
+
+    /// Move `size` words between RAM and the device, returns the cycles the GPU spent
+    /// drawing any commands that were sent to it
+    fn transfer_words(system: &mut System, port: Port, base: u32, size: u32) -> u64 {
+        let channel = &system.dma.channels[port as usize];
+        let (step, dir) = (channel.step(), channel.ctl.dir());
 
         debug!(target: "dma", ?port, size, ?dir, ?step, "dma addr={base:x}");
 
@@ -144,7 +218,13 @@ impl DMAController {
                 Direction::FromRam => {
                     let src_word = system.ram.read::<4>(cur_addr);
                     match port {
-                        Port::Gpu => system.gpu.gp0(src_word),
+                        Port::Gpu => {
+                            let vertex = system
+                                .pgxp
+                                .as_ref()
+                                .and_then(|p| p.load(cur_addr, src_word));
+                            system.gpu.gp0_with_vertex(src_word, vertex);
+                        }
                         Port::Spu => system.spu.ram_write::<4>(src_word),
                         Port::MdecIn => system.mdec.command_or_param(src_word),
                         _ => todo!("DMA destination {port:?}"),
@@ -153,30 +233,62 @@ impl DMAController {
             }
             addr = addr.wrapping_add_signed(step);
         }
-        system.dma.channels[port as usize].done();
+
+        system.gpu.take_draw_cycles()
     }
 
-    fn do_dma_linked_list(system: &mut System, port: Port) {
-        let channel = &mut system.dma.channels[port as usize];
-        let mut addr = channel.base & 0x1F_FFFC;
+    /// Send one linked list packet to the GPU, returns the cycles used and if it was the last one.
+    /// The packet is charged for both the bus transfer and the time the GPU takes to draw it.
+    fn transfer_node(system: &mut System, port: Port) -> (u64, bool) {
+        let addr = system.dma.channels[port as usize].base & 0x1F_FFFC;
 
-        loop {
-            let header = system.ram.read::<4>(addr);
-            let size = header >> 24;
+        let header = system.ram.read::<4>(addr);
+        let size = header >> 24;
 
-            for i in 0..size {
-                let data = system.ram.read::<4>((addr + 4 * (i + 1)) & 0x1F_FFFC);
-                system.gpu.gp0(data);
-            }
+        for i in 0..size {
+            let word_addr = (addr + 4 * (i + 1)) & 0x1F_FFFC;
+            let data = system.ram.read::<4>(word_addr);
+            let vertex = system.pgxp.as_ref().and_then(|p| p.load(word_addr, data));
+            system.gpu.gp0_with_vertex(data, vertex);
+        }
 
-            let next_addr = header & 0xFF_FFFF;
-            if next_addr & (1 << 23) != 0 {
-                break;
-            }
-            addr = next_addr & 0x1F_FFFC;
+        // Hardware leaves the next address (or end marker) in MADR
+        let next_addr = header & 0xFF_FFFF;
+
+        let channel = &mut system.dma.channels[port as usize];
+        channel.base = next_addr;
+        channel.nodes += 1;
+
+        let cycles = u64::from(size + 1) * cycles_per_word(port) + system.gpu.take_draw_cycles();
+
+        // Real hardware would walk a cyclic list forever, a node pointing at itself or more
+        // nodes than fit in RAM means the list can never end, so the transfer is cut short
+        let cyclic = next_addr & 0x1F_FFFC == addr || channel.nodes >= MAX_LINKED_LIST_NODES;
+        if cyclic && next_addr & (1 << 23) == 0 {
+            warn!(
+                target: "dma",
+                ?port,
+                nodes = channel.nodes,
+                "linked list never terminates, aborting at {addr:x}"
+            );
+            return (cycles, true);
         }
 
-        channel.done();
+        (cycles, next_addr & (1 << 23) != 0)
+    }
+}
+
+const MIN_CHUNK_CYCLES: u64 = 64;
+
+/// Every node takes at least one word, so a list longer than RAM must be revisiting nodes
+const MAX_LINKED_LIST_NODES: u32 = 0x20_0000 / 4;
+
+/// Rough bus cost of moving one word for each channel
+const fn cycles_per_word(port: Port) -> u64 {
+    match port {
+        Port::CdRom => 24,
+        Port::Spu => 4,
+        _ => 1,
     }
 }
 
@@ -212,7 +324,7 @@ pub fn write<const WIDTH: usize>(system: &mut System, addr: u32, data: u32) {
             }
 
             if system.dma.channel_enabled(port) && system.dma.channels[channel].active() {
-                DMAController::do_dma(system, port);
+                DMAController::start_dma(system, port);
             }
         }
 
@@ -0,0 +1,239 @@
+//! Built in database of known games, keyed by the serial of the boot executable on each disc
+
+use cue::DiscSource;
+use cue::iso9660::Filesystem;
+
+use crate::CpuTiming;
+use crate::region::Region;
+
+#[derive(Debug)]
+pub struct GameInfo {
+    /// Serial of this disc, like `SLUS-00594`
+    pub serial: &'static str,
+    /// Serial of the first disc, the same for every disc of a game
+    pub id: &'static str,
+    pub title: &'static str,
+    pub region: Region,
+    /// Which disc this is, counting from 1, out of `discs`
+    pub disc: u8,
+    pub discs: u8,
+    /// CPU timing the game needs to run correctly, if it has to differ from the user's choice
+    pub cpu_timing: Option<CpuTiming>,
+}
+
+impl GameInfo {
+    /// Known game with a disc of this serial
+    #[must_use]
+    pub fn lookup(serial: &str) -> Option<&'static Self> {
+        GAMES.iter().find(|g| g.serial.eq_ignore_ascii_case(serial))
+    }
+}
+
+/// Serial of the executable SYSTEM.CNF boots, from a line like `BOOT = cdrom:\SLUS_005.94;1`
+pub fn disc_serial(source: &mut dyn DiscSource) -> Option<String> {
+    let mut fs = Filesystem::open(source).ok()?;
+    let entry = fs.find("SYSTEM.CNF").ok()?;
+    let system_cnf = fs.read_file(&entry).ok()?;
+
+    String::from_utf8_lossy(&system_cnf)
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            if !key.trim().eq_ignore_ascii_case("BOOT") {
+                return None;
+            }
+
+            let file = value.trim().rsplit(['\\', ':', '/']).next()?;
+            serial_from_file_name(file.split(';').next()?)
+        })
+}
+
+/// `SLUS_005.94` to `SLUS-00594`, executables not named after a serial have none
+fn serial_from_file_name(name: &str) -> Option<String> {
+    let (prefix, number) = name.split_once(['_', '-'])?;
+    let number = number.replace('.', "");
+
+    let valid = prefix.len() == 4
+        && prefix.bytes().all(|b| b.is_ascii_alphabetic())
+        && number.len() == 5
+        && number.bytes().all(|b| b.is_ascii_digit());
+
+    valid.then(|| format!("{}-{number}", prefix.to_ascii_uppercase()))
+}
+
+const fn game(
+    serial: &'static str,
+    id: &'static str,
+    title: &'static str,
+    region: Region,
+    disc: u8,
+    discs: u8,
+) -> GameInfo {
+    GameInfo {
+        serial,
+        id,
+        title,
+        region,
+        disc,
+        discs,
+        cpu_timing: None,
+    }
+}
+
+#[rustfmt::skip]
+static GAMES: &[GameInfo] = &[
+    game("SCUS-94900", "SCUS-94900", "Crash Bandicoot", Region::NorthAmerica, 1, 1),
+    game("SCES-00344", "SCES-00344", "Crash Bandicoot", Region::Europe, 1, 1),
+    game("SCUS-94228", "SCUS-94228", "Spyro the Dragon", Region::NorthAmerica, 1, 1),
+    game("SLUS-00067", "SLUS-00067", "Castlevania: Symphony of the Night", Region::NorthAmerica, 1, 1),
+    game("SLUS-00707", "SLUS-00707", "Silent Hill", Region::NorthAmerica, 1, 1),
+
+    game("SLUS-00421", "SLUS-00421", "Resident Evil 2", Region::NorthAmerica, 1, 2),
+    game("SLUS-00592", "SLUS-00421", "Resident Evil 2", Region::NorthAmerica, 2, 2),
+
+    game("SLPS-01230", "SLPS-01230", "Parasite Eve", Region::Japan, 1, 2),
+    game("SLPS-01231", "SLPS-01230", "Parasite Eve", Region::Japan, 2, 2),
+    game("SLUS-00662", "SLUS-00662", "Parasite Eve", Region::NorthAmerica, 1, 2),
+    game("SLUS-00668", "SLUS-00662", "Parasite Eve", Region::NorthAmerica, 2, 2),
+
+    game("SLUS-00594", "SLUS-00594", "Metal Gear Solid", Region::NorthAmerica, 1, 2),
+    game("SLUS-00776", "SLUS-00594", "Metal Gear Solid", Region::NorthAmerica, 2, 2),
+
+    game("SLPS-00700", "SLPS-00700", "Final Fantasy VII", Region::Japan, 1, 3),
+    game("SLPS-00701", "SLPS-00700", "Final Fantasy VII", Region::Japan, 2, 3),
+    game("SLPS-00702", "SLPS-00700", "Final Fantasy VII", Region::Japan, 3, 3),
+    game("SCUS-94163", "SCUS-94163", "Final Fantasy VII", Region::NorthAmerica, 1, 3),
+    game("SCUS-94164", "SCUS-94163", "Final Fantasy VII", Region::NorthAmerica, 2, 3),
+    game("SCUS-94165", "SCUS-94163", "Final Fantasy VII", Region::NorthAmerica, 3, 3),
+    game("SCES-00867", "SCES-00867", "Final Fantasy VII", Region::Europe, 1, 3),
+    game("SCES-10867", "SCES-00867", "Final Fantasy VII", Region::Europe, 2, 3),
+    game("SCES-20867", "SCES-00867", "Final Fantasy VII", Region::Europe, 3, 3),
+
+    game("SLUS-00892", "SLUS-00892", "Final Fantasy VIII", Region::NorthAmerica, 1, 4),
+    game("SLUS-00908", "SLUS-00892", "Final Fantasy VIII", Region::NorthAmerica, 2, 4),
+    game("SLUS-00909", "SLUS-00892", "Final Fantasy VIII", Region::NorthAmerica, 3, 4),
+    game("SLUS-00910", "SLUS-00892", "Final Fantasy VIII", Region::NorthAmerica, 4, 4),
+
+    game("SLUS-01041", "SLUS-01041", "Chrono Cross", Region::NorthAmerica, 1, 2),
+    game("SLUS-01080", "SLUS-01041", "Chrono Cross", Region::NorthAmerica, 2, 2),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTOR_SIZE: usize = 2352;
+    /// Filesystem sectors start after the 2 second lead in
+    const LEAD_IN: usize = 150;
+
+    /// Mode 1 disc with only a SYSTEM.CNF in its root directory
+    fn disc_with_system_cnf(system_cnf: &str) -> Box<[u8]> {
+        let mut image = vec![0u8; (LEAD_IN + 20) * SECTOR_SIZE];
+        let mut data = |lba: usize| {
+            let start = (LEAD_IN + lba) * SECTOR_SIZE;
+            image[start + 0xF] = 1;
+            start + 0x10
+        };
+
+        let record = |lba: u32, size: u32, flags: u8, name: &[u8]| {
+            let mut record = vec![0u8; 33 + name.len() + (1 - name.len() % 2)];
+            record[0] = record.len() as u8;
+            record[2..6].copy_from_slice(&lba.to_le_bytes());
+            record[10..14].copy_from_slice(&size.to_le_bytes());
+            record[25] = flags;
+            record[32] = name.len() as u8;
+            record[33..33 + name.len()].copy_from_slice(name);
+            record
+        };
+
+        let pvd = data(16);
+        let terminator = data(17);
+        let root = data(18);
+        let file = data(19);
+
+        image[pvd] = 1;
+        image[pvd + 1..pvd + 6].copy_from_slice(b"CD001");
+        let root_record = record(18, 2048, 2, b"\0");
+        image[pvd + 156..pvd + 156 + root_record.len()].copy_from_slice(&root_record);
+
+        image[terminator] = 0xFF;
+        image[terminator + 1..terminator + 6].copy_from_slice(b"CD001");
+
+        let mut offset = root;
+        for entry in [
+            root_record,
+            record(18, 2048, 2, b"\x01"),
+            record(19, system_cnf.len() as u32, 0, b"SYSTEM.CNF;1"),
+        ] {
+            image[offset..offset + entry.len()].copy_from_slice(&entry);
+            offset += entry.len();
+        }
+
+        image[file..file + system_cnf.len()].copy_from_slice(system_cnf.as_bytes());
+        image.into_boxed_slice()
+    }
+
+    #[test]
+    fn serial_from_boot_file_name() {
+        assert_eq!(
+            serial_from_file_name("SLUS_005.94").as_deref(),
+            Some("SLUS-00594")
+        );
+        assert_eq!(
+            serial_from_file_name("scus_941.63").as_deref(),
+            Some("SCUS-94163")
+        );
+        assert_eq!(
+            serial_from_file_name("SLPS-00700").as_deref(),
+            Some("SLPS-00700")
+        );
+
+        assert_eq!(serial_from_file_name("MAIN.EXE"), None);
+        assert_eq!(serial_from_file_name("SLUS_0059.4X"), None);
+        assert_eq!(serial_from_file_name("SLU_005.94"), None);
+        assert_eq!(serial_from_file_name("SLUS_0059.41"), None);
+    }
+
+    #[test]
+    fn serial_from_system_cnf() {
+        let mut disc =
+            disc_with_system_cnf("BOOT = cdrom:\\SLUS_005.94;1\r\nTCB = 4\r\nEVENT = 10\r\n");
+        assert_eq!(disc_serial(&mut disc).as_deref(), Some("SLUS-00594"));
+
+        // Keys are matched without case, and the path can use forward slashes
+        let mut disc = disc_with_system_cnf("stack=801FFFF0\nboot=cdrom:/SCES_008.67;1\n");
+        assert_eq!(disc_serial(&mut disc).as_deref(), Some("SCES-00867"));
+    }
+
+    #[test]
+    fn no_serial_without_one_to_boot() {
+        let mut disc = disc_with_system_cnf("BOOT = cdrom:\\PSX.EXE;1\n");
+        assert_eq!(disc_serial(&mut disc), None);
+
+        let mut disc = disc_with_system_cnf("TCB = 4\n");
+        assert_eq!(disc_serial(&mut disc), None);
+
+        // Audio discs have no filesystem at all
+        let mut disc: Box<[u8]> = vec![0u8; (LEAD_IN + 20) * SECTOR_SIZE].into_boxed_slice();
+        assert_eq!(disc_serial(&mut disc), None);
+    }
+
+    #[test]
+    fn every_disc_of_a_game_shares_its_id() {
+        for game in GAMES {
+            let first = GAMES
+                .iter()
+                .find(|g| g.serial == game.id)
+                .unwrap_or_else(|| panic!("{} has no first disc", game.serial));
+
+            assert_eq!(first.disc, 1, "{}", game.serial);
+            assert_eq!(first.title, game.title, "{}", game.serial);
+            assert_eq!(first.discs, game.discs, "{}", game.serial);
+            assert_eq!(first.cpu_timing, game.cpu_timing, "{}", game.serial);
+            assert!(game.disc <= game.discs, "{}", game.serial);
+        }
+
+        let parasite_eve = GameInfo::lookup("slus-00668").map(|g| (g.id, g.disc));
+        assert_eq!(parasite_eve, Some(("SLUS-00662", 2)));
+    }
+}
@@ -2,6 +2,7 @@ use starpsx_renderer::utils::Color;
 use starpsx_renderer::utils::RectTextureOptions;
 use starpsx_renderer::utils::Texture;
 use starpsx_renderer::utils::TextureOptions;
+use starpsx_renderer::vec2::PreciseVertex;
 use starpsx_renderer::vec2::Vec2;
 
 use super::Command;
@@ -183,12 +184,12 @@ impl Gpu {
         let v2 = parse_xy(params[3].0);
 
         self.renderer
-            .draw_triangle::<SEMI_TRANS>([v0, v1, v2], color);
+            .draw_triangle::<SEMI_TRANS>([v0, v1, v2], self.precise([1, 2, 3]), color);
 
         if QUAD {
             let v3 = parse_xy(params[4].0);
             self.renderer
-                .draw_triangle::<SEMI_TRANS>([v1, v2, v3], color);
+                .draw_triangle::<SEMI_TRANS>([v1, v2, v3], self.precise([2, 3, 4]), color);
         }
 
         GP0State::AwaitCommand
@@ -206,14 +207,20 @@ impl Gpu {
         let c1 = Color::new_5bit(params[2].0);
         let c2 = Color::new_5bit(params[4].0);
 
-        self.renderer
-            .draw_triangle_shaded::<SEMI_TRANS>([v0, v1, v2], [c0, c1, c2]);
+        self.renderer.draw_triangle_shaded::<SEMI_TRANS>(
+            [v0, v1, v2],
+            self.precise([1, 3, 5]),
+            [c0, c1, c2],
+        );
 
         if QUAD {
             let v3 = parse_xy(params[7].0);
             let c3 = Color::new_5bit(params[6].0);
-            self.renderer
-                .draw_triangle_shaded::<SEMI_TRANS>([v1, v2, v3], [c1, c2, c3]);
+            self.renderer.draw_triangle_shaded::<SEMI_TRANS>(
+                [v1, v2, v3],
+                self.precise([3, 5, 7]),
+                [c1, c2, c3],
+            );
         }
 
         GP0State::AwaitCommand
@@ -238,6 +245,7 @@ impl Gpu {
 
         self.renderer.draw_triangle_textured::<SEMI_TRANS, BLEND>(
             [v0, v1, v2],
+            self.precise([1, 3, 5]),
             color,
             TextureOptions {
                 texture,
@@ -250,6 +258,7 @@ impl Gpu {
             let uv3 = parse_uv(params[8].0);
             self.renderer.draw_triangle_textured::<SEMI_TRANS, BLEND>(
                 [v1, v2, v3],
+                self.precise([3, 5, 7]),
                 color,
                 TextureOptions {
                     texture,
@@ -280,6 +289,7 @@ impl Gpu {
         self.renderer
             .draw_triangle_textured_shaded::<SEMI_TRANS, BLEND>(
                 [v0, v1, v2],
+                self.precise([1, 4, 7]),
                 [c0, c1, c2],
                 TextureOptions {
                     texture,
@@ -295,6 +305,7 @@ impl Gpu {
             self.renderer
                 .draw_triangle_textured_shaded::<SEMI_TRANS, BLEND>(
                     [v1, v2, v3],
+                    self.precise([4, 7, 10]),
                     [c1, c2, c3],
                     TextureOptions {
                         texture,
@@ -306,6 +317,11 @@ impl Gpu {
         GP0State::AwaitCommand
     }
 
+    /// Precise positions of the vertices at these argument indices
+    fn precise(&self, indices: [usize; 3]) -> [Option<PreciseVertex>; 3] {
+        indices.map(|i| self.vertices.get(i).copied().flatten())
+    }
+
     pub fn gp0_line_mono<const SEMI_TRANS: bool>(&mut self, params: &[Command]) -> GP0State {
         let v0 = parse_xy(params[1].0);
         let v1 = parse_xy(params[2].0);
@@ -3,6 +3,7 @@ use starpsx_renderer::vec2::Vec2;
 use super::Command;
 use super::GP0State;
 use super::Gpu;
+use super::VMode;
 use super::VerticalRes;
 
 impl Gpu {
@@ -39,6 +40,7 @@ impl Gpu {
 
         self.renderer.change_resolution(width, height);
         self.status.set_vmode(command.vmode());
+        self.renderer.ctx.display_pal = command.vmode() == VMode::Pal;
 
         if command.flip_screen() {
             unimplemented!("Flip screen bit not supported!");
@@ -63,6 +65,7 @@ impl Gpu {
 
         let dotclock = self.get_dot_clock_divider();
         self.renderer.ctx.display_hor_range = (((x2 - x1) / dotclock) + 2) & !3;
+        self.renderer.ctx.display_hor_start = x1;
     }
 
     pub fn gp1_display_vertical_range(&mut self, command: Command) {
@@ -70,6 +73,7 @@ impl Gpu {
         let y2 = command.vertical_y2();
 
         self.renderer.ctx.display_ver_range = y2 - y1;
+        self.renderer.ctx.display_ver_start = y1;
     }
 
     pub fn gp1_display_enable(&mut self, command: Command) {
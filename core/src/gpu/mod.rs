@@ -2,9 +2,12 @@ mod gp0;
 mod gp1;
 mod utils;
 
+use anyhow::bail;
 use arrayvec::ArrayVec;
 use starpsx_renderer::Renderer;
 use starpsx_renderer::utils::DisplayDepth;
+use starpsx_renderer::vec2::PreciseVertex;
+use tracing::info;
 pub use utils::CommandArguments;
 pub use utils::CommandFn;
 pub use utils::DmaDirection;
@@ -17,7 +20,11 @@ pub use utils::VerticalRes;
 pub use utils::VramCopyFields;
 
 use crate::System;
+use crate::consts::VideoTiming;
 use crate::gpu::utils::PolyLineFn;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
 
 bitfield::bitfield! {
     #[derive(Clone, Copy)]
@@ -159,6 +166,12 @@ pub struct Gpu {
     state: GP0State,
 
     in_vsync: bool,
+
+    /// Estimated cost of the commands drawn since it was last taken
+    draw_cycles: u64,
+
+    /// Precise positions of the words of the command being drawn, see [`crate::pgxp`]
+    vertices: ArrayVec<Option<PreciseVertex>, 16>,
 }
 
 impl Default for Gpu {
@@ -170,10 +183,87 @@ impl Default for Gpu {
             state: GP0State::AwaitCommand,
 
             in_vsync: false,
+            draw_cycles: 0,
+            vertices: ArrayVec::new(),
         }
     }
 }
 
+impl State for Gpu {
+    fn save(&self, w: &mut Writer) {
+        self.read.save(w);
+        self.status.0.save(w);
+        self.in_vsync.save(w);
+
+        self.renderer.ctx.save(w);
+        for halfword in self.renderer.vram().iter() {
+            halfword.save(w);
+        }
+
+        // Partially received commands are stored as their raw words
+        match &self.state {
+            GP0State::AwaitCommand => 0u8.save(w),
+            GP0State::AwaitArgs(x) => {
+                1u8.save(w);
+                x.words().save(w);
+            }
+            GP0State::CopyToVram(x) => {
+                2u8.save(w);
+                x.save(w);
+            }
+            GP0State::CopyFromVram(x) => {
+                3u8.save(w);
+                x.save(w);
+            }
+            GP0State::PolyLine(x) => {
+                4u8.save(w);
+                x.words().save(w);
+            }
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.read.load(r)?;
+        self.status.0.load(r)?;
+        self.in_vsync.load(r)?;
+
+        self.renderer.ctx.load(r)?;
+        for halfword in self.renderer.vram_mut().iter_mut() {
+            halfword.load(r)?;
+        }
+        self.renderer.resync_scaled_vram();
+        self.renderer.rebuild_frame_buffer();
+
+        let mut tag = 0u8;
+        tag.load(r)?;
+
+        self.state = GP0State::AwaitCommand;
+        match tag {
+            0 => (),
+            // Replay the received words to rebuild the pending command
+            1 | 4 => {
+                let mut words: Vec<u32> = Vec::new();
+                words.load(r)?;
+                for word in words {
+                    self.gp0(word);
+                }
+            }
+            2 | 3 => {
+                let mut fields = VramCopyFields::default();
+                fields.load(r)?;
+                self.state = if tag == 2 {
+                    GP0State::CopyToVram(fields)
+                } else {
+                    GP0State::CopyFromVram(fields)
+                };
+            }
+            x => bail!("invalid gp0 state {x}"),
+        }
+
+        Ok(())
+    }
+}
+
 const OPAQUE: bool = false;
 const SEMI_TRANS: bool = true;
 const BLEND: bool = true;
@@ -260,13 +350,16 @@ impl Gpu {
         ((y & 0x3FF) << 11) | (x & 0x7FF)
     }
 
-    pub fn write_reg(&mut self, addr: u32, data: u32) {
+    pub fn write_reg(&mut self, addr: u32, data: u32, vertex: Option<PreciseVertex>) {
         let offs = addr - PADDR_START;
         match offs {
-            0 => self.gp0(data),
+            0 => self.gp0_with_vertex(data, vertex),
             4 => self.gp1(data),
             _ => unimplemented!("GPU register write {offs:x} <- {data:08x}"),
         }
+
+        // Only DMA transfers get charged for drawing time, the CPU isn't stalled on the FIFO
+        self.draw_cycles = 0;
     }
 
     pub fn read_reg(&mut self, addr: u32) -> u32 {
@@ -305,15 +398,25 @@ impl Gpu {
     }
 
     pub fn gp0(&mut self, data: u32) {
+        self.gp0_with_vertex(data, None);
+    }
+
+    /// GP0 word that PGXP found to be a vertex projected by the GTE
+    pub fn gp0_with_vertex(&mut self, data: u32, vertex: Option<PreciseVertex>) {
         self.state = match std::mem::replace(&mut self.state, GP0State::AwaitCommand) {
             GP0State::AwaitCommand => self.process_command(data),
-            GP0State::AwaitArgs(x) => self.process_argument(data, x),
+            GP0State::AwaitArgs(x) => self.process_argument(data, vertex, x),
             GP0State::CopyToVram(x) => self.process_cpu_to_vram_copy(data, x),
             GP0State::PolyLine(x) => self.process_polyline_argument(data, x),
             GP0State::CopyFromVram(_) => unimplemented!("VRAM currently being copying to CPU!"),
         };
     }
 
+    /// Take the estimated CPU cycles spent drawing since the last call
+    pub const fn take_draw_cycles(&mut self) -> u64 {
+        std::mem::replace(&mut self.draw_cycles, 0)
+    }
+
     fn gp1(&mut self, data: u32) {
         let command = Command(data);
         match command.opcode() {
@@ -331,10 +434,17 @@ impl Gpu {
         }
     }
 
-    fn process_argument(&mut self, word: u32, mut cmd: CommandArguments) -> GP0State {
+    fn process_argument(
+        &mut self,
+        word: u32,
+        vertex: Option<PreciseVertex>,
+        mut cmd: CommandArguments,
+    ) -> GP0State {
         let command = Command(word);
         cmd.push(command);
+        self.vertices.push(vertex);
         if cmd.done() {
+            self.draw_cycles += cmd.draw_cycles();
             return cmd.call(self);
         }
         GP0State::AwaitArgs(cmd)
@@ -343,6 +453,7 @@ impl Gpu {
     fn process_polyline_argument(&mut self, word: u32, mut cmd: PolyLineArguments) -> GP0State {
         cmd.push(word);
         if cmd.done() {
+            self.draw_cycles += cmd.draw_cycles();
             return cmd.call(self);
         }
         GP0State::PolyLine(cmd)
@@ -481,12 +592,30 @@ impl Gpu {
 
                     _ => unimplemented!("GP0 command {data:08x}"),
                 };
-                return self.process_argument(data, CommandArguments::new(cmd, len));
+                self.vertices.clear();
+                return self.process_argument(data, None, CommandArguments::new(cmd, len));
             }
         };
         self.process_polyline_argument(data, PolyLineArguments::new(cmd, color))
     }
 
+    pub fn video_timing(&self) -> &'static VideoTiming {
+        self.status.vmode().timing()
+    }
+
+    /// Convert CPU cycles to dot clock ticks at the current resolution and video standard
+    pub fn cycles_to_dots(&self, cycles: u64) -> u64 {
+        let timing = self.video_timing();
+        let divider = u64::from(self.get_dot_clock_divider());
+        cycles * timing.line_dots / (timing.line_cycles * divider)
+    }
+
+    pub fn dots_to_cycles(&self, dots: u64) -> u64 {
+        let timing = self.video_timing();
+        let divider = u64::from(self.get_dot_clock_divider());
+        dots * divider * timing.line_cycles / timing.line_dots
+    }
+
     pub fn get_dot_clock_divider(&self) -> u16 {
         match self.status.hres() {
             HorizontalRes::X256 => 10,
@@ -503,5 +632,117 @@ pub fn read<const WIDTH: usize>(system: &mut System, offs: u32) -> u32 {
 }
 
 pub fn write<const WIDTH: usize>(system: &mut System, offs: u32, data: u32) {
-    system.gpu.write_reg(offs, data);
+    let vmode = system.gpu.status.vmode();
+    // Only GP0 takes vertices
+    let vertex = system
+        .pgxp
+        .as_ref()
+        .filter(|_| offs == PADDR_START)
+        .and_then(|_| system.cpu.precise_sxy(data));
+    system.gpu.write_reg(offs, data, vertex);
+
+    // Switching between NTSC and PAL changes the length of every line and frame
+    let new_vmode = system.gpu.status.vmode();
+    if new_vmode != vmode {
+        info!(?new_vmode, "video mode changed");
+        system.scheduler.schedule_video_events(new_vmode.timing());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn vertex(x: u32, y: u32) -> u32 {
+        (y << 16) | x
+    }
+
+    /// A 16x16 texture upload followed by one of each kind of primitive, with the mask
+    /// setting changed between uploads
+    fn command_stream() -> Vec<u32> {
+        let mut words = vec![
+            0xE100_0304, // 15 bit texture page at x 256, dithering
+            0xE300_0000,
+            0xE403_FCFF, // Drawing area up to 255, 255
+            0xE500_0000,
+            0xA000_0000,
+            vertex(256, 0),
+            vertex(16, 16),
+        ];
+        words.extend((0..128u32).map(|i| (i * 0x0123_4567) & 0x7FFF_7FFF));
+
+        words.extend([
+            0x0240_2010,
+            vertex(16, 16),
+            vertex(32, 16),
+            0x2000_00FF,
+            vertex(10, 10),
+            vertex(90, 20),
+            vertex(30, 70),
+            0x3200_FF00,
+            vertex(40, 40),
+            0x0000_00FF,
+            vertex(120, 50),
+            0x00FF_0000,
+            vertex(60, 110),
+            0x2C80_8080,
+            vertex(100, 100),
+            0x0000_0000,
+            vertex(180, 110),
+            0x0304_000F,
+            vertex(90, 170),
+            0x0000_0F00,
+            vertex(170, 190),
+            0x0000_0F0F,
+            0x6000_80FF,
+            vertex(200, 10),
+            vertex(30, 20),
+            0x6480_8080,
+            vertex(200, 40),
+            0x0000_0000,
+            vertex(16, 16),
+            0x4000_FFFF,
+            vertex(5, 250),
+            vertex(250, 130),
+            0x8000_0000,
+            vertex(0, 0),
+            vertex(0, 200),
+            vertex(64, 32),
+            0xE600_0003, // Set the mask bit and leave masked pixels alone
+            0xA000_0000,
+            vertex(8, 8),
+            vertex(4, 4),
+        ]);
+        words.extend((0..8u32).map(|i| i * 0x0011_0011));
+        words.extend([0x6000_FF00, vertex(0, 0), vertex(64, 64)]);
+
+        words
+    }
+
+    fn render(threaded: bool, scale: usize) -> Vec<u16> {
+        let mut gpu = Gpu::default();
+        gpu.renderer.set_resolution_scale(scale);
+        gpu.renderer.set_threaded(threaded);
+        assert_eq!(gpu.renderer.is_threaded(), threaded);
+
+        for word in command_stream() {
+            gpu.gp0(word);
+        }
+
+        gpu.renderer.vram().to_vec()
+    }
+
+    #[test]
+    fn renderer_thread_draws_the_same_vram() {
+        for scale in [1, 2] {
+            let inline = render(false, scale);
+            let threaded = render(true, scale);
+
+            // The second upload went in after the mask bit was forced on
+            assert_eq!(inline[1024 * 8 + 8] & 0x8000, 0x8000);
+            assert_eq!(inline[256] & 0x8000, 0);
+            let first_difference = inline.iter().zip(&threaded).position(|(a, b)| a != b);
+            assert_eq!(first_difference, None, "scale {scale}");
+        }
+    }
 }
@@ -1,12 +1,22 @@
 use num_enum::FromPrimitive;
 use num_enum::IntoPrimitive;
 use starpsx_renderer::utils::Clut;
+use starpsx_renderer::utils::DisplayDepth;
+use starpsx_renderer::utils::DrawContext;
 use starpsx_renderer::utils::Texture;
 use starpsx_renderer::vec2::Vec2;
 
 use super::ArrayVec;
 use super::Command;
 use super::Gpu;
+use crate::consts::NTSC_TIMING;
+use crate::consts::PAL_TIMING;
+use crate::consts::VideoTiming;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
+use crate::savestate::impl_state;
+use crate::savestate::impl_state_enum;
 
 /// Texture color bits per pixel
 #[derive(IntoPrimitive, FromPrimitive)]
@@ -27,6 +37,15 @@ pub enum VMode {
     Pal = 1,
 }
 
+impl VMode {
+    pub(crate) const fn timing(self) -> &'static VideoTiming {
+        match self {
+            Self::Ntsc => &NTSC_TIMING,
+            Self::Pal => &PAL_TIMING,
+        }
+    }
+}
+
 /// Requested DMA direction
 #[derive(IntoPrimitive, FromPrimitive)]
 #[repr(u8)]
@@ -81,7 +100,7 @@ impl VerticalRes {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct VramCopyFields {
     pub vram_x: u16,
     pub vram_y: u16,
@@ -91,6 +110,15 @@ pub struct VramCopyFields {
     pub current_col: u16,
 }
 
+impl_state!(VramCopyFields {
+    vram_x,
+    vram_y,
+    width,
+    height,
+    current_row,
+    current_col,
+});
+
 pub type CommandFn = fn(&mut Gpu, &[Command]) -> GP0State;
 pub type PolyLineFn = fn(&mut Gpu, Vec<u32>, &[u32]) -> GP0State;
 
@@ -120,6 +148,64 @@ impl CommandArguments {
     pub fn call(self, gpu: &mut Gpu) -> GP0State {
         (self.func)(gpu, &self.params)
     }
+
+    /// Words received so far, starting with the command word
+    pub fn words(&self) -> Vec<u32> {
+        self.params.iter().map(|c| c.0).collect()
+    }
+
+    /// Rough CPU cycles the GPU spends rasterizing this command
+    pub fn draw_cycles(&self) -> u64 {
+        let word = |i: usize| self.params[i].0;
+
+        let opcode = self.params[0].opcode();
+        let textured = opcode & 0x04 != 0;
+        let shaded = opcode & 0x10 != 0;
+
+        match opcode {
+            // Fills skip the pixel pipeline and write whole lines at once
+            0x02 => {
+                let width = (word(2) & 0x3FF).next_multiple_of(16);
+                let height = (word(2) >> 16) & 0x1FF;
+                u64::from(width * height) / 8
+            }
+
+            0x20..=0x3F => {
+                let stride = 1 + usize::from(shaded) + usize::from(textured);
+                let v = |i: usize| parse_xy(word(1 + i * stride));
+
+                let mut pixels = triangle_area([v(0), v(1), v(2)]);
+                if opcode & 0x08 != 0 {
+                    pixels += triangle_area([v(1), v(2), v(3)]);
+                }
+                DRAW_OVERHEAD + pixel_cycles(pixels, textured)
+            }
+
+            0x40..=0x5F => {
+                let v1 = if shaded { word(3) } else { word(2) };
+                DRAW_OVERHEAD + line_length(parse_xy(word(1)), parse_xy(v1))
+            }
+
+            0x60..=0x7F => {
+                let size = match (opcode >> 3) & 3 {
+                    0 => parse_xy(word(2 + usize::from(textured))),
+                    1 => Vec2::new(1, 1),
+                    2 => Vec2::new(8, 8),
+                    _ => Vec2::new(16, 16),
+                };
+                let pixels = u64::from(size.x.unsigned_abs() * size.y.unsigned_abs());
+                DRAW_OVERHEAD + pixel_cycles(pixels, textured)
+            }
+
+            // Every pixel is read and written back
+            0x80 => {
+                let size = parse_xy(word(3));
+                u64::from(size.x.unsigned_abs() * size.y.unsigned_abs()) * 2
+            }
+
+            _ => 0,
+        }
+    }
 }
 
 pub struct PolyLineArguments {
@@ -165,9 +251,34 @@ impl PolyLineArguments {
         (self.func)(gpu, self.vertices, &self.colors)
     }
 
+    /// Rough CPU cycles the GPU spends rasterizing every segment of the line
+    pub fn draw_cycles(&self) -> u64 {
+        let segments = self
+            .vertices
+            .windows(2)
+            .map(|w| line_length(parse_xy(w[0]), parse_xy(w[1])));
+
+        DRAW_OVERHEAD + segments.sum::<u64>()
+    }
+
     pub const fn done(&self) -> bool {
         self.done
     }
+
+    /// Words received so far in their original order, starting with the command word
+    pub fn words(&self) -> Vec<u32> {
+        let mut colors = self.colors.iter().copied();
+        let mut words: Vec<u32> = colors.next().into_iter().collect();
+
+        for &vertex in &self.vertices {
+            words.push(vertex);
+            if self.shaded {
+                words.extend(colors.next());
+            }
+        }
+
+        words
+    }
 }
 
 pub enum GP0State {
@@ -186,6 +297,26 @@ pub const fn parse_xy(data: u32) -> Vec2 {
     Vec2::new(x as i32, y as i32)
 }
 
+/// Fixed setup cost of a drawing command in CPU cycles
+const DRAW_OVERHEAD: u64 = 16;
+
+/// The GPU draws about two untextured or one textured pixel per CPU cycle
+const fn pixel_cycles(pixels: u64, textured: bool) -> u64 {
+    if textured { pixels } else { pixels / 2 }
+}
+
+fn triangle_area([a, b, c]: [Vec2; 3]) -> u64 {
+    let cross =
+        i64::from(b.x - a.x) * i64::from(c.y - a.y) - i64::from(c.x - a.x) * i64::from(b.y - a.y);
+    cross.unsigned_abs() / 2
+}
+
+const fn line_length(a: Vec2, b: Vec2) -> u64 {
+    let dx = (b.x - a.x).unsigned_abs();
+    let dy = (b.y - a.y).unsigned_abs();
+    (if dx > dy { dx } else { dy }) as u64
+}
+
 pub fn parse_clut_uv(data: u32) -> (Clut, Vec2) {
     let uv = parse_uv(data);
     let clut = Clut::new((data >> 16) as u16);
@@ -203,3 +334,49 @@ pub const fn parse_uv(data: u32) -> Vec2 {
     let v = (data >> 8) & 0xFF;
     Vec2::new(u as i32, v as i32)
 }
+
+impl_state!(Vec2 { x, y });
+
+impl_state_enum!(DisplayDepth);
+
+impl State for Texture {
+    fn save(&self, w: &mut Writer) {
+        let (data, clut) = self.raw();
+        data.save(w);
+        clut.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let mut data = 0u16;
+        let mut clut: Option<u16> = None;
+        data.load(r)?;
+        clut.load(r)?;
+
+        *self = Self::new(data, clut.map(Clut::new));
+        Ok(())
+    }
+}
+
+impl_state!(DrawContext {
+    drawing_area_top_left,
+    drawing_area_bottom_right,
+    drawing_area_offset,
+    texture_window_mask,
+    texture_window_offset,
+    display_vram_start,
+    display_width,
+    display_height,
+    display_hor_range,
+    display_ver_range,
+    display_hor_start,
+    display_ver_start,
+    rect_texture,
+    preserve_masked_pixels,
+    force_set_masked_bit,
+    display_depth,
+    display_disabled,
+    display_pal,
+    interlaced,
+    frame_counter,
+    line_counter,
+});
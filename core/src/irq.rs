@@ -1,4 +1,5 @@
 use crate::System;
+use crate::savestate::impl_state;
 
 pub const PADDR_START: u32 = 0x1F80_1070;
 pub const PADDR_END: u32 = 0x1F80_1078;
@@ -25,6 +26,8 @@ pub struct InterruptController {
     stat: IStat,
 }
 
+impl_state!(InterruptController { mask, stat.0 });
+
 impl InterruptController {
     pub fn read_reg(&self, addr: u32) -> u32 {
         let offs = addr - PADDR_START;
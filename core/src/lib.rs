@@ -2,10 +2,14 @@ mod cdrom;
 mod consts;
 mod cpu;
 mod dma;
+mod game_db;
 mod gpu;
 mod irq;
 mod mdec;
 mod mem;
+mod pgxp;
+mod region;
+mod savestate;
 mod sched;
 mod sio;
 mod spu;
@@ -14,14 +18,22 @@ mod timers;
 use std::collections::HashSet;
 
 use anyhow::Context;
+use anyhow::bail;
+use starpsx_renderer::Deinterlace;
 use starpsx_renderer::FrameBuffer;
+use starpsx_renderer::Overscan;
 use tracing::info;
+use tracing::warn;
 
 use crate::cdrom::CdRom;
 use crate::cdrom::Image;
-use crate::consts::SAMPLES_PER_FRAME;
+use crate::cdrom::ResponseType;
+use crate::consts::LID_OPEN_CYCLES;
+use crate::consts::PAL_TIMING;
 use crate::cpu::Cpu;
+pub use crate::cpu::timing::CpuTiming;
 use crate::dma::DMAController;
+pub use crate::game_db::GameInfo;
 use crate::gpu::Gpu;
 pub use crate::gpu::Snapshot as GpuSnapshot;
 pub use crate::gpu::VMode;
@@ -30,6 +42,11 @@ use crate::mdec::MacroDecoder;
 use crate::mem::bios::Bios;
 use crate::mem::ram::Ram;
 use crate::mem::scratch::Scratch;
+use crate::pgxp::Pgxp;
+pub use crate::region::Region;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
 use crate::sched::Event;
 use crate::sched::EventScheduler;
 use crate::sio::Sio0;
@@ -49,6 +66,17 @@ pub enum Media {
     Executable(Vec<u8>),
 }
 
+impl Media {
+    /// Serial of the disc's boot executable as named in SYSTEM.CNF, like `SLUS-00594`
+    #[must_use]
+    pub fn serial(&mut self) -> Option<String> {
+        match self {
+            Self::Disc(disc) => game_db::disc_serial(disc.source.as_mut()),
+            Self::Binary(_) | Self::Executable(_) => None,
+        }
+    }
+}
+
 pub struct System {
     cpu: Cpu,
     gpu: Gpu,
@@ -57,6 +85,8 @@ pub struct System {
     ram: Ram,
     bios: Bios,
     scratch: Scratch,
+    /// Precise vertices in RAM, only tracked with PGXP on
+    pgxp: Option<Pgxp>,
 
     dma: DMAController,
     timers: Timers,
@@ -69,6 +99,8 @@ pub struct System {
     sio1: Sio1,
 
     tty: Vec<u8>,
+    tty_log: Option<String>,
+    exit_code: Option<u32>,
     scheduler: EventScheduler,
 
     // RGBA frame buffer
@@ -147,6 +179,10 @@ impl System {
         let pc = self.cpu.pc & 0x1FFF_FFFF;
         if (pc == 0xA0 && self.cpu.regs[9] == 0x3C) || (pc == 0xB0 && self.cpu.regs[9] == 0x3D) {
             let byte = self.cpu.regs[4] as u8;
+            if let Some(log) = &mut self.tty_log {
+                log.push(char::from(byte));
+            }
+
             if byte == b'\n' || byte == b'\r' {
                 info!("[TTY]" = %String::from_utf8_lossy(&self.tty));
                 self.tty.clear();
@@ -156,17 +192,203 @@ impl System {
         }
     }
 
+    /// Everything printed to the TTY so far, if enabled through [`PSXBuilder::with_tty_log`]
+    #[must_use]
+    pub fn tty_log(&self) -> Option<&str> {
+        self.tty_log.as_deref()
+    }
+
+    /// Value written to the pcsx-redux exit code register, used by test programs
+    #[must_use]
+    pub const fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    /// Frames per second of the video mode the GPU is currently set to
+    #[must_use]
+    pub fn refresh_rate(&self) -> u32 {
+        self.gpu.video_timing().refresh_rate
+    }
+
+    /// Draw the picture at `scale` times the native resolution, 1 for native
+    pub fn set_resolution_scale(&mut self, scale: usize) {
+        self.gpu.renderer.set_resolution_scale(scale);
+    }
+
+    /// Rasterise on a separate thread. The picture is the same either way, the emulator just
+    /// doesn't wait on large primitives unless the game reads VRAM back.
+    pub fn set_threaded_renderer(&mut self, threaded: bool) {
+        self.gpu.renderer.set_threaded(threaded);
+    }
+
+    /// Follow projected vertices from the GTE to the GPU unrounded (PGXP), so polygons are
+    /// placed between pixels instead of wobbling. Only visible above native resolution.
+    pub fn set_precise_geometry(&mut self, enabled: bool) {
+        if enabled != self.pgxp.is_some() {
+            self.pgxp = enabled.then(Pgxp::default);
+        }
+        self.gpu.renderer.set_precise_geometry(enabled);
+    }
+
+    /// How much of the picture around the display area goes into frames
+    pub fn set_overscan(&mut self, overscan: Overscan) {
+        self.gpu.renderer.set_overscan(overscan);
+    }
+
+    /// How the two fields of 480i are put together into one frame
+    pub const fn set_deinterlace(&mut self, deinterlace: Deinterlace) {
+        self.gpu.renderer.set_deinterlace(deinterlace);
+    }
+
+    /// Interpolate textures by depth on polygons with precise vertices, see
+    /// [`System::set_precise_geometry`]
+    pub const fn set_perspective_correct(&mut self, enabled: bool) {
+        self.gpu.renderer.set_perspective_correct(enabled);
+    }
+
+    /// Gamepad plugged into controller `port` (0 or 1), if any
+    ///
+    /// # Panics
+    ///
+    /// Panics if `port` is not 0 or 1
+    pub const fn gamepad_mut(&mut self, port: usize) -> Option<&mut Gamepad> {
+        self.sio0.device_manager.gamepads[port].as_mut()
+    }
+
+    /// Memory card inserted into `slot` (0 or 1), if any
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slot` is not 0 or 1
+    pub const fn memory_card(&mut self, slot: usize) -> Option<&mut MemoryCard> {
+        self.sio0.device_manager.memcards[slot].as_mut()
+    }
+
+    /// Open the drive lid and close it again on `media` about a second later, while the game
+    /// keeps running. `None` leaves the drive empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `media` is an executable, which can't go in the drive
+    pub fn swap_disc(&mut self, media: Option<Media>) -> anyhow::Result<()> {
+        let image = match media {
+            Some(Media::Disc(disc)) => Some(Image::from_disc(disc)),
+            Some(Media::Binary(bytes)) => Some(Image::from_bytes(bytes)),
+            Some(Media::Executable(_)) => bail!("executables can't be inserted as a disc"),
+            None => None,
+        };
+
+        // Opening the lid stops whatever the drive was reading
+        self.scheduler
+            .unschedule(&Event::CdromResultIrq(ResponseType::INT1));
+        self.scheduler.unschedule(&Event::CdromLidClose);
+
+        self.cdrom.swap_disc(image);
+        self.scheduler
+            .schedule(Event::CdromLidClose, LID_OPEN_CYCLES, None);
+
+        info!("drive lid opened for a disc swap");
+        Ok(())
+    }
+
+    /// Serialize the whole machine state into a versioned blob.
+    /// BIOS, disc contents and memory card data are not included.
+    #[must_use]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(&savestate::MAGIC);
+        savestate::VERSION.save(&mut w);
+
+        let sections: [([u8; 4], &dyn State); 13] = [
+            (*b"CPU ", &self.cpu),
+            (*b"GPU ", &self.gpu),
+            (*b"SPU ", &self.spu),
+            (*b"RAM ", &self.ram),
+            (*b"SCRT", &self.scratch),
+            (*b"DMA ", &self.dma),
+            (*b"TIMR", &self.timers),
+            (*b"IRQC", &self.irqctl),
+            (*b"CDRM", &self.cdrom),
+            (*b"MDEC", &self.mdec),
+            (*b"SIO0", &self.sio0),
+            (*b"TTY ", &self.tty),
+            (*b"SCHD", &self.scheduler),
+        ];
+
+        for (tag, component) in sections {
+            w.section(tag);
+            component.save(&mut w);
+        }
+
+        w.into_inner()
+    }
+
+    /// Restore a blob produced by [`System::save_state`].
+    /// On failure the system is left exactly as it was before the call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blob is not a save state, was made by an incompatible
+    /// version, is corrupted or doesn't match the currently inserted disc and devices
+    ///
     /// # Panics
     ///
-    /// Panics if console has no virtual gamepad in port 0
-    pub const fn gamepad_mut(&mut self) -> &mut Gamepad {
-        self.sio0.device_manager.gamepads[0]
-            .as_mut()
-            .expect("atleast 1 gamepad")
+    /// Panics if the pre-load backup can't be restored, which would be a bug
+    pub fn load_state(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let mut r = Reader::new(data);
+        if r.array::<4>().ok() != Some(savestate::MAGIC) {
+            bail!("not a save state");
+        }
+
+        let mut version = 0u32;
+        version.load(&mut r)?;
+        if version != savestate::VERSION {
+            bail!(
+                "save state version {version} is not supported (expected {})",
+                savestate::VERSION
+            );
+        }
+
+        let backup = self.save_state();
+        if let Err(e) = self.load_sections(&mut r) {
+            self.load_sections(&mut Reader::new(&backup[8..]))
+                .expect("restoring backup state");
+            return Err(e);
+        }
+
+        self.frame_buffer = None;
+        self.audio_samples.clear();
+
+        Ok(())
     }
 
-    pub const fn memory_card(&mut self) -> Option<&mut MemoryCard> {
-        self.sio0.device_manager.memcards[0].as_mut()
+    fn load_sections(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let sections: [([u8; 4], &mut dyn State); 13] = [
+            (*b"CPU ", &mut self.cpu),
+            (*b"GPU ", &mut self.gpu),
+            (*b"SPU ", &mut self.spu),
+            (*b"RAM ", &mut self.ram),
+            (*b"SCRT", &mut self.scratch),
+            (*b"DMA ", &mut self.dma),
+            (*b"TIMR", &mut self.timers),
+            (*b"IRQC", &mut self.irqctl),
+            (*b"CDRM", &mut self.cdrom),
+            (*b"MDEC", &mut self.mdec),
+            (*b"SIO0", &mut self.sio0),
+            (*b"TTY ", &mut self.tty),
+            (*b"SCHD", &mut self.scheduler),
+        ];
+
+        for (tag, component) in sections {
+            r.section(tag)?;
+            component.load(r)?;
+        }
+
+        if !r.is_empty() {
+            bail!("save state has trailing data");
+        }
+
+        Ok(())
     }
 
     #[must_use]
@@ -190,7 +412,7 @@ impl System {
     pub fn run_frame(&mut self, show_vram: bool) {
         self.audio_samples.clear();
 
-        while self.audio_samples.len() != SAMPLES_PER_FRAME {
+        while self.audio_samples.len() < self.gpu.video_timing().samples_per_frame() {
             if let Some(event) = self.scheduler.get_next_event() {
                 match event {
                     Event::VBlankStart => self.frame_buffer = Some(self.enter_vsync(show_vram)),
@@ -201,6 +423,9 @@ impl System {
                     Event::SerialSend => Sio0::process_serial_send(self),
                     Event::CdromResultIrq(x) => CdRom::handle_response(self, x),
                     Event::DsrOff => self.sio0.turn_off_dsr(),
+                    Event::DmaChunk(port) => DMAController::transfer_chunk(self, port),
+                    Event::DmaComplete(port) => DMAController::complete(self, port),
+                    Event::CdromLidClose => self.cdrom.close_shell(self.scheduler.sysclk()),
                     Event::SpuTick => {
                         let samples = Spu::tick(self);
                         self.audio_samples.push(samples);
@@ -209,13 +434,13 @@ impl System {
             }
 
             // Run instructions in blocks of 20
+            let mut cycles = 0;
             for _ in 0..20 {
-                Cpu::run_next_instruction(self);
+                cycles += Cpu::run_next_instruction(self);
                 self.check_for_tty_output();
             }
 
-            // Fixed 2 CPI right now
-            self.scheduler.advance(40);
+            self.scheduler.advance(cycles);
         }
     }
 
@@ -230,6 +455,9 @@ impl System {
                 Event::SerialSend => Sio0::process_serial_send(self),
                 Event::CdromResultIrq(x) => CdRom::handle_response(self, x),
                 Event::DsrOff => self.sio0.turn_off_dsr(),
+                Event::DmaChunk(port) => DMAController::transfer_chunk(self, port),
+                Event::DmaComplete(port) => DMAController::complete(self, port),
+                Event::CdromLidClose => self.cdrom.close_shell(self.scheduler.sysclk()),
                 Event::SpuTick => {
                     // Tick the spu but ignore the samples
                     let _ = Spu::tick(self);
@@ -237,9 +465,8 @@ impl System {
             }
         }
 
-        // Fixed 2 CPI right now
-        Cpu::run_next_instruction(self);
-        self.scheduler.advance(2);
+        let cycles = Cpu::run_next_instruction(self);
+        self.scheduler.advance(cycles);
 
         self.check_for_tty_output();
     }
@@ -269,7 +496,16 @@ pub struct PSXBuilder {
     bios: Bios,
     disc: Option<Image>,
     exec: Option<Vec<u8>>,
-    card: Option<MemoryCard>,
+    gamepads: [bool; 2],
+    cards: [Option<MemoryCard>; 2],
+    cpu_timing: CpuTiming,
+    resolution_scale: usize,
+    threaded_renderer: bool,
+    precise_geometry: bool,
+    perspective_correct: bool,
+    overscan: Overscan,
+    deinterlace: Deinterlace,
+    tty_log: bool,
 }
 
 impl PSXBuilder {
@@ -279,7 +515,16 @@ impl PSXBuilder {
             bios: Bios::new(bios),
             disc: None,
             exec: None,
-            card: None,
+            gamepads: [true, false],
+            cards: [None, None],
+            cpu_timing: CpuTiming::Fast,
+            resolution_scale: 1,
+            threaded_renderer: false,
+            precise_geometry: false,
+            perspective_correct: false,
+            overscan: Overscan::Cropped,
+            deinterlace: Deinterlace::Weave,
+            tty_log: false,
         }
     }
 
@@ -294,9 +539,82 @@ impl PSXBuilder {
         self
     }
 
+    /// Insert a memory card into `slot` (0 or 1)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slot` is not 0 or 1
+    #[must_use]
+    pub fn with_card(mut self, slot: usize, card: Box<[u8; 0x20000]>) -> Self {
+        self.cards[slot] = Some(MemoryCard::from_bytes(card));
+        self
+    }
+
+    /// Plug a gamepad into controller `port` (0 or 1), or unplug it. Port 0 has one by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `port` is not 0 or 1
+    #[must_use]
+    pub const fn with_gamepad(mut self, port: usize, connected: bool) -> Self {
+        self.gamepads[port] = connected;
+        self
+    }
+
+    /// Pick how CPU instructions are timed, [`CpuTiming::Fast`] by default
+    #[must_use]
+    pub const fn with_cpu_timing(mut self, timing: CpuTiming) -> Self {
+        self.cpu_timing = timing;
+        self
+    }
+
+    /// Draw at `scale` times the native resolution, native by default.
+    /// See [`System::set_resolution_scale`].
+    #[must_use]
+    pub const fn with_resolution_scale(mut self, scale: usize) -> Self {
+        self.resolution_scale = scale;
+        self
+    }
+
+    /// Rasterise on a separate thread, see [`System::set_threaded_renderer`]
+    #[must_use]
+    pub const fn with_threaded_renderer(mut self, threaded: bool) -> Self {
+        self.threaded_renderer = threaded;
+        self
+    }
+
+    /// See [`System::set_precise_geometry`]
+    #[must_use]
+    pub const fn with_precise_geometry(mut self, enabled: bool) -> Self {
+        self.precise_geometry = enabled;
+        self
+    }
+
+    /// See [`System::set_perspective_correct`]
+    #[must_use]
+    pub const fn with_perspective_correct(mut self, enabled: bool) -> Self {
+        self.perspective_correct = enabled;
+        self
+    }
+
+    /// See [`System::set_overscan`]
     #[must_use]
-    pub fn with_card(mut self, card: Box<[u8; 0x20000]>) -> Self {
-        self.card = Some(MemoryCard::from_bytes(card));
+    pub const fn with_overscan(mut self, overscan: Overscan) -> Self {
+        self.overscan = overscan;
+        self
+    }
+
+    /// See [`System::set_deinterlace`]
+    #[must_use]
+    pub const fn with_deinterlace(mut self, deinterlace: Deinterlace) -> Self {
+        self.deinterlace = deinterlace;
+        self
+    }
+
+    /// Keep a copy of all TTY output, readable through [`System::tty_log`]
+    #[must_use]
+    pub const fn with_tty_log(mut self) -> Self {
+        self.tty_log = true;
         self
     }
 
@@ -312,6 +630,7 @@ impl PSXBuilder {
             ram: Ram::default(),
             bios: self.bios,
             scratch: Scratch::default(),
+            pgxp: None,
 
             dma: DMAController::default(),
             timers: Timers::default(),
@@ -320,16 +639,25 @@ impl PSXBuilder {
             mdec: MacroDecoder::default(),
 
             tty: Vec::new(),
+            tty_log: self.tty_log.then(String::new),
+            exit_code: None,
             scheduler: EventScheduler::default(),
 
-            // Only 1 gamepad and memory card  for now
-            sio0: Sio0::new([Some(Gamepad::default()), None], [self.card, None]),
+            sio0: Sio0::new(self.gamepads.map(|c| c.then(Gamepad::default)), self.cards),
             sio1: Sio1, // Does nothing
 
             frame_buffer: None,
-            audio_samples: Vec::with_capacity(SAMPLES_PER_FRAME),
+            audio_samples: Vec::with_capacity(PAL_TIMING.samples_per_frame()),
         };
 
+        psx.cpu.timing = self.cpu_timing;
+        psx.set_resolution_scale(self.resolution_scale);
+        psx.set_threaded_renderer(self.threaded_renderer);
+        psx.set_precise_geometry(self.precise_geometry);
+        psx.set_perspective_correct(self.perspective_correct);
+        psx.set_overscan(self.overscan);
+        psx.set_deinterlace(self.deinterlace);
+
         // Open the shell if nothing is loaded
         if self.exec.is_none() && self.disc.is_none() {
             psx.cdrom.open_shell();
@@ -340,11 +668,101 @@ impl PSXBuilder {
         }
 
         if let Some(image) = self.disc {
+            if let (Some(bios), Some(disc)) = (psx.bios.region(), image.region())
+                && bios != disc
+            {
+                warn!(
+                    ?bios,
+                    ?disc,
+                    "disc region doesn't match the BIOS, it may refuse to boot"
+                );
+            }
+
             psx.cdrom.insert_disc(image);
         }
 
-        psx.scheduler.init_with_events();
+        psx.scheduler.init_with_events(psx.gpu.video_timing());
 
         Ok(psx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A BIOS that counts up in RAM forever
+    fn counting_system() -> anyhow::Result<System> {
+        let code: [u32; 5] = [
+            0x3C08_8010, // lui   t0, 0x8010
+            0x2529_0001, // addiu t1, t1, 1
+            0xAD09_0000, // sw    t1, 0(t0)
+            0x1000_FFFD, // b     -3
+            0x0000_0000, // nop
+        ];
+
+        let mut bios = vec![0u8; 0x80000];
+        for (i, ins) in code.iter().enumerate() {
+            bios[i * 4..i * 4 + 4].copy_from_slice(&ins.to_le_bytes());
+        }
+
+        let bios = bios.into_boxed_slice().try_into().expect("bios is 512KiB");
+        PSXBuilder::new(bios).build()
+    }
+
+    fn run_frames(system: &mut System, frames: usize) {
+        for _ in 0..frames {
+            system.run_frame(false);
+        }
+    }
+
+    #[test]
+    fn loaded_state_runs_the_same_as_the_original() -> anyhow::Result<()> {
+        let mut system = counting_system()?;
+        run_frames(&mut system, 5);
+
+        let saved = system.save_state();
+        run_frames(&mut system, 10);
+        let first_run = system.save_state();
+        assert_ne!(first_run, saved);
+
+        system.load_state(&saved)?;
+        assert_eq!(system.save_state(), saved);
+        run_frames(&mut system, 10);
+        assert_eq!(system.save_state(), first_run);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_states_leave_the_system_untouched() -> anyhow::Result<()> {
+        let mut system = counting_system()?;
+        run_frames(&mut system, 5);
+        let good = system.save_state();
+
+        run_frames(&mut system, 5);
+        let before = system.save_state();
+
+        let mut bad_magic = good.clone();
+        bad_magic[0] ^= 0xFF;
+        let err = system.load_state(&bad_magic).expect_err("bad magic");
+        assert!(err.to_string().contains("not a save state"), "{err}");
+        assert_eq!(system.save_state(), before);
+
+        let mut wrong_version = good.clone();
+        wrong_version[4..8].copy_from_slice(&(savestate::VERSION + 1).to_le_bytes());
+        let err = system
+            .load_state(&wrong_version)
+            .expect_err("wrong version");
+        assert!(err.to_string().contains("not supported"), "{err}");
+        assert_eq!(system.save_state(), before);
+
+        // Cut off partway through the sections, after some of them were already loaded
+        for len in [0, 6, good.len() / 2, good.len() - 1] {
+            assert!(system.load_state(&good[..len]).is_err(), "{len} bytes");
+            assert_eq!(system.save_state(), before, "{len} bytes");
+        }
+
+        Ok(())
+    }
+}
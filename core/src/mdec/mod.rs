@@ -2,6 +2,7 @@ mod util;
 
 use std::collections::VecDeque;
 
+use anyhow::bail;
 use num_enum::FromPrimitive;
 
 use crate::System;
@@ -11,6 +12,10 @@ use crate::mdec::util::level_shift_8bpp;
 use crate::mdec::util::signed10bit;
 use crate::mdec::util::yuv_to_rgb15_block;
 use crate::mdec::util::yuv_to_rgb24_block;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
+use crate::savestate::impl_state;
 
 pub const PADDR_START: u32 = 0x1F80_1820;
 pub const PADDR_END: u32 = 0x1F80_1828;
@@ -73,6 +78,69 @@ struct Command {
     parameters: Vec<u32>,
 }
 
+impl Default for Command {
+    fn default() -> Self {
+        Self {
+            command_type: CommandType::SetScaleTable,
+            parameters: Vec::new(),
+        }
+    }
+}
+
+impl State for Command {
+    fn save(&self, w: &mut Writer) {
+        match self.command_type {
+            CommandType::SetQuantTable(color) => {
+                0u8.save(w);
+                (color as u32).save(w);
+            }
+            CommandType::SetScaleTable => 1u8.save(w),
+            CommandType::DecodeMacroblock {
+                depth,
+                is_signed,
+                b15,
+            } => {
+                2u8.save(w);
+                (depth as u32).save(w);
+                is_signed.save(w);
+                b15.save(w);
+            }
+        }
+
+        self.parameters.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let mut tag = 0u8;
+        tag.load(r)?;
+
+        self.command_type = match tag {
+            0 => {
+                let mut color = 0u32;
+                color.load(r)?;
+                CommandType::SetQuantTable(Color::from(color))
+            }
+            1 => CommandType::SetScaleTable,
+            2 => {
+                let mut depth = 0u32;
+                let mut is_signed = false;
+                let mut b15 = false;
+                depth.load(r)?;
+                is_signed.load(r)?;
+                b15.load(r)?;
+                CommandType::DecodeMacroblock {
+                    depth: Depth::from(depth),
+                    is_signed,
+                    b15,
+                }
+            }
+            x => bail!("invalid mdec command {x}"),
+        };
+
+        self.parameters.load(r)
+    }
+}
+
 pub struct MacroDecoder {
     status: Status,
     collecting: Option<Command>,
@@ -98,6 +166,16 @@ impl Default for MacroDecoder {
         }
     }
 }
+impl_state!(MacroDecoder {
+    status.0,
+    collecting,
+    output_fifo,
+    params_remaining,
+    scale_table,
+    luminance_table,
+    chrominance_table,
+});
+
 impl MacroDecoder {
     const fn status(&self) -> u32 {
         // Bits 0-15 show number of remaining parameters minus 1, 0xFFFF = 0
@@ -13,6 +13,7 @@ use crate::spu;
 use crate::timers;
 
 pub mod bios {
+    use crate::region::Region;
 
     pub const PADDR_START: u32 = 0x1FC0_0000;
     pub const PADDR_END: u32 = 0x1FC8_0000;
@@ -34,10 +35,19 @@ pub mod bios {
 
             u32::from_le_bytes(buffer)
         }
+
+        /// Region the BIOS was made for, if its version string says
+        pub fn region(&self) -> Option<Region> {
+            Region::from_bios(self.bytes.as_slice())
+        }
     }
 }
 
 pub mod ram {
+    use crate::savestate::Reader;
+    use crate::savestate::State;
+    use crate::savestate::Writer;
+
     pub const PADDR_START: u32 = 0x0000_0000;
     pub const PADDR_END: u32 = 0x0080_0000;
 
@@ -70,9 +80,23 @@ pub mod ram {
             self.bytes[addr..addr + WIDTH].copy_from_slice(&bytes[..WIDTH]);
         }
     }
+
+    impl State for Ram {
+        fn save(&self, w: &mut Writer) {
+            w.bytes(self.bytes.as_slice());
+        }
+
+        fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+            r.bytes_into(self.bytes.as_mut_slice())
+        }
+    }
 }
 
 pub mod scratch {
+    use crate::savestate::Reader;
+    use crate::savestate::State;
+    use crate::savestate::Writer;
+
     pub const PADDR_START: u32 = 0x1F80_0000;
     pub const PADDR_END: u32 = 0x1F80_0400;
 
@@ -105,6 +129,16 @@ pub mod scratch {
             self.bytes[addr..addr + WIDTH].copy_from_slice(&bytes[..WIDTH]);
         }
     }
+
+    impl State for Scratch {
+        fn save(&self, w: &mut Writer) {
+            w.bytes(self.bytes.as_slice());
+        }
+
+        fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+            r.bytes_into(self.bytes.as_mut_slice())
+        }
+    }
 }
 
 macro_rules! stubbed {
@@ -144,6 +178,7 @@ impl System {
         }
 
         let addr = mask_region(addr);
+        self.cpu.charge_access::<WIDTH>(addr, false);
 
         let data = match addr {
             ram::PADDR_START..ram::PADDR_END => self.ram.read::<WIDTH>(addr),
@@ -202,9 +237,20 @@ impl System {
             return Err(Exception::StoreAddressError(addr));
         }
         let addr = mask_region(addr);
+        self.cpu.charge_access::<WIDTH>(addr, true);
 
         match addr {
-            ram::PADDR_START..ram::PADDR_END => self.ram.write::<WIDTH>(addr, data),
+            ram::PADDR_START..ram::PADDR_END => {
+                self.ram.write::<WIDTH>(addr, data);
+
+                // Only GTE output is tracked, other stores cost nothing more than the check
+                if WIDTH == 4
+                    && let Some(pgxp) = &mut self.pgxp
+                    && let Some(vertex) = self.cpu.precise_sxy(data)
+                {
+                    pgxp.store(addr, data, vertex);
+                }
+            }
 
             scratch::PADDR_START..scratch::PADDR_END => self.scratch.write::<WIDTH>(addr, data),
 
@@ -244,7 +290,10 @@ impl System {
                 trace!(target: "mem", region = "expansion2", "stubbed write addr={:#08x}", addr);
             }
 
-            0x1F80_2082 => info!(exit_code = data, "pcsx-redux exit code"),
+            0x1F80_2082 => {
+                info!(exit_code = data, "pcsx-redux exit code");
+                self.exit_code = Some(data);
+            }
 
             _ => unimplemented!("write at {addr:#08X}"),
         }
@@ -0,0 +1,68 @@
+//! Precise geometry (PGXP). The GTE rounds projected vertices down to whole pixels, which is
+//! what makes polygons wobble. Their unrounded positions are followed from the GTE into RAM
+//! and from there to the GPU, so the renderer can place them between pixels.
+
+use std::collections::HashMap;
+
+use starpsx_renderer::vec2::PreciseVertex;
+
+#[derive(Default)]
+pub struct Pgxp {
+    /// Projected vertices stored to RAM by word address, with the word that was stored. Other
+    /// stores leave entries alone, [`Pgxp::load`] drops them once the word has changed.
+    ram: HashMap<u32, (u32, PreciseVertex)>,
+}
+
+impl Pgxp {
+    /// A word that came out of the GTE stored to RAM
+    pub fn store(&mut self, addr: u32, word: u32, vertex: PreciseVertex) {
+        self.ram.insert(addr & 0x1F_FFFC, (word, vertex));
+    }
+
+    /// Vertex stored at `addr`, if it still holds the word it was stored as. Nothing else that
+    /// writes RAM is tracked, so this check is what catches overwritten entries.
+    pub fn load(&self, addr: u32, word: u32) -> Option<PreciseVertex> {
+        self.ram
+            .get(&(addr & 0x1F_FFFC))
+            .filter(|(stored, _)| *stored == word)
+            .map(|&(_, vertex)| vertex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERTEX: PreciseVertex = PreciseVertex {
+        x: 10.25,
+        y: -3.5,
+        w: 200.0,
+    };
+
+    #[test]
+    fn vertices_are_found_where_they_were_stored() {
+        let mut pgxp = Pgxp::default();
+        pgxp.store(0x0001_0010, 0x0004_000A, VERTEX);
+
+        assert_eq!(pgxp.load(0x0001_0010, 0x0004_000A), Some(VERTEX));
+        assert_eq!(pgxp.load(0x0001_0014, 0x0004_000A), None);
+
+        // KUSEG, KSEG0 and KSEG1 all reach the same RAM
+        assert_eq!(pgxp.load(0x8001_0010, 0x0004_000A), Some(VERTEX));
+        assert_eq!(pgxp.load(0xA001_0010, 0x0004_000A), Some(VERTEX));
+    }
+
+    #[test]
+    fn overwritten_words_lose_their_vertex() {
+        let mut pgxp = Pgxp::default();
+        pgxp.store(0x100, 0x0004_000A, VERTEX);
+
+        // Any other store or a DMA write changes the word
+        assert_eq!(pgxp.load(0x100, 0x0004_000B), None);
+
+        let moved = PreciseVertex { x: 11.0, ..VERTEX };
+        pgxp.store(0x100, 0x0004_000B, moved);
+        assert_eq!(pgxp.load(0x100, 0x0004_000B), Some(moved));
+        assert_eq!(pgxp.load(0x100, 0x0004_000A), None);
+    }
+}
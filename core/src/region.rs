@@ -0,0 +1,49 @@
+/// Console region, a BIOS only boots discs licensed for its own region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Japan,
+    NorthAmerica,
+    Europe,
+}
+
+impl Region {
+    /// Last letter of the `SCEx` string the drive reports for licensed discs
+    pub(crate) const fn license_letter(self) -> u8 {
+        match self {
+            Self::Japan => b'I',
+            Self::NorthAmerica => b'A',
+            Self::Europe => b'E',
+        }
+    }
+
+    /// Region named in the license text on sector 4 of licensed discs,
+    /// "Licensed by Sony Computer Entertainment Amer ica" and so on
+    pub(crate) fn from_license_sector(sector: &[u8]) -> Option<Self> {
+        const LICENSEE: &[u8] = b"Sony Computer Entertainment ";
+
+        let start = sector.windows(LICENSEE.len()).position(|w| w == LICENSEE)? + LICENSEE.len();
+
+        match sector.get(start..start + 4)? {
+            b"Inc." => Some(Self::Japan),
+            b"Amer" => Some(Self::NorthAmerica),
+            b"Euro" => Some(Self::Europe),
+            _ => None,
+        }
+    }
+
+    /// Region letter at the end of the BIOS version string,
+    /// "System ROM Version 4.1 12/16/97 A"
+    pub(crate) fn from_bios(bios: &[u8]) -> Option<Self> {
+        const VERSION: &[u8] = b"System ROM Version ";
+
+        let start = bios.windows(VERSION.len()).position(|w| w == VERSION)?;
+        let len = bios[start..].iter().position(|&b| b == 0)?;
+
+        match bios[start..start + len].trim_ascii_end().last()? {
+            b'J' => Some(Self::Japan),
+            b'A' => Some(Self::NorthAmerica),
+            b'E' => Some(Self::Europe),
+            _ => None,
+        }
+    }
+}
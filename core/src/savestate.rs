@@ -0,0 +1,315 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+
+use anyhow::Context;
+use anyhow::bail;
+use arrayvec::ArrayVec;
+
+/// Identifies a starpsx save state blob
+pub const MAGIC: [u8; 4] = *b"SPSX";
+
+/// Bumped whenever the layout of any component changes
+pub const VERSION: u32 = 12;
+
+/// Little endian byte sink for save states
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Tags the start of a component so mismatches are caught early on load
+    pub fn section(&mut self, tag: [u8; 4]) {
+        self.bytes(&tag);
+    }
+
+    pub fn length(&mut self, len: usize) {
+        (len as u32).save(self);
+    }
+}
+
+/// Little endian byte source for save states
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.data.len() < len {
+            bail!("save state truncated");
+        }
+
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn bytes_into(&mut self, dest: &mut [u8]) -> anyhow::Result<()> {
+        dest.copy_from_slice(self.take(dest.len())?);
+        Ok(())
+    }
+
+    pub fn section(&mut self, tag: [u8; 4]) -> anyhow::Result<()> {
+        let found = self.take(4)?;
+        if found != tag {
+            bail!(
+                "save state expected section {} but found {}",
+                String::from_utf8_lossy(&tag),
+                String::from_utf8_lossy(found)
+            );
+        }
+        Ok(())
+    }
+
+    pub fn length(&mut self) -> anyhow::Result<usize> {
+        let mut len = 0u32;
+        len.load(self)?;
+        Ok(len as usize)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        self.take(N)?.try_into().context("array size")
+    }
+}
+
+/// A piece of emulator state that can be written to and restored from a save state.
+/// Loading happens in place so components keep anything that isn't part of the state.
+pub trait State {
+    fn save(&self, w: &mut Writer);
+
+    /// # Errors
+    ///
+    /// Returns an error if the save state is truncated or contains invalid values
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()>;
+}
+
+macro_rules! impl_state_int {
+    ($($ty:ty),*) => {
+        $(
+            impl State for $ty {
+                fn save(&self, w: &mut Writer) {
+                    w.bytes(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+                    *self = Self::from_le_bytes(r.array()?);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_state_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+// Host sized, always stored as 64 bits
+impl State for usize {
+    fn save(&self, w: &mut Writer) {
+        (*self as u64).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        *self = u64::from_le_bytes(r.array()?)
+            .try_into()
+            .context("usize out of range")?;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, w: &mut Writer) {
+        u8::from(*self).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        *self = match r.array::<1>()?[0] {
+            0 => false,
+            1 => true,
+            x => bail!("invalid bool {x}"),
+        };
+        Ok(())
+    }
+}
+
+impl State for Cell<bool> {
+    fn save(&self, w: &mut Writer) {
+        self.get().save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.get_mut().load(r)
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save(&self, w: &mut Writer) {
+        for x in self {
+            x.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.iter_mut().try_for_each(|x| x.load(r))
+    }
+}
+
+impl<A: State, B: State> State for (A, B) {
+    fn save(&self, w: &mut Writer) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.0.load(r)?;
+        self.1.load(r)
+    }
+}
+
+impl<T: State + Default> State for Option<T> {
+    fn save(&self, w: &mut Writer) {
+        self.is_some().save(w);
+        if let Some(x) = self {
+            x.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let mut present = false;
+        present.load(r)?;
+
+        *self = if present {
+            let mut x = T::default();
+            x.load(r)?;
+            Some(x)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for Vec<T> {
+    fn save(&self, w: &mut Writer) {
+        w.length(self.len());
+        for x in self {
+            x.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let len = r.length()?;
+        if len > r.data.len() {
+            bail!("save state truncated");
+        }
+
+        self.clear();
+        for _ in 0..len {
+            let mut x = T::default();
+            x.load(r)?;
+            self.push(x);
+        }
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for VecDeque<T> {
+    fn save(&self, w: &mut Writer) {
+        w.length(self.len());
+        for x in self {
+            x.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let len = r.length()?;
+        if len > r.data.len() {
+            bail!("save state truncated");
+        }
+
+        self.clear();
+        for _ in 0..len {
+            let mut x = T::default();
+            x.load(r)?;
+            self.push_back(x);
+        }
+        Ok(())
+    }
+}
+
+impl<T: State + Default, const N: usize> State for ArrayVec<T, N> {
+    fn save(&self, w: &mut Writer) {
+        w.length(self.len());
+        for x in self {
+            x.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        let len = r.length()?;
+        if len > N {
+            bail!("save state has {len} elements for a capacity of {N}");
+        }
+
+        self.clear();
+        for _ in 0..len {
+            let mut x = T::default();
+            x.load(r)?;
+            self.push(x);
+        }
+        Ok(())
+    }
+}
+
+/// Implements [`State`] by visiting the listed fields in order
+macro_rules! impl_state {
+    ($ty:ty { $($($field:tt).+),* $(,)? }) => {
+        impl $crate::savestate::State for $ty {
+            fn save(&self, w: &mut $crate::savestate::Writer) {
+                $( $crate::savestate::State::save(&self.$($field).+, w); )*
+            }
+
+            fn load(&mut self, r: &mut $crate::savestate::Reader) -> anyhow::Result<()> {
+                $( $crate::savestate::State::load(&mut self.$($field).+, r)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Implements [`State`] for a fieldless enum through its `u8` representation
+macro_rules! impl_state_enum {
+    ($ty:ty) => {
+        impl $crate::savestate::State for $ty {
+            fn save(&self, w: &mut $crate::savestate::Writer) {
+                $crate::savestate::State::save(&u8::from(*self), w);
+            }
+
+            fn load(&mut self, r: &mut $crate::savestate::Reader) -> anyhow::Result<()> {
+                let mut raw = 0u8;
+                $crate::savestate::State::load(&mut raw, r)?;
+                *self = <$ty>::try_from(raw)
+                    .map_err(|_| anyhow::anyhow!("invalid {} {raw}", stringify!($ty)))?;
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use impl_state;
+pub(crate) use impl_state_enum;
@@ -1,8 +1,12 @@
+use anyhow::bail;
 use arrayvec::ArrayVec;
 
 use crate::cdrom::ResponseType;
-use crate::consts::HBLANK_DURATION;
-use crate::consts::LINE_DURATION;
+use crate::consts::VideoTiming;
+use crate::dma::utils::Port;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct TimerInterrupt {
@@ -21,6 +25,87 @@ pub enum Event {
     SerialSend,
     DsrOff,
     SpuTick,
+    DmaChunk(Port),
+    DmaComplete(Port),
+    CdromLidClose,
+}
+
+impl Event {
+    fn save(&self, w: &mut Writer) {
+        match self {
+            Self::VBlankStart => 0u8.save(w),
+            Self::VBlankEnd => 1u8.save(w),
+            Self::HBlankStart => 2u8.save(w),
+            Self::HBlankEnd => 3u8.save(w),
+            Self::Timer(x) => {
+                4u8.save(w);
+                x.which.save(w);
+                x.toggle.save(w);
+            }
+            Self::CdromResultIrq(x) => {
+                5u8.save(w);
+                x.save(w);
+            }
+            Self::SerialSend => 6u8.save(w),
+            Self::DsrOff => 7u8.save(w),
+            Self::SpuTick => 8u8.save(w),
+            Self::DmaChunk(port) => {
+                9u8.save(w);
+                (*port as u8).save(w);
+            }
+            Self::DmaComplete(port) => {
+                10u8.save(w);
+                (*port as u8).save(w);
+            }
+            Self::CdromLidClose => 11u8.save(w),
+        }
+    }
+
+    fn load(r: &mut Reader) -> anyhow::Result<Self> {
+        let mut tag = 0u8;
+        tag.load(r)?;
+
+        let event = match tag {
+            0 => Self::VBlankStart,
+            1 => Self::VBlankEnd,
+            2 => Self::HBlankStart,
+            3 => Self::HBlankEnd,
+            4 => {
+                let mut x = TimerInterrupt {
+                    which: 0,
+                    toggle: false,
+                };
+                x.which.load(r)?;
+                x.toggle.load(r)?;
+                Self::Timer(x)
+            }
+            5 => {
+                let mut x = ResponseType::INT1;
+                x.load(r)?;
+                Self::CdromResultIrq(x)
+            }
+            6 => Self::SerialSend,
+            7 => Self::DsrOff,
+            8 => Self::SpuTick,
+            9 => Self::DmaChunk(load_port(r)?),
+            10 => Self::DmaComplete(load_port(r)?),
+            11 => Self::CdromLidClose,
+            x => bail!("invalid scheduler event {x}"),
+        };
+
+        Ok(event)
+    }
+}
+
+fn load_port(r: &mut Reader) -> anyhow::Result<Port> {
+    let mut port = 0u8;
+    port.load(r)?;
+
+    if port > 6 {
+        bail!("invalid dma port {port}");
+    }
+
+    Ok(Port::from(usize::from(port)))
 }
 
 pub struct Task {
@@ -32,7 +117,44 @@ pub struct Task {
 #[derive(Default)]
 pub struct EventScheduler {
     sysclk: u64,
-    tasks: ArrayVec<Task, 32>,
+    tasks: ArrayVec<Task, 48>,
+}
+
+impl State for EventScheduler {
+    fn save(&self, w: &mut Writer) {
+        self.sysclk.save(w);
+        w.length(self.tasks.len());
+
+        for task in &self.tasks {
+            task.event.save(w);
+            task.cycle.save(w);
+            task.repeat.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.sysclk.load(r)?;
+
+        let len = r.length()?;
+        if len > self.tasks.capacity() {
+            bail!("save state has {len} scheduled tasks");
+        }
+
+        self.tasks.clear();
+        for _ in 0..len {
+            let event = Event::load(r)?;
+            let mut task = Task {
+                event,
+                cycle: 0,
+                repeat: None,
+            };
+            task.cycle.load(r)?;
+            task.repeat.load(r)?;
+            self.tasks.push(task);
+        }
+
+        Ok(())
+    }
 }
 
 impl EventScheduler {
@@ -63,6 +185,10 @@ impl EventScheduler {
         self.tasks.retain(|e| e.event != *event);
     }
 
+    pub fn unschedule_matching(&mut self, predicate: impl Fn(&Event) -> bool) {
+        self.tasks.retain(|e| !predicate(&e.event));
+    }
+
     pub fn schedule(&mut self, event: Event, cycles_length: u64, repeat: Option<u64>) {
         self.unschedule(&event);
 
@@ -84,28 +210,28 @@ impl EventScheduler {
         );
     }
 
-    pub fn init_with_events(&mut self) {
-        self.schedule(
-            Event::VBlankStart,
-            LINE_DURATION * 240,
-            Some(LINE_DURATION * 263),
-        );
+    pub fn init_with_events(&mut self, timing: &VideoTiming) {
+        self.schedule_video_events(timing);
 
-        self.schedule(
-            Event::VBlankEnd,
-            LINE_DURATION * 263,
-            Some(LINE_DURATION * 263),
-        );
+        // SPU clocks at 44100Hz, which translates to 768 cycles
+        self.schedule(Event::SpuTick, 768, Some(768));
+    }
+
+    /// (Re)start the blanking events for a video standard, the new frame begins right away
+    pub fn schedule_video_events(&mut self, timing: &VideoTiming) {
+        let line = timing.line_cycles;
+        let frame = timing.frame_cycles();
 
         self.schedule(
-            Event::HBlankStart,
-            LINE_DURATION - HBLANK_DURATION,
-            Some(LINE_DURATION),
+            Event::VBlankStart,
+            line * timing.vblank_start_line,
+            Some(frame),
         );
 
-        self.schedule(Event::HBlankEnd, LINE_DURATION, Some(LINE_DURATION));
+        self.schedule(Event::VBlankEnd, frame, Some(frame));
 
-        // SPU clocks at 44100Hz, which translates to 768 cycles
-        self.schedule(Event::SpuTick, 768, Some(768));
+        self.schedule(Event::HBlankStart, line - timing.hblank_cycles, Some(line));
+
+        self.schedule(Event::HBlankEnd, line, Some(line));
     }
 }
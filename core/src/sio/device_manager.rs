@@ -1,20 +1,73 @@
+use anyhow::bail;
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
+
 use super::System;
 use super::gamepad::Gamepad;
 use super::memory_card::MemoryCard;
+use crate::savestate;
+use crate::savestate::Reader;
+use crate::savestate::Writer;
+use crate::savestate::impl_state_enum;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
 enum State {
     None,
     GamepadComm,
     MemCardComm,
 }
 
+impl_state_enum!(State);
+
 pub struct DeviceManager {
     current_state: State,
     pub gamepads: [Option<Gamepad>; 2],
     pub memcards: [Option<MemoryCard>; 2],
 }
 
+impl savestate::State for DeviceManager {
+    fn save(&self, w: &mut Writer) {
+        self.current_state.save(w);
+        self.gamepads
+            .iter()
+            .for_each(|gp| save_device(gp.as_ref(), w));
+        self.memcards
+            .iter()
+            .for_each(|mc| save_device(mc.as_ref(), w));
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.current_state.load(r)?;
+        for gp in &mut self.gamepads {
+            load_device(gp.as_mut(), r)?;
+        }
+        for mc in &mut self.memcards {
+            load_device(mc.as_mut(), r)?;
+        }
+        Ok(())
+    }
+}
+
+// Devices are owned by the frontend, so only ones that are plugged in on both ends can be restored
+fn save_device(device: Option<&impl savestate::State>, w: &mut Writer) {
+    savestate::State::save(&device.is_some(), w);
+    if let Some(device) = device {
+        device.save(w);
+    }
+}
+
+fn load_device(device: Option<&mut impl savestate::State>, r: &mut Reader) -> anyhow::Result<()> {
+    let mut present = false;
+    savestate::State::load(&mut present, r)?;
+
+    match device {
+        Some(device) if present => device.load(r),
+        None if !present => Ok(()),
+        _ => bail!("save state was made with a different controller/memory card configuration"),
+    }
+}
+
 impl DeviceManager {
     pub const fn new(gamepads: [Option<Gamepad>; 2], memcards: [Option<MemoryCard>; 2]) -> Self {
         Self {
@@ -1,3 +1,9 @@
+use num_enum::FromPrimitive;
+use num_enum::IntoPrimitive;
+
+use crate::savestate::impl_state;
+use crate::savestate::impl_state_enum;
+
 pub struct Gamepad {
     state: State,
     mode: Mode,
@@ -19,6 +25,14 @@ impl Default for Gamepad {
     }
 }
 
+impl_state!(Gamepad {
+    state,
+    mode,
+    digital_switches,
+    joystick_axes,
+    in_ack,
+});
+
 impl Gamepad {
     pub fn send_and_receive_byte(&mut self, data: u8) -> u8 {
         let received = match self.state {
@@ -76,8 +90,8 @@ impl Gamepad {
     }
 }
 
-#[repr(C)]
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
 enum State {
     #[default]
     Init,
@@ -91,13 +105,18 @@ enum State {
     AnalogInput3,
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+impl_state_enum!(State);
+
+#[derive(Debug, Default, Clone, Copy, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
 enum Mode {
     #[default]
     Digital,
     Analog,
 }
 
+impl_state_enum!(Mode);
+
 impl Mode {
     // valid next gamepad comm sequences alongside an optional check byte
     const GAMEPAD_DIGITAL_STATES: [(State, Option<u8>); 5] = [
@@ -1,5 +1,10 @@
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
 use tracing::warn;
 
+use crate::savestate::impl_state;
+use crate::savestate::impl_state_enum;
+
 const FRAME_SIZE: usize = 0x80;
 
 pub struct MemoryCard {
@@ -21,6 +26,20 @@ pub struct MemoryCard {
     is_dirty: bool,
 }
 
+// Card contents are persisted separately, only the transfer state is saved
+impl_state!(MemoryCard {
+    in_ack,
+    state,
+    command,
+    state_idx,
+    sector_number,
+    checksum,
+    sector_buffer,
+    bytes_left,
+    end_response,
+    directory_not_read,
+});
+
 impl MemoryCard {
     pub const fn from_bytes(data: Box<[u8; 0x20000]>) -> Self {
         Self {
@@ -181,7 +200,7 @@ impl MemoryCard {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 enum EndResponse {
     Good = 0x47,
@@ -189,7 +208,10 @@ enum EndResponse {
     BadSector = 0xFF,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl_state_enum!(EndResponse);
+
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
 enum State {
     Init,
     Flag,
@@ -211,13 +233,18 @@ enum State {
     MemEnd,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl_state_enum!(State);
+
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
 enum Command {
     Read,
     Write,
     GetId,
 }
 
+impl_state_enum!(Command);
+
 impl Command {
     const GETID_STATES: [(State, Option<u8>); 10] = [
         (State::Init, None),
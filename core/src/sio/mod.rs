@@ -5,6 +5,7 @@ pub mod memory_card;
 use arrayvec::ArrayVec;
 
 use crate::System;
+use crate::savestate::impl_state;
 use crate::sched::Event;
 use crate::sio::device_manager::DeviceManager;
 use crate::sio::gamepad::Gamepad;
@@ -56,6 +57,16 @@ pub struct Sio0 {
     pub device_manager: DeviceManager,
 }
 
+impl_state!(Sio0 {
+    transfer,
+    received,
+    status.0,
+    control.0,
+    mode,
+    baud_timer_reload_value,
+    device_manager,
+});
+
 pub struct Sio1;
 
 impl SerialInterface for Sio1 {
@@ -1,6 +1,11 @@
 use num_enum::FromPrimitive;
+use num_enum::IntoPrimitive;
 
-#[derive(Default, PartialEq, Eq, Clone, Copy)]
+use crate::savestate::impl_state;
+use crate::savestate::impl_state_enum;
+
+#[derive(Default, PartialEq, Eq, Clone, Copy, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
 pub enum AdsrPhase {
     #[default]
     Off,
@@ -64,6 +69,22 @@ pub struct AdsrEnvelope {
     sustain_level: u16,
 }
 
+impl_state_enum!(AdsrPhase);
+
+impl_state!(AdsrEnvelope {
+    phase,
+    volume,
+    register.0,
+    counter,
+    counter_reload,
+    step,
+    exponential,
+    decreasing,
+    shift,
+    step_index,
+    sustain_level,
+});
+
 impl AdsrEnvelope {
     fn calc(&mut self) {
         const DIRTABLE: [[i16; 4]; 2] = [[7, 6, 5, 4], [-8, -7, -6, -5]];
@@ -13,6 +13,10 @@ pub use snapshot::VoiceSnapshot;
 use tracing::debug;
 
 use crate::System;
+use crate::savestate::Reader;
+use crate::savestate::State;
+use crate::savestate::Writer;
+use crate::savestate::impl_state;
 use crate::spu::reverb::Reverb;
 use crate::spu::voice::GAUSSIAN_TABLE;
 use crate::spu::voice::Voice;
@@ -65,6 +69,28 @@ pub struct Spu {
     reverb: Reverb,
 }
 
+impl_state!(Spu {
+    control.0,
+    main_volume,
+    cd_volume,
+    ex_volume,
+    voice_pitch_enable,
+    voice_noise_enable,
+    voice_reverb_enable,
+    ram_data_transfer_control,
+    ram_data_transfer_address,
+    voice_key_off,
+    voice_key_on,
+    current_address,
+    voices,
+    sound_ram,
+    last_irq_line,
+    irq_requested,
+    noise_generator,
+    capture_buffer_ptr,
+    reverb,
+});
+
 impl Spu {
     pub fn ram_read<const WIDTH: usize>(&mut self) -> u32 {
         let addr = self.current_address & 0x7FFFF;
@@ -556,6 +582,18 @@ pub struct Volume<T> {
     r: T,
 }
 
+impl<T: State> State for Volume<T> {
+    fn save(&self, w: &mut Writer) {
+        self.l.save(w);
+        self.r.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        self.l.load(r)?;
+        self.r.load(r)
+    }
+}
+
 impl Volume<Sweep> {
     const fn set_l(&mut self, v: u16) {
         debug_assert!(v & 0x8000 == 0, "Fixed volume");
@@ -581,6 +619,8 @@ impl Volume<i16> {
 #[derive(Default)]
 struct Sweep(i16);
 
+impl_state!(Sweep { 0 });
+
 fn apply_volume(sample: i16, volume: i16) -> i16 {
     ((i32::from(sample) * i32::from(volume)) >> 15) as i16
 }
@@ -593,6 +633,13 @@ struct NoiseGenerator {
     timer: i32,
 }
 
+impl_state!(NoiseGenerator {
+    lfsr,
+    step,
+    shift,
+    timer,
+});
+
 impl NoiseGenerator {
     // Ticked at 44100 Hz
     fn tick(&mut self) {
@@ -636,6 +683,22 @@ struct SoundRam {
     irq: Cell<bool>,
 }
 
+impl State for SoundRam {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(self.ram.as_slice());
+        self.irq_enabled.save(w);
+        self.irq_address.save(w);
+        self.irq.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> anyhow::Result<()> {
+        r.bytes_into(self.ram.as_mut_slice())?;
+        self.irq_enabled.load(r)?;
+        self.irq_address.load(r)?;
+        self.irq.load(r)
+    }
+}
+
 impl Default for SoundRam {
     fn default() -> Self {
         Self {
@@ -1,3 +1,4 @@
+use crate::savestate::impl_state;
 use crate::spu::SoundRam;
 use crate::spu::Volume;
 use crate::spu::clamped_i16;
@@ -60,6 +61,46 @@ pub struct Reverb {
     pub r_out: i32,
 }
 
+impl_state!(Reverb {
+    v_out,
+    v_in,
+    m_base,
+    d_apf1,
+    d_apf2,
+    v_iir,
+    v_comb1,
+    v_comb2,
+    v_comb3,
+    v_comb4,
+    v_wall,
+    v_apf1,
+    v_apf2,
+    m_lsame,
+    m_rsame,
+    d_lsame,
+    d_rsame,
+    m_lcomb1,
+    m_rcomb1,
+    m_lcomb2,
+    m_rcomb2,
+    m_lcomb3,
+    m_rcomb3,
+    m_lcomb4,
+    m_rcomb4,
+    m_ldiff,
+    m_rdiff,
+    d_ldiff,
+    d_rdiff,
+    m_lapf1,
+    m_rapf1,
+    m_lapf2,
+    m_rapf2,
+    half_tick,
+    current_buffer_addr,
+    l_out,
+    r_out,
+});
+
 impl Reverb {
     pub fn set_base_addr(&mut self, addr: u16) {
         self.m_base = usize::from(addr) * 8;
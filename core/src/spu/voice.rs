@@ -1,5 +1,6 @@
 use crate::consts::NEG_ADPCM_TABLE;
 use crate::consts::POS_ADPCM_TABLE;
+use crate::savestate::impl_state;
 use crate::spu::SoundRam;
 use crate::spu::Sweep;
 use crate::spu::Volume;
@@ -43,6 +44,26 @@ pub struct Voice {
     pub reached_loop_end: bool,
 }
 
+impl_state!(Voice {
+    volume,
+    sample_rate,
+    start_address,
+    repeat_address,
+    current_address,
+    pitch_modulation_enabled,
+    noise_enabled,
+    reverb_enabled,
+    envelope,
+    decode_buffer,
+    current_buffer_idx,
+    adpcm_old_sample,
+    adpcm_older_sample,
+    pitch_counter,
+    samples_history,
+    ignore_loop_address,
+    reached_loop_end,
+});
+
 impl Voice {
     pub fn set_adsr<const HIGH: usize>(&mut self, val: u16) {
         write_half::<HIGH>(&mut self.envelope.register.0, val);
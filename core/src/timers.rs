@@ -2,7 +2,7 @@ use derive_more::Index;
 use derive_more::IndexMut;
 
 use crate::System;
-use crate::consts::LINE_DURATION;
+use crate::savestate::impl_state;
 use crate::sched::Event;
 use crate::sched::TimerInterrupt;
 
@@ -19,6 +19,13 @@ pub struct Timers {
     hblanks: u32,
 }
 
+impl_state!(Timers {
+    counters,
+    in_vsync,
+    in_hsync,
+    hblanks,
+});
+
 impl Timers {
     fn clock_source(&self, which: usize) -> Clock {
         let source_raw = self[which].mode.clock_src();
@@ -40,8 +47,8 @@ impl Timers {
         match system.timers.clock_source(which) {
             Clock::Cpu => ticks,
             Clock::CpuDiv8 => ticks * 8,
-            Clock::Dot => ticks * u64::from(system.gpu.get_dot_clock_divider()),
-            Clock::HBlank => ticks * LINE_DURATION,
+            Clock::Dot => system.gpu.dots_to_cycles(ticks),
+            Clock::HBlank => ticks * system.gpu.video_timing().line_cycles,
         }
     }
 
@@ -117,7 +124,7 @@ impl Timers {
         let delta = match system.timers.clock_source(which) {
             Clock::Cpu => clock_delta,
             Clock::CpuDiv8 => clock_delta / 8,
-            Clock::Dot => clock_delta / u32::from(system.gpu.get_dot_clock_divider()),
+            Clock::Dot => system.gpu.cycles_to_dots(clock_delta.into()) as u32,
             Clock::HBlank => {
                 let h = system.timers.hblanks;
                 system.timers.hblanks = 0;
@@ -327,6 +334,13 @@ pub struct Timer {
     last_read: u64,
 }
 
+impl_state!(Timer {
+    counter,
+    mode.0,
+    target,
+    last_read,
+});
+
 impl Timer {
     const fn counter(&self) -> u16 {
         self.counter as u16
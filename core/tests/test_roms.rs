@@ -0,0 +1,336 @@
+//! Conformance harness for PS-EXE test programs.
+//!
+//! Programs are sideloaded, their TTY output is captured and the value they write to the
+//! pcsx-redux exit port (`0x1F80_2082`) decides pass or fail, 0 being a pass.
+//!
+//! External suites (amidog, ps1-tests, ...) are run by pointing `STARPSX_BIOS` at a BIOS
+//! image and `STARPSX_TEST_ROMS` at a directory of `.exe` files:
+//!
+//! ```sh
+//! STARPSX_BIOS=scph1001.bin STARPSX_TEST_ROMS=roms/ cargo test -p starpsx-core --test test_roms
+//! ```
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use starpsx_core::CpuTiming;
+use starpsx_core::Media;
+use starpsx_core::PSXBuilder;
+
+/// Frames to run before a program is considered hung
+const FRAME_LIMIT: u32 = 60 * 60;
+
+#[derive(Debug)]
+enum Outcome {
+    Exited(u32),
+    Timeout,
+}
+
+struct TestRun {
+    outcome: Outcome,
+    tty: String,
+}
+
+impl TestRun {
+    const fn passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Exited(0))
+    }
+}
+
+fn run_exe(bios: Box<[u8; 0x80000]>, exe: Vec<u8>) -> anyhow::Result<TestRun> {
+    let mut system = PSXBuilder::new(bios)
+        .with_media(Media::Executable(exe))
+        .with_tty_log()
+        .build()?;
+
+    let mut outcome = Outcome::Timeout;
+    for _ in 0..FRAME_LIMIT {
+        system.run_frame(false);
+
+        if let Some(code) = system.exit_code() {
+            outcome = Outcome::Exited(code);
+            break;
+        }
+    }
+
+    let tty = system.tty_log().unwrap_or_default().to_owned();
+    Ok(TestRun { outcome, tty })
+}
+
+fn load_bios(path: &Path) -> Box<[u8; 0x80000]> {
+    std::fs::read(path)
+        .expect("read bios")
+        .try_into()
+        .expect("bios is 512KiB")
+}
+
+#[test]
+fn external_test_roms() {
+    let (Some(bios), Some(roms)) = (
+        std::env::var_os("STARPSX_BIOS"),
+        std::env::var_os("STARPSX_TEST_ROMS"),
+    ) else {
+        eprintln!("STARPSX_BIOS or STARPSX_TEST_ROMS not set, skipping");
+        return;
+    };
+
+    let bios = load_bios(Path::new(&bios));
+
+    let mut exes: Vec<PathBuf> = std::fs::read_dir(roms)
+        .expect("read test rom directory")
+        .map(|entry| entry.expect("dir entry").path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("exe"))
+        })
+        .collect();
+    exes.sort();
+
+    let mut failures = Vec::new();
+    for path in &exes {
+        let exe = std::fs::read(path).expect("read test rom");
+        let run = run_exe(bios.clone(), exe).expect("build system");
+
+        let name = path.file_name().expect("file name").to_string_lossy();
+        if run.passed() {
+            eprintln!("PASS {name}");
+        } else {
+            eprintln!("FAIL {name} ({:?})\n{}", run.outcome, run.tty);
+            failures.push(name.into_owned());
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}/{} test roms failed: {failures:?}",
+        failures.len(),
+        exes.len()
+    );
+}
+
+// Minimal MIPS encoder for the self contained programs below
+mod asm {
+    pub const ZERO: u32 = 0;
+    pub const A0: u32 = 4;
+    pub const T0: u32 = 8;
+    pub const T1: u32 = 9;
+    pub const RA: u32 = 31;
+
+    pub const NOP: u32 = 0;
+
+    pub const fn lui(rt: u32, imm: u16) -> u32 {
+        0x3C00_0000 | rt << 16 | imm as u32
+    }
+
+    pub const fn ori(rt: u32, rs: u32, imm: u16) -> u32 {
+        0x3400_0000 | rs << 21 | rt << 16 | imm as u32
+    }
+
+    pub const fn addiu(rt: u32, rs: u32, imm: u16) -> u32 {
+        0x2400_0000 | rs << 21 | rt << 16 | imm as u32
+    }
+
+    pub const fn sw(rt: u32, offset: u16, rs: u32) -> u32 {
+        0xAC00_0000 | rs << 21 | rt << 16 | offset as u32
+    }
+
+    pub const fn sb(rt: u32, offset: u16, rs: u32) -> u32 {
+        0xA000_0000 | rs << 21 | rt << 16 | offset as u32
+    }
+
+    pub const fn lw(rt: u32, offset: u16, rs: u32) -> u32 {
+        0x8C00_0000 | rs << 21 | rt << 16 | offset as u32
+    }
+
+    pub const fn srl(rd: u32, rt: u32, sa: u32) -> u32 {
+        rt << 16 | rd << 11 | sa << 6 | 0x02
+    }
+
+    pub const fn andi(rt: u32, rs: u32, imm: u16) -> u32 {
+        0x3000_0000 | rs << 21 | rt << 16 | imm as u32
+    }
+
+    /// Branch by a signed number of instructions, relative to the delay slot
+    pub const fn beq(rs: u32, rt: u32, offset: i16) -> u32 {
+        0x1000_0000 | rs << 21 | rt << 16 | offset as u16 as u32
+    }
+
+    /// Branch by a signed number of instructions, relative to the delay slot
+    pub const fn bne(rs: u32, rt: u32, offset: i16) -> u32 {
+        0x1400_0000 | rs << 21 | rt << 16 | offset as u16 as u32
+    }
+
+    pub const fn div(rs: u32, rt: u32) -> u32 {
+        rs << 21 | rt << 16 | 0x1A
+    }
+
+    pub const fn mflo(rd: u32) -> u32 {
+        rd << 11 | 0x12
+    }
+
+    pub const fn jal(target: u32) -> u32 {
+        0x0C00_0000 | (target >> 2) & 0x03FF_FFFF
+    }
+
+    pub const fn jr(rs: u32) -> u32 {
+        rs << 21 | 0x08
+    }
+}
+
+/// A BIOS that jumps straight to the shell entry point where executables get sideloaded
+fn stub_bios() -> Box<[u8; 0x80000]> {
+    use asm::*;
+
+    let code = [lui(T0, 0x8003), jr(T0), NOP];
+
+    let mut bios = vec![0u8; 0x80000];
+    for (i, ins) in code.iter().enumerate() {
+        bios[i * 4..i * 4 + 4].copy_from_slice(&ins.to_le_bytes());
+    }
+    bios.try_into().expect("bios is 512KiB")
+}
+
+const LOAD_ADDR: u32 = 0x8001_0000;
+
+/// Builds a PS-EXE that prints `message` through the A0 putchar call and exits with `code`
+fn stub_exe(message: &str, code: u8) -> Vec<u8> {
+    use asm::*;
+
+    // Stand in for the kernel A0 table handler with a plain return
+    let mut program = vec![
+        lui(T0, (jr(RA) >> 16) as u16),
+        ori(T0, T0, jr(RA) as u16),
+        sw(T0, 0xA0, ZERO),
+        sw(ZERO, 0xA4, ZERO),
+    ];
+
+    for byte in message.bytes() {
+        program.extend([
+            addiu(A0, ZERO, byte.into()),
+            addiu(T1, ZERO, 0x3C),
+            jal(0xA0),
+            NOP,
+        ]);
+    }
+
+    program.extend(exit_with(code));
+    build_exe(&program)
+}
+
+/// Writes `code` to the exit port and spins
+fn exit_with(code: u8) -> [u32; 5] {
+    use asm::*;
+
+    [
+        lui(T0, 0x1F80),
+        addiu(T1, ZERO, code.into()),
+        sb(T1, 0x2082, T0),
+        beq(ZERO, ZERO, -1), // spin
+        NOP,
+    ]
+}
+
+fn build_exe(program: &[u32]) -> Vec<u8> {
+    let text: Vec<u8> = program.iter().flat_map(|ins| ins.to_le_bytes()).collect();
+
+    let mut exe = vec![0u8; 2048];
+    exe[..8].copy_from_slice(b"PS-X EXE");
+    exe[0x10..0x14].copy_from_slice(&LOAD_ADDR.to_le_bytes()); // pc
+    exe[0x18..0x1C].copy_from_slice(&LOAD_ADDR.to_le_bytes()); // load address
+    exe[0x1C..0x20].copy_from_slice(&(text.len() as u32).to_le_bytes());
+    exe[0x30..0x34].copy_from_slice(&0x801F_FF00u32.to_le_bytes()); // stack
+    exe.extend(text);
+    exe
+}
+
+#[test]
+fn harness_reports_pass() {
+    let run = run_exe(stub_bios(), stub_exe("all tests passed\n", 0)).expect("build system");
+
+    assert!(run.passed(), "{:?}", run.outcome);
+    assert_eq!(run.tty, "all tests passed\n");
+}
+
+#[test]
+fn harness_reports_failure() {
+    let run = run_exe(stub_bios(), stub_exe("1 test failed\n", 3)).expect("build system");
+
+    assert!(!run.passed());
+    assert!(matches!(run.outcome, Outcome::Exited(3)));
+    assert_eq!(run.tty, "1 test failed\n");
+}
+
+#[test]
+fn cyclic_linked_list_dma_terminates() {
+    use asm::*;
+
+    // Two ordering table nodes pointing at each other, then a GPU linked list transfer
+    // that is polled until the busy bit clears
+    let mut program = vec![
+        lui(T0, 0x8010),
+        lui(T1, 0x0010),
+        ori(T1, T1, 0x0010),
+        sw(T1, 0x0000, T0), // 0x100000 -> 0x100010
+        lui(T1, 0x0010),
+        sw(T1, 0x0010, T0), // 0x100010 -> 0x100000
+        lui(T0, 0x1F80),
+        lui(T1, 0x0765),
+        ori(T1, T1, 0x4B21),
+        sw(T1, 0x10F0, T0), // enable the GPU channel in DPCR
+        lui(T1, 0x0010),
+        sw(T1, 0x10A0, T0), // MADR
+        lui(T1, 0x0100),
+        ori(T1, T1, 0x0401),
+        sw(T1, 0x10A8, T0), // CHCR: start, linked list, from RAM
+        lw(T1, 0x10A8, T0),
+        NOP,
+        srl(T1, T1, 24),
+        andi(T1, T1, 1),
+        bne(T1, ZERO, -5),
+        NOP,
+    ];
+    program.extend(exit_with(0));
+
+    let run = run_exe(stub_bios(), build_exe(&program)).expect("build system");
+    assert!(run.passed(), "{:?}", run.outcome);
+}
+
+#[test]
+fn accurate_timing_charges_division_stalls() {
+    use asm::*;
+
+    // Divide in a tight loop and read every quotient back, waiting on the divider each time
+    let mut program = vec![
+        addiu(T0, ZERO, 100),
+        lui(T1, 0x0001),
+        div(T0, T1),
+        mflo(A0),
+        addiu(T1, T1, 0xFFFF),
+        bne(T1, ZERO, -4),
+        NOP,
+    ];
+    program.extend(exit_with(0));
+    let exe = build_exe(&program);
+
+    let frames_to_exit = |timing| {
+        let mut system = PSXBuilder::new(stub_bios())
+            .with_media(Media::Executable(exe.clone()))
+            .with_cpu_timing(timing)
+            .build()
+            .expect("build system");
+
+        (1..=FRAME_LIMIT)
+            .find(|_| {
+                system.run_frame(false);
+                system.exit_code().is_some()
+            })
+            .expect("program exits")
+    };
+
+    let fast = frames_to_exit(CpuTiming::Fast);
+    let accurate = frames_to_exit(CpuTiming::Accurate);
+    assert!(
+        accurate > fast,
+        "accurate took {accurate} frames, fast took {fast}"
+    );
+}
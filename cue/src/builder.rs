@@ -1,70 +1,213 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use super::CueSheet;
+use super::CueTrack;
 use super::File;
+use super::FileType;
 use super::Path;
+use super::PathBuf;
 use super::Track;
+use super::TrackIndex;
 use crate::TrackType;
+use crate::source::DiscSource;
+use crate::source::FileSource;
+use crate::source::ImageFile;
+use crate::subchannel::Subchannel;
 
 pub const SECTOR_SIZE: usize = 0x930;
-const SEC_2: usize = SECTOR_SIZE * 75 * 2;
+pub const SEC_2: usize = SECTOR_SIZE * 75 * 2;
 
 pub struct CueBuilder<'a> {
     parent_dir: &'a Path,
-    current: usize,
-    sectors: Vec<u8>,
+    source: FileSource,
     tracks: Vec<Track>,
+    /// Silence owed after the current track, added when the next one starts
+    postgap: usize,
+    /// File of the sheet that is read from somewhere other than its path
+    replaced: Option<(PathBuf, Arc<dyn ImageFile>)>,
 }
 
 impl<'a> CueBuilder<'a> {
-    pub const fn new(parent_dir: &'a Path) -> Self {
+    pub fn new(parent_dir: &'a Path) -> Self {
         Self {
             parent_dir,
-            current: 0,
-            sectors: Vec::new(),
+            source: FileSource::default(),
             tracks: Vec::new(),
+            postgap: 0,
+            replaced: None,
         }
     }
 
+    /// Read the sheet's file `path` out of `file`, for images stored in another format
+    pub fn with_file(mut self, path: PathBuf, file: Arc<dyn ImageFile>) -> Self {
+        self.replaced = Some((path, file));
+        self
+    }
+
     pub fn build_disk(mut self, cue_sheet: CueSheet) -> anyhow::Result<Disc> {
-        self.current += SEC_2;
-        self.sectors.extend_from_slice(&vec![0; SEC_2]);
+        self.source.push_silence(SEC_2 / SECTOR_SIZE);
 
         for file in cue_sheet.files {
-            self.insert_file(file)?;
+            self.insert_file(&file)?;
         }
+        self.source.push_silence(self.postgap);
 
         Ok(Disc {
-            sectors: self.sectors.into_boxed_slice(),
+            source: Box::new(self.source),
             tracks: self.tracks.into_boxed_slice(),
+            subchannel: Subchannel::default(),
         })
     }
 
-    fn insert_file(&mut self, mut file: File) -> anyhow::Result<()> {
-        let mut data = std::fs::read(self.parent_dir.join(&file.path))?;
+    fn insert_file(&mut self, file: &File) -> anyhow::Result<()> {
+        let data: Arc<dyn ImageFile> = match &self.replaced {
+            Some((path, data)) if *path == file.path => Arc::clone(data),
+            _ => Arc::new(std::fs::File::open(self.parent_dir.join(&file.path))?),
+        };
 
-        let first_index = &mut file.tracks[0].indexes[0];
-        if first_index.id == 1 && first_index.lba == SEC_2 {
-            data = data.split_off(SEC_2);
-            first_index.lba = 0;
-        }
+        let (mut offset, len) = match file.format {
+            FileType::Wave => wave_samples(data.as_ref())?,
+            FileType::Binary | FileType::Motorola => (0, data.size()?),
+        };
+        let end = offset + len;
+
+        for (i, track) in file.tracks.iter().enumerate() {
+            // The file is split between tracks at their first index, sectors before the first
+            // one still belong to it
+            let first = match i {
+                0 => 0,
+                _ => track.indexes.first().map_or(0, |index| index.lba),
+            };
+            let size = track.track_type.sector_size() as u64;
+            let bytes = file
+                .tracks
+                .get(i + 1)
+                .and_then(|next| next.indexes.first())
+                .map_or(u64::MAX, |next| {
+                    next.lba.saturating_sub(first) as u64 * size
+                })
+                .min(end - offset);
+
+            let lead_in = self.tracks.is_empty();
+            if self.tracks.last().is_none_or(|last| last.id != track.id) {
+                self.start_track(track);
+            }
 
-        for mut track in file.tracks {
-            for index in &mut track.indexes {
-                index.lba += self.current;
+            // The first track's pregap is the 2 seconds every disc starts with, which are
+            // already there
+            let skipped = if lead_in {
+                track
+                    .indexes
+                    .iter()
+                    .find(|index| index.id == 1)
+                    .map_or(0, |index| index.lba - first)
+            } else {
+                0
+            };
+
+            let start = self.source.sector_count();
+            let current = self.tracks.last_mut().expect("track was started");
+            for index in &track.indexes {
+                if let Some(sector) = index.lba.checked_sub(first + skipped) {
+                    current.indexes.push(TrackIndex {
+                        id: index.id,
+                        lba: (start + sector) * SECTOR_SIZE,
+                    });
+                }
             }
-            self.tracks.push(track);
+
+            let skipped_bytes = (skipped as u64 * size).min(bytes);
+            self.source.push_file(
+                Arc::clone(&data),
+                offset + skipped_bytes,
+                bytes - skipped_bytes,
+                track.track_type,
+                file.format == FileType::Motorola,
+            );
+            offset += bytes;
         }
 
-        self.current += data.len();
-        self.sectors.extend(data);
         Ok(())
     }
+
+    /// Lay out the silence between the previous track and this one
+    fn start_track(&mut self, track: &CueTrack) {
+        self.source.push_silence(self.postgap);
+        self.postgap = track.postgap;
+
+        let mut indexes = Vec::new();
+        if track.pregap > 0 && !self.tracks.is_empty() {
+            if !track.indexes.iter().any(|index| index.id == 0) {
+                indexes.push(TrackIndex {
+                    id: 0,
+                    lba: self.source.sector_count() * SECTOR_SIZE,
+                });
+            }
+            self.source.push_silence(track.pregap);
+        }
+
+        self.tracks.push(Track {
+            id: track.id,
+            indexes,
+            track_type: track.track_type,
+        });
+    }
+}
+
+/// Offset and length of the samples in a WAVE file, which have to be CD audio already
+fn wave_samples(file: &dyn ImageFile) -> anyhow::Result<(u64, u64)> {
+    let mut header = [0u8; 12];
+    file.read_at(0, &mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        anyhow::bail!("Not a WAVE file.");
+    }
+
+    let file_len = file.size()?;
+    let mut pos = 12;
+
+    loop {
+        let mut chunk = [0u8; 8];
+        if file.read_at(pos, &mut chunk).is_err() {
+            anyhow::bail!("WAVE file has no data chunk.");
+        }
+        let len = u64::from(u32::from_le_bytes(chunk[4..].try_into().expect("4 bytes")));
+
+        match &chunk[..4] {
+            b"fmt " => {
+                let mut format = [0u8; 16];
+                file.read_at(pos + 8, &mut format)?;
+
+                let field = |i: usize| u16::from_le_bytes([format[i], format[i + 1]]);
+                let rate = u32::from_le_bytes(format[4..8].try_into().expect("4 bytes"));
+                if (field(0), field(2), rate, field(14)) != (1, 2, 44100, 16) {
+                    anyhow::bail!("WAVE file is not 16 bit stereo 44.1 kHz PCM.");
+                }
+            }
+
+            // Streamed files can leave the length unset
+            b"data" => return Ok((pos + 8, len.min(file_len - pos - 8))),
+
+            _ => (),
+        }
+
+        pos += 8 + len.next_multiple_of(2);
+    }
 }
 
 pub struct Disc {
-    pub sectors: Box<[u8]>,
+    pub source: Box<dyn DiscSource>,
     pub tracks: Box<[Track]>,
+    /// Subchannel Q sectors that differ from what the track layout gives
+    pub subchannel: Subchannel,
+}
+
+impl Disc {
+    /// Pick up the SBI or LSD file dumped next to the image
+    pub(crate) fn with_subchannel_beside(mut self, image: &Path) -> anyhow::Result<Self> {
+        self.subchannel = Subchannel::load_beside(image)?;
+        Ok(self)
+    }
 }
 
 impl Debug for Disc {
@@ -85,7 +228,7 @@ impl Debug for Disc {
                 .map_or(0, |idx| idx.lba)
         }
 
-        let total_sectors = self.sectors.len() / SECTOR_SIZE;
+        let total_sectors = self.source.sector_count();
 
         writeln!(f, "{:<9} {:<12} {:<10} Length", "#", "Mode", "Start")?;
         writeln!(f, "{}", "-".repeat(45))?;
@@ -93,6 +236,10 @@ impl Debug for Disc {
         for (i, track) in self.tracks.iter().enumerate() {
             let mode = match track.track_type {
                 TrackType::Audio => "Audio",
+                TrackType::Mode1_2048 => "Mode1/2048",
+                TrackType::Mode1_2352 => "Mode1/2352",
+                TrackType::Mode2_2048 => "Mode2/2048",
+                TrackType::Mode2_2336 => "Mode2/2336",
                 TrackType::Mode2_2352 => "Mode2/2352",
             };
 
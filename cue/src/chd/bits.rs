@@ -0,0 +1,262 @@
+/// MSB first bit reader, reading past the end of the data gives zeroes
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Look at the next `count` (up to 32) bits without consuming them
+    pub fn peek(&self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+
+        let byte = self.pos / 8;
+        let mut window = [0u8; 8];
+        if let Some(rest) = self.data.get(byte..) {
+            let len = rest.len().min(8);
+            window[..len].copy_from_slice(&rest[..len]);
+        }
+
+        let bits = u64::from_be_bytes(window) << (self.pos % 8);
+        (bits >> (64 - count)) as u32
+    }
+
+    pub const fn skip(&mut self, count: u32) {
+        self.pos += count as usize;
+    }
+
+    pub fn read(&mut self, count: u32) -> u32 {
+        let value = self.peek(count);
+        self.skip(count);
+        value
+    }
+
+    pub fn read_signed(&mut self, count: u32) -> i32 {
+        if count == 0 {
+            return 0;
+        }
+        let shift = 32 - count;
+        ((self.read(count) << shift) as i32) >> shift
+    }
+
+    pub fn read_u64(&mut self, count: u32) -> u64 {
+        if count > 32 {
+            let high = u64::from(self.read(count - 32));
+            return high << 32 | u64::from(self.read(32));
+        }
+        u64::from(self.read(count))
+    }
+
+    /// Count zero bits up to the next set bit, which is consumed too
+    pub fn read_unary(&mut self) -> anyhow::Result<u32> {
+        let mut zeroes = 0;
+        loop {
+            if self.overrun() {
+                anyhow::bail!("unary code runs past the end of the data");
+            }
+
+            let leading = self.peek(32).leading_zeros();
+            if leading < 32 {
+                self.skip(leading + 1);
+                return Ok(zeroes + leading);
+            }
+
+            zeroes += 32;
+            self.skip(32);
+        }
+    }
+
+    pub const fn align(&mut self) {
+        self.pos = self.pos.next_multiple_of(8);
+    }
+
+    /// Bytes consumed so far, counting a partly read byte
+    pub const fn byte_pos(&self) -> usize {
+        self.pos.div_ceil(8)
+    }
+
+    pub const fn overrun(&self) -> bool {
+        self.pos > self.data.len() * 8
+    }
+}
+
+/// Canonical huffman decoder for the small code sets CHD uses, decoded with a single table lookup
+pub struct Huffman {
+    max_bits: u32,
+    /// Symbol in the upper bits, code length in the lower 5
+    lookup: Vec<u16>,
+}
+
+impl Huffman {
+    /// Read code lengths stored in CHD's run length encoded form, where a length of 1
+    /// escapes either a literal 1 or a run of the following length
+    pub fn import_rle(
+        reader: &mut BitReader,
+        num_codes: usize,
+        max_bits: u32,
+    ) -> anyhow::Result<Self> {
+        let field_bits = match max_bits {
+            16.. => 5,
+            8..16 => 4,
+            _ => 3,
+        };
+
+        let mut lengths = Vec::with_capacity(num_codes);
+        while lengths.len() < num_codes {
+            let length = reader.read(field_bits);
+            if length != 1 {
+                lengths.push(length);
+                continue;
+            }
+
+            let length = reader.read(field_bits);
+            if length == 1 {
+                lengths.push(length);
+                continue;
+            }
+
+            let repeat = reader.read(field_bits) + 3;
+            lengths.extend(std::iter::repeat_n(length, repeat as usize));
+        }
+
+        if lengths.len() != num_codes {
+            anyhow::bail!(
+                "huffman tree has {} codes, expected {num_codes}",
+                lengths.len()
+            );
+        }
+
+        Self::from_lengths(&lengths, max_bits)
+    }
+
+    fn from_lengths(lengths: &[u32], max_bits: u32) -> anyhow::Result<Self> {
+        // Codes are handed out starting from the longest lengths
+        let mut first_code = [0u32; 33];
+        for &length in lengths {
+            if length > max_bits {
+                anyhow::bail!("huffman code length {length} is over {max_bits}");
+            }
+            first_code[length as usize] += 1;
+        }
+
+        let mut start = 0;
+        for length in (1..=32).rev() {
+            // Codes of one length pair up into the next shorter one, and only two fit in 1 bit
+            let count = start + first_code[length];
+            if (length != 1 && !count.is_multiple_of(2)) || (length == 1 && count > 2) {
+                anyhow::bail!("huffman code lengths are inconsistent");
+            }
+            first_code[length] = start;
+            start = count >> 1;
+        }
+
+        let mut lookup = vec![0u16; 1 << max_bits];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+
+            let code = first_code[length as usize];
+            first_code[length as usize] += 1;
+
+            let shift = max_bits - length;
+            let entry = (symbol as u16) << 5 | length as u16;
+            let start = (code << shift) as usize;
+            lookup[start..start + (1 << shift)].fill(entry);
+        }
+
+        Ok(Self { max_bits, lookup })
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> u32 {
+        let entry = self.lookup[reader.peek(self.max_bits) as usize];
+        reader.skip(u32::from(entry & 0x1F));
+        u32::from(entry >> 5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_msb_first() {
+        let mut reader = BitReader::new(&[0b1011_0010, 0xFF, 0x01]);
+        assert_eq!(reader.peek(3), 0b101);
+        assert_eq!(reader.read(3), 0b101);
+        assert_eq!(reader.read_signed(3), -4);
+        assert_eq!(reader.read(10), 0b10_1111_1111);
+        assert_eq!(reader.byte_pos(), 2);
+
+        reader.align();
+        assert_eq!(reader.read_u64(8), 1);
+        assert!(!reader.overrun());
+    }
+
+    #[test]
+    fn zeroes_past_the_end() {
+        let mut reader = BitReader::new(&[0xFF]);
+        assert_eq!(reader.read(12), 0xFF0);
+        assert!(reader.overrun());
+        assert!(reader.read_unary().is_err());
+    }
+
+    #[test]
+    fn unary_codes() -> anyhow::Result<()> {
+        // 40 zeroes before the first set bit, then two more codes
+        let mut reader = BitReader::new(&[0, 0, 0, 0, 0, 0b1010_0000]);
+        assert_eq!(reader.read_unary()?, 40);
+        assert_eq!(reader.read_unary()?, 1);
+        assert_eq!(reader.read_signed(0), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn huffman_literal_lengths() -> anyhow::Result<()> {
+        // Lengths 1, 2, 3, 3 in 4 bit fields, a length of 1 is escaped
+        let mut reader = BitReader::new(&[0x11, 0x23, 0x30]);
+        let huffman = Huffman::import_rle(&mut reader, 4, 8)?;
+        assert_eq!(reader.byte_pos(), 3);
+
+        // Longer codes come first: 2 is 000, 3 is 001, 1 is 01 and 0 is 1
+        let mut reader = BitReader::new(&[0b1010_0000, 0b1100_0000]);
+        let symbols: Vec<u32> = (0..5).map(|_| huffman.decode(&mut reader)).collect();
+        assert_eq!(symbols, [0, 1, 2, 3, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn huffman_run_of_lengths() -> anyhow::Result<()> {
+        // A run of four codes of length 2
+        let mut reader = BitReader::new(&[0x12, 0x10]);
+        let huffman = Huffman::import_rle(&mut reader, 4, 8)?;
+
+        let mut reader = BitReader::new(&[0b1110_0100]);
+        let symbols: Vec<u32> = (0..4).map(|_| huffman.decode(&mut reader)).collect();
+        assert_eq!(symbols, [3, 2, 1, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn huffman_bad_lengths() {
+        // Three codes of length 1
+        let mut reader = BitReader::new(&[0x11, 0x11, 0x11]);
+        assert!(Huffman::import_rle(&mut reader, 3, 8).is_err());
+
+        // A run that overshoots the number of codes
+        let mut reader = BitReader::new(&[0x12, 0x20]);
+        assert!(Huffman::import_rle(&mut reader, 3, 8).is_err());
+
+        // Code longer than the table
+        let mut reader = BitReader::new(&[0x90]);
+        assert!(Huffman::import_rle(&mut reader, 1, 8).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! FLAC frame decoder for CD audio hunks. CHD stores bare frames without the stream
+//! header, so every frame is expected to carry its own block size and sample format.
+
+use super::bits::BitReader;
+
+/// Decode 16 bit stereo frames into `dest` as big endian samples until it is full.
+/// Returns the number of bytes of `src` that were used.
+pub fn decode_cd_audio(src: &[u8], dest: &mut [u8]) -> anyhow::Result<usize> {
+    let mut reader = BitReader::new(src);
+    let mut channels = [Vec::new(), Vec::new()];
+    let mut pos = 0;
+
+    while pos < dest.len() {
+        let block_size = decode_frame(&mut reader, &mut channels)?;
+
+        let Some(out) = dest.get_mut(pos..pos + block_size * 4) else {
+            anyhow::bail!("flac frame overflows the output");
+        };
+
+        for (i, sample) in out.chunks_exact_mut(4).enumerate() {
+            sample[..2].copy_from_slice(&(channels[0][i] as i16).to_be_bytes());
+            sample[2..].copy_from_slice(&(channels[1][i] as i16).to_be_bytes());
+        }
+        pos += block_size * 4;
+    }
+
+    Ok(reader.byte_pos())
+}
+
+/// Read one frame into `channels`, returns its block size
+fn decode_frame(reader: &mut BitReader, channels: &mut [Vec<i32>; 2]) -> anyhow::Result<usize> {
+    if reader.read(14) != 0x3FFE {
+        anyhow::bail!("missing flac frame sync code");
+    }
+    reader.skip(2); // reserved, blocking strategy
+
+    let block_code = reader.read(4);
+    let rate_code = reader.read(4);
+    let assignment = reader.read(4);
+    let size_code = reader.read(3);
+    reader.skip(1);
+
+    // Frame or sample number, UTF-8 style variable length
+    let first = reader.read(8);
+    let extra = (first << 24).leading_ones().saturating_sub(1);
+    reader.skip(8 * extra);
+
+    let block_size = match block_code {
+        1 => 192,
+        2..=5 => 576 << (block_code - 2),
+        6 => reader.read(8) as usize + 1,
+        7 => reader.read(16) as usize + 1,
+        8..=15 => 256 << (block_code - 8),
+        _ => anyhow::bail!("reserved flac block size"),
+    };
+
+    match rate_code {
+        12 => reader.skip(8),
+        13 | 14 => reader.skip(16),
+        15 => anyhow::bail!("invalid flac sample rate"),
+        _ => (),
+    }
+
+    let bits_per_sample = match size_code {
+        0 | 4 => 16,
+        1 => 8,
+        2 => 12,
+        5 => 20,
+        6 => 24,
+        _ => anyhow::bail!("reserved flac sample size"),
+    };
+
+    reader.skip(8); // header crc
+
+    // Side channels carry one extra bit
+    let side_bits = match assignment {
+        1 => [bits_per_sample; 2],
+        8 | 10 => [bits_per_sample, bits_per_sample + 1],
+        9 => [bits_per_sample + 1, bits_per_sample],
+        _ => anyhow::bail!("cd audio needs stereo flac frames, got assignment {assignment}"),
+    };
+
+    for (channel, bits) in channels.iter_mut().zip(side_bits) {
+        channel.clear();
+        channel.resize(block_size, 0);
+        decode_subframe(reader, channel, bits)?;
+    }
+
+    let [left, right] = channels;
+    match assignment {
+        8 => {
+            for (l, s) in left.iter().zip(right.iter_mut()) {
+                *s = *l - *s;
+            }
+        }
+        9 => {
+            for (s, r) in left.iter_mut().zip(right.iter()) {
+                *s += *r;
+            }
+        }
+        10 => {
+            for (m, s) in left.iter_mut().zip(right.iter_mut()) {
+                let mid = (*m << 1) | (*s & 1);
+                *m = (mid + *s) >> 1;
+                *s = (mid - *s) >> 1;
+            }
+        }
+        _ => (),
+    }
+
+    reader.align();
+    reader.skip(16); // frame crc
+
+    if reader.overrun() {
+        anyhow::bail!("flac frame is truncated");
+    }
+
+    Ok(block_size)
+}
+
+fn decode_subframe(reader: &mut BitReader, out: &mut [i32], bits: u32) -> anyhow::Result<()> {
+    if reader.read(1) != 0 {
+        anyhow::bail!("invalid flac subframe padding");
+    }
+
+    let kind = reader.read(6);
+    let wasted = if reader.read(1) == 1 {
+        reader.read_unary()? + 1
+    } else {
+        0
+    };
+    let bits = bits - wasted;
+
+    match kind {
+        0 => out.fill(reader.read_signed(bits)),
+        1 => {
+            for sample in out.iter_mut() {
+                *sample = reader.read_signed(bits);
+            }
+        }
+        8..=12 => {
+            let order = (kind - 8) as usize;
+            warmup(reader, out, order, bits)?;
+            residual(reader, out, order)?;
+            fixed_prediction(out, order);
+        }
+        32..=63 => {
+            let order = (kind - 31) as usize;
+            warmup(reader, out, order, bits)?;
+
+            let precision = reader.read(4) + 1;
+            if precision == 16 {
+                anyhow::bail!("invalid flac lpc precision");
+            }
+            let shift = reader.read_signed(5);
+            if shift < 0 {
+                anyhow::bail!("negative flac lpc shift");
+            }
+
+            let coefs: Vec<i64> = (0..order)
+                .map(|_| i64::from(reader.read_signed(precision)))
+                .collect();
+
+            residual(reader, out, order)?;
+            for i in order..out.len() {
+                let prediction: i64 = coefs
+                    .iter()
+                    .zip(out[i - order..i].iter().rev())
+                    .map(|(c, s)| c * i64::from(*s))
+                    .sum();
+                out[i] += (prediction >> shift) as i32;
+            }
+        }
+        _ => anyhow::bail!("reserved flac subframe type {kind}"),
+    }
+
+    if wasted > 0 {
+        for sample in out.iter_mut() {
+            *sample <<= wasted;
+        }
+    }
+
+    Ok(())
+}
+
+fn warmup(reader: &mut BitReader, out: &mut [i32], order: usize, bits: u32) -> anyhow::Result<()> {
+    let Some(warmup) = out.get_mut(..order) else {
+        anyhow::bail!("flac predictor order is bigger than the block");
+    };
+    for sample in warmup {
+        *sample = reader.read_signed(bits);
+    }
+    Ok(())
+}
+
+/// Rice coded residuals, stored after the warmup samples
+fn residual(reader: &mut BitReader, out: &mut [i32], order: usize) -> anyhow::Result<()> {
+    let (param_bits, escape) = match reader.read(2) {
+        0 => (4, 0xF),
+        1 => (5, 0x1F),
+        _ => anyhow::bail!("reserved flac residual coding"),
+    };
+
+    let partition_order = reader.read(4);
+    let partition_len = out.len() >> partition_order;
+    if partition_len < order || partition_len << partition_order != out.len() {
+        anyhow::bail!("invalid flac residual partitioning");
+    }
+
+    let mut pos = order;
+    for partition in 0..1usize << partition_order {
+        let end = (partition + 1) * partition_len;
+        let param = reader.read(param_bits);
+
+        if param == escape {
+            let bits = reader.read(5);
+            for sample in &mut out[pos..end] {
+                *sample = reader.read_signed(bits);
+            }
+        } else {
+            for sample in &mut out[pos..end] {
+                let value = reader.read_unary()? << param | reader.read(param);
+                *sample = (value >> 1) as i32 ^ -((value & 1) as i32);
+            }
+        }
+
+        pos = end;
+    }
+
+    Ok(())
+}
+
+fn fixed_prediction(out: &mut [i32], order: usize) {
+    for i in order..out.len() {
+        let prediction = match order {
+            0 => 0,
+            1 => out[i - 1],
+            2 => 2 * out[i - 1] - out[i - 2],
+            3 => 3 * out[i - 1] - 3 * out[i - 2] + out[i - 3],
+            _ => 4 * out[i - 1] - 6 * out[i - 2] + 4 * out[i - 3] - out[i - 4],
+        };
+        out[i] += prediction;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Four 16 sample frames: independent constant and verbatim subframes, mid/side with
+    /// fixed predictors and wasted bits, left/side with LPC and an escaped partition, and
+    /// right/side
+    const FRAMES: [u8; 191] = [
+        0xFF, 0xF8, 0x69, 0x18, 0x00, 0x0F, 0x92, 0x00, 0xFE, 0xD4, 0x02, 0xFE, 0x0C, 0xFE, 0x0F,
+        0xFE, 0x18, 0xFE, 0x27, 0xFE, 0x3C, 0xFE, 0x57, 0xFE, 0x78, 0xFE, 0x9F, 0xFE, 0xCC, 0xFE,
+        0xFF, 0xFF, 0x38, 0xFF, 0x77, 0xFF, 0xBC, 0x00, 0x07, 0x00, 0x58, 0x00, 0xAF, 0x8C, 0xEE,
+        0xFF, 0xF8, 0x69, 0xA8, 0x01, 0x0F, 0x6D, 0x14, 0x00, 0x00, 0x00, 0x15, 0x04, 0x02, 0x10,
+        0x84, 0x21, 0x00, 0x84, 0x21, 0x08, 0x42, 0x11, 0x38, 0x1F, 0x40, 0x04, 0x00, 0xC0, 0x18,
+        0x06, 0x03, 0x03, 0x06, 0x18, 0xCD, 0xAA, 0x94, 0x50, 0xA0, 0x97, 0x78, 0xFF, 0xF8, 0x69,
+        0x88, 0x02, 0x0F, 0x11, 0x42, 0x13, 0x88, 0x12, 0x63, 0xB5, 0x3C, 0xD6, 0x33, 0x02, 0x63,
+        0x44, 0x9E, 0x69, 0x2F, 0x10, 0x61, 0x8D, 0xE8, 0x84, 0x80, 0x00, 0x3C, 0xB0, 0x00, 0x00,
+        0x2A, 0x5F, 0x01, 0x80, 0xDD, 0x3A, 0x81, 0xC7, 0xCB, 0xFF, 0xD8, 0x00, 0x00, 0x00, 0x02,
+        0x8F, 0xFF, 0x13, 0xFF, 0xEC, 0x00, 0x05, 0x00, 0x03, 0xCF, 0xFF, 0x60, 0x6A, 0xC4, 0xFF,
+        0xF8, 0x69, 0x98, 0x03, 0x0F, 0xA6, 0x00, 0x00, 0x06, 0x0B, 0x00, 0x00, 0x7F, 0xCE, 0xFF,
+        0xA0, 0x00, 0x00, 0x01, 0x00, 0x08, 0x00, 0x40, 0x02, 0x00, 0x10, 0x00, 0x80, 0x04, 0x00,
+        0x20, 0x01, 0x00, 0x08, 0x00, 0x40, 0x02, 0x00, 0x10, 0x26, 0xD0,
+    ];
+
+    /// Left and right sample `n` of every frame in [`FRAMES`]
+    fn expected_samples() -> Vec<(i32, i32)> {
+        let frames: [fn(i32) -> (i32, i32); 4] = [
+            |n| (-300, 3 * n * n - 500),
+            |n| (1000 + 40 * n, -1000 + 2 * n * n),
+            |n| {
+                let left = 5000 - 300 * n + 7 * n * n;
+                (left, left + ((n * 5) % 7 - 3) * 20)
+            },
+            |n| (-100 * n + n * n * n + 12, -100 * n + n * n * n),
+        ];
+
+        frames.iter().flat_map(|frame| (0..16).map(frame)).collect()
+    }
+
+    fn to_be_bytes(samples: &[(i32, i32)]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|&(l, r)| [(l as i16).to_be_bytes(), (r as i16).to_be_bytes()])
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn known_frames() -> anyhow::Result<()> {
+        let mut src = FRAMES.to_vec();
+        // The subcode stream follows in a CHD hunk
+        src.extend_from_slice(&[0xAA; 8]);
+
+        let mut dest = [0u8; 64 * 4];
+        let used = decode_cd_audio(&src, &mut dest)?;
+
+        assert_eq!(used, FRAMES.len());
+        assert_eq!(dest[..], to_be_bytes(&expected_samples())[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn frames_past_the_output() {
+        let mut dest = [0u8; 40 * 4];
+        assert!(decode_cd_audio(&FRAMES, &mut dest).is_err());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let mut dest = [0u8; 64 * 4];
+        assert!(decode_cd_audio(&FRAMES[..120], &mut dest).is_err());
+    }
+
+    #[test]
+    fn missing_sync_code() {
+        let mut src = FRAMES;
+        src[1] = 0xF0;
+        assert!(decode_cd_audio(&src, &mut [0u8; 16 * 4]).is_err());
+    }
+}
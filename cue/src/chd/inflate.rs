@@ -0,0 +1,379 @@
+//! Raw deflate (RFC 1951) decoder, CHD streams have no zlib header or checksum
+
+/// Inflate `src` into `dest`, which has to be filled exactly
+pub fn inflate(src: &[u8], dest: &mut [u8]) -> anyhow::Result<()> {
+    let mut inflater = Inflater {
+        bits: LsbReader::new(src),
+        dest,
+        pos: 0,
+    };
+
+    loop {
+        let last = inflater.bits.read(1)? == 1;
+        match inflater.bits.read(2)? {
+            0 => inflater.stored()?,
+            1 => inflater.codes(&FIXED.0, &FIXED.1)?,
+            2 => {
+                let (lengths, distances) = inflater.dynamic_tables()?;
+                inflater.codes(&lengths, &distances)?;
+            }
+            _ => anyhow::bail!("invalid deflate block type"),
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    if inflater.pos != inflater.dest.len() {
+        anyhow::bail!(
+            "deflate stream ended after {} of {} bytes",
+            inflater.pos,
+            inflater.dest.len()
+        );
+    }
+
+    Ok(())
+}
+
+struct LsbReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> LsbReader<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> anyhow::Result<u32> {
+        while self.count < count {
+            let Some(&byte) = self.data.get(self.pos) else {
+                anyhow::bail!("deflate stream is truncated");
+            };
+            self.buffer |= u32::from(byte) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1u64 << count) - 1) as u32;
+        self.buffer = self.buffer.checked_shr(count).unwrap_or(0);
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Drop the rest of the current byte, returns the following bytes
+    fn align(&mut self) -> &'a [u8] {
+        self.buffer = 0;
+        self.count = 0;
+        &self.data[self.pos..]
+    }
+}
+
+const MAX_BITS: usize = 15;
+
+/// Canonical huffman code, decoded a bit at a time by walking the code lengths
+struct Codes {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Codes {
+    fn new(lengths: &[u8]) -> anyhow::Result<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+
+        // Over-subscribed sets can't be decoded, incomplete ones are allowed
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                anyhow::bail!("deflate code lengths are over-subscribed");
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut LsbReader) -> anyhow::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        anyhow::bail!("invalid deflate huffman code")
+    }
+}
+
+static FIXED: std::sync::LazyLock<(Codes, Codes)> = std::sync::LazyLock::new(|| {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+
+    (
+        Codes::new(&lengths).expect("fixed literal codes"),
+        Codes::new(&[5; 30]).expect("fixed distance codes"),
+    )
+});
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order the code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct Inflater<'a, 'b> {
+    bits: LsbReader<'a>,
+    dest: &'b mut [u8],
+    pos: usize,
+}
+
+impl Inflater<'_, '_> {
+    fn stored(&mut self) -> anyhow::Result<()> {
+        let rest = self.bits.align();
+        let (Some(header), Some(data)) = (rest.get(..4), rest.get(4..)) else {
+            anyhow::bail!("stored deflate block is truncated");
+        };
+
+        let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+        let nlen = u16::from_le_bytes([header[2], header[3]]);
+        if len != usize::from(!nlen) {
+            anyhow::bail!("stored deflate block length is corrupt");
+        }
+
+        let (Some(data), Some(out)) =
+            (data.get(..len), self.dest.get_mut(self.pos..self.pos + len))
+        else {
+            anyhow::bail!("stored deflate block does not fit");
+        };
+
+        out.copy_from_slice(data);
+        self.pos += len;
+        self.bits.pos += 4 + len;
+        Ok(())
+    }
+
+    fn dynamic_tables(&mut self) -> anyhow::Result<(Codes, Codes)> {
+        let literals = self.bits.read(5)? as usize + 257;
+        let distances = self.bits.read(5)? as usize + 1;
+        let code_lengths = self.bits.read(4)? as usize + 4;
+
+        let mut lengths = [0u8; 19];
+        for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[index] = self.bits.read(3)? as u8;
+        }
+        let length_codes = Codes::new(&lengths)?;
+
+        let mut lengths = vec![0u8; literals + distances];
+        let mut index = 0;
+        while index < lengths.len() {
+            let symbol = length_codes.decode(&mut self.bits)?;
+            if symbol < 16 {
+                lengths[index] = symbol as u8;
+                index += 1;
+                continue;
+            }
+
+            let (value, repeat) = match symbol {
+                16 if index == 0 => anyhow::bail!("deflate length repeat with no previous length"),
+                16 => (lengths[index - 1], 3 + self.bits.read(2)?),
+                17 => (0, 3 + self.bits.read(3)?),
+                _ => (0, 11 + self.bits.read(7)?),
+            };
+
+            let Some(run) = lengths.get_mut(index..index + repeat as usize) else {
+                anyhow::bail!("deflate code lengths overflow");
+            };
+            run.fill(value);
+            index += repeat as usize;
+        }
+
+        if lengths[256] == 0 {
+            anyhow::bail!("deflate block has no end code");
+        }
+
+        Ok((
+            Codes::new(&lengths[..literals])?,
+            Codes::new(&lengths[literals..])?,
+        ))
+    }
+
+    fn codes(&mut self, lengths: &Codes, distances: &Codes) -> anyhow::Result<()> {
+        loop {
+            let symbol = lengths.decode(&mut self.bits)?;
+
+            match symbol {
+                0..256 => {
+                    let Some(byte) = self.dest.get_mut(self.pos) else {
+                        anyhow::bail!("deflate stream overflows the output");
+                    };
+                    *byte = symbol as u8;
+                    self.pos += 1;
+                }
+
+                256 => return Ok(()),
+
+                257..286 => {
+                    let symbol = usize::from(symbol - 257);
+                    let len = usize::from(LENGTH_BASE[symbol])
+                        + self.bits.read(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+
+                    let symbol = usize::from(distances.decode(&mut self.bits)?);
+                    if symbol >= DIST_BASE.len() {
+                        anyhow::bail!("invalid deflate distance code");
+                    }
+                    let dist = usize::from(DIST_BASE[symbol])
+                        + self.bits.read(u32::from(DIST_EXTRA[symbol]))? as usize;
+
+                    if dist > self.pos || self.pos + len > self.dest.len() {
+                        anyhow::bail!("deflate match is out of bounds");
+                    }
+
+                    // Matches can overlap themselves, so copy a byte at a time
+                    for _ in 0..len {
+                        self.dest[self.pos] = self.dest[self.pos - dist];
+                        self.pos += 1;
+                    }
+                }
+
+                _ => anyhow::bail!("invalid deflate length code"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the compressed vectors below decode to
+    fn sectors_text() -> Vec<u8> {
+        let mut text: Vec<u8> = (0..40)
+            .flat_map(|i| format!("Sector {} of track {}, ", i % 5, i % 3).into_bytes())
+            .collect();
+        text.resize(text.len() + 300, 0);
+        text
+    }
+
+    fn quick_fox() -> Vec<u8> {
+        b"The quick brown fox jumps over the lazy dog. ".repeat(3)
+    }
+
+    #[test]
+    fn stored_block() -> anyhow::Result<()> {
+        let data = b"stored bytes";
+        let mut src = vec![0x01, 12, 0, !12, 0xFF];
+        src.extend_from_slice(data);
+
+        let mut dest = [0u8; 12];
+        inflate(&src, &mut dest)?;
+        assert_eq!(&dest, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_codes() -> anyhow::Result<()> {
+        // zlib level 9 with Z_FIXED
+        let src = [
+            0x0B, 0xC9, 0x48, 0x55, 0x28, 0x2C, 0xCD, 0x4C, 0xCE, 0x56, 0x48, 0x2A, 0xCA, 0x2F,
+            0xCF, 0x53, 0x48, 0xCB, 0xAF, 0x50, 0xC8, 0x2A, 0xCD, 0x2D, 0x28, 0x56, 0xC8, 0x2F,
+            0x4B, 0x2D, 0x52, 0x28, 0x01, 0x4A, 0xE7, 0x24, 0x56, 0x55, 0x2A, 0xA4, 0xE4, 0xA7,
+            0xEB, 0x29, 0x84, 0xD0, 0x4C, 0x31, 0x00,
+        ];
+
+        let expected = quick_fox();
+        let mut dest = vec![0u8; expected.len()];
+        inflate(&src, &mut dest)?;
+        assert_eq!(dest, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dynamic_codes() -> anyhow::Result<()> {
+        // zlib level 9
+        let src = [
+            0xED, 0xD0, 0xBB, 0x09, 0xC0, 0x30, 0x14, 0x43, 0xD1, 0x8C, 0xA2, 0x01, 0x5C, 0xBC,
+            0xDF, 0x26, 0x9E, 0x20, 0x98, 0xA4, 0x49, 0x61, 0x30, 0xDE, 0x9F, 0x14, 0x01, 0xBB,
+            0x51, 0x91, 0x01, 0x9E, 0xCA, 0x0B, 0xA7, 0x51, 0xBD, 0xDA, 0xEC, 0x03, 0x82, 0x7E,
+            0x63, 0x8E, 0xB3, 0x3D, 0x90, 0x82, 0xFA, 0x45, 0xDD, 0x51, 0x57, 0xB4, 0x1D, 0x6D,
+            0x45, 0x67, 0x3C, 0x18, 0x17, 0xC6, 0x95, 0x71, 0x63, 0xDC, 0x19, 0x0F, 0xC6, 0x85,
+            0x71, 0x65, 0xDC, 0x18, 0x77, 0xC6, 0x83, 0xF1, 0xBC, 0x2E, 0xAF, 0x2B, 0x38, 0x72,
+            0xBF, 0xF7, 0x02,
+        ];
+
+        let expected = sectors_text();
+        let mut dest = vec![0u8; expected.len()];
+        inflate(&src, &mut dest)?;
+        assert_eq!(dest, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_has_to_be_filled() {
+        let src = [0x01, 3, 0, !3, 0xFF, b'a', b'b', b'c'];
+
+        let mut short = [0u8; 2];
+        assert!(inflate(&src, &mut short).is_err());
+
+        let mut long = [0u8; 4];
+        assert!(inflate(&src, &mut long).is_err());
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let src = [0x0B, 0xC9, 0x48, 0x55, 0x28];
+        let mut dest = vec![0u8; quick_fox().len()];
+        assert!(inflate(&src, &mut dest).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! Raw LZMA decoder. CHD doesn't store the stream properties, every hunk uses the
+//! encoder defaults (lc=3, lp=0, pb=2) and is decoded straight into the hunk buffer,
+//! so the dictionary never needs to be bigger than the output.
+
+const LC: u32 = 3;
+const LP: u32 = 0;
+const PB: u32 = 2;
+
+const NUM_STATES: usize = 12;
+const POS_STATES: usize = 1 << PB;
+const END_POS_MODEL: usize = 14;
+const FULL_DISTANCES: usize = 1 << (END_POS_MODEL / 2);
+const ALIGN_BITS: u32 = 4;
+
+/// Decode `src` until `dest` is full
+pub fn decompress(src: &[u8], dest: &mut [u8]) -> anyhow::Result<()> {
+    let mut rc = RangeDecoder::new(src)?;
+    let mut probs = Probabilities::default();
+    let mut match_len = LenDecoder::default();
+    let mut rep_len = LenDecoder::default();
+
+    let mut state = 0usize;
+    let mut reps = [0usize; 4];
+    let mut pos = 0usize;
+
+    while pos < dest.len() {
+        let pos_state = pos & (POS_STATES - 1);
+
+        if rc.bit(&mut probs.is_match[state][pos_state]) == 0 {
+            let prev = if pos > 0 { dest[pos - 1] } else { 0 };
+            let lit_state = ((pos & ((1 << LP) - 1)) << LC) + usize::from(prev >> (8 - LC));
+            let literal = &mut probs.literal[0x300 * lit_state..0x300 * (lit_state + 1)];
+
+            let mut symbol = 1usize;
+            if state >= 7 {
+                // After a match the literal is coded against the byte at the last distance
+                let mut match_byte = dest[pos - reps[0] - 1];
+                while symbol < 0x100 {
+                    let match_bit = usize::from(match_byte >> 7);
+                    match_byte <<= 1;
+                    let bit = rc.bit(&mut literal[((1 + match_bit) << 8) + symbol]);
+                    symbol = symbol << 1 | bit as usize;
+                    if match_bit != bit as usize {
+                        break;
+                    }
+                }
+            }
+
+            while symbol < 0x100 {
+                symbol = symbol << 1 | rc.bit(&mut literal[symbol]) as usize;
+            }
+
+            dest[pos] = symbol as u8;
+            pos += 1;
+
+            state = match state {
+                0..4 => 0,
+                4..10 => state - 3,
+                _ => state - 6,
+            };
+            continue;
+        }
+
+        let len = if rc.bit(&mut probs.is_rep[state]) == 0 {
+            reps[3] = reps[2];
+            reps[2] = reps[1];
+            reps[1] = reps[0];
+
+            let len = match_len.decode(&mut rc, pos_state);
+            state = if state < 7 { 7 } else { 10 };
+
+            let dist = decode_distance(&mut rc, &mut probs, len);
+            if dist == 0xFFFF_FFFF {
+                anyhow::bail!("lzma stream ended after {pos} of {} bytes", dest.len());
+            }
+            reps[0] = dist as usize;
+            len
+        } else {
+            if pos == 0 {
+                anyhow::bail!("lzma stream starts with a repeated match");
+            }
+
+            if rc.bit(&mut probs.is_rep_g0[state]) == 0 {
+                if rc.bit(&mut probs.is_rep0_long[state][pos_state]) == 0 {
+                    // Single byte from the last distance
+                    state = if state < 7 { 9 } else { 11 };
+                    dest[pos] = dest[pos - reps[0] - 1];
+                    pos += 1;
+                    continue;
+                }
+            } else {
+                let dist = if rc.bit(&mut probs.is_rep_g1[state]) == 0 {
+                    reps[1]
+                } else {
+                    let dist = if rc.bit(&mut probs.is_rep_g2[state]) == 0 {
+                        reps[2]
+                    } else {
+                        let dist = reps[3];
+                        reps[3] = reps[2];
+                        dist
+                    };
+                    reps[2] = reps[1];
+                    dist
+                };
+                reps[1] = reps[0];
+                reps[0] = dist;
+            }
+
+            state = if state < 7 { 8 } else { 11 };
+            rep_len.decode(&mut rc, pos_state)
+        };
+
+        if reps[0] >= pos {
+            anyhow::bail!("lzma match distance is out of bounds");
+        }
+
+        let end = (pos + len as usize + 2).min(dest.len());
+        while pos < end {
+            dest[pos] = dest[pos - reps[0] - 1];
+            pos += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn decode_distance(rc: &mut RangeDecoder, probs: &mut Probabilities, len: u32) -> u32 {
+    let len_state = len.min(3) as usize;
+    let slot = rc.bit_tree(&mut probs.pos_slot[len_state], 6);
+    if slot < 4 {
+        return slot;
+    }
+
+    let direct_bits = (slot >> 1) - 1;
+    let dist = (2 | (slot & 1)) << direct_bits;
+
+    if (slot as usize) < END_POS_MODEL {
+        let base = dist as usize - slot as usize;
+        return dist + rc.reverse_bit_tree(&mut probs.pos_special[base..], direct_bits);
+    }
+
+    let high = rc.direct_bits(direct_bits - ALIGN_BITS) << ALIGN_BITS;
+    dist.wrapping_add(high)
+        .wrapping_add(rc.reverse_bit_tree(&mut probs.align, ALIGN_BITS))
+}
+
+const PROB_INIT: u16 = 1024;
+
+struct Probabilities {
+    is_match: [[u16; POS_STATES]; NUM_STATES],
+    is_rep: [u16; NUM_STATES],
+    is_rep_g0: [u16; NUM_STATES],
+    is_rep_g1: [u16; NUM_STATES],
+    is_rep_g2: [u16; NUM_STATES],
+    is_rep0_long: [[u16; POS_STATES]; NUM_STATES],
+    pos_slot: [[u16; 1 << 6]; 4],
+    pos_special: [u16; 1 + FULL_DISTANCES - END_POS_MODEL],
+    align: [u16; 1 << ALIGN_BITS],
+    literal: Vec<u16>,
+}
+
+impl Default for Probabilities {
+    fn default() -> Self {
+        Self {
+            is_match: [[PROB_INIT; POS_STATES]; NUM_STATES],
+            is_rep: [PROB_INIT; NUM_STATES],
+            is_rep_g0: [PROB_INIT; NUM_STATES],
+            is_rep_g1: [PROB_INIT; NUM_STATES],
+            is_rep_g2: [PROB_INIT; NUM_STATES],
+            is_rep0_long: [[PROB_INIT; POS_STATES]; NUM_STATES],
+            pos_slot: [[PROB_INIT; 1 << 6]; 4],
+            pos_special: [PROB_INIT; 1 + FULL_DISTANCES - END_POS_MODEL],
+            align: [PROB_INIT; 1 << ALIGN_BITS],
+            literal: vec![PROB_INIT; 0x300 << (LC + LP)],
+        }
+    }
+}
+
+struct LenDecoder {
+    choice: u16,
+    choice_2: u16,
+    low: [[u16; 1 << 3]; POS_STATES],
+    mid: [[u16; 1 << 3]; POS_STATES],
+    high: [u16; 1 << 8],
+}
+
+impl Default for LenDecoder {
+    fn default() -> Self {
+        Self {
+            choice: PROB_INIT,
+            choice_2: PROB_INIT,
+            low: [[PROB_INIT; 1 << 3]; POS_STATES],
+            mid: [[PROB_INIT; 1 << 3]; POS_STATES],
+            high: [PROB_INIT; 1 << 8],
+        }
+    }
+}
+
+impl LenDecoder {
+    /// Match length minus the minimum of 2
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> u32 {
+        if rc.bit(&mut self.choice) == 0 {
+            return rc.bit_tree(&mut self.low[pos_state], 3);
+        }
+        if rc.bit(&mut self.choice_2) == 0 {
+            return 8 + rc.bit_tree(&mut self.mid[pos_state], 3);
+        }
+        16 + rc.bit_tree(&mut self.high, 8)
+    }
+}
+
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> anyhow::Result<Self> {
+        let Some(init) = data.get(..5) else {
+            anyhow::bail!("lzma stream is truncated");
+        };
+        if init[0] != 0 {
+            anyhow::bail!("lzma stream has a corrupt header");
+        }
+
+        Ok(Self {
+            data,
+            pos: 5,
+            range: u32::MAX,
+            code: u32::from_be_bytes([init[1], init[2], init[3], init[4]]),
+        })
+    }
+
+    fn normalize(&mut self) {
+        if self.range < 1 << 24 {
+            let byte = self.data.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+            self.range <<= 8;
+            self.code = self.code << 8 | u32::from(byte);
+        }
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> u32 {
+        let bound = (self.range >> 11) * u32::from(*prob);
+        let bit = if self.code < bound {
+            self.range = bound;
+            *prob += (2048 - *prob) >> 5;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> 5;
+            1
+        };
+        self.normalize();
+        bit
+    }
+
+    fn direct_bits(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = u32::from(self.code >= self.range);
+            if bit == 1 {
+                self.code -= self.range;
+            }
+            value = value << 1 | bit;
+            self.normalize();
+        }
+        value
+    }
+
+    fn bit_tree(&mut self, probs: &mut [u16], count: u32) -> u32 {
+        let mut m = 1;
+        for _ in 0..count {
+            m = m << 1 | self.bit(&mut probs[m as usize]);
+        }
+        m - (1 << count)
+    }
+
+    fn reverse_bit_tree(&mut self, probs: &mut [u16], count: u32) -> u32 {
+        let mut m = 1;
+        let mut symbol = 0;
+        for i in 0..count {
+            let bit = self.bit(&mut probs[m as usize]);
+            m = m << 1 | bit;
+            symbol |= bit << i;
+        }
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Raw LZMA1 from liblzma with lc=3, lp=0, pb=2 and an end marker
+    const SECTORS_TEXT: [u8; 77] = [
+        0x00, 0x29, 0x99, 0x48, 0x67, 0x77, 0xF7, 0x4F, 0xBE, 0xE3, 0x8B, 0xE8, 0x1E, 0x49, 0xDD,
+        0xAF, 0xAE, 0xCC, 0x97, 0x69, 0x5F, 0x9D, 0x1F, 0x75, 0x14, 0x22, 0xF4, 0x19, 0xA0, 0x9B,
+        0x6B, 0xD4, 0x31, 0x7A, 0x67, 0x8F, 0x4D, 0x89, 0xB2, 0x5F, 0x0D, 0xBC, 0x4C, 0x19, 0x4E,
+        0x7D, 0x4E, 0xDA, 0xAE, 0x53, 0x6A, 0xBF, 0x0C, 0xD5, 0xD2, 0x60, 0x54, 0xC9, 0xF9, 0x84,
+        0xFC, 0x6F, 0xEB, 0xF5, 0xF4, 0x4E, 0xA8, 0xE9, 0xC5, 0xB9, 0xEB, 0xFF, 0xFF, 0x7C, 0xBB,
+        0x80, 0x00,
+    ];
+
+    fn sectors_text() -> Vec<u8> {
+        let mut text: Vec<u8> = (0..40)
+            .flat_map(|i| format!("Sector {} of track {}, ", i % 5, i % 3).into_bytes())
+            .collect();
+        text.resize(text.len() + 300, 0);
+        text
+    }
+
+    #[test]
+    fn known_stream() -> anyhow::Result<()> {
+        let expected = sectors_text();
+        let mut dest = vec![0u8; expected.len()];
+        decompress(&SECTORS_TEXT, &mut dest)?;
+        assert_eq!(dest, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stops_once_the_output_is_full() -> anyhow::Result<()> {
+        let mut dest = [0u8; 100];
+        decompress(&SECTORS_TEXT, &mut dest)?;
+        assert_eq!(dest[..], sectors_text()[..100]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn end_marker_before_the_output_is_full() {
+        let mut dest = vec![0u8; sectors_text().len() + 1];
+        assert!(decompress(&SECTORS_TEXT, &mut dest).is_err());
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let mut src = SECTORS_TEXT;
+        src[0] = 0x5D;
+        assert!(decompress(&src, &mut [0u8; 16]).is_err());
+        assert!(decompress(&SECTORS_TEXT[..4], &mut [0u8; 16]).is_err());
+    }
+}
@@ -0,0 +1,825 @@
+mod bits;
+mod flac;
+mod inflate;
+mod lzma;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+
+use bits::BitReader;
+use bits::Huffman;
+
+use crate::Disc;
+use crate::DiscSource;
+use crate::Track;
+use crate::TrackIndex;
+use crate::TrackType;
+use crate::builder::SEC_2;
+use crate::builder::SECTOR_SIZE;
+use crate::sector;
+use crate::source::Layout;
+use crate::source::read_exact_at;
+use crate::subchannel::Subchannel;
+
+const HEADER_SIZE: usize = 124;
+
+/// Raw sector plus 96 bytes of subchannel data, the unit CD images are stored in
+const FRAME_SIZE: usize = SECTOR_SIZE + SUBCODE_SIZE;
+const SUBCODE_SIZE: usize = 96;
+
+/// Tracks start on a multiple of 4 frames inside the image
+const TRACK_PADDING: usize = 4;
+
+const CODEC_CD_ZLIB: u32 = u32::from_be_bytes(*b"cdzl");
+const CODEC_CD_LZMA: u32 = u32::from_be_bytes(*b"cdlz");
+const CODEC_CD_FLAC: u32 = u32::from_be_bytes(*b"cdfl");
+
+const TRACK_METADATA: u32 = u32::from_be_bytes(*b"CHTR");
+const TRACK_METADATA_2: u32 = u32::from_be_bytes(*b"CHT2");
+
+pub fn build_disk(path: &Path) -> anyhow::Result<Disc> {
+    let chd = Chd::open(File::open(path)?)?;
+    let tracks = chd.tracks()?;
+
+    let mut layout = Layout::default();
+    layout.push(SEC_2 / SECTOR_SIZE, None);
+    let mut disc_tracks = Vec::with_capacity(tracks.len());
+    let mut frame_ofs = 0;
+
+    for info in tracks {
+        let mut indexes = Vec::with_capacity(2);
+
+        // Track 1's pregap is the 2 seconds every disc starts with, which are already there
+        let skipped = if info.id == 1 && info.pregap_stored {
+            info.pregap
+        } else {
+            0
+        };
+        let generated_gap = if info.id == 1 || info.pregap_stored {
+            0
+        } else {
+            info.pregap
+        };
+        let stored_gap = if info.pregap_stored {
+            info.pregap
+                .checked_sub(skipped)
+                .ok_or_else(|| anyhow::anyhow!("CHD track pregap longer than track"))?
+        } else {
+            0
+        };
+        let frames = info
+            .frames
+            .checked_sub(skipped)
+            .ok_or_else(|| anyhow::anyhow!("CHD track pregap longer than track"))?;
+
+        if info.pregap > 0 && info.id != 1 {
+            indexes.push(TrackIndex {
+                id: 0,
+                lba: layout.sectors() * SECTOR_SIZE,
+            });
+        }
+        layout.push(generated_gap, None);
+
+        indexes.push(TrackIndex {
+            id: 1,
+            lba: (layout.sectors() + stored_gap) * SECTOR_SIZE,
+        });
+
+        layout.push(
+            frames,
+            Some(FrameRun {
+                first: frame_ofs + skipped,
+                audio: info.track_type == TrackType::Audio,
+            }),
+        );
+        layout.push(info.postgap, None);
+        frame_ofs += info.frames.next_multiple_of(TRACK_PADDING);
+
+        disc_tracks.push(Track {
+            id: info.id,
+            indexes,
+            track_type: info.track_type,
+        });
+    }
+
+    Ok(Disc {
+        source: Box::new(ChdSource {
+            chd,
+            hunk: HunkCache::default(),
+            layout,
+        }),
+        tracks: disc_tracks.into_boxed_slice(),
+        subchannel: Subchannel::default(),
+    })
+}
+
+/// Sectors decompressed from the CHD as they are read, a hunk at a time
+struct ChdSource {
+    chd: Chd,
+    hunk: HunkCache,
+    layout: Layout<FrameRun>,
+}
+
+/// Consecutive frames of one track
+struct FrameRun {
+    first: usize,
+    audio: bool,
+}
+
+impl DiscSource for ChdSource {
+    fn sector_count(&self) -> usize {
+        self.layout.sectors()
+    }
+
+    fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> anyhow::Result<()> {
+        let Some((run, index)) = self.layout.find(lba)? else {
+            buf.fill(0);
+            return Ok(());
+        };
+
+        let frame = self.chd.read_frame(&mut self.hunk, run.first + index)?;
+        buf.copy_from_slice(&frame[..SECTOR_SIZE]);
+
+        // Audio is stored as big endian samples
+        if run.audio {
+            for sample in buf.chunks_exact_mut(2) {
+                sample.swap(0, 1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An open V5 CHD file, only the hunk map is kept in memory
+struct Chd {
+    file: File,
+    compressors: [u32; 4],
+    hunk_bytes: usize,
+    meta_offset: usize,
+    map: Vec<MapEntry>,
+}
+
+#[derive(Clone, Copy)]
+enum MapEntry {
+    /// Compressed with one of the four header codecs
+    Compressed {
+        codec: usize,
+        offset: usize,
+        length: usize,
+        crc: u16,
+    },
+    Uncompressed {
+        offset: usize,
+        crc: Option<u16>,
+    },
+    Zeroes,
+    /// Copy of an earlier hunk
+    SelfRef(usize),
+}
+
+impl Chd {
+    fn open(file: File) -> anyhow::Result<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        if read_exact_at(&file, 0, &mut header).is_err() {
+            anyhow::bail!("file is too small to be a CHD");
+        }
+
+        if &header[..8] != b"MComprHD" {
+            anyhow::bail!("missing CHD signature");
+        }
+
+        let version = be_u32(&header, 12);
+        if version != 5 {
+            anyhow::bail!("CHD version {version} is not supported, only version 5 is");
+        }
+
+        let compressors = [
+            be_u32(&header, 16),
+            be_u32(&header, 20),
+            be_u32(&header, 24),
+            be_u32(&header, 28),
+        ];
+        let logical_bytes = be_u64(&header, 32);
+        let map_offset = be_u64(&header, 40) as usize;
+        let meta_offset = be_u64(&header, 48) as usize;
+        let hunk_bytes = be_u32(&header, 56) as usize;
+        let unit_bytes = be_u32(&header, 60) as usize;
+
+        if header[104..124].iter().any(|&b| b != 0) {
+            anyhow::bail!("CHDs that depend on a parent CHD are not supported");
+        }
+
+        if unit_bytes != FRAME_SIZE || hunk_bytes == 0 || !hunk_bytes.is_multiple_of(FRAME_SIZE) {
+            anyhow::bail!("CHD is not a CD image ({hunk_bytes} byte hunks of {unit_bytes})");
+        }
+
+        let hunks = logical_bytes.div_ceil(hunk_bytes as u64) as usize;
+
+        let map = if compressors[0] == 0 {
+            read_uncompressed_map(&file, map_offset, hunks, hunk_bytes)?
+        } else {
+            read_compressed_map(&file, map_offset, hunks, hunk_bytes)?
+        };
+
+        Ok(Self {
+            file,
+            compressors,
+            hunk_bytes,
+            meta_offset,
+            map,
+        })
+    }
+
+    /// Frame `index` of the image, decompressing the hunk it lives in if needed
+    fn read_frame<'a>(&self, cache: &'a mut HunkCache, index: usize) -> anyhow::Result<&'a [u8]> {
+        let frames_per_hunk = self.hunk_bytes / FRAME_SIZE;
+        let hunk = index / frames_per_hunk;
+
+        if cache.index != Some(hunk) {
+            cache.data.resize(self.hunk_bytes, 0);
+            self.read_hunk(hunk, &mut cache.data)?;
+            cache.index = Some(hunk);
+        }
+
+        let start = (index % frames_per_hunk) * FRAME_SIZE;
+        Ok(&cache.data[start..start + FRAME_SIZE])
+    }
+
+    fn read_hunk(&self, hunk: usize, dest: &mut [u8]) -> anyhow::Result<()> {
+        let Some(&entry) = self.map.get(hunk) else {
+            anyhow::bail!("hunk {hunk} is past the end of the CHD");
+        };
+
+        let crc = match entry {
+            MapEntry::Compressed {
+                codec,
+                offset,
+                length,
+                crc,
+            } => {
+                let src = read_bytes(&self.file, offset, length)?;
+                self.decompress(self.compressors[codec], &src, dest)?;
+                Some(crc)
+            }
+
+            MapEntry::Uncompressed { offset, crc } => {
+                dest.copy_from_slice(&read_bytes(&self.file, offset, self.hunk_bytes)?);
+                crc
+            }
+
+            MapEntry::Zeroes => {
+                dest.fill(0);
+                None
+            }
+
+            MapEntry::SelfRef(source) if source < hunk => return self.read_hunk(source, dest),
+            MapEntry::SelfRef(source) => {
+                anyhow::bail!("hunk {hunk} refers to later hunk {source}")
+            }
+        };
+
+        if crc.is_some_and(|crc| crc16(dest) != crc) {
+            anyhow::bail!("hunk {hunk} failed its CRC check");
+        }
+
+        Ok(())
+    }
+
+    /// Decompress a hunk stored with one of the CD codecs, which compress sector data and
+    /// subchannel data separately and may leave out sync patterns and ECC
+    fn decompress(&self, codec: u32, src: &[u8], dest: &mut [u8]) -> anyhow::Result<()> {
+        let frames = self.hunk_bytes / FRAME_SIZE;
+        let mut sectors = vec![0u8; frames * SECTOR_SIZE];
+        let mut subcode = vec![0u8; frames * SUBCODE_SIZE];
+
+        let ecc_flags = if codec == CODEC_CD_FLAC {
+            let used = flac::decode_cd_audio(src, &mut sectors)?;
+            inflate::inflate(src.get(used..).unwrap_or_default(), &mut subcode)?;
+            &[][..]
+        } else {
+            let ecc_bytes = frames.div_ceil(8);
+            let len_bytes = if self.hunk_bytes < 0x10000 { 2 } else { 3 };
+            let header_bytes = ecc_bytes + len_bytes;
+
+            let Some(header) = src.get(..header_bytes) else {
+                anyhow::bail!("CD hunk is truncated");
+            };
+            let base_len = header[ecc_bytes..]
+                .iter()
+                .fold(0, |len, &b| len << 8 | usize::from(b));
+
+            let Some((base, rest)) = src[header_bytes..].split_at_checked(base_len) else {
+                anyhow::bail!("CD hunk is truncated");
+            };
+
+            match codec {
+                CODEC_CD_ZLIB => inflate::inflate(base, &mut sectors)?,
+                CODEC_CD_LZMA => lzma::decompress(base, &mut sectors)?,
+                _ => anyhow::bail!("unsupported CHD codec '{}'", codec_name(codec)),
+            }
+            inflate::inflate(rest, &mut subcode)?;
+
+            &header[..ecc_bytes]
+        };
+
+        for (i, frame) in dest.chunks_exact_mut(FRAME_SIZE).enumerate() {
+            frame[..SECTOR_SIZE].copy_from_slice(&sectors[i * SECTOR_SIZE..][..SECTOR_SIZE]);
+            frame[SECTOR_SIZE..].copy_from_slice(&subcode[i * SUBCODE_SIZE..][..SUBCODE_SIZE]);
+
+            // Sectors whose ECC could be regenerated were stored without it
+            if ecc_flags
+                .get(i / 8)
+                .is_some_and(|b| b & (1 << (i % 8)) != 0)
+            {
+                frame[..12].copy_from_slice(&sector::SYNC);
+                sector::generate_ecc(frame);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Track layout from the CD metadata entries
+    fn tracks(&self) -> anyhow::Result<Vec<TrackInfo>> {
+        let mut tracks = Vec::new();
+        let mut offset = self.meta_offset;
+        let mut visited = HashSet::new();
+
+        while offset != 0 {
+            if !visited.insert(offset) {
+                anyhow::bail!("CHD metadata at {offset:#x} loops back on itself");
+            }
+
+            let header = read_bytes(&self.file, offset, 16)?;
+            let tag = be_u32(&header, 0);
+            let length = be_u32(&header, 4) as usize & 0xFF_FFFF;
+            let next = be_u64(&header, 8) as usize;
+
+            if tag == TRACK_METADATA || tag == TRACK_METADATA_2 {
+                let text = read_bytes(&self.file, offset + 16, length)?;
+                let text = str::from_utf8(&text)?.trim_end_matches('\0');
+                tracks.push(TrackInfo::parse(text)?);
+            }
+
+            offset = next;
+        }
+
+        if tracks.is_empty() {
+            anyhow::bail!("CHD has no CD track metadata");
+        }
+
+        tracks.sort_by_key(|t| t.id);
+        Ok(tracks)
+    }
+}
+
+#[derive(Default)]
+struct HunkCache {
+    index: Option<usize>,
+    data: Vec<u8>,
+}
+
+struct TrackInfo {
+    id: u8,
+    track_type: TrackType,
+    frames: usize,
+    pregap: usize,
+    /// Pregap sectors are part of the image rather than generated silence
+    pregap_stored: bool,
+    postgap: usize,
+}
+
+impl TrackInfo {
+    /// Parse `TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:1234 PREGAP:0 ...`
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let field = |name: &str| {
+            text.split_whitespace()
+                .find_map(|f| f.strip_prefix(name)?.strip_prefix(':'))
+        };
+
+        let number =
+            |name: &str| -> anyhow::Result<usize> { field(name).map_or(Ok(0), |v| Ok(v.parse()?)) };
+
+        let track_type = match field("TYPE") {
+            Some("AUDIO") => TrackType::Audio,
+            Some("MODE1_RAW") => TrackType::Mode1_2352,
+            Some("MODE2_RAW") => TrackType::Mode2_2352,
+            Some(other) => anyhow::bail!("CHD track type {other} is not supported"),
+            None => anyhow::bail!("CHD track metadata has no type: {text}"),
+        };
+
+        Ok(Self {
+            id: number("TRACK")?.try_into()?,
+            track_type,
+            frames: number("FRAMES")?,
+            pregap: number("PREGAP")?,
+            pregap_stored: field("PGTYPE").is_some_and(|t| t.starts_with('V')),
+            postgap: number("POSTGAP")?,
+        })
+    }
+}
+
+fn read_uncompressed_map(
+    file: &File,
+    offset: usize,
+    hunks: usize,
+    hunk_bytes: usize,
+) -> anyhow::Result<Vec<MapEntry>> {
+    let raw = read_bytes(file, offset, hunks * 4)?;
+
+    Ok(raw
+        .chunks_exact(4)
+        .map(|e| match be_u32(e, 0) as usize {
+            0 => MapEntry::Zeroes,
+            block => MapEntry::Uncompressed {
+                offset: block * hunk_bytes,
+                crc: None,
+            },
+        })
+        .collect())
+}
+
+// Hunk kinds in the compressed map
+const KIND_CODEC_3: u32 = 3;
+const KIND_NONE: u32 = 4;
+const KIND_SELF: u32 = 5;
+const KIND_PARENT: u32 = 6;
+const KIND_RLE_SMALL: u32 = 7;
+const KIND_RLE_LARGE: u32 = 8;
+const KIND_SELF_0: u32 = 9;
+const KIND_SELF_1: u32 = 10;
+
+fn read_compressed_map(
+    file: &File,
+    offset: usize,
+    hunks: usize,
+    hunk_bytes: usize,
+) -> anyhow::Result<Vec<MapEntry>> {
+    let header = read_bytes(file, offset, 16)?;
+
+    let map_bytes = be_u32(&header, 0) as usize;
+    let first_offset = be_u64(&header, 4) >> 16;
+    let map_crc = u16::from_be_bytes([header[10], header[11]]);
+    let length_bits = u32::from(header[12]);
+    let self_bits = u32::from(header[13]);
+
+    let compressed = read_bytes(file, offset + 16, map_bytes)?;
+    let mut reader = BitReader::new(&compressed);
+
+    // Hunk kinds first, huffman coded with runs of repeats
+    let huffman = Huffman::import_rle(&mut reader, 16, 8)?;
+    let mut kinds = Vec::with_capacity(hunks);
+    let mut last = 0;
+    let mut repeat = 0;
+    while kinds.len() < hunks {
+        if repeat > 0 {
+            kinds.push(last);
+            repeat -= 1;
+            continue;
+        }
+
+        match huffman.decode(&mut reader) {
+            KIND_RLE_SMALL => repeat = 2 + huffman.decode(&mut reader),
+            KIND_RLE_LARGE => {
+                repeat = 2 + 16 + (huffman.decode(&mut reader) << 4);
+                repeat += huffman.decode(&mut reader);
+            }
+            kind => last = kind,
+        }
+        kinds.push(last);
+    }
+
+    // Then the offsets, lengths and CRCs each kind needs
+    let mut map = Vec::with_capacity(hunks);
+    let mut raw_map = Vec::with_capacity(hunks * 12);
+    let mut file_offset = first_offset;
+    let mut last_self = 0;
+
+    for kind in kinds {
+        let (stored_kind, length, offset, crc) = match kind {
+            0..=KIND_CODEC_3 | KIND_NONE => {
+                let length = if kind == KIND_NONE {
+                    hunk_bytes as u64
+                } else {
+                    u64::from(reader.read(length_bits))
+                };
+                let crc = reader.read(16) as u16;
+                let offset = file_offset;
+                file_offset += length;
+                (kind, length, offset, crc)
+            }
+            KIND_SELF => {
+                last_self = reader.read_u64(self_bits);
+                (KIND_SELF, 0, last_self, 0)
+            }
+            KIND_SELF_0 | KIND_SELF_1 => {
+                last_self += u64::from(kind == KIND_SELF_1);
+                (KIND_SELF, 0, last_self, 0)
+            }
+            KIND_PARENT.. => anyhow::bail!("CHDs that depend on a parent CHD are not supported"),
+        };
+
+        raw_map.push(stored_kind as u8);
+        raw_map.extend_from_slice(&(length as u32).to_be_bytes()[1..]);
+        raw_map.extend_from_slice(&offset.to_be_bytes()[2..]);
+        raw_map.extend_from_slice(&crc.to_be_bytes());
+
+        map.push(match stored_kind {
+            KIND_NONE => MapEntry::Uncompressed {
+                offset: offset as usize,
+                crc: Some(crc),
+            },
+            KIND_SELF => MapEntry::SelfRef(offset as usize),
+            codec => MapEntry::Compressed {
+                codec: codec as usize,
+                offset: offset as usize,
+                length: length as usize,
+                crc,
+            },
+        });
+    }
+
+    if reader.overrun() || crc16(&raw_map) != map_crc {
+        anyhow::bail!("CHD hunk map is corrupt");
+    }
+
+    Ok(map)
+}
+
+/// CRC-16/CCITT, used for the map and every hunk
+fn crc16(data: &[u8]) -> u16 {
+    const TABLE: [u16; 256] = {
+        let mut table = [0u16; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = (i as u16) << 8;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 0x8000 != 0 {
+                    crc << 1 ^ 0x1021
+                } else {
+                    crc << 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    data.iter().fold(0xFFFF, |crc, &b| {
+        crc << 8 ^ TABLE[usize::from((crc >> 8) as u8 ^ b)]
+    })
+}
+
+fn read_bytes(file: &File, offset: usize, length: usize) -> anyhow::Result<Vec<u8>> {
+    let mut data = vec![0u8; length];
+    if read_exact_at(file, offset as u64, &mut data).is_err() {
+        anyhow::bail!("CHD is truncated at {offset:#x}");
+    }
+    Ok(data)
+}
+
+fn codec_name(codec: u32) -> String {
+    String::from_utf8_lossy(&codec.to_be_bytes()).into_owned()
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().expect("8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("starpsx-{}-{name}", std::process::id()));
+        std::fs::write(&path, data).expect("write temp file");
+        path
+    }
+
+    /// Deflate stream of stored blocks, which is all a test needs to feed the decoder
+    fn stored_deflate(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut chunks = data.chunks(0xFFFF).peekable();
+        while let Some(chunk) = chunks.next() {
+            let len = chunk.len() as u16;
+            out.push(u8::from(chunks.peek().is_none()));
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&(!len).to_le_bytes());
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    /// A Mode 1 data frame and an audio frame, each with its own subcode
+    fn test_frames() -> Vec<u8> {
+        let mut frames = vec![0u8; 2 * FRAME_SIZE];
+        let (data, audio) = frames.split_at_mut(FRAME_SIZE);
+
+        sector::write_header(data, 166, 1);
+        for (i, b) in data[16..0x810].iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        sector::generate_mode1(data);
+        data[SECTOR_SIZE..].fill(0x11);
+
+        for (i, b) in audio[..SECTOR_SIZE].iter_mut().enumerate() {
+            *b = (i * 13 + 5) as u8;
+        }
+        audio[SECTOR_SIZE..].fill(0x22);
+
+        frames
+    }
+
+    /// Uncompressed CHD of one frame hunks, `map` holding the block of each, with `frames`
+    /// stored from block 1 on
+    fn uncompressed_chd(map: &[u32], tracks: &[&str], frames: &[u8]) -> Vec<u8> {
+        let mut file = vec![0u8; FRAME_SIZE];
+        file[..8].copy_from_slice(b"MComprHD");
+        file[12..16].copy_from_slice(&5u32.to_be_bytes());
+        file[32..40].copy_from_slice(&(map.len() as u64 * FRAME_SIZE as u64).to_be_bytes());
+        file[40..48].copy_from_slice(&(HEADER_SIZE as u64).to_be_bytes());
+        file[56..60].copy_from_slice(&(FRAME_SIZE as u32).to_be_bytes());
+        file[60..64].copy_from_slice(&(FRAME_SIZE as u32).to_be_bytes());
+
+        for (i, block) in map.iter().enumerate() {
+            file[HEADER_SIZE + i * 4..][..4].copy_from_slice(&block.to_be_bytes());
+        }
+
+        let mut offset = HEADER_SIZE + map.len() * 4;
+        file[48..56].copy_from_slice(&(offset as u64).to_be_bytes());
+        for (i, text) in tracks.iter().enumerate() {
+            let next = offset + 16 + text.len();
+            let next = if i + 1 < tracks.len() { next } else { 0 };
+            file[offset..offset + 4].copy_from_slice(&TRACK_METADATA.to_be_bytes());
+            file[offset + 4..offset + 8].copy_from_slice(&(text.len() as u32).to_be_bytes());
+            file[offset + 8..offset + 16].copy_from_slice(&(next as u64).to_be_bytes());
+            file[offset + 16..][..text.len()].copy_from_slice(text.as_bytes());
+            offset += 16 + text.len();
+        }
+
+        file.extend_from_slice(frames);
+        file
+    }
+
+    #[test]
+    fn cd_zlib_hunk_round_trip() -> anyhow::Result<()> {
+        let frames = test_frames();
+
+        // The data sector goes in without its sync pattern and ECC, which get rebuilt
+        let mut sectors = Vec::new();
+        let mut subcode = Vec::new();
+        for frame in frames.chunks_exact(FRAME_SIZE) {
+            sectors.extend_from_slice(&frame[..SECTOR_SIZE]);
+            subcode.extend_from_slice(&frame[SECTOR_SIZE..]);
+        }
+        sectors[..12].fill(0);
+        sectors[0x81C..SECTOR_SIZE].fill(0);
+
+        let base = stored_deflate(&sectors);
+        let mut hunk = vec![0b01];
+        hunk.extend_from_slice(&(base.len() as u16).to_be_bytes());
+        hunk.extend_from_slice(&base);
+        hunk.extend_from_slice(&stored_deflate(&subcode));
+
+        let path = temp_file("hunk.bin", &hunk);
+        let compressed = |crc| MapEntry::Compressed {
+            codec: 0,
+            offset: 0,
+            length: hunk.len(),
+            crc,
+        };
+        let mut chd = Chd {
+            file: File::open(&path)?,
+            compressors: [CODEC_CD_ZLIB, 0, 0, 0],
+            hunk_bytes: frames.len(),
+            meta_offset: 0,
+            map: vec![compressed(crc16(&frames))],
+        };
+
+        let mut cache = HunkCache::default();
+        assert_eq!(chd.read_frame(&mut cache, 0)?, &frames[..FRAME_SIZE]);
+        assert_eq!(chd.read_frame(&mut cache, 1)?, &frames[FRAME_SIZE..]);
+
+        chd.map[0] = compressed(crc16(&frames) ^ 1);
+        let mut cache = HunkCache::default();
+        assert!(chd.read_frame(&mut cache, 0).is_err());
+
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn uncompressed_image() -> anyhow::Result<()> {
+        let frames = test_frames();
+        let tracks = [
+            "TRACK:1 TYPE:MODE1_RAW SUBTYPE:NONE FRAMES:2 PREGAP:0 PGTYPE:MODE1 POSTGAP:0",
+            "TRACK:2 TYPE:AUDIO SUBTYPE:NONE FRAMES:1 PREGAP:150 PGTYPE:MODE1 POSTGAP:0",
+        ];
+
+        // One frame hunks, track 1 pads out to frame 4 where track 2 starts. Block 0 is the
+        // header, so the second frame of track 1 reads back as zeroes.
+        let file = uncompressed_chd(&[1, 0, 0, 0, 2], &tracks, &frames);
+        let path = temp_file("image.chd", &file);
+        let mut disc = build_disk(&path)?;
+        std::fs::remove_file(path)?;
+
+        let starts: Vec<Vec<(u8, usize)>> = disc
+            .tracks
+            .iter()
+            .map(|t| {
+                t.indexes
+                    .iter()
+                    .map(|i| (i.id, i.lba / SECTOR_SIZE))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(starts, [vec![(1, 150)], vec![(0, 152), (1, 302)]]);
+        assert_eq!(disc.source.sector_count(), 303);
+
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        disc.source.read_sector(150, &mut sector)?;
+        assert_eq!(sector, frames[..SECTOR_SIZE]);
+
+        disc.source.read_sector(151, &mut sector)?;
+        assert!(sector.iter().all(|&b| b == 0));
+
+        // Audio comes out little endian
+        disc.source.read_sector(302, &mut sector)?;
+        let audio = &frames[FRAME_SIZE..][..SECTOR_SIZE];
+        assert!(
+            sector
+                .chunks_exact(2)
+                .zip(audio.chunks_exact(2))
+                .all(|(le, be)| le[0] == be[1] && le[1] == be[0])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn track_metadata() -> anyhow::Result<()> {
+        let info = TrackInfo::parse(
+            "TRACK:3 TYPE:AUDIO SUBTYPE:NONE FRAMES:4500 PREGAP:150 PGTYPE:VAUDIO POSTGAP:2",
+        )?;
+        assert_eq!(info.id, 3);
+        assert_eq!(info.track_type, TrackType::Audio);
+        assert_eq!(info.frames, 4500);
+        assert_eq!(info.pregap, 150);
+        assert!(info.pregap_stored);
+        assert_eq!(info.postgap, 2);
+
+        assert!(TrackInfo::parse("TRACK:1 TYPE:MODE1 FRAMES:10").is_err());
+        assert!(TrackInfo::parse("TRACK:1 FRAMES:10").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pregap_longer_than_its_track() -> anyhow::Result<()> {
+        let tracks = ["TRACK:1 TYPE:MODE1_RAW SUBTYPE:NONE FRAMES:2 PREGAP:150 PGTYPE:VMODE1"];
+        let file = uncompressed_chd(&[1, 2], &tracks, &test_frames());
+        let path = temp_file("long-pregap.chd", &file);
+        let result = build_disk(&path);
+        std::fs::remove_file(path)?;
+
+        let Err(err) = result else {
+            panic!("pregap longer than the track was accepted");
+        };
+        assert_eq!(err.to_string(), "CHD track pregap longer than track");
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_that_loops() -> anyhow::Result<()> {
+        let tracks = ["TRACK:1 TYPE:MODE1_RAW SUBTYPE:NONE FRAMES:2 PREGAP:0"];
+        let mut file = uncompressed_chd(&[1, 2], &tracks, &test_frames());
+
+        // The only entry points back at itself
+        let offset = HEADER_SIZE + 2 * 4;
+        file[offset + 8..offset + 16].copy_from_slice(&(offset as u64).to_be_bytes());
+
+        let path = temp_file("metadata-loop.chd", &file);
+        let result = build_disk(&path);
+        std::fs::remove_file(path)?;
+
+        let Err(err) = result else {
+            panic!("metadata loop was accepted");
+        };
+        assert!(err.to_string().contains("loops back on itself"), "{err}");
+
+        Ok(())
+    }
+}
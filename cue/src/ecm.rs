@@ -0,0 +1,423 @@
+//! ECM images are raw images with everything that can be regenerated (sync patterns, EDC and
+//! ECC) left out. The file is a list of records, each holding a run of literal bytes or of
+//! stripped sectors, so the records are indexed once and sectors are rebuilt as they are read.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+
+use crate::Disc;
+use crate::DiscSource;
+use crate::Track;
+use crate::builder::SEC_2;
+use crate::builder::SECTOR_SIZE;
+use crate::sector;
+use crate::source::ImageFile;
+use crate::source::read_exact_at;
+use crate::subchannel::Subchannel;
+
+const MAGIC: &[u8; 4] = b"ECM\0";
+
+/// Record count that marks the end of the records
+const END_OF_RECORDS: u64 = 0xFFFF_FFFF;
+
+/// The whole image as one Mode 2 track, for ECMs without a cue sheet
+pub fn build_disk(ecm: Ecm) -> Disc {
+    Disc {
+        source: Box::new(ecm),
+        tracks: Box::new([Track::single()]),
+        subchannel: Subchannel::default(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    Literal,
+    /// Mode 1 sector, stored as its address and data
+    Mode1,
+    /// Mode 2 Form 1 and Form 2 sectors without sync and header, stored as their subheader
+    /// and data
+    Form1,
+    Form2,
+}
+
+impl RecordKind {
+    const fn from_bits(bits: u8) -> Self {
+        match bits & 3 {
+            0 => Self::Literal,
+            1 => Self::Mode1,
+            2 => Self::Form1,
+            _ => Self::Form2,
+        }
+    }
+
+    /// Bytes one unit of the record takes up in the ECM file and once decoded
+    const fn unit_sizes(self) -> (u64, u64) {
+        match self {
+            Self::Literal => (1, 1),
+            Self::Mode1 => (0x803, 0x930),
+            Self::Form1 => (0x804, 0x920),
+            Self::Form2 => (0x918, 0x920),
+        }
+    }
+}
+
+struct Record {
+    kind: RecordKind,
+    /// Where the record's data starts in the ECM file
+    input: u64,
+    /// Where it starts in the decoded image
+    output: u64,
+}
+
+pub struct Ecm {
+    file: File,
+    records: Vec<Record>,
+    /// Size of the decoded image
+    len: u64,
+}
+
+impl Ecm {
+    pub fn open(file: File) -> anyhow::Result<Self> {
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            anyhow::bail!("missing ECM signature");
+        }
+
+        let mut records = Vec::new();
+        let mut input = 4;
+        let mut output = 0;
+
+        loop {
+            // Kind in the low 2 bits, then a count stored 5 bits and then 7 bits at a time
+            let mut byte = read_byte(&mut reader)?;
+            let kind = RecordKind::from_bits(byte);
+            let mut count = u64::from(byte >> 2 & 0x1F);
+            let mut shift = 5;
+            input += 1;
+
+            while byte & 0x80 != 0 {
+                if shift > 32 {
+                    anyhow::bail!("ECM record count is corrupt");
+                }
+                byte = read_byte(&mut reader)?;
+                count |= u64::from(byte & 0x7F) << shift;
+                shift += 7;
+                input += 1;
+            }
+
+            if count == END_OF_RECORDS {
+                break;
+            }
+
+            let count = count + 1;
+            if count >= 0x8000_0000 {
+                anyhow::bail!("ECM record count is corrupt");
+            }
+
+            let (input_size, output_size) = kind.unit_sizes();
+            records.push(Record {
+                kind,
+                input,
+                output,
+            });
+
+            input += count * input_size;
+            output += count * output_size;
+            reader.seek_relative((count * input_size).cast_signed())?;
+        }
+
+        if input > file_len {
+            anyhow::bail!("ECM file is truncated");
+        }
+
+        Ok(Self {
+            file,
+            records,
+            len: output,
+        })
+    }
+
+    /// Rebuild one stripped sector, returns the part of it the image holds
+    fn decode_sector<'a>(
+        &self,
+        kind: RecordKind,
+        input: u64,
+        sector: &'a mut [u8; SECTOR_SIZE],
+    ) -> anyhow::Result<&'a [u8]> {
+        sector.fill(0);
+        sector[..12].copy_from_slice(&sector::SYNC);
+
+        match kind {
+            RecordKind::Mode1 => {
+                read_exact_at(&self.file, input, &mut sector[0xC..0xF])?;
+                read_exact_at(&self.file, input + 3, &mut sector[0x10..0x810])?;
+                sector[0xF] = 1;
+                sector::generate_mode1(sector);
+                Ok(&sector[..])
+            }
+
+            RecordKind::Form1 | RecordKind::Form2 => {
+                let data_end = if kind == RecordKind::Form1 {
+                    0x818
+                } else {
+                    0x92C
+                };
+                read_exact_at(&self.file, input, &mut sector[0x14..data_end])?;
+                sector.copy_within(0x14..0x18, 0x10);
+                sector[0xF] = 2;
+
+                if kind == RecordKind::Form1 {
+                    sector::generate_form1(sector);
+                } else {
+                    sector::generate_form2(sector);
+                }
+                Ok(&sector[0x10..])
+            }
+
+            RecordKind::Literal => unreachable!("literal records are copied directly"),
+        }
+    }
+}
+
+impl ImageFile for Ecm {
+    fn size(&self) -> anyhow::Result<u64> {
+        Ok(self.len)
+    }
+
+    /// Past the end reads as zeroes
+    fn read_at(&self, mut offset: u64, buf: &mut [u8]) -> anyhow::Result<()> {
+        let mut filled = 0;
+        let mut sector = [0u8; SECTOR_SIZE];
+
+        while filled < buf.len() && offset < self.len {
+            let index = self.records.partition_point(|r| r.output <= offset) - 1;
+            let record = &self.records[index];
+            let end = self.records.get(index + 1).map_or(self.len, |r| r.output);
+            let left = buf.len() - filled;
+
+            let len = if record.kind == RecordKind::Literal {
+                let len = (end - offset).min(left as u64) as usize;
+                let input = record.input + (offset - record.output);
+                read_exact_at(&self.file, input, &mut buf[filled..filled + len])?;
+                len
+            } else {
+                let (input_size, output_size) = record.kind.unit_sizes();
+                let unit = (offset - record.output) / output_size;
+                let within = ((offset - record.output) % output_size) as usize;
+
+                let decoded =
+                    self.decode_sector(record.kind, record.input + unit * input_size, &mut sector)?;
+                let len = (decoded.len() - within).min(left);
+                buf[filled..filled + len].copy_from_slice(&decoded[within..within + len]);
+                len
+            };
+
+            filled += len;
+            offset += len as u64;
+        }
+
+        buf[filled..].fill(0);
+        Ok(())
+    }
+}
+
+impl DiscSource for Ecm {
+    fn sector_count(&self) -> usize {
+        SEC_2 / SECTOR_SIZE + (self.len as usize).div_ceil(SECTOR_SIZE)
+    }
+
+    fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> anyhow::Result<()> {
+        // The image starts after the 2 second lead in
+        let Some(image_lba) = lba.checked_sub(SEC_2 / SECTOR_SIZE) else {
+            buf.fill(0);
+            return Ok(());
+        };
+
+        let offset = (image_lba * SECTOR_SIZE) as u64;
+        if offset >= self.len {
+            anyhow::bail!("sector {lba} is past the end of the disc");
+        }
+
+        self.read_at(offset, buf)
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut byte = [0];
+    if reader.read_exact(&mut byte).is_err() {
+        anyhow::bail!("ECM file is truncated");
+    }
+    Ok(byte[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("starpsx-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    /// Record header, the count is stored minus one
+    fn record(kind: u8, count: u64) -> Vec<u8> {
+        let mut count = count - 1;
+        let mut out = vec![kind | ((count & 0x1F) as u8) << 2];
+        count >>= 5;
+        while count != 0 {
+            *out.last_mut().expect("header byte") |= 0x80;
+            out.push((count & 0x7F) as u8);
+            count >>= 7;
+        }
+        out
+    }
+
+    fn end_of_records() -> Vec<u8> {
+        let mut end = record(0, END_OF_RECORDS + 1);
+        end.extend_from_slice(&[0; 4]); // EDC of the whole image
+        end
+    }
+
+    /// A Mode 1 sector, a Mode 2 Form 1 and a Form 2 one, as a raw image and as an ECM of it
+    fn test_image() -> (Vec<u8>, Vec<u8>) {
+        let mut raw = vec![0u8; 3 * SECTOR_SIZE];
+        let fill = |data: &mut [u8], seed: usize| {
+            for (i, b) in data.iter_mut().enumerate() {
+                *b = (i * seed) as u8;
+            }
+        };
+
+        let (mode1, rest) = raw.split_at_mut(SECTOR_SIZE);
+        sector::write_header(mode1, 150, 1);
+        fill(&mut mode1[0x10..0x810], 3);
+        sector::generate_mode1(mode1);
+
+        let (form1, form2) = rest.split_at_mut(SECTOR_SIZE);
+        sector::write_header(form1, 151, 2);
+        for copy in form1[0x10..0x18].chunks_exact_mut(4) {
+            copy.copy_from_slice(&[1, 0, 0x08, 0]);
+        }
+        fill(&mut form1[0x18..0x818], 5);
+        sector::generate_form1(form1);
+
+        sector::write_header(form2, 152, 2);
+        for copy in form2[0x10..0x18].chunks_exact_mut(4) {
+            copy.copy_from_slice(&[1, 0, 0x24, 0]);
+        }
+        fill(&mut form2[0x18..0x92C], 7);
+        sector::generate_form2(form2);
+
+        let mut ecm = MAGIC.to_vec();
+        ecm.extend(record(1, 1));
+        ecm.extend_from_slice(&raw[0xC..0xF]);
+        ecm.extend_from_slice(&raw[0x10..0x810]);
+
+        // Mode 2 sectors keep their sync and header as literal bytes
+        let form1 = &raw[SECTOR_SIZE..];
+        ecm.extend(record(0, 16));
+        ecm.extend_from_slice(&form1[..0x10]);
+        ecm.extend(record(2, 1));
+        ecm.extend_from_slice(&form1[0x14..0x818]);
+
+        let form2 = &raw[2 * SECTOR_SIZE..];
+        ecm.extend(record(0, 16));
+        ecm.extend_from_slice(&form2[..0x10]);
+        ecm.extend(record(3, 1));
+        ecm.extend_from_slice(&form2[0x14..0x92C]);
+
+        ecm.extend(end_of_records());
+        (raw, ecm)
+    }
+
+    #[test]
+    fn record_counts() {
+        assert_eq!(record(1, 1), [0x01]);
+        assert_eq!(record(0, 32), [0x7C]);
+        assert_eq!(record(2, 33), [0x82, 0x01]);
+    }
+
+    #[test]
+    fn rebuilds_stripped_sectors() -> anyhow::Result<()> {
+        let (raw, ecm) = test_image();
+        let path = temp_dir("ecm").join("game.bin.ecm");
+        std::fs::write(&path, ecm)?;
+
+        let mut disc = crate::build_ecm_disk(&path)?;
+        assert_eq!(disc.tracks.len(), 1);
+        assert_eq!(disc.source.sector_count(), 150 + 3);
+
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        for (i, expected) in raw.chunks_exact(SECTOR_SIZE).enumerate() {
+            disc.source.read_sector(150 + i, &mut sector)?;
+            assert_eq!(sector, expected, "sector {i}");
+        }
+        assert!(disc.source.read_sector(153, &mut sector).is_err());
+
+        std::fs::remove_dir_all(path.parent().expect("temp dir"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn cue_sheet_and_subchannel_beside() -> anyhow::Result<()> {
+        let (raw, ecm) = test_image();
+        let dir = temp_dir("ecm-cue");
+        std::fs::write(dir.join("game.bin.ecm"), ecm)?;
+        std::fs::write(
+            dir.join("game.cue"),
+            "FILE \"game.bin\" BINARY\n\
+             TRACK 01 MODE2/2352\n\
+             INDEX 01 00:00:00\n\
+             TRACK 02 AUDIO\n\
+             INDEX 01 00:00:02\n",
+        )?;
+
+        // One patched Q sector at 00:02:00
+        let mut sbi = b"SBI\0".to_vec();
+        sbi.extend_from_slice(&[0x00, 0x02, 0x00, 0x01]);
+        sbi.extend_from_slice(&[0x41, 0x01, 0x01, 0, 0, 0, 0, 0, 0x02, 0]);
+        std::fs::write(dir.join("game.sbi"), sbi)?;
+
+        let mut disc = crate::build_ecm_disk(dir.join("game.bin.ecm"))?;
+        std::fs::remove_dir_all(&dir)?;
+
+        let starts: Vec<(u8, usize)> = disc
+            .tracks
+            .iter()
+            .map(|t| (t.id, t.indexes[0].lba / SECTOR_SIZE))
+            .collect();
+        assert_eq!(starts, [(1, 150), (2, 152)]);
+        assert_eq!(disc.subchannel.len(), 1);
+
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        disc.source.read_sector(152, &mut sector)?;
+        assert_eq!(sector, raw[2 * SECTOR_SIZE..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_files() {
+        let (_, ecm) = test_image();
+        let dir = temp_dir("ecm-corrupt");
+        let path = dir.join("game.ecm");
+
+        let open = |data: &[u8]| {
+            std::fs::write(&path, data).expect("write ecm");
+            Ecm::open(File::open(&path).expect("open ecm"))
+        };
+        assert!(open(b"ECM").is_err());
+        assert!(open(b"MCE\0\0").is_err());
+        assert!(open(&ecm[..100]).is_err());
+        assert!(open(&ecm[..ecm.len() - 9]).is_err());
+
+        std::fs::remove_dir_all(dir).expect("remove temp dir");
+    }
+}
@@ -0,0 +1,453 @@
+//! Read only access to the ISO9660 filesystem on the data track of a disc. Sector numbers in
+//! the filesystem start at the end of the 2 second lead in.
+//!
+//! Files can also be stored as Mode 2 Form 2 sectors (XA audio, and STR videos interleaving
+//! both forms), these are read whole with their subheaders since the data doesn't fit the
+//! 2048 bytes the directory sizes are counted in.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::DiscSource;
+use crate::builder::SEC_2;
+use crate::builder::SECTOR_SIZE;
+
+/// Bytes of data in a Mode 1 or Mode 2 Form 1 sector
+const DATA_SIZE: usize = 2048;
+
+/// Bytes of a Mode 2 sector after the sync and header, subheader included
+pub const RAW_DATA_SIZE: usize = SECTOR_SIZE - 0x10;
+
+/// Sector of the first volume descriptor
+const FIRST_DESCRIPTOR: usize = 16;
+
+/// XA attribute bits for files with Form 2 sectors
+const XA_FORM2: u16 = 1 << 12;
+const XA_INTERLEAVED: u16 = 1 << 13;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Name without the ";1" version suffix
+    pub name: String,
+    /// Sector the data starts at, counted from the start of the filesystem
+    pub lba: usize,
+    pub size: usize,
+    pub is_dir: bool,
+    /// Has Form 2 sectors, so it's only readable whole with [`Filesystem::read_file_raw`]
+    pub form2: bool,
+}
+
+impl DirEntry {
+    #[must_use]
+    pub const fn sectors(&self) -> usize {
+        self.size.div_ceil(DATA_SIZE)
+    }
+}
+
+pub struct Filesystem<'a> {
+    source: &'a mut dyn DiscSource,
+    root: DirEntry,
+}
+
+impl<'a> Filesystem<'a> {
+    /// # Errors
+    ///
+    /// Returns an error if the disc has no ISO9660 primary volume descriptor
+    pub fn open(source: &'a mut dyn DiscSource) -> anyhow::Result<Self> {
+        let mut sector = [0u8; DATA_SIZE];
+
+        for lba in FIRST_DESCRIPTOR.. {
+            read_data(source, lba, &mut sector)?;
+
+            if &sector[1..6] != b"CD001" {
+                anyhow::bail!("disc has no ISO9660 filesystem");
+            }
+
+            match sector[0] {
+                // Primary volume descriptor, the root directory record is at 156
+                1 => {
+                    let (root, _) = parse_record(&sector[156..])
+                        .ok_or_else(|| anyhow::anyhow!("root directory record is corrupt"))?;
+                    return Ok(Self { source, root });
+                }
+                // Set terminator
+                0xFF => break,
+                _ => (),
+            }
+        }
+
+        anyhow::bail!("disc has no ISO9660 primary volume descriptor")
+    }
+
+    #[must_use]
+    pub const fn root(&self) -> &DirEntry {
+        &self.root
+    }
+
+    /// Entries of a directory, without "." and ".."
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` is not a directory or its sectors can't be read
+    pub fn read_dir(&mut self, dir: &DirEntry) -> anyhow::Result<Vec<DirEntry>> {
+        if !dir.is_dir {
+            anyhow::bail!("{} is not a directory", dir.name);
+        }
+
+        let mut entries = Vec::new();
+        let mut sector = [0u8; DATA_SIZE];
+
+        for lba in dir.lba..dir.lba + dir.size.div_ceil(DATA_SIZE) {
+            read_data(self.source, lba, &mut sector)?;
+
+            // Records never cross sectors, a zero length pads out the rest of one
+            let mut offset = 0;
+            while let Some((entry, len)) = parse_record(&sector[offset..]) {
+                offset += len;
+                if entry.name != "\0" && entry.name != "\u{1}" {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Look up a path like `SYSTEM.CNF` or `\MOVIE\INTRO.STR`, ignoring case and versions
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any part of the path doesn't exist
+    pub fn find(&mut self, path: &str) -> anyhow::Result<DirEntry> {
+        let mut entry = self.root.clone();
+
+        for part in path.split(['/', '\\']).filter(|p| !p.is_empty()) {
+            let part = part.split(';').next().unwrap_or(part);
+            entry = self
+                .read_dir(&entry)?
+                .into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(part))
+                .ok_or_else(|| anyhow::anyhow!("{path} not found on the disc"))?;
+        }
+
+        Ok(entry)
+    }
+
+    /// Whole contents of a file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `file` is a directory or its sectors can't be read
+    pub fn read_file(&mut self, file: &DirEntry) -> anyhow::Result<Vec<u8>> {
+        if file.is_dir {
+            anyhow::bail!("{} is a directory", file.name);
+        }
+
+        let mut data = vec![0u8; file.sectors() * DATA_SIZE];
+        for (i, chunk) in data.chunks_exact_mut(DATA_SIZE).enumerate() {
+            read_data(self.source, file.lba + i, chunk)?;
+        }
+
+        data.truncate(file.size);
+        Ok(data)
+    }
+
+    /// Every sector of a file from the subheader on, [`RAW_DATA_SIZE`] bytes each
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `file` is a directory or its sectors can't be read
+    pub fn read_file_raw(&mut self, file: &DirEntry) -> anyhow::Result<Vec<u8>> {
+        if file.is_dir {
+            anyhow::bail!("{} is a directory", file.name);
+        }
+
+        let mut data = vec![0u8; file.sectors() * RAW_DATA_SIZE];
+        let mut sector = [0u8; SECTOR_SIZE];
+
+        for (i, chunk) in data.chunks_exact_mut(RAW_DATA_SIZE).enumerate() {
+            self.source
+                .read_sector(SEC_2 / SECTOR_SIZE + file.lba + i, &mut sector)?;
+            chunk.copy_from_slice(&sector[0x10..]);
+        }
+
+        Ok(data)
+    }
+
+    /// Write a file into `dest`, or a directory and everything under it.
+    /// Form 2 files are written raw. Returns how many files were written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the disc can't be read, the files can't be written, or a name on the
+    /// disc would write outside of `dest` or a directory contains itself
+    pub fn export(&mut self, entry: &DirEntry, dest: &Path) -> anyhow::Result<usize> {
+        self.export_into(entry, dest, &mut HashSet::new())
+    }
+
+    /// [`Self::export`], remembering the extents of the directories already entered
+    fn export_into(
+        &mut self,
+        entry: &DirEntry,
+        dest: &Path,
+        visited: &mut HashSet<usize>,
+    ) -> anyhow::Result<usize> {
+        if !entry.is_dir {
+            let data = if entry.form2 {
+                self.read_file_raw(entry)?
+            } else {
+                self.read_file(entry)?
+            };
+
+            std::fs::write(dest.join(checked_name(&entry.name)?), data)?;
+            return Ok(1);
+        }
+
+        // Directories point to their extent, one that leads back up would never end
+        if !visited.insert(entry.lba) {
+            anyhow::bail!("directory {} loops back on itself", entry.name);
+        }
+
+        // The root directory has no name of its own
+        let dir = if entry == &self.root {
+            dest.to_path_buf()
+        } else {
+            dest.join(checked_name(&entry.name)?)
+        };
+        std::fs::create_dir_all(&dir)?;
+
+        let mut written = 0;
+        for child in self.read_dir(entry)? {
+            written += self.export_into(&child, &dir, visited)?;
+        }
+
+        Ok(written)
+    }
+}
+
+/// Name of an entry if it's safe to join onto a path, it must stay a single component
+fn checked_name(name: &str) -> anyhow::Result<&str> {
+    let is_component =
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', ':', '\0']);
+
+    if !is_component {
+        anyhow::bail!("file name {name:?} on the disc is not safe to export");
+    }
+
+    Ok(name)
+}
+
+/// Directory record at the start of `bytes` and its length, `None` for padding
+fn parse_record(bytes: &[u8]) -> Option<(DirEntry, usize)> {
+    let len = usize::from(*bytes.first()?);
+    let name_len = usize::from(*bytes.get(32)?);
+    if len < 33 + name_len || len > bytes.len() {
+        return None;
+    }
+
+    let le32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().expect("4 bytes"));
+    let name = String::from_utf8_lossy(&bytes[33..33 + name_len]);
+    let name = name.split(';').next().unwrap_or_default();
+
+    // XA extension after the name padded to an even length: ids, attributes then "XA"
+    let xa = 33 + name_len + (1 - name_len % 2);
+    let attributes = bytes[..len]
+        .get(xa..xa + 8)
+        .filter(|xa| &xa[6..8] == b"XA")
+        .map_or(0, |xa| u16::from_be_bytes([xa[4], xa[5]]));
+
+    let entry = DirEntry {
+        name: name.to_string(),
+        lba: le32(2) as usize,
+        size: le32(10) as usize,
+        is_dir: bytes[25] & 2 != 0,
+        form2: attributes & (XA_FORM2 | XA_INTERLEAVED) != 0,
+    };
+
+    Some((entry, len))
+}
+
+/// The 2048 data bytes of a filesystem sector
+fn read_data(source: &mut dyn DiscSource, lba: usize, buf: &mut [u8]) -> anyhow::Result<()> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    source.read_sector(SEC_2 / SECTOR_SIZE + lba, &mut sector)?;
+
+    // Mode 2 sectors have an 8 byte subheader before the data
+    let start = if sector[0xF] == 1 { 0x10 } else { 0x18 };
+    buf.copy_from_slice(&sector[start..start + DATA_SIZE]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("starpsx-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    fn record(lba: usize, size: usize, is_dir: bool, name: &[u8]) -> Vec<u8> {
+        let mut record = vec![0u8; 33 + name.len() + (1 - name.len() % 2)];
+        record[0] = record.len() as u8;
+        record[2..6].copy_from_slice(&(lba as u32).to_le_bytes());
+        record[10..14].copy_from_slice(&(size as u32).to_le_bytes());
+        record[25] = if is_dir { 2 } else { 0 };
+        record[32] = name.len() as u8;
+        record[33..33 + name.len()].copy_from_slice(name);
+        record
+    }
+
+    fn dir(lba: usize, name: &[u8]) -> Vec<u8> {
+        record(lba, DATA_SIZE, true, name)
+    }
+
+    /// Data of a filesystem sector, made a Mode 1 one
+    fn data(image: &mut [u8], lba: usize) -> &mut [u8] {
+        let start = SEC_2 + lba * SECTOR_SIZE;
+        image[start + 0xF] = 1;
+        &mut image[start + 0x10..start + 0x10 + DATA_SIZE]
+    }
+
+    /// Mode 1 image with the root directory at sector 18 holding `root`, then each of `sectors`
+    /// from sector 19 on
+    fn image(root: &[Vec<u8>], sectors: &[&[u8]]) -> Box<[u8]> {
+        let mut image = vec![0u8; SEC_2 + (19 + sectors.len()) * SECTOR_SIZE];
+
+        let pvd = data(&mut image, FIRST_DESCRIPTOR);
+        pvd[0] = 1;
+        pvd[1..6].copy_from_slice(b"CD001");
+        let root_record = dir(18, b"\0");
+        pvd[156..156 + root_record.len()].copy_from_slice(&root_record);
+
+        let terminator = data(&mut image, FIRST_DESCRIPTOR + 1);
+        terminator[0] = 0xFF;
+        terminator[1..6].copy_from_slice(b"CD001");
+
+        let records: Vec<u8> = [root_record, dir(18, b"\x01")]
+            .iter()
+            .chain(root)
+            .flatten()
+            .copied()
+            .collect();
+        data(&mut image, 18)[..records.len()].copy_from_slice(&records);
+
+        for (i, sector) in sectors.iter().enumerate() {
+            data(&mut image, 19 + i)[..sector.len()].copy_from_slice(sector);
+        }
+
+        image.into_boxed_slice()
+    }
+
+    /// Records of a directory's own sector, after its "." and ".."
+    fn dir_sector(lba: usize, parent: usize, entries: &[Vec<u8>]) -> Vec<u8> {
+        [dir(lba, b"\0"), dir(parent, b"\x01")]
+            .iter()
+            .chain(entries)
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    const SYSTEM_CNF: &[u8] = b"BOOT = cdrom:\\SLUS_005.94;1\r\n";
+    const README: &[u8] = b"Hello from a subdirectory";
+
+    fn nested_image() -> Box<[u8]> {
+        let data = dir_sector(20, 18, &[record(21, README.len(), false, b"README.TXT;1")]);
+        image(
+            &[
+                record(19, SYSTEM_CNF.len(), false, b"SYSTEM.CNF;1"),
+                dir(20, b"DATA"),
+            ],
+            &[SYSTEM_CNF, &data, README],
+        )
+    }
+
+    #[test]
+    fn finds_and_reads_files() -> anyhow::Result<()> {
+        let mut image = nested_image();
+        let mut fs = Filesystem::open(&mut image)?;
+
+        let root = fs.root().clone();
+        let names: Vec<_> = fs.read_dir(&root)?.into_iter().map(|e| e.name).collect();
+        assert_eq!(names, ["SYSTEM.CNF", "DATA"]);
+
+        let system_cnf = fs.find("system.cnf;1")?;
+        assert_eq!(fs.read_file(&system_cnf)?, SYSTEM_CNF);
+
+        let readme = fs.find("\\DATA\\README.TXT")?;
+        assert_eq!((readme.lba, readme.size), (21, README.len()));
+        assert_eq!(fs.read_file(&readme)?, README);
+
+        assert!(fs.find("DATA/MISSING.TXT").is_err());
+        let data = fs.find("DATA")?;
+        assert!(fs.read_file(&data).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn no_filesystem_on_an_empty_disc() {
+        let mut image = vec![0u8; 200 * SECTOR_SIZE].into_boxed_slice();
+        assert!(Filesystem::open(&mut image).is_err());
+    }
+
+    #[test]
+    fn exports_a_directory_tree() -> anyhow::Result<()> {
+        let dest = temp_dir("iso-export");
+        let mut image = nested_image();
+        let mut fs = Filesystem::open(&mut image)?;
+
+        let root = fs.root().clone();
+        assert_eq!(fs.export(&root, &dest)?, 2);
+        assert_eq!(std::fs::read(dest.join("SYSTEM.CNF"))?, SYSTEM_CNF);
+        assert_eq!(std::fs::read(dest.join("DATA").join("README.TXT"))?, README);
+
+        let data = fs.find("DATA")?;
+        let only_data = temp_dir("iso-export-dir");
+        assert_eq!(fs.export(&data, &only_data)?, 1);
+        assert!(only_data.join("DATA").join("README.TXT").is_file());
+
+        std::fs::remove_dir_all(dest)?;
+        std::fs::remove_dir_all(only_data)?;
+        Ok(())
+    }
+
+    #[test]
+    fn export_refuses_names_leaving_the_destination() -> anyhow::Result<()> {
+        let dest = temp_dir("iso-export-escape");
+
+        for name in [&b".."[..], b"../ESCAPE.TXT", b"A\\B.TXT", b"C:X.TXT"] {
+            let mut image = image(&[record(19, README.len(), false, name)], &[README]);
+            let mut fs = Filesystem::open(&mut image)?;
+
+            let root = fs.root().clone();
+            assert!(fs.export(&root, &dest).is_err(), "{}", name.escape_ascii());
+        }
+
+        assert!(!dest.with_file_name("ESCAPE.TXT").exists());
+        assert_eq!(std::fs::read_dir(&dest)?.count(), 0);
+
+        std::fs::remove_dir_all(dest)?;
+        Ok(())
+    }
+
+    #[test]
+    fn export_stops_at_directory_loops() -> anyhow::Result<()> {
+        let dest = temp_dir("iso-export-loop");
+
+        // LOOP lists the root directory again under itself
+        let mut image = image(&[dir(18, b"LOOP")], &[]);
+        let mut fs = Filesystem::open(&mut image)?;
+
+        let root = fs.root().clone();
+        let err = fs.export(&root, &dest).err().map(|e| e.to_string());
+        assert_eq!(err.as_deref(), Some("directory LOOP loops back on itself"));
+
+        std::fs::remove_dir_all(dest)?;
+        Ok(())
+    }
+}
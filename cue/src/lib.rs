@@ -1,14 +1,28 @@
 mod builder;
+mod chd;
+mod ecm;
+pub mod iso9660;
 mod parser;
 mod scanner;
+mod sector;
+mod source;
+mod subchannel;
 
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use builder::CueBuilder;
 pub use builder::Disc;
+use builder::SEC_2;
+use builder::SECTOR_SIZE;
 use parser::CueParser;
 use scanner::Scanner;
+pub use source::DiscSource;
+use source::FileSource;
+pub use subchannel::SubQ;
+pub use subchannel::Subchannel;
+pub use subchannel::q_crc;
 
 /// # Errors
 ///
@@ -17,12 +31,118 @@ use scanner::Scanner;
 /// * The file content contains invalid tokens or malformed syntax.
 /// * The disk layout is invalid or references missing resources.
 pub fn build_disk<P: AsRef<Path>>(cue_path: P) -> anyhow::Result<Disc> {
-    let cue_file = std::fs::read(cue_path.as_ref())?;
-    let tokens = Scanner::with_source(cue_file).tokenize()?;
-    let cue_sheet = CueParser::new(tokens).parse_cuesheet()?;
+    let cue_sheet = read_cue_sheet(cue_path.as_ref())?;
 
     let parent_dir = cue_path.as_ref().parent().unwrap_or_else(|| Path::new("."));
-    CueBuilder::new(parent_dir).build_disk(cue_sheet)
+    CueBuilder::new(parent_dir)
+        .build_disk(cue_sheet)?
+        .with_subchannel_beside(cue_path.as_ref())
+}
+
+fn read_cue_sheet(cue_path: &Path) -> anyhow::Result<CueSheet> {
+    let cue_file = std::fs::read(cue_path)?;
+    let tokens = Scanner::with_source(cue_file).tokenize()?;
+    CueParser::new(tokens).parse_cuesheet()
+}
+
+/// Single track raw image with no cue sheet, read as one Mode 2 data track
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened.
+pub fn build_bin_disk<P: AsRef<Path>>(bin_path: P) -> anyhow::Result<Disc> {
+    let mut source = FileSource::default();
+    source.push_silence(SEC_2 / SECTOR_SIZE);
+    let file = std::fs::File::open(bin_path.as_ref())?;
+    let len = file.metadata()?.len();
+    source.push_file(Arc::new(file), 0, len, TrackType::Mode2_2352, false);
+
+    Disc {
+        source: Box::new(source),
+        tracks: Box::new([Track::single()]),
+        subchannel: Subchannel::default(),
+    }
+    .with_subchannel_beside(bin_path.as_ref())
+}
+
+/// Plain ISO image of 2048 byte sectors, which are given the rest of a Mode 2 Form 1 sector.
+/// Raw 2352 byte images with the same extension are read as they are.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or isn't made of whole sectors.
+pub fn build_iso_disk<P: AsRef<Path>>(iso_path: P) -> anyhow::Result<Disc> {
+    let file = std::fs::File::open(iso_path.as_ref())?;
+    let len = file.metadata()?.len();
+
+    let mut sync = [0u8; 12];
+    source::read_exact_at(&file, 0, &mut sync)?;
+
+    let track_type = if sync == sector::SYNC {
+        TrackType::Mode2_2352
+    } else if len.is_multiple_of(2048) {
+        TrackType::Mode2_2048
+    } else {
+        anyhow::bail!("image is neither made of raw nor 2048 byte sectors");
+    };
+
+    let mut source = FileSource::default();
+    source.push_silence(SEC_2 / SECTOR_SIZE);
+    source.push_file(Arc::new(file), 0, len, track_type, false);
+
+    Disc {
+        source: Box::new(source),
+        tracks: Box::new([Track {
+            track_type,
+            ..Track::single()
+        }]),
+        subchannel: Subchannel::default(),
+    }
+    .with_subchannel_beside(iso_path.as_ref())
+}
+
+/// ECM of a raw image, `game.bin.ecm` holds `game.bin`. Its tracks come from `game.cue`
+/// when that is next to it, and it is read as one Mode 2 track otherwise.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The ECM file cannot be read or is missing its signature.
+/// * The record list is corrupt or runs past the end of the file.
+/// * The cue sheet next to it is invalid or references other files that cannot be read.
+pub fn build_ecm_disk<P: AsRef<Path>>(ecm_path: P) -> anyhow::Result<Disc> {
+    let ecm = ecm::Ecm::open(std::fs::File::open(ecm_path.as_ref())?)?;
+    let image = ecm_path.as_ref().with_extension("");
+    let cue_path = image.with_extension("cue");
+
+    let disc = if cue_path.is_file() {
+        let cue_sheet = read_cue_sheet(&cue_path)?;
+
+        // A sheet for just this image may still call it something else
+        let name = match cue_sheet.files.as_slice() {
+            [only] => only.path.clone(),
+            _ => image.file_name().map(PathBuf::from).unwrap_or_default(),
+        };
+
+        let parent_dir = cue_path.parent().unwrap_or_else(|| Path::new("."));
+        CueBuilder::new(parent_dir)
+            .with_file(name, Arc::new(ecm))
+            .build_disk(cue_sheet)?
+    } else {
+        ecm::build_disk(ecm)
+    };
+
+    disc.with_subchannel_beside(&image)
+}
+
+/// # Errors
+///
+/// Returns an error if:
+/// * The CHD file cannot be read, or is not a version 5 CD image.
+/// * A hunk uses a codec other than the CD zlib, LZMA or FLAC ones, or fails to decompress.
+/// * The track metadata is missing or describes an unsupported track type.
+pub fn build_chd_disk<P: AsRef<Path>>(chd_path: P) -> anyhow::Result<Disc> {
+    chd::build_disk(chd_path.as_ref())?.with_subchannel_beside(chd_path.as_ref())
 }
 
 #[derive(Debug)]
@@ -32,15 +152,30 @@ struct CueSheet {
 
 #[derive(Debug)]
 struct File {
-    #[expect(unused)]
     format: FileType,
     path: PathBuf,
-    tracks: Vec<Track>,
+    /// The first entry continues the previous file's last track when the sheet puts a
+    /// track's indexes in two files
+    tracks: Vec<CueTrack>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum FileType {
     Binary,
+    /// Raw audio with big endian samples
+    Motorola,
+    Wave,
+}
+
+#[derive(Debug)]
+struct CueTrack {
+    id: u8,
+    track_type: TrackType,
+    /// Silence in sectors that is not part of the file, before and after the track
+    pregap: usize,
+    postgap: usize,
+    /// Index positions are in sectors from the start of the file
+    indexes: Vec<TrackIndex>,
 }
 
 #[derive(Debug)]
@@ -56,7 +191,7 @@ impl Track {
         Self {
             track_type: TrackType::Mode2_2352,
             id: 1,
-            indexes: vec![TrackIndex { id: 1, lba: 0 }],
+            indexes: vec![TrackIndex { id: 1, lba: SEC_2 }],
         }
     }
 }
@@ -64,9 +199,25 @@ impl Track {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TrackType {
     Audio,
+    Mode1_2048,
+    Mode1_2352,
+    Mode2_2048,
+    Mode2_2336,
     Mode2_2352,
 }
 
+impl TrackType {
+    /// Bytes each sector of the track takes up in an image file
+    #[must_use]
+    pub const fn sector_size(self) -> usize {
+        match self {
+            Self::Mode1_2048 | Self::Mode2_2048 => 2048,
+            Self::Mode2_2336 => 2336,
+            Self::Audio | Self::Mode1_2352 | Self::Mode2_2352 => 2352,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TrackIndex {
     pub id: u8,
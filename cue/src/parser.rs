@@ -1,8 +1,8 @@
 use super::CueSheet;
+use super::CueTrack;
 use super::File;
 use super::FileType;
 use super::PathBuf;
-use super::Track;
 use super::TrackIndex;
 use super::TrackType;
 use crate::scanner::Token;
@@ -10,25 +10,45 @@ use crate::scanner::Token;
 pub struct CueParser {
     tokens: Vec<Token>,
     current: usize,
+    /// Id and type of the last track, for indexes that continue it in a new file
+    last_track: Option<(u8, TrackType)>,
 }
 
 impl CueParser {
     pub const fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            last_track: None,
+        }
     }
 
-    /// cue -> file*
+    /// cue -> metadata* file*
     pub fn parse_cuesheet(mut self) -> anyhow::Result<CueSheet> {
         let mut files = Vec::new();
 
         while !self.is_at_end() && self.peek() != &Token::Eof {
+            if self.skip_metadata()? {
+                continue;
+            }
             files.push(self.parse_file()?);
         }
 
+        // Tracks start at INDEX 01, which can be in the file after the rest of the track
+        let mut tracks = files.iter().flat_map(|f| &f.tracks);
+        let started: Vec<u8> = tracks
+            .clone()
+            .filter(|t| t.indexes.iter().any(|i| i.id == 1))
+            .map(|t| t.id)
+            .collect();
+        if let Some(track) = tracks.find(|t| !started.contains(&t.id)) {
+            anyhow::bail!("Track {} has no INDEX 01.", track.id);
+        }
+
         Ok(CueSheet { files })
     }
 
-    /// file -> "FILE" filename filetype "\n" track*
+    /// file -> "FILE" filename filetype "\n" index* track*
     fn parse_file(&mut self) -> anyhow::Result<File> {
         let Token::File = self.advance() else {
             anyhow::bail!("Expect 'FILE'.");
@@ -40,6 +60,8 @@ impl CueParser {
 
         let file_type = match self.advance() {
             Token::Binary => FileType::Binary,
+            Token::Motorola => FileType::Motorola,
+            Token::Wave => FileType::Wave,
             t => anyhow::bail!("File type {t:?} not implemented."),
         };
 
@@ -49,6 +71,32 @@ impl CueParser {
 
         let mut tracks = Vec::new();
 
+        // Indexes before any track continue the previous file's last track
+        let mut indexes = Vec::new();
+        loop {
+            match self.peek() {
+                Token::Index => indexes.push(self.parse_index()?),
+                Token::Metadata(_) => {
+                    self.skip_metadata()?;
+                }
+                _ => break,
+            }
+        }
+
+        if !indexes.is_empty() {
+            let Some((id, track_type)) = self.last_track else {
+                anyhow::bail!("Index before the first track.");
+            };
+
+            tracks.push(CueTrack {
+                id,
+                track_type,
+                pregap: 0,
+                postgap: 0,
+                indexes,
+            });
+        }
+
         while matches!(self.peek(), Token::Track) {
             tracks.push(self.parse_track()?);
         }
@@ -60,8 +108,8 @@ impl CueParser {
         })
     }
 
-    /// track -> "TRACK" tracknumber tracktype "\n" flags? index*
-    fn parse_track(&mut self) -> anyhow::Result<Track> {
+    /// track -> "TRACK" tracknumber tracktype "\n" (metadata | pregap | index | postgap)*
+    fn parse_track(&mut self) -> anyhow::Result<CueTrack> {
         let Token::Track = self.advance() else {
             anyhow::bail!("Expect 'TRACK'.");
         };
@@ -72,6 +120,10 @@ impl CueParser {
 
         let track_type = match self.advance() {
             Token::Audio => TrackType::Audio,
+            Token::Mode1_2048 => TrackType::Mode1_2048,
+            Token::Mode1_2352 => TrackType::Mode1_2352,
+            Token::Mode2_2048 => TrackType::Mode2_2048,
+            Token::Mode2_2336 => TrackType::Mode2_2336,
             Token::Mode2_2352 => TrackType::Mode2_2352,
             t => anyhow::bail!("Track type {t:?} not implemented."),
         };
@@ -80,39 +132,46 @@ impl CueParser {
             anyhow::bail!("Expect newline after track.");
         };
 
-        let mut indexes = Vec::new();
+        self.last_track = Some((id, track_type));
 
-        // Consume useless flags
-        if matches!(self.peek(), Token::Flags) {
-            self.parse_flags()?;
-        }
+        let mut track = CueTrack {
+            id,
+            track_type,
+            pregap: 0,
+            postgap: 0,
+            indexes: Vec::new(),
+        };
 
-        while matches!(self.peek(), Token::Index) {
-            indexes.push(self.parse_index()?);
+        loop {
+            match self.peek() {
+                Token::Index => track.indexes.push(self.parse_index()?),
+                Token::Pregap => track.pregap = self.parse_gap()?,
+                Token::Postgap => track.postgap = self.parse_gap()?,
+                Token::Metadata(_) => {
+                    self.skip_metadata()?;
+                }
+                _ => break,
+            }
         }
 
-        Ok(Track {
-            id,
-            indexes,
-            track_type,
-        })
+        Ok(track)
     }
 
-    /// flags -> "FLAGS" "DCP" "\n"
-    fn parse_flags(&mut self) -> anyhow::Result<()> {
-        let Token::Flags = self.advance() else {
-            anyhow::bail!("Expect 'FLAGS'.");
+    /// gap -> ("PREGAP" | "POSTGAP") length "\n"
+    fn parse_gap(&mut self) -> anyhow::Result<usize> {
+        let (Token::Pregap | Token::Postgap) = self.advance() else {
+            anyhow::bail!("Expect 'PREGAP' or 'POSTGAP'.");
         };
 
-        let Token::Dcp = self.advance() else {
-            anyhow::bail!("Expect 'DCP'");
+        let &Token::CdTime(length) = self.advance() else {
+            anyhow::bail!("Expect gap length.");
         };
 
         let Token::Newline = self.advance() else {
-            anyhow::bail!("Expect newline after flags.");
+            anyhow::bail!("Expect newline after gap.");
         };
 
-        Ok(())
+        Ok(length)
     }
 
     /// index -> "INDEX" indexnumber sector "\n"
@@ -136,6 +195,22 @@ impl CueParser {
         Ok(TrackIndex { id, lba })
     }
 
+    /// metadata -> ("REM" | "CATALOG" | "TITLE" | "FLAGS" | ...) text "\n"
+    ///
+    /// Returns whether a line was skipped
+    fn skip_metadata(&mut self) -> anyhow::Result<bool> {
+        let Token::Metadata(_) = self.peek() else {
+            return Ok(false);
+        };
+        self.advance();
+
+        let Token::Newline = self.advance() else {
+            anyhow::bail!("Expect newline after metadata.");
+        };
+
+        Ok(true)
+    }
+
     const fn is_at_end(&self) -> bool {
         self.current >= self.tokens.len()
     }
@@ -149,3 +224,89 @@ impl CueParser {
         &self.tokens[self.current]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn parse(text: &str) -> anyhow::Result<CueSheet> {
+        let tokens = Scanner::with_source(text.as_bytes().to_vec()).tokenize()?;
+        CueParser::new(tokens).parse_cuesheet()
+    }
+
+    #[test]
+    fn tracks_and_gaps() -> anyhow::Result<()> {
+        let sheet = parse(
+            "CATALOG 0000000000000\n\
+             FILE \"game.bin\" BINARY\n\
+             \x20 TRACK 01 MODE2/2352\n\
+             \x20   INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n\
+             \x20   FLAGS DCP\n\
+             \x20   PREGAP 00:02:00\n\
+             \x20   INDEX 00 10:00:00\n\
+             \x20   INDEX 01 10:02:00\n\
+             \x20   POSTGAP 00:00:10\n",
+        )?;
+
+        let [file] = sheet.files.as_slice() else {
+            panic!("expected one file, got {}", sheet.files.len());
+        };
+        assert_eq!(file.path, PathBuf::from("game.bin"));
+        assert_eq!(file.format, FileType::Binary);
+
+        let [data, audio] = file.tracks.as_slice() else {
+            panic!("expected two tracks, got {}", file.tracks.len());
+        };
+        assert_eq!((data.id, data.track_type), (1, TrackType::Mode2_2352));
+        assert_eq!((audio.id, audio.track_type), (2, TrackType::Audio));
+        assert_eq!((audio.pregap, audio.postgap), (150, 10));
+
+        let indexes: Vec<(u8, usize)> = audio.indexes.iter().map(|i| (i.id, i.lba)).collect();
+        assert_eq!(indexes, [(0, 45000), (1, 45150)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_continues_into_the_next_file() -> anyhow::Result<()> {
+        let sheet = parse(
+            "FILE \"1.bin\" BINARY\n\
+             TRACK 01 MODE2/2352\n\
+             INDEX 01 00:00:00\n\
+             TRACK 02 AUDIO\n\
+             INDEX 00 01:00:00\n\
+             FILE \"2.bin\" BINARY\n\
+             INDEX 01 00:00:00\n",
+        )?;
+
+        let continued = &sheet.files[1].tracks[0];
+        assert_eq!((continued.id, continued.track_type), (2, TrackType::Audio));
+        assert_eq!(continued.indexes[0].id, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn track_without_index_01() {
+        let sheet = parse(
+            "FILE \"game.bin\" BINARY\n\
+             TRACK 01 MODE2/2352\n\
+             INDEX 01 00:00:00\n\
+             TRACK 02 AUDIO\n\
+             INDEX 00 01:00:00\n",
+        );
+        assert!(sheet.is_err());
+    }
+
+    #[test]
+    fn malformed_sheets() {
+        assert!(parse("FILE \"game.bin\" BINARY\nINDEX 01 00:00:00\n").is_err());
+        assert!(parse("FILE \"game.bin\" MP3\n").is_err());
+        assert!(
+            parse("FILE \"game.bin\" BINARY\nTRACK 01 MODE2/2352 INDEX 01 00:00:00\n").is_err()
+        );
+        assert!(parse("TRACK 01 MODE2/2352\n").is_err());
+    }
+}
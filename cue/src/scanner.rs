@@ -1,5 +1,3 @@
-use super::builder;
-
 pub struct Scanner {
     source: Vec<u8>,
     tokens: Vec<Token>,
@@ -8,7 +6,12 @@ pub struct Scanner {
 }
 
 impl Scanner {
-    pub const fn with_source(source: Vec<u8>) -> Self {
+    pub fn with_source(mut source: Vec<u8>) -> Self {
+        // Sheets written by Windows tools often start with a UTF-8 byte order mark
+        if source.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            source.drain(..3);
+        }
+
         Self {
             source,
             tokens: Vec::new(),
@@ -23,6 +26,8 @@ impl Scanner {
             self.start = self.current;
         }
 
+        // The last line doesn't always end in a newline
+        self.push(Token::Newline);
         self.tokens.push(Token::Eof);
 
         Ok(self.tokens)
@@ -43,11 +48,34 @@ impl Scanner {
             c => anyhow::bail!("Unexpected character '{c}'"),
         };
 
-        self.tokens.push(token);
+        let is_file = token == Token::File;
+        self.push(token);
+
+        // File names are allowed without quotes as long as they have no spaces
+        if is_file {
+            self.skip_spaces();
+            if self.peek() != '"' {
+                self.start = self.current;
+                while !self.is_at_end() && !self.peek().is_ascii_whitespace() {
+                    self.advance();
+                }
+
+                let bytes = &self.source[self.start..self.current];
+                self.push(Token::String(str::from_utf8(bytes)?.to_string()));
+            }
+        }
 
         Ok(())
     }
 
+    /// Add a token, folding blank lines into a single newline
+    fn push(&mut self, token: Token) {
+        let blank_line = matches!(self.tokens.last(), None | Some(Token::Newline));
+        if token != Token::Newline || !blank_line {
+            self.tokens.push(token);
+        }
+    }
+
     fn string(&mut self) -> anyhow::Result<String> {
         while !self.is_at_end() && self.peek() != '"' {
             self.advance();
@@ -71,9 +99,27 @@ impl Scanner {
         }
 
         let bytes = &self.source[self.start..self.current];
-        let keyword = str::from_utf8(bytes).expect("UTF-8");
+        let keyword = str::from_utf8(bytes)?.to_ascii_uppercase();
+
+        // Everything after these up to the end of the line is free form text
+        if matches!(
+            keyword.as_str(),
+            "REM"
+                | "CATALOG"
+                | "TITLE"
+                | "PERFORMER"
+                | "SONGWRITER"
+                | "ISRC"
+                | "CDTEXTFILE"
+                | "FLAGS"
+        ) {
+            while !self.is_at_end() && self.peek() != '\n' {
+                self.advance();
+            }
+            return Ok(Token::Metadata(keyword));
+        }
 
-        try_to_keyword(keyword)
+        try_to_keyword(&keyword)
     }
 
     fn number_or_time(&mut self) -> anyhow::Result<Token> {
@@ -90,6 +136,9 @@ impl Scanner {
 
         // We are parsing MM:SS:FF
         for _ in 0..2 {
+            if self.peek() != ':' {
+                anyhow::bail!("Invalid CD time format");
+            }
             self.advance(); // consume ':'
 
             if !self.peek().is_ascii_digit() {
@@ -102,17 +151,31 @@ impl Scanner {
         }
 
         let bytes = &self.source[self.start..self.current];
-        let word = std::str::from_utf8(bytes)?;
+        let time = std::str::from_utf8(bytes)?;
+        let mut fields = time.split(':');
+        let mut field = || -> anyhow::Result<usize> {
+            let field = fields
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("CD time {time} needs three fields"))?;
+            Ok(field.parse()?)
+        };
+
+        let (minutes, seconds, frames) = (field()?, field()?, field()?);
+        if seconds >= 60 || frames >= 75 {
+            anyhow::bail!("CD time {time} is out of range");
+        }
 
-        Ok(Token::CdTime(to_sectors(
-            word[0..2].parse()?, // MM
-            word[3..5].parse()?, // HH
-            word[6..8].parse()?, // SS
-        )))
+        Ok(Token::CdTime(to_sectors(minutes, seconds, frames)))
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), ' ' | '\t') {
+            self.advance();
+        }
     }
 
     fn peek(&self) -> char {
-        self.source[self.current] as char
+        self.source.get(self.current).map_or('\0', |&ch| ch as char)
     }
 
     fn advance(&mut self) -> char {
@@ -133,12 +196,20 @@ const fn is_alnum(ch: char) -> bool {
 fn try_to_keyword(s: &str) -> anyhow::Result<Token> {
     match s {
         "FILE" => Ok(Token::File),
-        "BINARY" => Ok(Token::Binary),
         "TRACK" => Ok(Token::Track),
         "INDEX" => Ok(Token::Index),
+        "PREGAP" => Ok(Token::Pregap),
+        "POSTGAP" => Ok(Token::Postgap),
+        "BINARY" => Ok(Token::Binary),
+        "MOTOROLA" => Ok(Token::Motorola),
+        "WAVE" => Ok(Token::Wave),
+        "AIFF" => Ok(Token::Aiff),
+        "MP3" => Ok(Token::Mp3),
         "AUDIO" => Ok(Token::Audio),
-        "FLAGS" => Ok(Token::Flags),
-        "DCP" => Ok(Token::Dcp),
+        "MODE1/2048" => Ok(Token::Mode1_2048),
+        "MODE1/2352" => Ok(Token::Mode1_2352),
+        "MODE2/2048" => Ok(Token::Mode2_2048),
+        "MODE2/2336" => Ok(Token::Mode2_2336),
         "MODE2/2352" => Ok(Token::Mode2_2352),
         word => anyhow::bail!("Invalid keyword: -{word}-"),
     }
@@ -149,26 +220,110 @@ pub enum Token {
     File,
     Track,
     Index,
+    Pregap,
+    Postgap,
 
     // File types
     Binary,
+    Motorola,
+    Wave,
+    Aiff,
+    Mp3,
 
     // Track types
     Audio,
+    Mode1_2048,
+    Mode1_2352,
+    Mode2_2048,
+    Mode2_2336,
     Mode2_2352,
 
     String(String),
     Number(u8),
+    /// Time in sectors
     CdTime(usize),
 
+    /// Line we have no use for, only the command is kept
+    Metadata(String),
+
     Newline,
     Eof,
-
-    // Random useless stuff
-    Flags,
-    Dcp,
 }
 
 const fn to_sectors(minutes: usize, seconds: usize, frames: usize) -> usize {
-    (minutes * 60 * 75 + seconds * 75 + frames) * builder::SECTOR_SIZE
+    minutes * 60 * 75 + seconds * 75 + frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(text: &[u8]) -> anyhow::Result<Vec<Token>> {
+        Scanner::with_source(text.to_vec()).tokenize()
+    }
+
+    #[test]
+    fn track_lines() -> anyhow::Result<()> {
+        let tokens = tokenize(b"\xEF\xBB\xBFfile \"a b.bin\" binary\r\n\n  track 01 mode2/2352\n")?;
+        assert_eq!(
+            tokens,
+            [
+                Token::File,
+                Token::String("a b.bin".to_string()),
+                Token::Binary,
+                Token::Newline,
+                Token::Track,
+                Token::Number(1),
+                Token::Mode2_2352,
+                Token::Newline,
+                Token::Eof,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn times_are_in_sectors() -> anyhow::Result<()> {
+        let tokens = tokenize(b"INDEX 01 12:34:56")?;
+        assert_eq!(tokens[2], Token::CdTime((12 * 60 + 34) * 75 + 56));
+
+        assert!(tokenize(b"INDEX 01 12:34:").is_err());
+        assert!(tokenize(b"INDEX 01 12:34").is_err());
+        assert!(tokenize(b"INDEX 01 12:34 56").is_err());
+
+        // 59 seconds and 74 frames are as high as a time goes
+        let tokens = tokenize(b"INDEX 01 00:59:74")?;
+        assert_eq!(tokens[2], Token::CdTime(59 * 75 + 74));
+        assert!(tokenize(b"INDEX 01 00:60:00").is_err());
+        assert!(tokenize(b"INDEX 01 00:99:80").is_err());
+        assert!(tokenize(b"INDEX 01 00:00:75").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unquoted_file_names_and_metadata() -> anyhow::Result<()> {
+        let tokens = tokenize(b"REM GENRE \"Game\"\nFILE game.bin BINARY")?;
+        assert_eq!(
+            tokens[..5],
+            [
+                Token::Metadata("REM".to_string()),
+                Token::Newline,
+                Token::File,
+                Token::String("game.bin".to_string()),
+                Token::Binary,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_input() {
+        assert!(tokenize(b"FILE \"game.bin BINARY").is_err());
+        assert!(tokenize(b"FILE \"\xFF.bin\" BINARY").is_err());
+        assert!(tokenize(b"TRAKC 01 AUDIO").is_err());
+        assert!(tokenize(b"TRACK 01 AUDIO \xC3\xA9").is_err());
+    }
 }
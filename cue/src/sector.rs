@@ -0,0 +1,123 @@
+//! Helpers to rebuild the parts of raw 2352 byte sectors that images often leave out
+
+/// 12 byte sync pattern every data sector starts with
+pub const SYNC: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+
+/// Reed-Solomon lookup tables over GF(2^8): multiply by 2, and the inverse of `x ^ 2x`
+const ECC_TABLES: ([u8; 256], [u8; 256]) = {
+    let mut f = [0u8; 256];
+    let mut b = [0u8; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let j = (i << 1) ^ if i & 0x80 != 0 { 0x11D } else { 0 };
+        f[i] = j as u8;
+        b[i ^ j] = i as u8;
+        i += 1;
+    }
+
+    (f, b)
+};
+
+/// Fill in the P and Q parity of a Mode 1 sector, computed over its header and data
+pub fn generate_ecc(sector: &mut [u8]) {
+    // Q parity covers the P parity too, so P goes first
+    compute_ecc_block(sector, 86, 24, 2, 86, 0x81C);
+    compute_ecc_block(sector, 52, 43, 86, 88, 0x8C8);
+}
+
+fn compute_ecc_block(
+    sector: &mut [u8],
+    major_count: usize,
+    minor_count: usize,
+    major_mult: usize,
+    minor_inc: usize,
+    dest: usize,
+) {
+    let (f, b) = &ECC_TABLES;
+    let size = major_count * minor_count;
+
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 1);
+        let (mut ecc_a, mut ecc_b) = (0u8, 0u8);
+
+        for _ in 0..minor_count {
+            let value = sector[0xC + index];
+            index += minor_inc;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= value;
+            ecc_b ^= value;
+            ecc_a = f[usize::from(ecc_a)];
+        }
+
+        ecc_a = b[usize::from(f[usize::from(ecc_a)] ^ ecc_b)];
+        sector[dest + major] = ecc_a;
+        sector[dest + major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+/// Write the sync pattern and header of the sector at disc position `lba`
+pub fn write_header(sector: &mut [u8], lba: usize, mode: u8) {
+    let bcd = |value: usize| (((value / 10) << 4) | (value % 10)) as u8;
+
+    sector[..12].copy_from_slice(&SYNC);
+    sector[12] = bcd(lba / 75 / 60);
+    sector[13] = bcd(lba / 75 % 60);
+    sector[14] = bcd(lba % 75);
+    sector[15] = mode;
+}
+
+/// Fill in the EDC and ECC of a Mode 1 sector that has its header and 2048 bytes of data
+pub fn generate_mode1(sector: &mut [u8]) {
+    let edc = generate_edc(&sector[..0x810]);
+    sector[0x810..0x814].copy_from_slice(&edc.to_le_bytes());
+    sector[0x814..0x81C].fill(0);
+
+    generate_ecc(sector);
+}
+
+/// Fill in the EDC and ECC of a Mode 2 Form 1 sector that has its subheader and 2048 bytes
+/// of data
+pub fn generate_form1(sector: &mut [u8]) {
+    let edc = generate_edc(&sector[0x10..0x818]);
+    sector[0x818..0x81C].copy_from_slice(&edc.to_le_bytes());
+
+    // Mode 2 parity is computed as if the header was zero, so it survives a header rewrite
+    let header: [u8; 4] = sector[0xC..0x10].try_into().expect("4 bytes");
+    sector[0xC..0x10].fill(0);
+    generate_ecc(sector);
+    sector[0xC..0x10].copy_from_slice(&header);
+}
+
+/// Fill in the EDC of a Mode 2 Form 2 sector, which has no ECC
+pub fn generate_form2(sector: &mut [u8]) {
+    let edc = generate_edc(&sector[0x10..0x92C]);
+    sector[0x92C..0x930].copy_from_slice(&edc.to_le_bytes());
+}
+
+/// CRC32 variant (polynomial 0x8001801B, bit reversed) that sectors store after their data
+pub fn generate_edc(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut edc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                edc = (edc >> 1) ^ if edc & 1 != 0 { 0xD801_8001 } else { 0 };
+                bit += 1;
+            }
+            table[i] = edc;
+            i += 1;
+        }
+        table
+    };
+
+    data.iter().fold(0, |edc, &b| {
+        (edc >> 8) ^ TABLE[((edc ^ u32::from(b)) & 0xFF) as usize]
+    })
+}
@@ -0,0 +1,224 @@
+//! Sector level access to disc images. Images are described as runs of sectors that are either
+//! silence or come from some backing storage, and sectors are only read when they are asked for.
+
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::sync::Arc;
+
+use crate::TrackType;
+use crate::builder::SECTOR_SIZE;
+use crate::sector;
+
+/// Random access to the raw 2352 byte sectors of a disc, starting with the 2 second lead in
+pub trait DiscSource: Send {
+    /// Number of sectors on the disc
+    fn sector_count(&self) -> usize;
+
+    /// Fill `buf`, which is one sector long, with the contents of sector `lba`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sector is past the end of the disc or can't be read.
+    fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> anyhow::Result<()>;
+}
+
+/// Image that is already fully in memory
+impl DiscSource for Box<[u8]> {
+    fn sector_count(&self) -> usize {
+        self.len() / SECTOR_SIZE
+    }
+
+    fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> anyhow::Result<()> {
+        let Some(sector) = self.get(lba * SECTOR_SIZE..(lba + 1) * SECTOR_SIZE) else {
+            anyhow::bail!("sector {lba} is past the end of the disc");
+        };
+        buf.copy_from_slice(sector);
+        Ok(())
+    }
+}
+
+/// Contents of a file a cue sheet refers to
+pub trait ImageFile: Send + Sync {
+    /// Size of the file in bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the size can't be read.
+    fn size(&self) -> anyhow::Result<u64>;
+
+    /// Fill `buf` with the bytes starting at `offset`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes can't be read.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> anyhow::Result<()>;
+}
+
+impl ImageFile for File {
+    fn size(&self) -> anyhow::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> anyhow::Result<()> {
+        Ok(read_exact_at(self, offset, buf)?)
+    }
+}
+
+/// Submode of synthesized Mode 2 Form 1 sectors, just the data bit
+const DATA_SUBMODE: u8 = 0x08;
+
+/// Sectors read straight out of one or more BIN files
+#[derive(Default)]
+pub struct FileSource {
+    layout: Layout<FileRun>,
+}
+
+struct FileRun {
+    file: Arc<dyn ImageFile>,
+    /// Where the run starts inside the file
+    offset: u64,
+    /// Bytes the run has in the file, the last sector may be cut short
+    len: u64,
+    /// Sectors of anything but raw tracks are rebuilt around what the file holds
+    track_type: TrackType,
+    /// Audio samples are stored big endian
+    big_endian: bool,
+}
+
+impl FileSource {
+    pub fn push_silence(&mut self, sectors: usize) {
+        self.layout.push(sectors, None);
+    }
+
+    /// Append `len` bytes of `file` starting at `offset`, returns how many sectors were added
+    pub fn push_file(
+        &mut self,
+        file: Arc<dyn ImageFile>,
+        offset: u64,
+        len: u64,
+        track_type: TrackType,
+        big_endian: bool,
+    ) -> usize {
+        let sectors = len.div_ceil(track_type.sector_size() as u64) as usize;
+
+        self.layout.push(
+            sectors,
+            Some(FileRun {
+                file,
+                offset,
+                len,
+                track_type,
+                big_endian,
+            }),
+        );
+        sectors
+    }
+}
+
+impl DiscSource for FileSource {
+    fn sector_count(&self) -> usize {
+        self.layout.sectors()
+    }
+
+    fn read_sector(&mut self, lba: usize, buf: &mut [u8]) -> anyhow::Result<()> {
+        let Some((run, index)) = self.layout.find(lba)? else {
+            buf.fill(0);
+            return Ok(());
+        };
+
+        let size = run.track_type.sector_size();
+        let start = (index * size) as u64;
+        let len = run.len.saturating_sub(start).min(size as u64) as usize;
+
+        // Cooked sectors are missing their sync pattern and header
+        let header = match run.track_type {
+            TrackType::Mode1_2048 | TrackType::Mode2_2336 => 0x10,
+            TrackType::Mode2_2048 => 0x18,
+            _ => 0,
+        };
+        buf.fill(0);
+        run.file
+            .read_at(run.offset + start, &mut buf[header..header + len])?;
+
+        match run.track_type {
+            TrackType::Audio if run.big_endian => {
+                for sample in buf.chunks_exact_mut(2) {
+                    sample.swap(0, 1);
+                }
+            }
+            TrackType::Mode1_2048 => {
+                sector::write_header(buf, lba, 1);
+                sector::generate_mode1(buf);
+            }
+            TrackType::Mode2_2048 => {
+                sector::write_header(buf, lba, 2);
+                // Plain data subheader, which is stored twice
+                for copy in buf[0x10..0x18].chunks_exact_mut(4) {
+                    copy.copy_from_slice(&[0, 0, DATA_SUBMODE, 0]);
+                }
+                sector::generate_form1(buf);
+            }
+            TrackType::Mode2_2336 => sector::write_header(buf, lba, 2),
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs of sectors in disc order, each one either silence or backed by a `T`
+pub struct Layout<T> {
+    runs: Vec<Run<T>>,
+    sectors: usize,
+}
+
+struct Run<T> {
+    first: usize,
+    backing: Option<T>,
+}
+
+impl<T> Default for Layout<T> {
+    fn default() -> Self {
+        Self {
+            runs: Vec::new(),
+            sectors: 0,
+        }
+    }
+}
+
+impl<T> Layout<T> {
+    pub fn push(&mut self, sectors: usize, backing: Option<T>) {
+        if sectors == 0 {
+            return;
+        }
+
+        self.runs.push(Run {
+            first: self.sectors,
+            backing,
+        });
+        self.sectors += sectors;
+    }
+
+    pub const fn sectors(&self) -> usize {
+        self.sectors
+    }
+
+    /// Backing of the run `lba` is in and its index inside that run, `None` for silence
+    pub fn find(&self, lba: usize) -> anyhow::Result<Option<(&T, usize)>> {
+        if lba >= self.sectors {
+            anyhow::bail!("sector {lba} is past the end of the disc");
+        }
+
+        let run = &self.runs[self.runs.partition_point(|r| r.first <= lba) - 1];
+        Ok(run.backing.as_ref().map(|b| (b, lba - run.first)))
+    }
+}
+
+/// Positioned read that works on a shared file handle
+pub fn read_exact_at(file: &File, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
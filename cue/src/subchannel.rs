@@ -0,0 +1,137 @@
+//! Subchannel Q data. Images only hold the main channel, so Q is rebuilt from the track layout
+//! and the few sectors copy protection modified come from an SBI or LSD file dumped next
+//! to the image.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Control/ADR, track, index, relative MSF, zero, absolute MSF and CRC, all but the CRC in BCD
+pub type SubQ = [u8; 12];
+
+#[derive(Default)]
+pub struct Subchannel {
+    /// Q of the sectors that differ from the synthesized one, by disc sector
+    patches: BTreeMap<usize, SubQ>,
+}
+
+impl Subchannel {
+    /// Load `<image>.sbi` or `<image>.lsd` if one exists
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a subchannel file exists but is corrupt
+    pub fn load_beside(image: &Path) -> anyhow::Result<Self> {
+        for ext in ["sbi", "SBI", "lsd", "LSD"] {
+            let path = image.with_extension(ext);
+            let Ok(bytes) = std::fs::read(&path) else {
+                continue;
+            };
+
+            let patches = if ext.eq_ignore_ascii_case("sbi") {
+                parse_sbi(&bytes)?
+            } else {
+                parse_lsd(&bytes)?
+            };
+
+            return Ok(Self { patches });
+        }
+
+        Ok(Self::default())
+    }
+
+    #[must_use]
+    pub fn patch(&self, lba: usize) -> Option<&SubQ> {
+        self.patches.get(&lba)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.patches.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+}
+
+/// "SBI\0" then entries of an MSF, a type and the replaced Q. Only type 1, a whole Q without
+/// its CRC, is used by real dumps.
+fn parse_sbi(bytes: &[u8]) -> anyhow::Result<BTreeMap<usize, SubQ>> {
+    let Some(mut rest) = bytes.strip_prefix(b"SBI\0") else {
+        anyhow::bail!("missing SBI signature");
+    };
+
+    let mut patches = BTreeMap::new();
+    while !rest.is_empty() {
+        let Some((&[m, s, f, kind], tail)) = rest.split_first_chunk::<4>() else {
+            anyhow::bail!("SBI file is truncated");
+        };
+
+        let len = match kind {
+            1 => 10,
+            2 | 3 => 3,
+            _ => anyhow::bail!("invalid SBI entry type {kind}"),
+        };
+        if tail.len() < len {
+            anyhow::bail!("SBI file is truncated");
+        }
+
+        if kind == 1 {
+            let mut q = [0u8; 12];
+            q[..10].copy_from_slice(&tail[..10]);
+            let crc = q_crc(&q);
+            q[10..].copy_from_slice(&crc.to_be_bytes());
+            patches.insert(msf_to_lba(m, s, f)?, q);
+        }
+
+        rest = &tail[len..];
+    }
+
+    Ok(patches)
+}
+
+/// Entries of an MSF and the whole Q as read from the disc, CRC included
+fn parse_lsd(bytes: &[u8]) -> anyhow::Result<BTreeMap<usize, SubQ>> {
+    if !bytes.len().is_multiple_of(15) {
+        anyhow::bail!("LSD file is truncated");
+    }
+
+    bytes
+        .chunks_exact(15)
+        .map(|entry| {
+            let q = entry[3..].try_into().expect("12 bytes of Q");
+            Ok((msf_to_lba(entry[0], entry[1], entry[2])?, q))
+        })
+        .collect()
+}
+
+fn msf_to_lba(m: u8, s: u8, f: u8) -> anyhow::Result<usize> {
+    let bcd = |x: u8| {
+        let (tens, ones) = (x >> 4, x & 0xF);
+        if tens > 9 || ones > 9 {
+            anyhow::bail!("invalid BCD time {x:02X}");
+        }
+        Ok(usize::from(tens * 10 + ones))
+    };
+
+    Ok((bcd(m)? * 60 + bcd(s)?) * 75 + bcd(f)?)
+}
+
+/// CRC-16/CCITT of the first 10 bytes, stored inverted
+#[must_use]
+pub fn q_crc(q: &SubQ) -> u16 {
+    let crc = q[..10].iter().fold(0u16, |mut crc, &byte| {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    });
+
+    !crc
+}
@@ -2,7 +2,10 @@ use crossbeam::channel::Receiver;
 use crossbeam::channel::Sender;
 use eframe::egui::TextureOptions;
 use eframe::egui::{self};
+use starpsx_core::GameInfo;
+use starpsx_renderer::Deinterlace;
 use starpsx_renderer::FrameBuffer;
+use starpsx_renderer::Overscan;
 
 use crate::debugger::Debugger;
 use crate::emulator::UiCommand;
@@ -10,10 +13,16 @@ use crate::input::GamepadState;
 
 // This holds all the state required after emulator init
 pub struct AppState {
+    /// File name of the running game, if one was loaded
+    pub title: Option<String>,
+    /// Game database entry of the running disc, if it's a known game
+    pub game: Option<&'static GameInfo>,
     pub debugger: Debugger,
     pub frame_rx: Receiver<FrameBuffer>,
-    pub input_tx: Sender<GamepadState>,
+    pub input_tx: Sender<(usize, GamepadState)>,
     pub texture: egui::TextureHandle,
+    /// Shape the last frame is meant to be shown at
+    pub aspect_ratio: f32,
 }
 
 impl AppState {
@@ -22,6 +31,7 @@ impl AppState {
         let image = egui::ColorImage::from_rgba_unmultiplied(fb.resolution, rgba_bytes);
 
         self.texture.set(image, TextureOptions::NEAREST);
+        self.aspect_ratio = fb.aspect_ratio;
     }
 
     pub fn set_vram_display(&self, is_enabled: bool) {
@@ -36,4 +46,33 @@ impl AppState {
     pub fn set_speed(&self, val: bool) {
         self.debugger.sync_send(UiCommand::SetSpeed(val));
     }
+
+    pub fn set_resolution_scale(&self, scale: usize) {
+        self.debugger
+            .sync_send(UiCommand::SetResolutionScale(scale));
+    }
+
+    pub fn set_threaded_renderer(&self, threaded: bool) {
+        self.debugger
+            .sync_send(UiCommand::SetThreadedRenderer(threaded));
+    }
+
+    pub fn set_overscan(&self, overscan: Overscan) {
+        self.debugger.sync_send(UiCommand::SetOverscan(overscan));
+    }
+
+    pub fn set_deinterlace(&self, deinterlace: Deinterlace) {
+        self.debugger
+            .sync_send(UiCommand::SetDeinterlace(deinterlace));
+    }
+
+    pub fn set_precise_geometry(&self, enabled: bool) {
+        self.debugger
+            .sync_send(UiCommand::SetPreciseGeometry(enabled));
+    }
+
+    pub fn set_perspective_correct(&self, enabled: bool) {
+        self.debugger
+            .sync_send(UiCommand::SetPerspectiveCorrect(enabled));
+    }
 }
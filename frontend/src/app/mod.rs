@@ -2,6 +2,7 @@ mod app_state;
 mod ui;
 mod util;
 
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::task::Context;
@@ -17,6 +18,7 @@ use eframe::egui::ViewportCommand;
 use eframe::egui::vec2;
 use eframe::egui::{self};
 use egui_notify::Toasts;
+use starpsx_core::GameInfo;
 use tracing::error;
 use tracing::info;
 use tracing::trace;
@@ -29,6 +31,7 @@ use crate::config::MediaPath;
 use crate::config::{self};
 use crate::debugger::Debugger;
 use crate::emulator::SharedState;
+use crate::emulator::SystemOptions;
 use crate::emulator::UiChannels;
 use crate::emulator::{self};
 use crate::input::ActionValue;
@@ -37,7 +40,10 @@ use crate::input::{self};
 
 pub struct Application {
     gamepad: gilrs::Gilrs,
-    input_state: input::GamepadState,
+    input_state: [input::GamepadState; 2],
+
+    /// Physical gamepad driving each controller port, the keyboard always drives port 1 as well
+    gamepad_ports: [Option<gilrs::GamepadId>; 2],
 
     app_config: config::AppConfig,
     config_path: PathBuf,
@@ -57,6 +63,9 @@ pub struct Application {
     previous_pause: bool,
     full_speed: bool,
 
+    /// CPU timing picked on the command line, wins over the configured ones
+    forced_cpu_timing: Option<config::CpuTimingMode>,
+
     pending_dialog: Option<PendingDialog>,
     displayed_metrics: MetricsSnapshot,
 }
@@ -95,26 +104,25 @@ impl eframe::App for Application {
         if let Some(mut emu) = self.app_state.take() {
             // Process all the input events
             if !is_paused_now {
-                let mut input_dirty = false;
-                let was_analog = self.input_state.analog_mode;
-
-                input_dirty |= self.process_gamepad_events();
-                input_dirty |= self.process_keyboard_events(ctx);
+                let was_analog = self.input_state.each_ref().map(|s| s.analog_mode);
 
-                let is_analog = self.input_state.analog_mode;
+                let mut input_dirty = self.process_gamepad_events();
+                input_dirty[0] |= self.process_keyboard_events(ctx);
 
-                if was_analog != is_analog {
-                    let msg = if is_analog {
-                        "Controller switched to analog mode"
-                    } else {
-                        "Controller switched to digital mode"
-                    };
+                for port in 0..2 {
+                    let is_analog = self.input_state[port].analog_mode;
 
-                    self.toasts.info(msg).duration(Duration::from_secs(2));
-                }
+                    if was_analog[port] != is_analog {
+                        let mode = if is_analog { "analog" } else { "digital" };
+                        let msg = format!("Controller {} switched to {mode} mode", port + 1);
+                        self.toasts.info(msg).duration(Duration::from_secs(2));
+                    }
 
-                if input_dirty {
-                    let _ = emu.input_tx.try_send(self.input_state.clone());
+                    if input_dirty[port] {
+                        let _ = emu
+                            .input_tx
+                            .try_send((port, self.input_state[port].clone()));
+                    }
                 }
             }
 
@@ -163,7 +171,8 @@ impl Application {
             egui_ctx: cc.egui_ctx.clone(),
 
             gamepad: gilrs::Gilrs::new().expect("could not initalize gilrs"),
-            input_state: input::GamepadState::default(),
+            input_state: Default::default(),
+            gamepad_ports: [None; 2],
 
             app_state: None,
 
@@ -180,6 +189,7 @@ impl Application {
 
             previous_pause: false,
             full_speed: launch_config.full_speed,
+            forced_cpu_timing: launch_config.cpu_timing,
 
             pending_dialog: None,
 
@@ -215,68 +225,92 @@ impl Application {
                 };
 
                 if i.key_pressed(*key) {
-                    changed |= self
-                        .input_state
-                        .handle_action(action, ActionValue::Digital(true));
+                    changed |=
+                        self.input_state[0].handle_action(action, ActionValue::Digital(true));
                 }
 
                 if i.key_released(*key) {
-                    changed |= self
-                        .input_state
-                        .handle_action(action, ActionValue::Digital(false));
+                    changed |=
+                        self.input_state[0].handle_action(action, ActionValue::Digital(false));
                 }
             }
         });
         changed
     }
 
-    fn process_gamepad_events(&mut self) -> bool {
-        let mut changed = false;
-        while let Some(gilrs::Event { event, .. }) = self.gamepad.next_event() {
-            match event {
-                gilrs::EventType::ButtonPressed(button, _) => {
-                    let phys = PhysicalInput::GilrsButton(button);
-                    if let Some(action) = self.app_config.keybinds.get(&phys).copied() {
-                        changed |= self
-                            .input_state
-                            .handle_action(action, ActionValue::Digital(true));
-                    }
-                }
-
-                gilrs::EventType::ButtonReleased(button, _) => {
-                    let phys = PhysicalInput::GilrsButton(button);
-                    if let Some(action) = self.app_config.keybinds.get(&phys).copied() {
-                        changed |= self
-                            .input_state
-                            .handle_action(action, ActionValue::Digital(false));
-                    }
-                }
+    /// Returns which controller ports changed state
+    fn process_gamepad_events(&mut self) -> [bool; 2] {
+        let mut changed = [false; 2];
+        while let Some(gilrs::Event { id, event, .. }) = self.gamepad.next_event() {
+            let (phys, value) = match event {
+                gilrs::EventType::ButtonPressed(button, _) => (
+                    PhysicalInput::GilrsButton(button),
+                    ActionValue::Digital(true),
+                ),
+
+                gilrs::EventType::ButtonReleased(button, _) => (
+                    PhysicalInput::GilrsButton(button),
+                    ActionValue::Digital(false),
+                ),
 
                 gilrs::EventType::AxisChanged(axis, value, _) => {
-                    let phys = PhysicalInput::GilrsAxis(axis);
-                    if let Some(action) = self.app_config.keybinds.get(&phys).copied() {
-                        changed |= self
-                            .input_state
-                            .handle_action(action, ActionValue::Analog(value));
-                    }
+                    (PhysicalInput::GilrsAxis(axis), ActionValue::Analog(value))
                 }
 
                 gilrs::EventType::Connected => {
-                    info!("gamepad connected");
-                    self.toasts.info("Gamepad Connected!");
+                    let port = self.gamepad_port(id);
+                    info!(?port, "gamepad connected");
+                    let msg = port.map_or_else(
+                        || "Gamepad Connected!".into(),
+                        |port| format!("Gamepad Connected as Controller {}!", port + 1),
+                    );
+                    self.toasts.info(msg);
+                    continue;
                 }
 
                 gilrs::EventType::Disconnected => {
                     info!("gamepad disconnected");
                     self.toasts.info("Gamepad Disconnected!");
+
+                    for port in &mut self.gamepad_ports {
+                        if *port == Some(id) {
+                            *port = None;
+                        }
+                    }
+                    continue;
                 }
 
-                _ => trace!(?event, "gamepad event ignored"),
+                _ => {
+                    trace!(?event, "gamepad event ignored");
+                    continue;
+                }
+            };
+
+            let Some(action) = self.app_config.keybinds.get(&phys).copied() else {
+                continue;
+            };
+
+            // Extra gamepads beyond the two ports are ignored
+            if let Some(port) = self.gamepad_port(id) {
+                changed[port] |= self.input_state[port].handle_action(action, value);
             }
         }
         changed
     }
 
+    /// Controller port driven by a physical gamepad, gamepads take free ports in the order
+    /// they are first seen. Port 1 is left to the keyboard if it has its own.
+    fn gamepad_port(&mut self, id: gilrs::GamepadId) -> Option<usize> {
+        if let Some(port) = self.gamepad_ports.iter().position(|p| *p == Some(id)) {
+            return Some(port);
+        }
+
+        let first = usize::from(self.app_config.keyboard_own_port);
+        let port = (first..self.gamepad_ports.len()).find(|&p| self.gamepad_ports[p].is_none())?;
+        self.gamepad_ports[port] = Some(id);
+        Some(port)
+    }
+
     fn is_paused(&self) -> bool {
         self.app_state
             .as_ref()
@@ -293,21 +327,36 @@ impl Application {
         // Message channels for thread communication
         let (frame_tx, frame_rx) = crossbeam::channel::bounded(1);
         let (ui_command_tx, ui_command_rx) = crossbeam::channel::bounded(1);
-        let (input_tx, input_rx) = crossbeam::channel::bounded(1);
+        let (input_tx, input_rx) = crossbeam::channel::bounded(8);
         let (snapshot_tx, snapshot_rx) = crossbeam::channel::bounded(1);
 
         let shared_state = Arc::new(SharedState::default());
 
+        let title = runnable_path.as_ref().map(MediaPath::file_prefix);
+        // Loaded once, the disc is identified from the same copy the system runs
+        let mut media = runnable_path.as_ref().map(MediaPath::load).transpose()?;
+        let (serial, game) = media.as_mut().map_or((None, None), emulator::identify);
+
+        let cpu_timing = self
+            .forced_cpu_timing
+            .unwrap_or_else(|| self.app_config.cpu_timing_for(title.as_deref(), game));
+        info!(?cpu_timing, "starting with cpu timing");
+
         let memory_card = {
             match self.app_config.memory_card_type {
                 config::MemoryCardType::PerTitle => runnable_path
                     .as_ref()
-                    .filter(|f| matches!(f, MediaPath::Cue(_)))
-                    .map(|f| {
-                        self.memory_cards_path
-                            .join(f.file_prefix())
-                            .with_extension("mcd")
-                    }),
+                    .filter(|f| {
+                        matches!(
+                            f,
+                            MediaPath::Cue(_)
+                                | MediaPath::Chd(_)
+                                | MediaPath::Ecm(_)
+                                | MediaPath::Iso(_)
+                                | MediaPath::M3u(_)
+                        )
+                    })
+                    .map(|f| self.per_title_card(f, serial.as_deref())),
                 config::MemoryCardType::Shared => {
                     Some(self.memory_cards_path.join("shared_card.mcd"))
                 }
@@ -315,6 +364,10 @@ impl Application {
             }
         };
 
+        // Slot 2 gets its own card next to the slot 1 one
+        let second_card = memory_card.as_deref().map(second_slot_card);
+        let memory_cards = [memory_card, second_card];
+
         // Build emulator from the provided configuration
         let emulator = emulator::Emulator::build(
             UiChannels {
@@ -325,14 +378,26 @@ impl Application {
             },
             shared_state.clone(),
             bios_path.clone(),
-            runnable_path,
-            memory_card,
+            runnable_path.clone().zip(media),
+            SystemOptions {
+                memory_cards,
+                player_2: self.app_config.player_2,
+                cpu_timing: cpu_timing.into(),
+                resolution_scale: self.app_config.resolution_scale.factor(),
+                threaded_renderer: self.app_config.threaded_renderer,
+                overscan: self.app_config.overscan.into(),
+                deinterlace: self.app_config.deinterlace.into(),
+                precise_geometry: self.app_config.precise_geometry,
+                perspective_correct: self.app_config.perspective_correct,
+            },
             self.app_config.display_vram,
             self.full_speed,
         )?;
 
         self.app_state = Some(AppState {
-            debugger: Debugger::new(shared_state, ui_command_tx, snapshot_rx),
+            title,
+            game,
+            debugger: Debugger::new(shared_state, ui_command_tx, snapshot_rx, runnable_path),
             input_tx,
             frame_rx,
             texture: self.egui_ctx.load_texture(
@@ -340,11 +405,48 @@ impl Application {
                 ColorImage::filled([100, 100], Color32::BLACK),
                 egui::TextureOptions::NEAREST,
             ),
+            aspect_ratio: 4.0 / 3.0,
         });
 
         emulator.run()
     }
 
+    /// Card named after the disc serial so it survives renaming the file. All discs of a known
+    /// game share the first one's card.
+    fn per_title_card(&self, media: &MediaPath, serial: Option<&str>) -> PathBuf {
+        let by_file = self
+            .memory_cards_path
+            .join(media.file_prefix())
+            .with_extension("mcd");
+
+        let Some(serial) = serial else {
+            return by_file;
+        };
+
+        let id = GameInfo::lookup(serial).map_or(serial, |g| g.id);
+        let by_serial = self.memory_cards_path.join(id).with_extension("mcd");
+
+        // Cards used to be named after the file, copy both slots over to the new name and keep
+        // the old files in case an older version is still used
+        if !by_serial.exists() && by_file.exists() {
+            for (from, to) in [
+                (by_file.clone(), by_serial.clone()),
+                (second_slot_card(&by_file), second_slot_card(&by_serial)),
+            ] {
+                if !from.exists() {
+                    continue;
+                }
+
+                match std::fs::copy(&from, &to) {
+                    Ok(_) => info!(?from, ?to, "copied memory card"),
+                    Err(err) => error!(%err, ?from, "could not copy memory card"),
+                }
+            }
+        }
+
+        by_serial
+    }
+
     fn start_bios(&mut self) -> anyhow::Result<()> {
         if let Some(state) = self.app_state.take() {
             state.shutdown();
@@ -364,9 +466,12 @@ impl Application {
 
         self.start_emulator(Some(runnable))?;
 
-        ctx.send_viewport_cmd(ViewportCommand::Title(
-            "StarPSX - ".to_string() + &file_prefix,
-        ));
+        let name = self
+            .app_state
+            .as_ref()
+            .and_then(|s| s.game)
+            .map_or(file_prefix, |g| g.title.to_string());
+        ctx.send_viewport_cmd(ViewportCommand::Title("StarPSX - ".to_string() + &name));
 
         Ok(())
     }
@@ -412,3 +517,9 @@ impl Application {
         Ok(())
     }
 }
+
+/// Slot 2 card that goes with a slot 1 card
+fn second_slot_card(path: &Path) -> PathBuf {
+    let stem = path.file_stem().expect("card file name").to_string_lossy();
+    path.with_file_name(format!("{stem}_2.mcd"))
+}
@@ -2,13 +2,18 @@ use eframe::egui::load::SizedTexture;
 use eframe::egui::{self};
 use egui_extras::Column;
 use rfd::AsyncFileDialog;
+use starpsx_core::GameInfo;
 use tracing::error;
 
 use crate::app::Application;
 use crate::app::app_state::AppState;
 use crate::app::util::PendingDialog;
 use crate::config;
+use crate::config::CpuTimingMode;
+use crate::config::DeinterlaceMode;
 use crate::config::MemoryCardType;
+use crate::config::OverscanMode;
+use crate::config::ResolutionScale;
 
 pub fn show_central_panel(app: &AppState, ctx: &egui::Context, vram_open: bool) {
     egui::CentralPanel::default()
@@ -17,7 +22,7 @@ pub fn show_central_panel(app: &AppState, ctx: &egui::Context, vram_open: bool)
             let (width, height) = if vram_open {
                 (1024.0, 512.0)
             } else {
-                (640.0, 480.0)
+                (480.0 * app.aspect_ratio, 480.0)
             };
 
             // No resolution means show a 4:3 black screen
@@ -47,7 +52,13 @@ pub fn show_top_menu(app: &mut Application, ctx: &egui::Context) {
                         if ui.button("Start File").clicked() {
                             app.pending_dialog = Some(PendingDialog::SelectFile(Box::pin(
                                 AsyncFileDialog::new()
-                                    .add_filter("Game", &["bin", "BIN", "cue", "exe", "ps-exe"])
+                                    .add_filter(
+                                        "Game",
+                                        &[
+                                            "bin", "BIN", "cue", "chd", "ecm", "iso", "img", "m3u",
+                                            "exe", "ps-exe",
+                                        ],
+                                    )
                                     .set_title("Select file to Run")
                                     .pick_file(),
                             )));
@@ -75,6 +86,10 @@ pub fn show_top_menu(app: &mut Application, ctx: &egui::Context) {
                         emu.debugger.restart();
                     }
 
+                    if ui.button("Change disc").clicked() {
+                        emu.debugger.change_disc();
+                    }
+
                     if ui.button("Stop").clicked() {
                         emu.shutdown();
                         ctx.send_viewport_cmd(egui::ViewportCommand::Title("StarPSX".to_string()));
@@ -102,6 +117,78 @@ pub fn show_top_menu(app: &mut Application, ctx: &egui::Context) {
                     app.memory_cards_modal_open = true;
                 }
 
+                if ui
+                    .checkbox(&mut app.app_config.player_2, "Controller 2")
+                    .on_hover_text("Takes effect the next time a game is started")
+                    .clicked()
+                {
+                    app.app_config.save_to_file(&app.config_path);
+                }
+
+                if ui
+                    .checkbox(
+                        &mut app.app_config.keyboard_own_port,
+                        "Keyboard as Controller 1",
+                    )
+                    .on_hover_text(
+                        "Gamepads start at controller 2, so keyboard and gamepad can play together",
+                    )
+                    .clicked()
+                {
+                    app.app_config.save_to_file(&app.config_path);
+                    // Gamepads pick their ports again on their next input
+                    app.gamepad_ports = [None; 2];
+                }
+
+                ui.menu_button("Resolution", |ui| show_resolution_menu(app, ui));
+                ui.menu_button("Overscan", |ui| show_overscan_menu(app, ui));
+                ui.menu_button("Deinterlacing", |ui| show_deinterlace_menu(app, ui));
+
+                if ui
+                    .checkbox(&mut app.app_config.threaded_renderer, "Threaded Renderer")
+                    .on_hover_text("Draw on a separate thread")
+                    .clicked()
+                {
+                    app.app_config.save_to_file(&app.config_path);
+                    if let Some(ref app_state) = app.app_state {
+                        app_state.set_threaded_renderer(app.app_config.threaded_renderer);
+                    }
+                }
+
+                if ui
+                    .checkbox(&mut app.app_config.precise_geometry, "Precise Geometry")
+                    .on_hover_text(
+                        "Place 3D vertices between pixels to stop polygons wobbling (PGXP)",
+                    )
+                    .clicked()
+                {
+                    app.app_config.save_to_file(&app.config_path);
+                    if let Some(ref app_state) = app.app_state {
+                        app_state.set_precise_geometry(app.app_config.precise_geometry);
+                    }
+                }
+
+                if ui
+                    .add_enabled(
+                        app.app_config.precise_geometry,
+                        egui::Checkbox::new(
+                            &mut app.app_config.perspective_correct,
+                            "Perspective Correct Textures",
+                        ),
+                    )
+                    .on_hover_text("Stop textures warping on polygons with precise geometry")
+                    .clicked()
+                {
+                    app.app_config.save_to_file(&app.config_path);
+                    if let Some(ref app_state) = app.app_state {
+                        app_state.set_perspective_correct(app.app_config.perspective_correct);
+                    }
+                }
+
+                ui.menu_button("CPU Timing", |ui| show_cpu_timing_menu(app, ui))
+                    .response
+                    .on_hover_text("Takes effect the next time a game is started");
+
                 if !ui.toggle_value(&mut app.full_speed, "Full Speed").clicked() {
                     return;
                 }
@@ -147,6 +234,104 @@ pub fn show_top_menu(app: &mut Application, ctx: &egui::Context) {
     });
 }
 
+const CPU_TIMING_MODES: [(CpuTimingMode, &str); 2] = [
+    (CpuTimingMode::Fast, "Fast"),
+    (CpuTimingMode::Accurate, "Accurate"),
+];
+
+/// Default timing model, plus an override for the running game
+fn show_cpu_timing_menu(app: &mut Application, ui: &mut egui::Ui) {
+    let mut changed = false;
+
+    ui.label("All games");
+    for (mode, label) in CPU_TIMING_MODES {
+        changed |= ui
+            .radio_value(&mut app.app_config.cpu_timing, mode, label)
+            .changed();
+    }
+
+    if let Some(title) = app.app_state.as_ref().and_then(|s| s.title.clone()) {
+        ui.separator();
+        ui.label(format!("Only {title}"));
+
+        let overrides = &mut app.app_config.cpu_timing_overrides;
+        let mut mode = overrides.get(&title).copied();
+
+        changed |= ui.radio_value(&mut mode, None, "Use default").changed();
+        for (value, label) in CPU_TIMING_MODES {
+            changed |= ui.radio_value(&mut mode, Some(value), label).changed();
+        }
+
+        match mode {
+            Some(mode) => overrides.insert(title, mode),
+            None => overrides.remove(&title),
+        };
+    }
+
+    if changed {
+        app.app_config.save_to_file(&app.config_path);
+    }
+}
+
+fn show_resolution_menu(app: &mut Application, ui: &mut egui::Ui) {
+    let mut changed = false;
+
+    for scale in ResolutionScale::ALL {
+        changed |= ui
+            .radio_value(&mut app.app_config.resolution_scale, scale, scale.label())
+            .changed();
+    }
+
+    if !changed {
+        return;
+    }
+
+    app.app_config.save_to_file(&app.config_path);
+    if let Some(ref app_state) = app.app_state {
+        app_state.set_resolution_scale(app.app_config.resolution_scale.factor());
+    }
+}
+
+fn show_overscan_menu(app: &mut Application, ui: &mut egui::Ui) {
+    let mut changed = false;
+
+    for mode in OverscanMode::ALL {
+        changed |= ui
+            .radio_value(&mut app.app_config.overscan, mode, mode.label())
+            .on_hover_text(mode.description())
+            .changed();
+    }
+
+    if !changed {
+        return;
+    }
+
+    app.app_config.save_to_file(&app.config_path);
+    if let Some(ref app_state) = app.app_state {
+        app_state.set_overscan(app.app_config.overscan.into());
+    }
+}
+
+fn show_deinterlace_menu(app: &mut Application, ui: &mut egui::Ui) {
+    let mut changed = false;
+
+    for mode in DeinterlaceMode::ALL {
+        changed |= ui
+            .radio_value(&mut app.app_config.deinterlace, mode, mode.label())
+            .on_hover_text(mode.description())
+            .changed();
+    }
+
+    if !changed {
+        return;
+    }
+
+    app.app_config.save_to_file(&app.config_path);
+    if let Some(ref app_state) = app.app_state {
+        app_state.set_deinterlace(app.app_config.deinterlace.into());
+    }
+}
+
 pub fn show_info_modal(show_modal: &mut bool, ctx: &egui::Context) {
     if !*show_modal {
         return;
@@ -194,7 +379,11 @@ pub fn show_info_modal(show_modal: &mut bool, ctx: &egui::Context) {
 pub fn show_performance_panel(app: &Application, ctx: &egui::Context) {
     egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
         ui.horizontal(|ui| {
-            ui.label(format!("FPS: {:.1}", app.displayed_metrics.fps));
+            let target = app
+                .app_state
+                .as_ref()
+                .map_or(0, |emu| emu.debugger.refresh_rate());
+            ui.label(format!("FPS: {:.1} / {target}", app.displayed_metrics.fps));
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                 ui.label("Software Renderer");
                 ui.separator();
@@ -265,7 +454,7 @@ pub fn show_memory_cards_modal(app: &mut Application, ctx: &egui::Context) {
             MemoryCardType::PerTitle => {
                 ui.colored_label(
                     ui.visuals().widgets.inactive.fg_stroke.color,
-                    "A separate memory card will be used for each title.",
+                    "A separate pair of memory cards will be used for each title.",
                 );
 
                 let mut per_title_cards = std::fs::read_dir(&cards_dir)
@@ -279,7 +468,10 @@ pub fn show_memory_cards_modal(app: &mut Application, ctx: &egui::Context) {
                             .is_some_and(|ext| ext.eq_ignore_ascii_case("mcd"))
                     })
                     .filter(|path| {
-                        path.file_name().and_then(|name| name.to_str()) != Some("shared_card.mcd")
+                        !matches!(
+                            path.file_name().and_then(|name| name.to_str()),
+                            Some("shared_card.mcd" | "shared_card_2.mcd")
+                        )
                     })
                     .filter_map(|path| {
                         path.file_name()
@@ -298,7 +490,12 @@ pub fn show_memory_cards_modal(app: &mut Application, ctx: &egui::Context) {
                 } else {
                     ui.label("Found memory cards:");
                     for card_name in per_title_cards {
-                        ui.monospace(card_name);
+                        // Cards of known games are named after their serial
+                        let serial = card_name.trim_end_matches(".mcd").trim_end_matches("_2");
+                        match GameInfo::lookup(serial) {
+                            Some(game) => ui.monospace(format!("{card_name} ({})", game.title)),
+                            None => ui.monospace(card_name),
+                        };
                     }
                 }
             }
@@ -400,6 +597,12 @@ pub fn show_keybinds(open: &mut bool, ctx: &egui::Context) {
         .default_pos(egui::pos2(30., 30.))
         .open(open)
         .show(ctx, |ui| {
+            ui.label(
+                "Controller 2 uses the same layout on the second connected gamepad, or the \
+                 first one with Keyboard as Controller 1.",
+            );
+            ui.add_space(6.0);
+
             egui_extras::TableBuilder::new(ui)
                 .striped(true)
                 .resizable(false)
@@ -44,7 +44,7 @@ impl MetricsSnapshot {
         }
 
         // Non interlaced displays have their rows duplicated so divide by 2
-        self.last_frame_data = if fb.is_interlaced {
+        self.last_frame_data = if fb.is_interlaced || fb.is_deinterlaced {
             Some((fb.resolution, true))
         } else {
             Some(([fb.resolution[0], fb.resolution[1] / 2], false))
@@ -1,19 +1,26 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::path::PathBuf;
 
 use anyhow::anyhow;
 use clap::Parser;
+use clap::ValueEnum;
 use eframe::egui::Key as EKey;
 use gilrs::Axis as GAxis;
 use gilrs::Button as GButton;
 use serde::Deserialize;
 use serde::Serialize;
+use starpsx_core::CpuTiming;
+use starpsx_core::GameInfo;
 use starpsx_core::Media;
 use starpsx_core::gamepad;
+use starpsx_renderer::Deinterlace;
+use starpsx_renderer::Overscan;
 use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use crate::emulator::parse_runnable;
 use crate::input;
 use crate::input::Action;
 use crate::input::PhysicalInput;
@@ -23,12 +30,23 @@ pub enum MediaPath {
     Exe(PathBuf),
     Bin(PathBuf),
     Cue(PathBuf),
+    Chd(PathBuf),
+    Ecm(PathBuf),
+    Iso(PathBuf),
+    /// Playlist of the discs of a multi-disc game
+    M3u(PathBuf),
 }
 
 impl MediaPath {
     pub fn file_prefix(&self) -> String {
         let buf = match self {
-            Self::Exe(path_buf) | Self::Bin(path_buf) | Self::Cue(path_buf) => path_buf,
+            Self::Exe(path_buf)
+            | Self::Bin(path_buf)
+            | Self::Cue(path_buf)
+            | Self::Chd(path_buf)
+            | Self::Ecm(path_buf)
+            | Self::Iso(path_buf)
+            | Self::M3u(path_buf) => path_buf,
         };
 
         buf.file_prefix()
@@ -40,12 +58,45 @@ impl MediaPath {
     pub fn load(&self) -> anyhow::Result<Media> {
         let media = match self {
             Self::Exe(path) => Media::Executable(std::fs::read(path)?),
-            Self::Bin(path) => Media::Binary(std::fs::read(path)?),
+            Self::Bin(path) => Media::Disc(cue::build_bin_disk(path)?),
             Self::Cue(path) => Media::Disc(cue::build_disk(path)?),
+            Self::Chd(path) => Media::Disc(cue::build_chd_disk(path)?),
+            Self::Ecm(path) => Media::Disc(cue::build_ecm_disk(path)?),
+            Self::Iso(path) => Media::Disc(cue::build_iso_disk(path)?),
+            Self::M3u(_) => return self.clone().into_discs()?[0].load(),
         };
 
         Ok(media)
     }
+
+    /// Discs that can be swapped between, every disc of a playlist or just this one
+    pub fn into_discs(self) -> anyhow::Result<Vec<Self>> {
+        let Self::M3u(path) = &self else {
+            return Ok(vec![self]);
+        };
+
+        // Entries are relative to the playlist, lines starting with # are comments
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let discs = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match parse_runnable(parent.join(line))? {
+                disc @ (Self::Bin(_)
+                | Self::Cue(_)
+                | Self::Chd(_)
+                | Self::Ecm(_)
+                | Self::Iso(_)) => Ok(disc),
+                _ => Err(anyhow!("playlist entry {line} is not a disc image")),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if discs.is_empty() {
+            anyhow::bail!("playlist has no discs");
+        }
+
+        Ok(discs)
+    }
 }
 
 /// Cross Platform PS1 Emulator written in Rust
@@ -68,11 +119,71 @@ pub struct Args {
     #[arg(short, long)]
     full_speed: bool,
 
+    /// CPU timing model, overrides the configured one for this run
+    #[arg(long, value_enum, value_name = "MODEL")]
+    cpu_timing: Option<CpuTimingMode>,
+
+    /// Run without a window, for scripted and automated runs
+    #[arg(long, requires = "file")]
+    headless: bool,
+
+    /// [headless] BIOS file to use instead of the configured one
+    #[arg(long, value_name = "PATH", requires = "headless")]
+    bios: Option<PathBuf>,
+
+    /// [headless] Stop after this many frames, 18000 (5 minutes) by default
+    #[arg(long, value_name = "N", requires = "headless")]
+    frames: Option<u64>,
+
+    /// [headless] Stop once this string is printed to the TTY
+    #[arg(long, value_name = "STRING", requires = "headless")]
+    until_tty: Option<String>,
+
+    /// [headless] Save the last frame to a .png or .ppm file
+    #[arg(long, value_name = "PATH", requires = "headless")]
+    screenshot: Option<PathBuf>,
+
+    /// [headless] Save all audio output to a .wav file
+    #[arg(long, value_name = "PATH", requires = "headless")]
+    audio: Option<PathBuf>,
+
+    /// List the files on the disc and exit
+    #[arg(long, requires = "file")]
+    list_files: bool,
+
+    /// Copy all files on the disc into a directory and exit
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "file",
+        conflicts_with = "list_files"
+    )]
+    export_files: Option<PathBuf>,
+
     /// File to start the emulator with
     #[arg(value_name = "FILE")]
     file: Option<PathBuf>,
 }
 
+/// Frames a headless run stops after when `--frames` isn't given, so it can't hang
+const DEFAULT_HEADLESS_FRAMES: u64 = 18000;
+
+/// Work on the disc's filesystem instead of running it
+pub enum DiscTool {
+    List,
+    Export(PathBuf),
+}
+
+pub struct HeadlessConfig {
+    pub bios_path: PathBuf,
+    /// Timing forced from the command line, else it's picked once the disc is identified
+    pub cpu_timing: Option<CpuTimingMode>,
+    pub frames: u64,
+    pub until_tty: Option<String>,
+    pub screenshot: Option<PathBuf>,
+    pub audio: Option<PathBuf>,
+}
+
 pub struct LaunchConfig {
     pub app_config: AppConfig,
     pub runnable_path: Option<PathBuf>,
@@ -80,6 +191,9 @@ pub struct LaunchConfig {
     pub config_path: PathBuf,
     pub memory_cards_path: PathBuf,
     pub full_speed: bool,
+    pub cpu_timing: Option<CpuTimingMode>,
+    pub headless: Option<HeadlessConfig>,
+    pub disc_tool: Option<DiscTool>,
 }
 
 impl LaunchConfig {
@@ -108,6 +222,24 @@ impl LaunchConfig {
             app_config.debugger_view = true;
         }
 
+        let headless = if args.headless {
+            let bios_path = args
+                .bios
+                .or_else(|| app_config.bios_path.clone())
+                .ok_or_else(|| anyhow!("no bios configured, pass one with --bios"))?;
+
+            Some(HeadlessConfig {
+                bios_path,
+                cpu_timing: args.cpu_timing,
+                frames: args.frames.unwrap_or(DEFAULT_HEADLESS_FRAMES),
+                until_tty: args.until_tty,
+                screenshot: args.screenshot,
+                audio: args.audio,
+            })
+        } else {
+            None
+        };
+
         Ok(Self {
             app_config,
             runnable_path,
@@ -115,6 +247,12 @@ impl LaunchConfig {
             memory_cards_path,
             auto_run: args.auto_run,
             full_speed: args.full_speed,
+            cpu_timing: args.cpu_timing,
+            headless,
+            disc_tool: args
+                .export_files
+                .map(DiscTool::Export)
+                .or_else(|| args.list_files.then_some(DiscTool::List)),
         })
     }
 }
@@ -127,6 +265,158 @@ pub enum MemoryCardType {
     None,
 }
 
+#[derive(
+    Default,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    Clone,
+    Copy,
+    Debug,
+    ValueEnum
+)]
+pub enum CpuTimingMode {
+    /// Flat 2 cycles per instruction
+    #[default]
+    Fast,
+
+    /// Memory wait states, instruction cache, MULT/DIV and GTE stalls
+    Accurate,
+}
+
+impl From<CpuTiming> for CpuTimingMode {
+    fn from(timing: CpuTiming) -> Self {
+        match timing {
+            CpuTiming::Fast => Self::Fast,
+            CpuTiming::Accurate => Self::Accurate,
+        }
+    }
+}
+
+impl From<CpuTimingMode> for CpuTiming {
+    fn from(mode: CpuTimingMode) -> Self {
+        match mode {
+            CpuTimingMode::Fast => Self::Fast,
+            CpuTimingMode::Accurate => Self::Accurate,
+        }
+    }
+}
+
+/// Internal resolution the renderer draws at, as a multiple of the console's
+#[derive(Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ResolutionScale {
+    #[default]
+    Native,
+    X2,
+    X3,
+    X4,
+}
+
+impl ResolutionScale {
+    pub const ALL: [Self; 4] = [Self::Native, Self::X2, Self::X3, Self::X4];
+
+    pub const fn factor(self) -> usize {
+        match self {
+            Self::Native => 1,
+            Self::X2 => 2,
+            Self::X3 => 3,
+            Self::X4 => 4,
+        }
+    }
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Native => "Native",
+            Self::X2 => "2x",
+            Self::X3 => "3x",
+            Self::X4 => "4x",
+        }
+    }
+}
+
+/// How much of the picture around the display area is shown
+#[derive(Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OverscanMode {
+    Full,
+    #[default]
+    Cropped,
+    AspectCorrected,
+}
+
+impl OverscanMode {
+    pub const ALL: [Self; 3] = [Self::Full, Self::Cropped, Self::AspectCorrected];
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Full => "Full",
+            Self::Cropped => "Cropped",
+            Self::AspectCorrected => "Aspect Corrected",
+        }
+    }
+
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Full => "Everything a TV could show, with borders around smaller pictures",
+            Self::Cropped => "The area a TV typically shows, stretched to 4:3",
+            Self::AspectCorrected => "The area a TV typically shows, at the shape a TV gives it",
+        }
+    }
+}
+
+impl From<OverscanMode> for Overscan {
+    fn from(mode: OverscanMode) -> Self {
+        match mode {
+            OverscanMode::Full => Self::Full,
+            OverscanMode::Cropped => Self::Cropped,
+            OverscanMode::AspectCorrected => Self::AspectCorrected,
+        }
+    }
+}
+
+/// How the two fields of 480i are put together into one frame
+#[derive(Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DeinterlaceMode {
+    #[default]
+    Weave,
+    Bob,
+    Blend,
+    MotionAdaptive,
+}
+
+impl DeinterlaceMode {
+    pub const ALL: [Self; 4] = [Self::Weave, Self::Bob, Self::Blend, Self::MotionAdaptive];
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Weave => "Weave",
+            Self::Bob => "Bob",
+            Self::Blend => "Blend",
+            Self::MotionAdaptive => "Motion Adaptive",
+        }
+    }
+
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Weave => "Interleave both fields, sharpest but moving edges comb",
+            Self::Bob => "Show the latest field with its lines doubled",
+            Self::Blend => "Average neighbouring lines of both fields",
+            Self::MotionAdaptive => "Weave still parts of the picture and bob moving ones",
+        }
+    }
+}
+
+impl From<DeinterlaceMode> for Deinterlace {
+    fn from(mode: DeinterlaceMode) -> Self {
+        match mode {
+            DeinterlaceMode::Weave => Self::Weave,
+            DeinterlaceMode::Bob => Self::Bob,
+            DeinterlaceMode::Blend => Self::Blend,
+            DeinterlaceMode::MotionAdaptive => Self::MotionAdaptive,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct AppConfig {
@@ -135,6 +425,38 @@ pub struct AppConfig {
     pub debugger_view: bool,
     pub memory_card_type: MemoryCardType,
 
+    /// Plug a second controller into port 2, driven by the second connected gamepad, or the
+    /// first one if the keyboard has its own port
+    pub player_2: bool,
+
+    /// The keyboard gets controller 1 to itself and gamepads start at controller 2, so the
+    /// keyboard and a gamepad can play together
+    pub keyboard_own_port: bool,
+
+    /// CPU timing model used unless a game has its own
+    pub cpu_timing: CpuTimingMode,
+
+    /// Per game CPU timing, keyed by the game's file name
+    pub cpu_timing_overrides: BTreeMap<String, CpuTimingMode>,
+
+    /// Internal resolution of the renderer, 2D and sprite heavy games look best at native
+    pub resolution_scale: ResolutionScale,
+
+    /// How much of the picture around the display area is shown
+    pub overscan: OverscanMode,
+
+    /// How 480i games are turned into progressive frames
+    pub deinterlace: DeinterlaceMode,
+
+    /// Rasterise on a separate thread, faster in 3D games on machines with cores to spare
+    pub threaded_renderer: bool,
+
+    /// Draw 3D vertices at the position the GTE projected them to rather than whole pixels
+    pub precise_geometry: bool,
+
+    /// Interpolate textures by depth on polygons drawn with precise geometry
+    pub perspective_correct: bool,
+
     #[serde(skip)]
     pub keybinds: input::Bindings,
 }
@@ -171,6 +493,15 @@ impl AppConfig {
         })
     }
 
+    /// The user's choice for the title, else what the game database asks for, else the default
+    pub fn cpu_timing_for(&self, title: Option<&str>, game: Option<&GameInfo>) -> CpuTimingMode {
+        title
+            .and_then(|t| self.cpu_timing_overrides.get(t))
+            .copied()
+            .or_else(|| game?.cpu_timing.map(CpuTimingMode::from))
+            .unwrap_or(self.cpu_timing)
+    }
+
     pub fn save_to_file(&self, path: &Path) {
         if let Ok(toml_str) = toml::to_string_pretty(self) {
             info!(?path, "saving config file to");
@@ -0,0 +1,247 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use cue::iso9660::DirEntry;
+use cue::iso9660::Filesystem;
+use eframe::egui::{self};
+use egui_extras::Column;
+use rfd::AsyncFileDialog;
+use rfd::FileHandle;
+use tracing::error;
+use tracing::info;
+
+type FolderDialog = Pin<Box<dyn Future<Output = Option<FileHandle>>>>;
+type ExportThread = JoinHandle<(cue::Disc, anyhow::Result<usize>)>;
+
+enum Export {
+    /// Waiting on the user to pick a folder
+    Picking(DirEntry, FolderDialog),
+    /// Files being written to a folder on their own thread, which has the disc until it's done
+    Writing(PathBuf, ExportThread),
+}
+
+/// Files on the running disc, read from a separate copy of the image
+pub struct DiscBrowser {
+    /// Away on the export thread while files are written
+    disc: Option<cue::Disc>,
+    /// Directories from the root down to the one being shown
+    path: Vec<DirEntry>,
+    entries: Vec<DirEntry>,
+    export: Option<Export>,
+    status: String,
+}
+
+impl DiscBrowser {
+    pub fn new(mut disc: cue::Disc) -> anyhow::Result<Self> {
+        let root = Filesystem::open(disc.source.as_mut())?.root().clone();
+
+        let mut browser = Self {
+            disc: Some(disc),
+            path: vec![root],
+            entries: Vec::new(),
+            export: None,
+            status: String::new(),
+        };
+
+        browser.refresh();
+        Ok(browser)
+    }
+
+    fn refresh(&mut self) {
+        let Some(disc) = self.disc.as_mut() else {
+            return;
+        };
+
+        let dir = self.path.last().expect("root directory").clone();
+        let entries = Filesystem::open(disc.source.as_mut()).and_then(|mut fs| fs.read_dir(&dir));
+
+        match entries {
+            Ok(mut entries) => {
+                entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
+                self.entries = entries;
+            }
+            Err(err) => {
+                error!(%err, "could not read disc directory");
+                self.status = format!("Could not read directory: {err}");
+                self.entries.clear();
+            }
+        }
+    }
+
+    /// Write the files on a thread of their own so a big export doesn't freeze the window
+    fn start_writing(&mut self, entry: DirEntry, dest: PathBuf) {
+        let Some(mut disc) = self.disc.take() else {
+            return;
+        };
+
+        self.status = format!("Exporting to {}...", dest.display());
+        let thread_dest = dest.clone();
+        let thread = std::thread::spawn(move || {
+            let written = Filesystem::open(disc.source.as_mut())
+                .and_then(|mut fs| fs.export(&entry, &thread_dest));
+            (disc, written)
+        });
+
+        self.export = Some(Export::Writing(dest, thread));
+    }
+
+    fn finish_writing(&mut self, dest: &Path, thread: ExportThread) {
+        let written = match thread.join() {
+            Ok((disc, written)) => {
+                self.disc = Some(disc);
+                written
+            }
+            Err(_) => Err(anyhow!("export thread panicked")),
+        };
+
+        self.status = match written {
+            Ok(files) => {
+                info!(files, ?dest, "exported disc files to");
+                format!("Exported {files} file(s) to {}", dest.display())
+            }
+            Err(err) => {
+                error!(%err, "could not export disc files");
+                format!("Export failed: {err}")
+            }
+        };
+    }
+
+    fn poll_export(&mut self, ctx: &egui::Context) {
+        match self.export.take() {
+            Some(Export::Picking(entry, mut dialog)) => {
+                let mut task_ctx = Context::from_waker(Waker::noop());
+                match dialog.as_mut().poll(&mut task_ctx) {
+                    Poll::Ready(Some(handle)) => {
+                        self.start_writing(entry, handle.path().to_path_buf());
+                    }
+                    Poll::Ready(None) => (),
+                    Poll::Pending => self.export = Some(Export::Picking(entry, dialog)),
+                }
+            }
+            Some(Export::Writing(dest, thread)) if thread.is_finished() => {
+                self.finish_writing(&dest, thread);
+            }
+            Some(writing @ Export::Writing(..)) => {
+                // Check back on the thread even if nothing else redraws the window
+                ctx.request_repaint_after(Duration::from_millis(100));
+                self.export = Some(writing);
+            }
+            None => (),
+        }
+    }
+
+    fn start_export(&mut self, entry: DirEntry) {
+        let dialog = AsyncFileDialog::new()
+            .set_title(format!("Export {}", entry.name))
+            .pick_folder();
+
+        self.export = Some(Export::Picking(entry, Box::pin(dialog)));
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        self.poll_export(ui.ctx());
+
+        let mut open = None;
+
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(self.path.len() > 1 && self.disc.is_some(), |ui| {
+                if ui.button("Up").clicked() {
+                    self.path.pop();
+                    self.refresh();
+                }
+            });
+
+            let names: Vec<_> = self.path[1..].iter().map(|d| d.name.as_str()).collect();
+            ui.monospace(format!("/{}", names.join("/")));
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.add_enabled_ui(self.export.is_none(), |ui| {
+                    if ui.button("Export folder").clicked() {
+                        let dir = self.path.last().expect("root directory").clone();
+                        self.start_export(dir);
+                    }
+                });
+            });
+        });
+
+        if !self.status.is_empty() {
+            ui.label(&self.status);
+        }
+
+        ui.separator();
+
+        let mut export = None;
+
+        egui_extras::TableBuilder::new(ui)
+            .id_salt("disc_files")
+            .striped(true)
+            .resizable(false)
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+            .column(Column::remainder())
+            .column(Column::auto().at_least(80.0))
+            .column(Column::auto().at_least(60.0))
+            .column(Column::auto().at_least(60.0))
+            .column(Column::auto())
+            .header(20.0, |mut header| {
+                for title in ["Name", "Size", "LBA", "Form", ""] {
+                    header.col(|ui| {
+                        ui.strong(title);
+                    });
+                }
+            })
+            .body(|body| {
+                body.rows(20.0, self.entries.len(), |mut row| {
+                    let entry = &self.entries[row.index()];
+
+                    row.col(|ui| {
+                        if entry.is_dir {
+                            let link = egui::Link::new(format!("{}/", entry.name));
+                            if ui.add_enabled(self.disc.is_some(), link).clicked() {
+                                open = Some(entry.clone());
+                            }
+                        } else {
+                            ui.monospace(&entry.name);
+                        }
+                    });
+                    row.col(|ui| {
+                        if !entry.is_dir {
+                            ui.monospace(entry.size.to_string());
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.monospace(entry.lba.to_string());
+                    });
+                    row.col(|ui| {
+                        if !entry.is_dir {
+                            ui.monospace(if entry.form2 { "2" } else { "1" });
+                        }
+                    });
+                    row.col(|ui| {
+                        let enabled = self.export.is_none();
+                        if ui
+                            .add_enabled(enabled, egui::Button::new("Export"))
+                            .clicked()
+                        {
+                            export = Some(entry.clone());
+                        }
+                    });
+                });
+            });
+
+        if let Some(dir) = open {
+            self.path.push(dir);
+            self.refresh();
+        }
+
+        if let Some(entry) = export {
+            self.start_export(entry);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 mod disasm;
+mod disc;
 pub mod snapshot;
 
 use std::collections::HashSet;
@@ -11,8 +12,12 @@ use eframe::egui::Color32;
 use eframe::egui::RichText;
 use eframe::egui::{self};
 use egui_extras::Column;
+use starpsx_core::Media;
 use starpsx_core::SystemSnapshot;
+use tracing::error;
 
+use crate::config::MediaPath;
+use crate::debugger::disc::DiscBrowser;
 use crate::emulator::SharedState;
 use crate::emulator::UiCommand;
 
@@ -29,6 +34,15 @@ pub struct Debugger {
     curr_snapshot: Option<SystemSnapshot>,
 
     pc_changed: bool,
+
+    /// Image the emulator was started with, only read once the Disc view is first shown
+    disc_path: Option<MediaPath>,
+    /// Discs that can be inserted, every disc of a playlist
+    discs: Vec<MediaPath>,
+    /// Which of them the browser shows, follows disc changes
+    browsed_disc: Option<usize>,
+    /// Files of the running disc, if it has a filesystem
+    disc_browser: Option<DiscBrowser>,
 }
 
 impl Debugger {
@@ -36,6 +50,7 @@ impl Debugger {
         shared_state: Arc<SharedState>,
         input_tx: Sender<UiCommand>,
         snapshot_rx: Receiver<SystemSnapshot>,
+        disc_path: Option<MediaPath>,
     ) -> Self {
         Self {
             shared_state,
@@ -47,6 +62,10 @@ impl Debugger {
             address_input: String::default(),
             prev_snapshot: None,
             curr_snapshot: None,
+            disc_path,
+            discs: Vec::new(),
+            browsed_disc: None,
+            disc_browser: None,
 
             pc_changed: false,
         }
@@ -64,6 +83,10 @@ impl Debugger {
         self.shared_state.is_paused()
     }
 
+    pub fn refresh_rate(&self) -> u32 {
+        self.shared_state.refresh_rate()
+    }
+
     pub fn toggle_pause(&self) {
         if self.shared_state.is_paused() {
             self.shared_state.resume();
@@ -76,6 +99,41 @@ impl Debugger {
         self.sync_send(UiCommand::Restart);
     }
 
+    pub fn change_disc(&self) {
+        self.sync_send(UiCommand::ChangeDisc);
+    }
+
+    /// Separate copy of the inserted disc for the browser, so the emulator thread isn't
+    /// disturbed. Reopened whenever the disc is changed.
+    fn open_disc_browser(&mut self) {
+        let index = self.shared_state.disc_index();
+        if self.browsed_disc == Some(index) {
+            return;
+        }
+        self.browsed_disc = Some(index);
+
+        if let Some(path) = self.disc_path.take() {
+            self.discs = path.into_discs().unwrap_or_else(|err| {
+                error!(%err, "could not read discs to browse");
+                Vec::new()
+            });
+        }
+
+        let Some(path) = self.discs.get(index) else {
+            self.disc_browser = None;
+            return;
+        };
+
+        self.disc_browser = match path.load() {
+            Ok(Media::Disc(disc)) => DiscBrowser::new(disc).ok(),
+            Ok(_) => None,
+            Err(err) => {
+                error!(%err, "could not load disc to browse");
+                None
+            }
+        };
+    }
+
     pub fn show_ui(&mut self, ctx: &egui::Context) {
         if !self.is_paused() {
             self.request_snapshot();
@@ -108,6 +166,7 @@ impl Debugger {
                 ui.selectable_value(&mut state_view, StateView::Cpu, "CPU");
                 ui.selectable_value(&mut state_view, StateView::Spu, "SPU");
                 ui.selectable_value(&mut state_view, StateView::Gpu, "GPU");
+                ui.selectable_value(&mut state_view, StateView::Disc, "Disc");
             },
             |ui| {
                 let is_paused = self.shared_state.is_paused();
@@ -129,6 +188,9 @@ impl Debugger {
         );
 
         self.state_view = state_view;
+        if self.state_view == StateView::Disc {
+            self.open_disc_browser();
+        }
 
         ui.separator();
 
@@ -136,6 +198,12 @@ impl Debugger {
             StateView::Cpu => self.cpu_state_view(ui),
             StateView::Gpu => self.gpu_state_view(ui),
             StateView::Spu => self.spu_state_view(ui),
+            StateView::Disc => match self.disc_browser.as_mut() {
+                Some(browser) => browser.show(ui),
+                None => {
+                    ui.label("No disc with a filesystem is inserted");
+                }
+            },
         }
     }
 
@@ -811,6 +879,7 @@ enum StateView {
     Cpu,
     Spu,
     Gpu,
+    Disc,
 }
 
 struct Breakpoint {
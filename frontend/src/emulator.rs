@@ -4,14 +4,21 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use cpal::traits::StreamTrait;
 use crossbeam::channel::Receiver;
 use crossbeam::channel::Sender;
+use starpsx_core::CpuTiming;
+use starpsx_core::GameInfo;
+use starpsx_core::Media;
 use starpsx_core::SystemSnapshot;
+use starpsx_renderer::Deinterlace;
 use starpsx_renderer::FrameBuffer;
+use starpsx_renderer::Overscan;
 use tracing::error;
 use tracing::info;
 use tracing::warn;
@@ -24,7 +31,14 @@ use crate::input::GamepadState;
 pub enum UiCommand {
     SetVramDisplay(bool),
     SetSpeed(bool),
+    SetResolutionScale(usize),
+    SetThreadedRenderer(bool),
+    SetOverscan(Overscan),
+    SetDeinterlace(Deinterlace),
+    SetPreciseGeometry(bool),
+    SetPerspectiveCorrect(bool),
     Restart,
+    ChangeDisc,
     Shutdown,
 
     DebugSetBreakpoint(u32, bool),
@@ -35,7 +49,7 @@ pub enum UiCommand {
 pub struct UiChannels {
     pub frame_tx: Sender<FrameBuffer>,
     pub ui_command_rx: Receiver<UiCommand>,
-    pub input_rx: Receiver<GamepadState>,
+    pub input_rx: Receiver<(usize, GamepadState)>,
     pub snapshot_tx: Sender<SystemSnapshot>,
 }
 
@@ -45,8 +59,10 @@ pub struct Emulator {
     system: starpsx_core::System,
     breakpoints: HashSet<u32>,
     bios_path: PathBuf,
-    file_path: Option<MediaPath>,
-    memory_card: Option<PathBuf>,
+    /// Discs of the running game and the one currently inserted
+    discs: Vec<MediaPath>,
+    disc_index: usize,
+    options: SystemOptions,
     show_vram: bool,
     full_speed: bool,
 }
@@ -56,18 +72,24 @@ impl Emulator {
         channels: UiChannels,
         shared_state: Arc<SharedState>,
         bios_path: PathBuf,
-        file_path: Option<MediaPath>,
-        memory_card: Option<PathBuf>,
+        file: Option<(MediaPath, Media)>,
+        options: SystemOptions,
         show_vram: bool,
         full_speed: bool,
     ) -> anyhow::Result<Self> {
+        let (discs, media) = match file {
+            Some((path, media)) => (path.into_discs()?, Some(media)),
+            None => (Vec::new(), None),
+        };
+
         Ok(Self {
             channels,
             shared_state,
-            system: build_system(&bios_path, file_path.as_ref(), memory_card.as_deref())?,
+            system: system_builder(&bios_path, media, &options)?.build()?,
             bios_path,
-            file_path,
-            memory_card,
+            discs,
+            disc_index: 0,
+            options,
             breakpoints: HashSet::new(),
             show_vram,
             full_speed,
@@ -89,29 +111,34 @@ impl Emulator {
         let _ = self.channels.snapshot_tx.try_send(self.system.snapshot());
     }
 
-    fn save_memory_card_to_disk(&mut self) {
-        let Some(path) = self.memory_card.as_ref() else {
-            return;
-        };
+    fn save_memory_cards_to_disk(&mut self) {
+        for (slot, path) in self.options.memory_cards.iter().enumerate() {
+            let Some(path) = path.as_ref() else {
+                continue;
+            };
 
-        let Some(card) = self.system.memory_card() else {
-            return;
-        };
+            let Some(card) = self.system.memory_card(slot) else {
+                continue;
+            };
 
-        let Some(data) = card.dirty_data() else {
-            return;
-        };
+            let Some(data) = card.dirty_data() else {
+                continue;
+            };
 
-        let tmp_path = path.with_extension("mcd.tmp");
-        if let Err(err) =
-            std::fs::write(&tmp_path, data).and_then(|()| std::fs::rename(&tmp_path, path))
-        {
-            tracing::error!("failed to save memory card: {err}");
+            let tmp_path = path.with_extension("mcd.tmp");
+            if let Err(err) =
+                std::fs::write(&tmp_path, data).and_then(|()| std::fs::rename(&tmp_path, path))
+            {
+                tracing::error!(slot, "failed to save memory card: {err}");
+            }
         }
     }
 
-    const fn update_core_gamepad(&mut self, new_state: &GamepadState) {
-        let gamepad = self.system.gamepad_mut();
+    const fn update_core_gamepad(&mut self, port: usize, new_state: &GamepadState) {
+        let Some(gamepad) = self.system.gamepad_mut(port) else {
+            return;
+        };
+
         gamepad.set_buttons(new_state.buttons);
         gamepad.set_analog_mode(new_state.analog_mode);
         gamepad.set_stick_axis(new_state.left_stick, new_state.right_stick);
@@ -122,6 +149,30 @@ impl Emulator {
         let _ = self.channels.frame_tx.try_send(buffer);
     }
 
+    /// Swap in the next disc of the playlist, wrapping around after the last one
+    fn change_disc(&mut self) {
+        if self.discs.is_empty() {
+            warn!("no disc to change to");
+            return;
+        }
+
+        let index = (self.disc_index + 1) % self.discs.len();
+        let disc = &self.discs[index];
+
+        // Only move on once the disc is in, so a failed change is retried with the same disc
+        let result = disc
+            .load()
+            .and_then(|media| self.system.swap_disc(Some(media)));
+        match result {
+            Ok(()) => {
+                info!(disc = disc.file_prefix(), "changed disc");
+                self.disc_index = index;
+                self.shared_state.set_disc_index(index);
+            }
+            Err(err) => error!(%err, "failed to change disc"),
+        }
+    }
+
     /// Process pending UI commands. Returns `true` if shutdown was requested.
     fn process_commands(&mut self) -> bool {
         while let Ok(command) = self.channels.ui_command_rx.try_recv() {
@@ -130,12 +181,34 @@ impl Emulator {
                 UiCommand::Shutdown => return true,
                 UiCommand::DebugRequestState => self.send_debug_snapshot(),
                 UiCommand::SetSpeed(value) => self.full_speed = value,
+                UiCommand::SetResolutionScale(scale) => {
+                    // Kept in the options so a restart comes back at the same scale
+                    self.options.resolution_scale = scale;
+                    self.system.set_resolution_scale(scale);
+                }
+                UiCommand::SetThreadedRenderer(threaded) => {
+                    self.options.threaded_renderer = threaded;
+                    self.system.set_threaded_renderer(threaded);
+                }
+                UiCommand::SetOverscan(overscan) => {
+                    self.options.overscan = overscan;
+                    self.system.set_overscan(overscan);
+                }
+                UiCommand::SetDeinterlace(deinterlace) => {
+                    self.options.deinterlace = deinterlace;
+                    self.system.set_deinterlace(deinterlace);
+                }
+                UiCommand::SetPreciseGeometry(enabled) => {
+                    self.options.precise_geometry = enabled;
+                    self.system.set_precise_geometry(enabled);
+                }
+                UiCommand::SetPerspectiveCorrect(enabled) => {
+                    self.options.perspective_correct = enabled;
+                    self.system.set_perspective_correct(enabled);
+                }
                 UiCommand::Restart => {
-                    match build_system(
-                        &self.bios_path,
-                        self.file_path.as_ref(),
-                        self.memory_card.as_deref(),
-                    ) {
+                    let disc = self.discs.get(self.disc_index);
+                    match build_system(&self.bios_path, disc, &self.options) {
                         Ok(system) => {
                             info!("emulator thread restarted");
                             self.system = system;
@@ -144,6 +217,7 @@ impl Emulator {
                     }
                     self.shared_state.resume();
                 }
+                UiCommand::ChangeDisc => self.change_disc(),
 
                 UiCommand::DebugSetBreakpoint(address, enabled) => {
                     if enabled {
@@ -175,8 +249,8 @@ impl Emulator {
                 break;
             }
 
-            if let Ok(new_state) = self.channels.input_rx.try_recv() {
-                self.update_core_gamepad(&new_state);
+            while let Ok((port, new_state)) = self.channels.input_rx.try_recv() {
+                self.update_core_gamepad(port, &new_state);
             }
 
             let paused = self.shared_state.is_paused();
@@ -203,7 +277,9 @@ impl Emulator {
                 continue;
             }
 
+            // Frames are paced by the audio stream, so PAL games run at 50Hz on their own
             system.run_frame(self.show_vram);
+            self.shared_state.set_refresh_rate(system.refresh_rate());
 
             if !self.full_speed {
                 for sample in &system.audio_samples {
@@ -213,7 +289,7 @@ impl Emulator {
 
             // Try to save memory_card to disk at the same frequency
             if let Some(fb) = system.frame_buffer.take() {
-                self.save_memory_card_to_disk();
+                self.save_memory_cards_to_disk();
                 self.send_frame_buffer(fb);
             }
         }
@@ -222,9 +298,27 @@ impl Emulator {
     }
 }
 
+/// Hardware the system is built with: devices in the controller ports and memory card
+/// slots, the CPU timing model and how the renderer runs
+#[derive(Default)]
+pub struct SystemOptions {
+    pub memory_cards: [Option<PathBuf>; 2],
+    pub player_2: bool,
+    pub cpu_timing: CpuTiming,
+    pub resolution_scale: usize,
+    pub threaded_renderer: bool,
+    pub overscan: Overscan,
+    pub deinterlace: Deinterlace,
+    pub precise_geometry: bool,
+    pub perspective_correct: bool,
+}
+
 #[derive(Default)]
 pub struct SharedState {
     is_paused: AtomicBool,
+    refresh_rate: AtomicU32,
+    /// Disc of the playlist that is inserted
+    disc_index: AtomicUsize,
 }
 
 impl SharedState {
@@ -239,6 +333,22 @@ impl SharedState {
     pub fn is_paused(&self) -> bool {
         self.is_paused.load(Ordering::Relaxed)
     }
+
+    pub fn set_refresh_rate(&self, hz: u32) {
+        self.refresh_rate.store(hz, Ordering::Relaxed);
+    }
+
+    pub fn refresh_rate(&self) -> u32 {
+        self.refresh_rate.load(Ordering::Relaxed)
+    }
+
+    pub fn set_disc_index(&self, index: usize) {
+        self.disc_index.store(index, Ordering::Relaxed);
+    }
+
+    pub fn disc_index(&self) -> usize {
+        self.disc_index.load(Ordering::Relaxed)
+    }
 }
 
 pub fn parse_runnable(path: PathBuf) -> anyhow::Result<MediaPath> {
@@ -246,30 +356,65 @@ pub fn parse_runnable(path: PathBuf) -> anyhow::Result<MediaPath> {
         Some("exe" | "ps-exe") => Ok(MediaPath::Exe(path)),
         Some("bin") => Ok(MediaPath::Bin(path)),
         Some("cue") => Ok(MediaPath::Cue(path)),
+        Some("chd") => Ok(MediaPath::Chd(path)),
+        Some("ecm") => Ok(MediaPath::Ecm(path)),
+        Some("iso" | "img") => Ok(MediaPath::Iso(path)),
+        Some("m3u") => Ok(MediaPath::M3u(path)),
         _ => anyhow::bail!("unsupported file format"),
     }
 }
 
+/// Serial of the loaded disc and the known game it belongs to
+pub fn identify(media: &mut Media) -> (Option<String>, Option<&'static GameInfo>) {
+    let serial = media.serial();
+    let game = serial.as_deref().and_then(GameInfo::lookup);
+
+    if let Some(serial) = &serial {
+        info!(serial, title = game.map(|g| g.title), "identified disc");
+    }
+
+    (serial, game)
+}
+
 fn build_system(
     bios_path: &Path,
     file_path: Option<&MediaPath>,
-    memory_card: Option<&Path>,
+    options: &SystemOptions,
 ) -> anyhow::Result<starpsx_core::System> {
+    let media = file_path.map(MediaPath::load).transpose()?;
+    system_builder(bios_path, media, options)?.build()
+}
+
+pub fn system_builder(
+    bios_path: &Path,
+    media: Option<Media>,
+    options: &SystemOptions,
+) -> anyhow::Result<starpsx_core::PSXBuilder> {
     let bios: Box<[u8; 0x80000]> = std::fs::read(bios_path)?
         .try_into()
         .map_err(|_| anyhow::anyhow!("bios is wrong size"))?;
 
-    let mut builder = starpsx_core::PSXBuilder::new(bios);
-
-    if let Some(path) = file_path {
-        builder = builder.with_media(path.load()?);
+    let mut builder = starpsx_core::PSXBuilder::new(bios)
+        .with_gamepad(1, options.player_2)
+        .with_cpu_timing(options.cpu_timing)
+        .with_resolution_scale(options.resolution_scale)
+        .with_threaded_renderer(options.threaded_renderer)
+        .with_overscan(options.overscan)
+        .with_deinterlace(options.deinterlace)
+        .with_precise_geometry(options.precise_geometry)
+        .with_perspective_correct(options.perspective_correct);
+
+    if let Some(media) = media {
+        builder = builder.with_media(media);
     }
 
-    if let Some(path) = memory_card {
-        builder = builder.with_card(load_or_create_card(path)?);
+    for (slot, path) in options.memory_cards.iter().enumerate() {
+        if let Some(path) = path {
+            builder = builder.with_card(slot, load_or_create_card(path)?);
+        }
     }
 
-    builder.build()
+    Ok(builder)
 }
 
 fn load_or_create_card(path: &Path) -> anyhow::Result<Box<[u8; 0x20000]>> {
@@ -0,0 +1,245 @@
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use cue::iso9660::DirEntry;
+use cue::iso9660::Filesystem;
+use starpsx_core::Media;
+use starpsx_renderer::FrameBuffer;
+use tracing::error;
+use tracing::info;
+
+use crate::audio::AudioSample;
+use crate::config::AppConfig;
+use crate::config::DiscTool;
+use crate::config::HeadlessConfig;
+use crate::emulator;
+use crate::emulator::SystemOptions;
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// Run the emulator without a window, returns the process exit code
+pub fn run(
+    config: &HeadlessConfig,
+    app_config: &AppConfig,
+    runnable_path: PathBuf,
+) -> anyhow::Result<i32> {
+    let path = emulator::parse_runnable(runnable_path)?;
+    let title = path.file_prefix();
+
+    let mut media = path.load()?;
+    let (_, game) = emulator::identify(&mut media);
+
+    let cpu_timing = config
+        .cpu_timing
+        .unwrap_or_else(|| app_config.cpu_timing_for(Some(&title), game));
+    let options = SystemOptions {
+        cpu_timing: cpu_timing.into(),
+        ..SystemOptions::default()
+    };
+
+    let mut system = emulator::system_builder(&config.bios_path, Some(media), &options)?
+        .with_tty_log()
+        .build()?;
+
+    info!(file = title, ?cpu_timing, "running headless");
+
+    let mut last_frame: Option<FrameBuffer> = None;
+    let mut samples: Vec<AudioSample> = Vec::new();
+    let mut frames = 0;
+    let mut tty_found = false;
+
+    while frames < config.frames {
+        system.run_frame(false);
+        frames += 1;
+
+        if let Some(fb) = system.frame_buffer.take() {
+            last_frame = Some(fb);
+        }
+
+        if config.audio.is_some() {
+            samples.extend_from_slice(&system.audio_samples);
+        }
+
+        if system.exit_code().is_some() {
+            break;
+        }
+
+        if let Some(pattern) = &config.until_tty
+            && system
+                .tty_log()
+                .is_some_and(|log| log.contains(pattern.as_str()))
+        {
+            tty_found = true;
+            break;
+        }
+    }
+
+    info!(frames, "headless run finished");
+
+    if let Some(path) = &config.screenshot {
+        let fb = last_frame.context("no frame was produced to take a screenshot of")?;
+        save_screenshot(path, &fb)?;
+        info!(?path, "saved screenshot to");
+    }
+
+    if let Some(path) = &config.audio {
+        save_wav(path, &samples)?;
+        info!(?path, "saved audio to");
+    }
+
+    if let Some(code) = system.exit_code() {
+        return Ok(code.cast_signed());
+    }
+
+    if config.until_tty.is_some() && !tty_found {
+        error!("tty string never appeared");
+        return Ok(1);
+    }
+
+    Ok(0)
+}
+
+/// List or export the files on a disc image, returns the process exit code
+pub fn run_disc_tool(tool: &DiscTool, runnable_path: PathBuf) -> anyhow::Result<i32> {
+    let Media::Disc(mut disc) = emulator::parse_runnable(runnable_path)?.load()? else {
+        anyhow::bail!("not a disc image");
+    };
+
+    let mut fs = Filesystem::open(disc.source.as_mut())?;
+    let root = fs.root().clone();
+
+    match tool {
+        DiscTool::List => list_dir(&mut fs, &root, "")?,
+        DiscTool::Export(dest) => {
+            let files = fs.export(&root, dest)?;
+            info!(files, ?dest, "exported disc files to");
+        }
+    }
+
+    Ok(0)
+}
+
+fn list_dir(fs: &mut Filesystem, dir: &DirEntry, path: &str) -> anyhow::Result<()> {
+    for entry in fs.read_dir(dir)? {
+        let path = format!("{path}/{}", entry.name);
+
+        if entry.is_dir {
+            println!("{path}/");
+            list_dir(fs, &entry, &path)?;
+        } else {
+            let form = if entry.form2 { "form 2" } else { "form 1" };
+            println!("{path}\t{} bytes\t{form}\tlba {}", entry.size, entry.lba);
+        }
+    }
+
+    Ok(())
+}
+
+fn save_screenshot(path: &Path, fb: &FrameBuffer) -> anyhow::Result<()> {
+    let [width, height] = fb.resolution;
+    let rgb: Vec<u8> = fb.rgba.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+
+    let bytes = match path.extension().and_then(|e| e.to_str()) {
+        Some("png") => encode_png(width, height, &rgb),
+        Some("ppm") => {
+            let mut bytes = format!("P6\n{width} {height}\n255\n").into_bytes();
+            bytes.extend(rgb);
+            bytes
+        }
+        _ => anyhow::bail!("unsupported screenshot format, use .png or .ppm"),
+    };
+
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+fn save_wav(path: &Path, samples: &[AudioSample]) -> anyhow::Result<()> {
+    let data_len = u32::try_from(samples.len() * 4).context("audio too long for a wav file")?;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_len).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+    file.write_all(&1u16.to_le_bytes())?; // PCM
+    file.write_all(&2u16.to_le_bytes())?; // channels
+    file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    file.write_all(&(SAMPLE_RATE * 4).to_le_bytes())?; // byte rate
+    file.write_all(&4u16.to_le_bytes())?; // block align
+    file.write_all(&16u16.to_le_bytes())?; // bits per sample
+    file.write_all(b"data")?;
+    file.write_all(&data_len.to_le_bytes())?;
+
+    for [l, r] in samples {
+        file.write_all(&l.to_le_bytes())?;
+        file.write_all(&r.to_le_bytes())?;
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+// Minimal 8-bit RGB png using uncompressed deflate blocks
+fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    // Every scanline is prefixed with filter type 0
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks_exact(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(u8::from(blocks.peek().is_none()));
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit depth, RGB
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, *b"IHDR", &ihdr);
+    png_chunk(&mut png, *b"IDAT", &zlib);
+    png_chunk(&mut png, *b"IEND", &[]);
+    png
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(&kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |c, _| {
+            if c & 1 == 1 {
+                (c >> 1) ^ 0xEDB8_8320
+            } else {
+                c >> 1
+            }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + u32::from(byte)) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
@@ -6,6 +6,7 @@ mod audio;
 mod config;
 mod debugger;
 mod emulator;
+mod headless;
 mod input;
 
 use clap::Parser;
@@ -26,14 +27,40 @@ fn main() -> eframe::Result {
     let args = config::Args::parse();
 
     // Making sure the log guard doesn't fall out of scope
-    let _log_guard = init_logging("logs", "psx.log");
+    let log_guard = init_logging("logs", "psx.log");
 
     let launch_config = LaunchConfig::build(args).unwrap_or_else(|err| {
         error!(%err, "error building launch config");
         std::process::exit(1);
     });
 
-    run_gui(launch_config)
+    if let Some(tool) = &launch_config.disc_tool {
+        let runnable_path = launch_config.runnable_path.clone().expect("disc file");
+        let code = headless::run_disc_tool(tool, runnable_path).unwrap_or_else(|err| {
+            error!(%err, "disc tool failed");
+            1
+        });
+
+        drop(log_guard);
+        std::process::exit(code);
+    }
+
+    if let Some(config) = &launch_config.headless {
+        let runnable_path = launch_config.runnable_path.clone().expect("headless file");
+        let code =
+            headless::run(config, &launch_config.app_config, runnable_path).unwrap_or_else(|err| {
+                error!(%err, "headless run failed");
+                1
+            });
+
+        // Flush logs before exiting
+        drop(log_guard);
+        std::process::exit(code);
+    }
+
+    let result = run_gui(launch_config);
+    drop(log_guard);
+    result
 }
 
 fn run_gui(launch_config: LaunchConfig) -> eframe::Result {
@@ -0,0 +1,798 @@
+use crate::VRAM_HEIGHT;
+use crate::VRAM_WIDTH;
+use crate::utils::Color;
+use crate::utils::DrawContext;
+use crate::utils::RectTextureOptions;
+use crate::utils::TextureOptions;
+use crate::vec2::PreciseVertex;
+use crate::vec2::Vec2;
+use crate::vec2::edge_function;
+use crate::vec2::is_top_left;
+use crate::vec2::needs_vertex_reordering;
+
+/// Pixels primitives are rasterised into, either native VRAM or the upscaled copy of it.
+/// Vertices come in native coordinates and are scaled here, textures are always read from
+/// native VRAM as it was before the primitive.
+pub struct Canvas<'a> {
+    pixels: &'a mut [u16],
+    /// Native VRAM when drawing into the upscaled copy
+    textures: Option<&'a [u16]>,
+    scale: i32,
+}
+
+impl<'a> Canvas<'a> {
+    pub const fn native(vram: &'a mut [u16]) -> Self {
+        Self {
+            pixels: vram,
+            textures: None,
+            scale: 1,
+        }
+    }
+
+    pub const fn scaled(pixels: &'a mut [u16], vram: &'a [u16], scale: usize) -> Self {
+        Self {
+            pixels,
+            textures: Some(vram),
+            scale: scale as i32,
+        }
+    }
+
+    fn vram(&self) -> &[u16] {
+        self.textures.unwrap_or(self.pixels)
+    }
+
+    const fn width(&self) -> usize {
+        VRAM_WIDTH * self.scale as usize
+    }
+
+    fn read(&self, x: usize, y: usize) -> u16 {
+        self.pixels[self.width() * y + x]
+    }
+
+    fn write(&mut self, ctx: &DrawContext, x: usize, y: usize, data: u16) {
+        let index = self.width() * y + x;
+        if ctx.preserve_masked_pixels && (self.pixels[index] & 0x8000) != 0 {
+            return;
+        }
+
+        self.pixels[index] = data | u16::from(ctx.force_set_masked_bit) << 15;
+    }
+
+    /// Dithering follows the native pixel grid so it looks the same at any scale
+    const fn dither(&self, color: &mut Color, x: usize, y: usize) {
+        let scale = self.scale as usize;
+        color.apply_dithering(x / scale, y / scale);
+    }
+
+    /// Drawing area corners in canvas pixels
+    fn drawing_area(&self, ctx: &DrawContext) -> (Vec2, Vec2) {
+        let s = self.scale;
+        let tl = ctx.drawing_area_top_left.scaled(s);
+        let br = ctx.drawing_area_bottom_right.scaled(s) + Vec2::new(s - 1, s - 1);
+        (tl, br)
+    }
+
+    fn clip_rect(
+        &self,
+        ctx: &DrawContext,
+        min_x: i32,
+        min_y: i32,
+        max_x: i32,
+        max_y: i32,
+    ) -> Option<(usize, usize, usize, usize)> {
+        // Don't draw large ass primitives
+        let s = self.scale;
+        if (max_x - min_x) > VRAM_WIDTH as i32 * s - 1
+            || (max_y - min_y) > VRAM_HEIGHT as i32 * s - 1
+        {
+            return None;
+        }
+
+        let (tl, br) = self.drawing_area(ctx);
+
+        // Trivial reject
+        if max_x < tl.x || min_x > br.x || max_y < tl.y || min_y > br.y {
+            return None;
+        }
+
+        Some((
+            min_x.max(tl.x) as usize,
+            min_y.max(tl.y) as usize,
+            max_x.min(br.x) as usize,
+            max_y.min(br.y) as usize,
+        ))
+    }
+
+    fn clip_triangle(
+        &self,
+        ctx: &DrawContext,
+        t: &[Vec2; 3],
+    ) -> Option<(usize, usize, usize, usize)> {
+        let min_x = t[0].x.min(t[1].x).min(t[2].x);
+        let min_y = t[0].y.min(t[1].y).min(t[2].y);
+        let max_x = t[0].x.max(t[1].x).max(t[2].x);
+        let max_y = t[0].y.max(t[1].y).max(t[2].y);
+
+        self.clip_rect(ctx, min_x, min_y, max_x, max_y)
+    }
+
+    /// Triangle corners in canvas pixels. Only the display copy puts precise vertices between
+    /// native pixels, native VRAM has to match what the console draws.
+    fn place(&self, t: [Vec2; 3], precise: Option<[PreciseVertex; 3]>) -> [Vec2; 3] {
+        let whole = t.map(|v| v.scaled(self.scale));
+        let Some(precise) = precise.filter(|_| self.textures.is_some()) else {
+            return whole;
+        };
+
+        let s = self.scale as f32;
+        let placed = precise.map(|v| Vec2::new((v.x * s).round() as i32, (v.y * s).round() as i32));
+
+        // Rounding can flip a sliver of a triangle around, the rasteriser needs it clockwise
+        if needs_vertex_reordering(&placed) {
+            whole
+        } else {
+            placed
+        }
+    }
+
+    /// Perspective correct texture mapping for the display copy, when every vertex has a depth
+    fn perspective(
+        &self,
+        precise: Option<[PreciseVertex; 3]>,
+        uvs: [Vec2; 3],
+    ) -> Option<Perspective> {
+        let precise = precise.filter(|_| self.textures.is_some())?;
+        if precise.iter().any(|v| v.w <= 0.0) {
+            return None;
+        }
+
+        Some(Perspective {
+            uvw: std::array::from_fn(|i| {
+                let w = precise[i].w;
+                [uvs[i].x as f32 / w, uvs[i].y as f32 / w, 1.0 / w]
+            }),
+        })
+    }
+
+    /// Fill a rectangle without blending or masking, `r` and `side` in native pixels
+    pub fn fill(&mut self, r: Vec2, side_x: i32, side_y: i32, color: Color) {
+        let s = self.scale;
+        let min_x = (r.x.max(0) * s) as usize;
+        let min_y = (r.y.max(0) * s) as usize;
+        let max_x = ((r.x + side_x).min(0x400) * s) as usize;
+        let max_y = ((r.y + side_y).min(0x200) * s) as usize;
+        let width = self.width();
+
+        for x in min_x..max_x {
+            for y in min_y..max_y {
+                self.pixels[width * y + x] = color.to_5bit(None);
+            }
+        }
+    }
+
+    /// Copy a rectangle inside the canvas, corners and size in native pixels
+    pub fn copy(&mut self, ctx: &DrawContext, src: Vec2, dst: Vec2, size: Vec2) {
+        let s = self.scale;
+        let (src, dst, size) = (src.scaled(s), dst.scaled(s), size.scaled(s));
+
+        for y in 0..size.y as usize {
+            for x in 0..size.x as usize {
+                let pixel = self.read(src.x as usize + x, src.y as usize + y);
+                self.write(ctx, dst.x as usize + x, dst.y as usize + y, pixel);
+            }
+        }
+    }
+
+    /// Write a native pixel, covering all the canvas pixels it scales to
+    pub fn write_block(&mut self, ctx: &DrawContext, x: usize, y: usize, data: u16) {
+        let s = self.scale as usize;
+        for dy in 0..s {
+            for dx in 0..s {
+                self.write(ctx, x * s + dx, y * s + dy, data);
+            }
+        }
+    }
+
+    pub fn rectangle<const SEMI_TRANS: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        r: Vec2,
+        side: Vec2,
+        color: Color,
+    ) {
+        let (r, side) = (r.scaled(self.scale), side.scaled(self.scale));
+
+        let Some((min_x, min_y, max_x, max_y)) =
+            self.clip_rect(ctx, r.x, r.y, r.x + side.x - 1, r.y + side.y - 1)
+        else {
+            return;
+        };
+
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let mut color = color;
+                if SEMI_TRANS {
+                    let old = self.read(x, y);
+                    color.blend_screen(Color::new_5bit(old), ctx.rect_texture.transparency_weights);
+                }
+                self.write(ctx, x, y, color.to_5bit(Some(ctx.force_set_masked_bit)));
+            }
+        }
+    }
+
+    pub fn rectangle_textured<const SEMI_TRANS: bool, const BLEND: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        r: Vec2,
+        side: Vec2,
+        color: Color,
+        tex: &RectTextureOptions,
+    ) {
+        let s = self.scale;
+        let (scaled_r, scaled_side) = (r.scaled(s), side.scaled(s));
+
+        let Some((min_x, min_y, max_x, max_y)) = self.clip_rect(
+            ctx,
+            scaled_r.x,
+            scaled_r.y,
+            scaled_r.x + scaled_side.x - 1,
+            scaled_r.y + scaled_side.y - 1,
+        ) else {
+            return;
+        };
+
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                // Rectangles map texels to native pixels one to one
+                let uv = tex.uv + Vec2::new(x as i32 / s, y as i32 / s) - r;
+                let texel = ctx.rect_texture.get_texel(ctx, self.vram(), uv);
+                if texel == 0 {
+                    continue;
+                }
+
+                let mut tex_color = Color::new_5bit(texel);
+                if BLEND {
+                    tex_color.blend(color);
+                }
+
+                if SEMI_TRANS && (texel & 0x8000) != 0 {
+                    let old = self.read(x, y);
+                    tex_color
+                        .blend_screen(Color::new_5bit(old), ctx.rect_texture.transparency_weights);
+                }
+                let mask = ctx.force_set_masked_bit.then_some(true);
+                self.write(ctx, x, y, tex_color.to_5bit(mask));
+            }
+        }
+    }
+
+    /// Line endpoints sit in the middle of the native pixel they started in
+    fn clip_line(&self, ctx: &DrawContext, l: [Vec2; 2]) -> Option<(i32, i32, i32, i32)> {
+        let half = Vec2::new(self.scale / 2, self.scale / 2);
+        let l = l.map(|p| p.scaled(self.scale) + half);
+
+        let (x0, y0, x1, y1) = self.clip_rect(ctx, l[0].x, l[0].y, l[1].x, l[1].y)?;
+        Some((x0 as i32, y0 as i32, x1 as i32, y1 as i32))
+    }
+
+    /// Plot a line pixel as thick as one native pixel across the line
+    fn line_pixel<const SEMI_TRANS: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        x: i32,
+        y: i32,
+        x_major: bool,
+        color: Color,
+    ) {
+        let (tl, br) = self.drawing_area(ctx);
+        let start = -(self.scale / 2);
+
+        for offset in start..start + self.scale {
+            let (x, y) = if x_major {
+                (x, y + offset)
+            } else {
+                (x + offset, y)
+            };
+            if x < tl.x || x > br.x || y < tl.y || y > br.y {
+                continue;
+            }
+
+            let (x, y) = (x as usize, y as usize);
+            let mut color = color;
+
+            if SEMI_TRANS {
+                let old = self.read(x, y);
+                color.blend_screen(Color::new_5bit(old), ctx.rect_texture.transparency_weights);
+            }
+
+            self.write(ctx, x, y, color.to_5bit(Some(ctx.force_set_masked_bit)));
+        }
+    }
+
+    pub fn line<const SEMI_TRANS: bool>(&mut self, ctx: &DrawContext, l: [Vec2; 2], mono: Color) {
+        let Some((x0, y0, x1, y1)) = self.clip_line(ctx, l) else {
+            return;
+        };
+
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+
+        let mut err = dx + dy;
+        let mut x = x0;
+        let mut y = y0;
+
+        loop {
+            self.line_pixel::<SEMI_TRANS>(ctx, x, y, dx >= -dy, mono);
+
+            let e2 = 2 * err;
+            if e2 >= dy {
+                if x == x1 {
+                    break;
+                }
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                if y == y1 {
+                    break;
+                }
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    pub fn line_shaded<const SEMI_TRANS: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        l: [Vec2; 2],
+        shaded: [Color; 2],
+    ) {
+        let Some((x0, y0, x1, y1)) = self.clip_line(ctx, l) else {
+            return;
+        };
+
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+
+        let mut err = dx + dy;
+        let mut x = x0;
+        let mut y = y0;
+
+        loop {
+            let color = {
+                let (num, denom) = if dx >= -dy {
+                    ((x - x0).abs(), dx)
+                } else {
+                    ((y - y0).abs(), -dy)
+                };
+
+                if denom == 0 {
+                    shaded[0]
+                } else {
+                    let inv = denom - num;
+                    let red = (i32::from(shaded[0].r) * inv + i32::from(shaded[1].r) * num) / denom;
+                    let green =
+                        (i32::from(shaded[0].g) * inv + i32::from(shaded[1].g) * num) / denom;
+                    let blue =
+                        (i32::from(shaded[0].b) * inv + i32::from(shaded[1].b) * num) / denom;
+
+                    Color {
+                        r: red as u8,
+                        g: green as u8,
+                        b: blue as u8,
+                        m: 0,
+                    }
+                }
+            };
+
+            self.line_pixel::<SEMI_TRANS>(ctx, x, y, dx >= -dy, color);
+
+            let e2 = 2 * err;
+            if e2 >= dy {
+                if x == x1 {
+                    break;
+                }
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                if y == y1 {
+                    break;
+                }
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// `t` is already in clockwise order and offset into the drawing area
+    pub fn triangle<const SEMI_TRANS: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        t: [Vec2; 3],
+        precise: Option<[PreciseVertex; 3]>,
+        mono: Color,
+    ) {
+        let t = self.place(t, precise);
+        let Some((min_x, min_y, max_x, max_y)) = self.clip_triangle(ctx, &t) else {
+            return;
+        };
+
+        let start = Vec2::new(min_x as i32, min_y as i32);
+        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
+        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
+        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
+
+        let bias1 = i32::from(!is_top_left(t[0], t[1]));
+        let bias2 = i32::from(!is_top_left(t[1], t[2]));
+        let bias3 = i32::from(!is_top_left(t[2], t[0]));
+
+        for y in min_y..=max_y {
+            let mut e1 = e1_row;
+            let mut e2 = e2_row;
+            let mut e3 = e3_row;
+
+            for x in min_x..=max_x {
+                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
+                    let mut color = mono;
+
+                    if SEMI_TRANS {
+                        let old = self.read(x, y);
+                        color.blend_screen(
+                            Color::new_5bit(old),
+                            ctx.rect_texture.transparency_weights,
+                        );
+                    }
+
+                    if ctx.rect_texture.dithering {
+                        self.dither(&mut color, x, y);
+                    }
+
+                    self.write(ctx, x, y, color.to_5bit(Some(ctx.force_set_masked_bit)));
+                }
+
+                e1 += a1;
+                e2 += a2;
+                e3 += a3;
+            }
+
+            e1_row += b1;
+            e2_row += b2;
+            e3_row += b3;
+        }
+    }
+
+    /// Interpolation is done in 64 bits, scaled edge functions overflow 32 bits at 4x
+    pub fn triangle_shaded<const SEMI_TRANS: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        t: [Vec2; 3],
+        precise: Option<[PreciseVertex; 3]>,
+        shaded: [Color; 3],
+    ) {
+        let t = self.place(t, precise);
+        let Some((min_x, min_y, max_x, max_y)) = self.clip_triangle(ctx, &t) else {
+            return;
+        };
+
+        let start = Vec2::new(min_x as i32, min_y as i32);
+        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
+        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
+        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
+
+        let bias1 = i32::from(!is_top_left(t[0], t[1]));
+        let bias2 = i32::from(!is_top_left(t[1], t[2]));
+        let bias3 = i32::from(!is_top_left(t[2], t[0]));
+
+        let (c_dx, c_dy, mut c_row) =
+            color_gradients(shaded, [a1, a2, a3], [b1, b2, b3], [e1_row, e2_row, e3_row]);
+
+        let sum = i64::from(e1_row + e2_row + e3_row);
+
+        for y in min_y..=max_y {
+            let mut e1 = e1_row;
+            let mut e2 = e2_row;
+            let mut e3 = e3_row;
+            let mut c_num = c_row;
+
+            for x in min_x..=max_x {
+                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
+                    let mut color = Color {
+                        r: (c_num[0] / sum) as u8,
+                        g: (c_num[1] / sum) as u8,
+                        b: (c_num[2] / sum) as u8,
+                        m: 0,
+                    };
+
+                    if SEMI_TRANS {
+                        let old = self.read(x, y);
+                        color.blend_screen(
+                            Color::new_5bit(old),
+                            ctx.rect_texture.transparency_weights,
+                        );
+                    }
+
+                    if ctx.rect_texture.dithering {
+                        self.dither(&mut color, x, y);
+                    }
+
+                    self.write(ctx, x, y, color.to_5bit(Some(ctx.force_set_masked_bit)));
+                }
+
+                e1 += a1;
+                e2 += a2;
+                e3 += a3;
+                for (num, d) in c_num.iter_mut().zip(c_dx) {
+                    *num += d;
+                }
+            }
+
+            e1_row += b1;
+            e2_row += b2;
+            e3_row += b3;
+            for (row, d) in c_row.iter_mut().zip(c_dy) {
+                *row += d;
+            }
+        }
+    }
+
+    pub fn triangle_textured<const SEMI_TRANS: bool, const BLEND: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        t: [Vec2; 3],
+        precise: Option<[PreciseVertex; 3]>,
+        mono: Color,
+        tex: &TextureOptions,
+    ) {
+        let t = self.place(t, precise);
+        let Some((min_x, min_y, max_x, max_y)) = self.clip_triangle(ctx, &t) else {
+            return;
+        };
+
+        let start = Vec2::new(min_x as i32, min_y as i32);
+        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
+        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
+        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
+
+        let bias1 = i32::from(!is_top_left(t[0], t[1]));
+        let bias2 = i32::from(!is_top_left(t[1], t[2]));
+        let bias3 = i32::from(!is_top_left(t[2], t[0]));
+
+        let (uv_dx, uv_dy, mut uv_row) = uv_gradients(
+            tex.uvs,
+            [a1, a2, a3],
+            [b1, b2, b3],
+            [e1_row, e2_row, e3_row],
+        );
+
+        let sum = i64::from(e1_row + e2_row + e3_row);
+        let perspective = self.perspective(precise, tex.uvs);
+
+        for y in min_y..=max_y {
+            let mut e1 = e1_row;
+            let mut e2 = e2_row;
+            let mut e3 = e3_row;
+            let mut uv_num = uv_row;
+
+            for x in min_x..=max_x {
+                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
+                    let uv = perspective.as_ref().map_or_else(
+                        || Vec2::new((uv_num[0] / sum) as i32, (uv_num[1] / sum) as i32),
+                        |p| p.uv([e1, e2, e3]),
+                    );
+                    let texel = tex.texture.get_texel(ctx, self.vram(), uv);
+
+                    // Fully black texels are ignored
+                    if texel != 0 {
+                        let mut color = Color::new_5bit(texel);
+                        if BLEND {
+                            color.blend(mono);
+                        }
+
+                        if SEMI_TRANS && (texel & 0x8000) != 0 {
+                            let old = self.read(x, y);
+                            color.blend_screen(
+                                Color::new_5bit(old),
+                                tex.texture.transparency_weights,
+                            );
+                        }
+
+                        if tex.texture.dithering {
+                            self.dither(&mut color, x, y);
+                        }
+
+                        self.write(
+                            ctx,
+                            x,
+                            y,
+                            color.to_5bit(ctx.force_set_masked_bit.then_some(true)),
+                        );
+                    }
+                }
+
+                e1 += a1;
+                e2 += a2;
+                e3 += a3;
+                uv_num[0] += uv_dx[0];
+                uv_num[1] += uv_dx[1];
+            }
+
+            e1_row += b1;
+            e2_row += b2;
+            e3_row += b3;
+            uv_row[0] += uv_dy[0];
+            uv_row[1] += uv_dy[1];
+        }
+    }
+
+    pub fn triangle_textured_shaded<const SEMI_TRANS: bool, const BLEND: bool>(
+        &mut self,
+        ctx: &DrawContext,
+        t: [Vec2; 3],
+        precise: Option<[PreciseVertex; 3]>,
+        shaded: [Color; 3],
+        tex: &TextureOptions,
+    ) {
+        let t = self.place(t, precise);
+        let Some((min_x, min_y, max_x, max_y)) = self.clip_triangle(ctx, &t) else {
+            return;
+        };
+
+        let start = Vec2::new(min_x as i32, min_y as i32);
+        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
+        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
+        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
+
+        let bias1 = i32::from(!is_top_left(t[0], t[1]));
+        let bias2 = i32::from(!is_top_left(t[1], t[2]));
+        let bias3 = i32::from(!is_top_left(t[2], t[0]));
+
+        let (c_dx, c_dy, mut c_row) =
+            color_gradients(shaded, [a1, a2, a3], [b1, b2, b3], [e1_row, e2_row, e3_row]);
+        let (uv_dx, uv_dy, mut uv_row) = uv_gradients(
+            tex.uvs,
+            [a1, a2, a3],
+            [b1, b2, b3],
+            [e1_row, e2_row, e3_row],
+        );
+
+        let sum = i64::from(e1_row + e2_row + e3_row);
+        let perspective = self.perspective(precise, tex.uvs);
+
+        for y in min_y..=max_y {
+            let mut e1 = e1_row;
+            let mut e2 = e2_row;
+            let mut e3 = e3_row;
+            let mut c_num = c_row;
+            let mut uv_num = uv_row;
+
+            for x in min_x..=max_x {
+                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
+                    let uv = perspective.as_ref().map_or_else(
+                        || Vec2::new((uv_num[0] / sum) as i32, (uv_num[1] / sum) as i32),
+                        |p| p.uv([e1, e2, e3]),
+                    );
+                    let texel = tex.texture.get_texel(ctx, self.vram(), uv);
+
+                    // Fully black texels are ignored
+                    if texel != 0 {
+                        let mut color = Color::new_5bit(texel);
+                        if BLEND {
+                            color.blend(Color {
+                                r: (c_num[0] / sum) as u8,
+                                g: (c_num[1] / sum) as u8,
+                                b: (c_num[2] / sum) as u8,
+                                m: 0,
+                            });
+                        }
+
+                        if SEMI_TRANS && (texel >> 15) & 1 == 1 {
+                            let old = self.read(x, y);
+                            color.blend_screen(
+                                Color::new_5bit(old),
+                                tex.texture.transparency_weights,
+                            );
+                        }
+
+                        if tex.texture.dithering {
+                            self.dither(&mut color, x, y);
+                        }
+
+                        self.write(
+                            ctx,
+                            x,
+                            y,
+                            color.to_5bit(ctx.force_set_masked_bit.then_some(true)),
+                        );
+                    }
+                }
+
+                e1 += a1;
+                e2 += a2;
+                e3 += a3;
+                for (num, d) in c_num.iter_mut().zip(c_dx) {
+                    *num += d;
+                }
+                uv_num[0] += uv_dx[0];
+                uv_num[1] += uv_dx[1];
+            }
+
+            e1_row += b1;
+            e2_row += b2;
+            e3_row += b3;
+            for (row, d) in c_row.iter_mut().zip(c_dy) {
+                *row += d;
+            }
+            uv_row[0] += uv_dy[0];
+            uv_row[1] += uv_dy[1];
+        }
+    }
+}
+
+/// Texture coordinates and 1 over the depth of each vertex. Unlike the coordinates
+/// themselves these interpolate linearly across the screen.
+struct Perspective {
+    uvw: [[f32; 3]; 3],
+}
+
+impl Perspective {
+    /// Texture coordinate at a pixel given its edge functions, weighted like [`gradients`]
+    fn uv(&self, e: [i32; 3]) -> Vec2 {
+        let weights = [e[1], e[2], e[0]].map(|e| e as f32);
+        let [u, v, q] = std::array::from_fn(|i| {
+            weights
+                .iter()
+                .zip(&self.uvw)
+                .map(|(w, uvw)| w * uvw[i])
+                .sum::<f32>()
+        });
+
+        Vec2::new((u / q) as i32, (v / q) as i32)
+    }
+}
+
+/// Steps in x and y and the value at the first row of an attribute interpolated across a
+/// triangle, scaled by the sum of the edge functions. Each vertex is weighted by the edge
+/// opposite to it.
+fn gradients<const N: usize>(
+    values: [[i32; N]; 3],
+    a: [i32; 3],
+    b: [i32; 3],
+    e: [i32; 3],
+) -> ([i64; N], [i64; N], [i64; N]) {
+    let weigh = |w: [i32; 3]| {
+        std::array::from_fn(|i| {
+            i64::from(w[1]) * i64::from(values[0][i])
+                + i64::from(w[2]) * i64::from(values[1][i])
+                + i64::from(w[0]) * i64::from(values[2][i])
+        })
+    };
+
+    (weigh(a), weigh(b), weigh(e))
+}
+
+fn color_gradients(
+    colors: [Color; 3],
+    a: [i32; 3],
+    b: [i32; 3],
+    e: [i32; 3],
+) -> ([i64; 3], [i64; 3], [i64; 3]) {
+    let values = colors.map(|c| [i32::from(c.r), i32::from(c.g), i32::from(c.b)]);
+    gradients(values, a, b, e)
+}
+
+fn uv_gradients(
+    uvs: [Vec2; 3],
+    a: [i32; 3],
+    b: [i32; 3],
+    e: [i32; 3],
+) -> ([i64; 2], [i64; 2], [i64; 2]) {
+    gradients(uvs.map(|uv| [uv.x, uv.y]), a, b, e)
+}
@@ -0,0 +1,310 @@
+use crate::utils::Color;
+
+/// Summed RGB difference above which a pixel counts as having moved between fields
+const MOTION_THRESHOLD: u16 = 24;
+
+/// How the two fields of 480i are put together into one progressive frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Deinterlace {
+    /// Interleave both fields, sharp when the picture holds still but moving edges comb
+    #[default]
+    Weave,
+
+    /// Double the lines of the latest field, a line lower on odd fields so the picture
+    /// stays in place
+    Bob,
+
+    /// Average every line with the one below it, a little soft but never combs
+    Blend,
+
+    /// Weave where the picture holds still, bob where the latest field moved
+    MotionAdaptive,
+}
+
+/// A frame both fields were woven into, `scale` frame rows to each field line
+pub struct Woven<'a> {
+    pub rows: &'a [Color],
+    /// The same frame before the latest field was drawn in
+    pub previous: &'a [Color],
+    pub width: usize,
+    pub scale: usize,
+    /// The latest field, 0 for even lines and 1 for odd
+    pub field: usize,
+}
+
+impl Woven<'_> {
+    fn row(&self, r: usize) -> &[Color] {
+        &self.rows[r * self.width..][..self.width]
+    }
+
+    const fn height(&self) -> usize {
+        if self.width == 0 {
+            return 0;
+        }
+
+        self.rows.len() / self.width
+    }
+
+    const fn is_latest(&self, r: usize) -> bool {
+        (r / self.scale) & 1 == self.field
+    }
+
+    /// Rows of the latest field right above and below row `r`, which is from the other one
+    fn neighbours(&self, r: usize) -> (Option<usize>, Option<usize>) {
+        let above = r.checked_sub(self.scale);
+        let below = Some(r + self.scale).filter(|&b| b < self.height());
+        (above, below)
+    }
+
+    /// The latest field changed pixel `x` of row `r` since it was last drawn. Everything
+    /// counts as moved right after the frame changed size.
+    fn moved(&self, r: usize, x: usize) -> bool {
+        if self.previous.len() != self.rows.len() {
+            return true;
+        }
+
+        let index = r * self.width + x;
+        difference(self.previous[index], self.rows[index]) > MOTION_THRESHOLD
+    }
+}
+
+impl Deinterlace {
+    /// Write the progressive frame for `woven` into `out`, which is the same size
+    pub fn apply(self, woven: &Woven, out: &mut [Color]) {
+        let width = woven.width;
+        if width == 0 {
+            return;
+        }
+
+        for (r, line) in out.chunks_exact_mut(width).enumerate() {
+            match self {
+                Self::Weave => line.copy_from_slice(woven.row(r)),
+
+                Self::Bob => {
+                    let source = if woven.is_latest(r) {
+                        r
+                    } else {
+                        // Odd fields have nothing above their first line
+                        let (above, below) = woven.neighbours(r);
+                        above.or(below).unwrap_or(r)
+                    };
+                    line.copy_from_slice(woven.row(source));
+                }
+
+                Self::Blend => {
+                    let below = (r + woven.scale).min(woven.height() - 1);
+                    for (x, pixel) in line.iter_mut().enumerate() {
+                        *pixel = average(woven.row(r)[x], woven.row(below)[x]);
+                    }
+                }
+
+                Self::MotionAdaptive => {
+                    line.copy_from_slice(woven.row(r));
+                    if woven.is_latest(r) {
+                        continue;
+                    }
+
+                    let (above, below) = woven.neighbours(r);
+                    for (x, pixel) in line.iter_mut().enumerate() {
+                        let moved = [above, below]
+                            .into_iter()
+                            .flatten()
+                            .any(|n| woven.moved(n, x));
+                        if !moved {
+                            continue;
+                        }
+
+                        *pixel = match (above, below) {
+                            (Some(a), Some(b)) => average(woven.row(a)[x], woven.row(b)[x]),
+                            (Some(n), None) | (None, Some(n)) => woven.row(n)[x],
+                            (None, None) => *pixel,
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn average(a: Color, b: Color) -> Color {
+    let mean = |a: u8, b: u8| u8::midpoint(a, b);
+    Color {
+        r: mean(a.r, b.r),
+        g: mean(a.g, b.g),
+        b: mean(a.b, b.b),
+        m: 0xFF,
+    }
+}
+
+fn difference(a: Color, b: Color) -> u16 {
+    let channel = |a: u8, b: u8| u16::from(a.abs_diff(b));
+    channel(a.r, b.r) + channel(a.g, b.g) + channel(a.b, b.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(v: u8) -> Color {
+        Color {
+            r: v,
+            g: v,
+            b: v,
+            m: 0xFF,
+        }
+    }
+
+    /// One pixel wide frame with a row for each value
+    fn column(values: &[u8]) -> Vec<Color> {
+        values.iter().map(|&v| gray(v)).collect()
+    }
+
+    /// Gray level of every pixel `mode` puts out
+    fn apply(mode: Deinterlace, woven: &Woven) -> Vec<u8> {
+        let mut out = vec![Color::BLACK; woven.rows.len()];
+        mode.apply(woven, &mut out);
+        out.iter().map(|c| c.r).collect()
+    }
+
+    #[test]
+    fn weave_keeps_both_fields() {
+        let rows = column(&[10, 20, 30, 40]);
+        for field in [0, 1] {
+            let woven = Woven {
+                rows: &rows,
+                previous: &rows,
+                width: 1,
+                scale: 1,
+                field,
+            };
+            assert_eq!(apply(Deinterlace::Weave, &woven), [10, 20, 30, 40]);
+        }
+    }
+
+    #[test]
+    fn bob_doubles_the_latest_field() {
+        let rows = column(&[10, 20, 30, 40]);
+        let woven = |field| Woven {
+            rows: &rows,
+            previous: &rows,
+            width: 1,
+            scale: 1,
+            field,
+        };
+
+        assert_eq!(apply(Deinterlace::Bob, &woven(0)), [10, 10, 30, 30]);
+        // The first line of an odd field is shown above it too
+        assert_eq!(apply(Deinterlace::Bob, &woven(1)), [20, 20, 20, 40]);
+    }
+
+    #[test]
+    fn bob_moves_whole_field_lines_when_upscaled() {
+        let rows = column(&[10, 10, 20, 20, 30, 30, 40, 40]);
+        let woven = Woven {
+            rows: &rows,
+            previous: &rows,
+            width: 1,
+            scale: 2,
+            field: 0,
+        };
+
+        assert_eq!(
+            apply(Deinterlace::Bob, &woven),
+            [10, 10, 10, 10, 30, 30, 30, 30]
+        );
+    }
+
+    #[test]
+    fn blend_averages_each_line_with_the_next() {
+        let rows = column(&[10, 20, 30, 40]);
+        let woven = Woven {
+            rows: &rows,
+            previous: &rows,
+            width: 1,
+            scale: 1,
+            field: 0,
+        };
+
+        // The last line has nothing below to blend with
+        assert_eq!(apply(Deinterlace::Blend, &woven), [15, 25, 35, 40]);
+    }
+
+    #[test]
+    fn motion_adaptive_weaves_still_pixels_and_bobs_moving_ones() {
+        let previous = column(&[10, 20, 30, 40, 50, 60]);
+
+        // Nothing moved
+        let woven = Woven {
+            rows: &previous,
+            previous: &previous,
+            width: 1,
+            scale: 1,
+            field: 0,
+        };
+        assert_eq!(
+            apply(Deinterlace::MotionAdaptive, &woven),
+            [10, 20, 30, 40, 50, 60]
+        );
+
+        // Line 2 of the even field moved, the odd lines next to it are interpolated
+        let rows = column(&[10, 20, 130, 40, 50, 60]);
+        let woven = Woven {
+            rows: &rows,
+            previous: &previous,
+            width: 1,
+            scale: 1,
+            field: 0,
+        };
+        assert_eq!(
+            apply(Deinterlace::MotionAdaptive, &woven),
+            [10, 70, 130, 90, 50, 60]
+        );
+
+        // Differences under the threshold count as noise
+        let rows = column(&[10, 20, 35, 40, 50, 60]);
+        let woven = Woven {
+            rows: &rows,
+            previous: &previous,
+            width: 1,
+            scale: 1,
+            field: 0,
+        };
+        assert_eq!(
+            apply(Deinterlace::MotionAdaptive, &woven),
+            [10, 20, 35, 40, 50, 60]
+        );
+    }
+
+    #[test]
+    fn motion_adaptive_bobs_everything_after_a_size_change() {
+        let rows = column(&[10, 20, 30, 40]);
+        let woven = Woven {
+            rows: &rows,
+            previous: &[],
+            width: 1,
+            scale: 1,
+            field: 1,
+        };
+
+        assert_eq!(apply(Deinterlace::MotionAdaptive, &woven), [20, 20, 30, 40]);
+    }
+
+    #[test]
+    fn empty_frames_are_left_alone() {
+        for mode in [
+            Deinterlace::Weave,
+            Deinterlace::Bob,
+            Deinterlace::Blend,
+            Deinterlace::MotionAdaptive,
+        ] {
+            let woven = Woven {
+                rows: &[],
+                previous: &[],
+                width: 0,
+                scale: 1,
+                field: 0,
+            };
+            assert!(apply(mode, &woven).is_empty());
+        }
+    }
+}
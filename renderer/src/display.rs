@@ -0,0 +1,239 @@
+use crate::utils::DrawContext;
+
+/// How much of the picture a TV would scan out goes into the frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Overscan {
+    /// Every line and dot a TV could show, games that don't fill it get borders
+    Full,
+
+    /// The area a TV typically leaves visible, stretched to 4:3
+    #[default]
+    Cropped,
+
+    /// The same area as [`Overscan::Cropped`], at the shape a TV gives it
+    AspectCorrected,
+}
+
+/// Part of the video signal, in GPU clock cycles across and scanlines down from sync
+struct Window {
+    hor: (u16, u16),
+    ver: (u16, u16),
+}
+
+// Whole active area of the signal, what a TV would show as full 4:3 without overscan
+const NTSC_FULL: Window = Window {
+    hor: (488, 3288),
+    ver: (16, 256),
+};
+const PAL_FULL: Window = Window {
+    hor: (487, 3282),
+    ver: (20, 308),
+};
+
+// The ranges the BIOS sets up, 320 dots centred on the TV and the lines left after overscan
+const NTSC_CROPPED: Window = Window {
+    hor: (0x260, 0xC60),
+    ver: (0x88 - 224 / 2, 0x88 + 224 / 2),
+};
+const PAL_CROPPED: Window = Window {
+    hor: (0x260, 0xC60),
+    ver: (0xA3 - 264 / 2, 0xA3 + 264 / 2),
+};
+
+impl Window {
+    const fn new(overscan: Overscan, pal: bool) -> &'static Self {
+        match (overscan, pal) {
+            (Overscan::Full, false) => &NTSC_FULL,
+            (Overscan::Full, true) => &PAL_FULL,
+            (Overscan::Cropped | Overscan::AspectCorrected, false) => &NTSC_CROPPED,
+            (Overscan::Cropped | Overscan::AspectCorrected, true) => &PAL_CROPPED,
+        }
+    }
+
+    const fn cycles(&self) -> u16 {
+        self.hor.1 - self.hor.0
+    }
+
+    const fn lines(&self) -> u16 {
+        self.ver.1 - self.ver.0
+    }
+}
+
+/// GPU clock cycles per dot for a horizontal resolution
+const fn dot_clock_divider(width: u16) -> u16 {
+    match width {
+        256 => 10,
+        320 => 8,
+        368 => 7,
+        512 => 5,
+        _ => 4,
+    }
+}
+
+/// Where the picture from VRAM goes in the frame, in native dots and VRAM rows. Rows are
+/// lines, or half lines in 480i where each field has its own.
+pub struct Layout {
+    pub width: usize,
+    pub rows: usize,
+
+    /// Top left of the picture, negative when the game starts it outside the window
+    pub x: i32,
+    pub y: i32,
+
+    pub image_width: usize,
+    pub image_rows: usize,
+
+    /// Both fields come from VRAM, every other row
+    pub interlaced: bool,
+    /// Width over height to show the frame at
+    pub aspect_ratio: f32,
+}
+
+impl Layout {
+    /// Place the display ranges GP1 set within the window a TV shows, the same ranges land in
+    /// the same spot no matter how they line up with VRAM
+    pub fn new(ctx: &DrawContext, overscan: Overscan) -> Self {
+        let window = Window::new(overscan, ctx.display_pal);
+        let full = Window::new(Overscan::Full, ctx.display_pal);
+        let dot = dot_clock_divider(ctx.display_width);
+
+        // 480i has 2 VRAM rows per line, one from each field
+        let interlaced = ctx.interlaced && ctx.display_height == 480;
+        let rows_per_line = if interlaced { 2 } else { 1 };
+
+        let x = (i32::from(ctx.display_hor_start) - i32::from(window.hor.0)) / i32::from(dot);
+        let y = i32::from(ctx.display_ver_start) - i32::from(window.ver.0);
+
+        let aspect_ratio = match overscan {
+            Overscan::Full | Overscan::Cropped => 4.0 / 3.0,
+            Overscan::AspectCorrected => {
+                let across = f32::from(window.cycles()) / f32::from(full.cycles());
+                let down = f32::from(window.lines()) / f32::from(full.lines());
+                4.0 / 3.0 * across / down
+            }
+        };
+
+        Self {
+            width: usize::from(window.cycles() / dot),
+            rows: usize::from(window.lines()) * rows_per_line,
+            x,
+            y: y * rows_per_line as i32,
+            image_width: usize::from(ctx.display_hor_range),
+            image_rows: usize::from(ctx.display_ver_range) * rows_per_line,
+            interlaced,
+            aspect_ratio,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 320x240 with the display ranges the BIOS sets up
+    fn bios_ctx(pal: bool) -> DrawContext {
+        let window = Window::new(Overscan::Cropped, pal);
+        DrawContext {
+            display_width: 320,
+            display_height: 240,
+            display_hor_start: window.hor.0,
+            display_ver_start: window.ver.0,
+            display_hor_range: 320,
+            display_ver_range: window.lines(),
+            display_pal: pal,
+            ..DrawContext::default()
+        }
+    }
+
+    #[test]
+    fn display_ranges_start_at_their_reset_values() {
+        let ctx = DrawContext::default();
+        assert_eq!(
+            (ctx.display_hor_start, ctx.display_ver_start),
+            (0x200, 0x10)
+        );
+
+        let mut ctx = bios_ctx(false);
+        ctx.reset();
+        assert_eq!(
+            (ctx.display_hor_start, ctx.display_ver_start),
+            (0x200, 0x10)
+        );
+    }
+
+    #[test]
+    fn cropped_fills_the_frame_with_the_bios_picture() {
+        for (pal, lines) in [(false, 224), (true, 264)] {
+            let layout = Layout::new(&bios_ctx(pal), Overscan::Cropped);
+
+            assert_eq!((layout.width, layout.rows), (320, lines));
+            assert_eq!((layout.x, layout.y), (0, 0));
+            assert_eq!((layout.image_width, layout.image_rows), (320, lines));
+            assert!(!layout.interlaced);
+            assert!((layout.aspect_ratio - 4.0 / 3.0).abs() < f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn full_overscan_shows_borders_around_the_picture() {
+        let layout = Layout::new(&bios_ctx(false), Overscan::Full);
+
+        // 2800 cycles at 8 per dot, and 240 lines
+        assert_eq!((layout.width, layout.rows), (350, 240));
+        assert_eq!((layout.x, layout.y), (15, 8));
+        assert_eq!((layout.image_width, layout.image_rows), (320, 224));
+        assert!((layout.aspect_ratio - 4.0 / 3.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn aspect_corrected_keeps_the_cropped_area_at_its_tv_shape() {
+        let cropped = Layout::new(&bios_ctx(false), Overscan::Cropped);
+        let corrected = Layout::new(&bios_ctx(false), Overscan::AspectCorrected);
+
+        assert_eq!(
+            (corrected.width, corrected.rows),
+            (cropped.width, cropped.rows)
+        );
+        assert_eq!((corrected.x, corrected.y), (cropped.x, cropped.y));
+
+        let expected = 4.0 / 3.0 * (2560.0 / 2800.0) / (224.0 / 240.0);
+        assert!((corrected.aspect_ratio - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn moved_display_ranges_move_the_picture() {
+        let mut ctx = bios_ctx(false);
+        ctx.display_width = 256;
+        ctx.display_hor_start += 10 * 20;
+        ctx.display_ver_start -= 4;
+
+        // 256 dots are 10 cycles each
+        let layout = Layout::new(&ctx, Overscan::Cropped);
+        assert_eq!(layout.width, 256);
+        assert_eq!((layout.x, layout.y), (20, -4));
+
+        // Reset values start the picture above and left of the cropped window
+        let layout = Layout::new(&DrawContext::default(), Overscan::Cropped);
+        assert_eq!((layout.x, layout.y), ((0x200 - 0x260) / 4, 0x10 - 24));
+    }
+
+    #[test]
+    fn only_480i_has_a_row_for_each_field() {
+        let mut ctx = bios_ctx(false);
+        ctx.display_height = 480;
+        ctx.display_ver_start += 2;
+
+        // 480 lines need interlacing to be on
+        let layout = Layout::new(&ctx, Overscan::Cropped);
+        assert!(!layout.interlaced);
+        assert_eq!((layout.rows, layout.y, layout.image_rows), (224, 2, 224));
+
+        ctx.interlaced = true;
+        let layout = Layout::new(&ctx, Overscan::Cropped);
+        assert!(layout.interlaced);
+        assert_eq!((layout.rows, layout.y, layout.image_rows), (448, 4, 448));
+
+        ctx.display_height = 240;
+        assert!(!Layout::new(&ctx, Overscan::Cropped).interlaced);
+    }
+}
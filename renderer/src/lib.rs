@@ -1,24 +1,51 @@
+mod canvas;
+mod deinterlace;
+mod display;
+mod raster;
 pub mod utils;
 pub mod vec2;
 
+use crate::canvas::Canvas;
+pub use crate::deinterlace::Deinterlace;
+use crate::deinterlace::Woven;
+use crate::display::Layout;
+pub use crate::display::Overscan;
+use crate::raster::Rasteriser;
+use crate::raster::ScaledVram;
+pub use crate::raster::VramGuard;
 use crate::utils::Color;
 use crate::utils::DrawContext;
 use crate::utils::RectTextureOptions;
 use crate::utils::TextureOptions;
+use crate::vec2::PreciseVertex;
 use crate::vec2::Vec2;
-use crate::vec2::edge_function;
-use crate::vec2::is_top_left;
 use crate::vec2::needs_vertex_reordering;
 
 const VRAM_WIDTH: usize = 1024;
 const VRAM_HEIGHT: usize = 512;
 const VRAM_SIZE: usize = VRAM_WIDTH * VRAM_HEIGHT;
 
+/// Highest internal resolution multiplier
+pub const MAX_RESOLUTION_SCALE: usize = 4;
+
 pub struct Renderer {
     pub ctx: DrawContext,
 
-    vram: Box<[u16; VRAM_SIZE]>,
+    raster: Rasteriser,
+    /// Internal resolution, mirrors the upscaled copy so it can be read without a sync
+    scale: usize,
+    /// Place GTE-projected vertices between pixels in the display copy
+    precise_geometry: bool,
+    /// Interpolate texture coordinates with the depth of precise vertices
+    perspective_correct: bool,
+    /// Part of the signal a TV would show that goes into frames
+    overscan: Overscan,
+    /// How 480i fields are put together
+    deinterlace: Deinterlace,
+    /// Both fields of 480i woven together, or the last frame for other modes
     frame: FrameBuffer,
+    /// Woven frame before the latest field went in, to see what moved
+    previous_field: Vec<Color>,
 }
 
 #[derive(Clone)]
@@ -28,17 +55,24 @@ pub struct FrameBuffer {
     /// Resolution in pixels
     pub resolution: [usize; 2],
     pub is_interlaced: bool,
+    /// Both fields of 480i were put together into a progressive frame
+    pub is_deinterlaced: bool,
+
+    /// Width over height the frame is meant to be shown at
+    pub aspect_ratio: f32,
 }
 
 impl FrameBuffer {
     /// Create a width x height framebuffer fully black
-    fn new(width: usize, height: usize, is_interlaced: bool) -> Self {
+    fn new(width: usize, height: usize, is_interlaced: bool, aspect_ratio: f32) -> Self {
         Self {
             rgba: vec![Color::BLACK; width * height],
             is_interlaced,
+            is_deinterlaced: false,
 
             // Interlaced frames have duplicated rows
             resolution: [width, height],
+            aspect_ratio,
         }
     }
 
@@ -48,6 +82,8 @@ impl FrameBuffer {
             rgba: vec![Color::BLACK],
             resolution: [1, 1],
             is_interlaced: false,
+            is_deinterlaced: false,
+            aspect_ratio: 4.0 / 3.0,
         }
     }
 }
@@ -56,8 +92,14 @@ impl Default for Renderer {
     fn default() -> Self {
         Self {
             ctx: DrawContext::default(),
-            vram: vec![0; VRAM_SIZE].try_into().expect("vram alloc"),
+            raster: Rasteriser::default(),
+            scale: 1,
+            precise_geometry: false,
+            perspective_correct: false,
+            overscan: Overscan::default(),
+            deinterlace: Deinterlace::default(),
             frame: FrameBuffer::black(),
+            previous_field: Vec::new(),
         }
     }
 }
@@ -72,208 +114,257 @@ impl Renderer {
         self.ctx.display_width = width;
         self.ctx.display_height = height;
 
-        let width = width as usize;
-        let height = height as usize * if self.ctx.interlaced { 1 } else { 2 };
-
         // Replace the frame buffer because resolution changed
-        self.frame = FrameBuffer::new(width, height, self.ctx.interlaced);
+        self.rebuild_frame_buffer();
+    }
+
+    /// Allocate a fresh frame buffer matching the display size in the draw context
+    pub fn rebuild_frame_buffer(&mut self) {
+        let layout = Layout::new(&self.ctx, self.overscan);
+        let [width, height] = self.frame_resolution(&layout);
+        self.frame = FrameBuffer::new(width, height, layout.interlaced, layout.aspect_ratio);
+    }
+
+    /// Output size, 24 bit displays (videos) are always shown at native resolution. Lines
+    /// outside 480i are doubled so every mode comes out the same height.
+    const fn frame_resolution(&self, layout: &Layout) -> [usize; 2] {
+        let scale = self.display_scale();
+        let width = layout.width * scale;
+        let height = layout.rows * scale * if layout.interlaced { 1 } else { 2 };
+
+        [width, height]
+    }
+
+    /// Show more or less of the picture around the display area, see [`Overscan`]
+    pub fn set_overscan(&mut self, overscan: Overscan) {
+        if overscan != self.overscan {
+            self.overscan = overscan;
+            self.rebuild_frame_buffer();
+        }
+    }
+
+    /// Put 480i fields together another way, see [`Deinterlace`]
+    pub const fn set_deinterlace(&mut self, deinterlace: Deinterlace) {
+        self.deinterlace = deinterlace;
+    }
+
+    const fn display_scale(&self) -> usize {
+        match self.ctx.display_depth {
+            utils::DisplayDepth::D15 => self.resolution_scale(),
+            utils::DisplayDepth::D24 => 1,
+        }
     }
 
+    /// Internal resolution as a multiple of the native one
     #[must_use]
-    pub fn vram_read(&self, x: usize, y: usize) -> u16 {
-        let index = VRAM_WIDTH * y + x;
-        self.vram[index]
+    pub const fn resolution_scale(&self) -> usize {
+        self.scale
     }
 
-    pub fn vram_write(&mut self, x: usize, y: usize, data: u16) {
-        let index = VRAM_WIDTH * y + x;
-        if self.ctx.preserve_masked_pixels && (self.vram[index] & 0x8000) != 0 {
+    /// Draw at `scale` times the native resolution as well, up to [`MAX_RESOLUTION_SCALE`].
+    /// Anything drawn before the switch is upscaled from native VRAM.
+    pub fn set_resolution_scale(&mut self, scale: usize) {
+        let scale = scale.clamp(1, MAX_RESOLUTION_SCALE);
+        if scale == self.scale {
             return;
         }
 
-        self.vram[index] = data | u16::from(self.ctx.force_set_masked_bit) << 15;
+        self.scale = scale;
+        self.resync_scaled_vram();
+        self.rebuild_frame_buffer();
     }
 
-    pub fn vram_self_copy(&mut self, src: Vec2, dst: Vec2, size: Vec2) {
-        let width = size.x as usize;
-        let height = size.y as usize;
+    /// Redo the upscaled copy from native VRAM, after replacing it through [`Self::vram_mut`]
+    pub fn resync_scaled_vram(&mut self) {
+        // Precise geometry needs a display copy to draw into even at native resolution
+        let display_copy = self.scale > 1 || self.precise_geometry;
 
-        for y in 0..height {
-            for x in 0..width {
-                let pixel = self.vram_read(src.x as usize + x, src.y as usize + y);
-                self.vram_write(dst.x as usize + x, dst.y as usize + y, pixel);
-            }
+        let mut target = self.raster.lock();
+        target.scaled = display_copy.then(|| ScaledVram::from_native(&target.vram[..], self.scale));
+    }
+
+    /// Draw triangles with the precise vertices passed along with them. This only changes what
+    /// is displayed, VRAM the game can read back is always drawn from whole pixels.
+    pub fn set_precise_geometry(&mut self, enabled: bool) {
+        if enabled != self.precise_geometry {
+            self.precise_geometry = enabled;
+            self.resync_scaled_vram();
         }
     }
 
+    /// Map textures with the depth of precise vertices instead of affinely like the console
+    pub const fn set_perspective_correct(&mut self, enabled: bool) {
+        self.perspective_correct = enabled;
+    }
+
+    #[must_use]
+    pub fn is_threaded(&self) -> bool {
+        self.raster.is_threaded()
+    }
+
+    /// Rasterise on a separate thread, the emulator only waits for it when it looks at VRAM
+    pub fn set_threaded(&mut self, threaded: bool) {
+        self.raster.set_threaded(threaded);
+    }
+
+    /// Whole VRAM as 1024x512 halfwords in row-major order
+    #[must_use]
+    pub fn vram(&self) -> VramGuard<'_> {
+        VramGuard(self.raster.lock())
+    }
+
+    pub fn vram_mut(&mut self) -> VramGuard<'_> {
+        VramGuard(self.raster.lock())
+    }
+
+    #[must_use]
+    pub fn vram_read(&self, x: usize, y: usize) -> u16 {
+        let index = VRAM_WIDTH * y + x;
+        self.raster.lock().vram[index]
+    }
+
+    /// Write a pixel of a VRAM transfer, drawn along with the rest of the transfer
+    pub fn vram_write(&mut self, x: usize, y: usize, data: u16) {
+        self.raster.upload(&self.ctx, x, y, data);
+    }
+
+    pub fn vram_self_copy(&mut self, src: Vec2, dst: Vec2, size: Vec2) {
+        self.rasterise(move |canvas, ctx| canvas.copy(ctx, src, dst, size));
+    }
+
+    fn rasterise(&self, draw: impl FnMut(&mut Canvas, &DrawContext) + Send + 'static) {
+        self.raster.draw(&self.ctx, draw);
+    }
+
     pub fn produce_frame_buffer(&mut self) -> FrameBuffer {
         // Display is disabled or resolution is invalid
         if self.ctx.display_disabled {
             return FrameBuffer::black();
         }
 
-        let (sx, sy, width, height, interlaced) = (
+        // The video mode and display depth can change without the resolution changing
+        let layout = Layout::new(&self.ctx, self.overscan);
+        if self.frame.resolution != self.frame_resolution(&layout)
+            || self.frame.is_interlaced != layout.interlaced
+        {
+            self.rebuild_frame_buffer();
+        }
+
+        let scale = self.display_scale();
+        let [width, _] = self.frame.resolution;
+        let (sx, sy) = (
             self.ctx.display_vram_start.x as usize,
             self.ctx.display_vram_start.y as usize,
-            self.ctx.display_width as usize,
-            self.ctx.display_height as usize,
-            self.ctx.interlaced,
         );
 
-        let draw_odd = self.ctx.frame_counter & 1 != 0;
-
-        match self.ctx.display_depth {
-            utils::DisplayDepth::D15 => {
-                if interlaced {
-                    // Draw alternating odd/even lines every frame
-                    for y in (usize::from(draw_odd)..height).step_by(2) {
-                        for x in 0..width {
-                            let pixel = self.vram_read(sx + x, sy + y);
-                            let index = y * width + x;
-                            self.frame.rgba[index] = Color::new_5bit(pixel).with_full_alpha();
-                        }
-                    }
-                } else {
-                    for y in 0..height {
-                        let base_y = y * 2;
-
-                        for x in 0..width {
-                            let pixel = self.vram_read(sx + x, sy + y);
-                            let index = base_y * width + x;
-                            self.frame.rgba[index] = Color::new_5bit(pixel).with_full_alpha();
-                        }
-
-                        // Non interlaced displays have each row duplicated
-                        let start = base_y * width;
-                        let end = start + width;
-                        self.frame.rgba.copy_within(start..end, end);
-                    }
-                }
-            }
-            utils::DisplayDepth::D24 => {
-                if interlaced {
-                    // Draw alternating odd/even lines every frame
-                    for y in (usize::from(draw_odd)..height).step_by(2) {
-                        let mut vram_x = 0;
-
-                        // Write one full row
-                        for x in (0..width).step_by(2) {
-                            let w0 = u32::from(self.vram_read(sx + vram_x, sy + y));
-                            let w1 = u32::from(self.vram_read(sx + vram_x + 1, sy + y));
-                            let w2 = u32::from(self.vram_read(sx + vram_x + 2, sy + y));
-
-                            let pixel0 = w0 | ((w1 & 0xFF) << 16);
-                            let pixel1 = (w2 << 8) | (w1 >> 8);
-
-                            let idx = y * width + x;
+        // Picture bounds within the frame, in frame pixels
+        let (left, top) = (layout.x * scale as i32, layout.y * scale as i32);
+        let (image_width, image_rows) = (layout.image_width * scale, layout.image_rows * scale);
 
-                            self.frame.rgba[idx] = Color::new_8bit(pixel0).with_full_alpha();
-                            self.frame.rgba[idx + 1] = Color::new_8bit(pixel1).with_full_alpha();
+        let field = usize::from(self.ctx.frame_counter & 1 != 0);
+        // Kept in every mode so switching to motion adaptive starts from the last field
+        if layout.interlaced {
+            self.previous_field.clone_from(&self.frame.rgba);
+        }
 
-                            vram_x += 3;
-                        }
-                    }
-                } else {
-                    for y in 0..height {
-                        let base_y = y * 2;
-                        let mut vram_x = 0;
+        let target = self.raster.lock();
+        let pixels = match self.ctx.display_depth {
+            utils::DisplayDepth::D15 => target
+                .scaled
+                .as_ref()
+                .map_or(&target.vram[..], |s| &s.pixels[..]),
+            utils::DisplayDepth::D24 => &target.vram[..],
+        };
+        let (vram_width, vram_height) = (VRAM_WIDTH * scale, VRAM_HEIGHT * scale);
 
-                        // Write one full row
-                        for x in (0..width).step_by(2) {
-                            let w0 = u32::from(self.vram_read(sx + vram_x, sy + y));
-                            let w1 = u32::from(self.vram_read(sx + vram_x + 1, sy + y));
-                            let w2 = u32::from(self.vram_read(sx + vram_x + 2, sy + y));
+        for row in 0..layout.rows * scale {
+            // Weave, each field only replaces its own rows
+            if layout.interlaced && (row / scale) & 1 != field {
+                continue;
+            }
 
-                            let pixel0 = w0 | ((w1 & 0xFF) << 16);
-                            let pixel1 = (w2 << 8) | (w1 >> 8);
+            let out = if layout.interlaced { row } else { row * 2 };
+            let line = &mut self.frame.rgba[out * width..][..width];
+            line.fill(Color::BLACK);
 
-                            let idx = base_y * width + x;
+            let Ok(y) = usize::try_from(row as i32 - top) else {
+                continue;
+            };
+            if y >= image_rows {
+                continue;
+            }
 
-                            self.frame.rgba[idx] = Color::new_8bit(pixel0).with_full_alpha();
-                            self.frame.rgba[idx + 1] = Color::new_8bit(pixel1).with_full_alpha();
+            // The display wraps around VRAM
+            let vram_row = &pixels[((sy * scale + y) % vram_height) * vram_width..][..vram_width];
 
-                            vram_x += 3;
-                        }
+            for (column, pixel) in line.iter_mut().enumerate() {
+                let Ok(x) = usize::try_from(column as i32 - left) else {
+                    continue;
+                };
+                if x >= image_width {
+                    break;
+                }
 
-                        // Non interlaced displays have each row duplicated
-                        let start = base_y * width;
-                        let end = start + width;
-                        self.frame.rgba.copy_within(start..end, end);
+                *pixel = match self.ctx.display_depth {
+                    utils::DisplayDepth::D15 => {
+                        Color::new_5bit(vram_row[(sx * scale + x) % vram_width])
                     }
+                    utils::DisplayDepth::D24 => Color::new_8bit(read_24bit(vram_row, sx, x)),
                 }
+                .with_full_alpha();
+            }
+
+            // Lines outside 480i are shown twice
+            if !layout.interlaced {
+                let start = out * width;
+                self.frame
+                    .rgba
+                    .copy_within(start..start + width, start + width);
             }
         }
+        drop(target);
 
-        // Black out all the lines that lie outside the vertical range (bottom lines only)
-        // This is not the correct way to do it and should be changed later, pixels outside the
-        // range should blacked out with the frame being in the center
-        let vrange = (self.ctx.display_ver_range as usize) * if interlaced { 2 } else { 1 };
-        if height >= vrange {
-            let starting_row = vrange * width * if interlaced { 1 } else { 2 };
-            self.frame.rgba[starting_row..].fill(Color::BLACK);
+        if !layout.interlaced {
+            return self.frame.clone();
         }
 
-        self.frame.clone()
+        let mut progressive = self.frame.clone();
+        let woven = Woven {
+            rows: &self.frame.rgba,
+            previous: &self.previous_field,
+            width,
+            scale,
+            field,
+        };
+        self.deinterlace.apply(&woven, &mut progressive.rgba);
+        progressive.is_interlaced = false;
+        progressive.is_deinterlaced = true;
+
+        progressive
     }
 
     #[must_use]
     pub fn produce_vram_framebuffer(&self) -> FrameBuffer {
         let (sx, sy, width, height) = (0, 0, VRAM_WIDTH, VRAM_HEIGHT);
 
-        let mut vram_frame = FrameBuffer::new(width, height, false);
+        let mut vram_frame = FrameBuffer::new(width, height, false, 2.0);
+        let vram = self.vram();
 
         for y in 0..height {
             for x in 0..width {
-                let pixel = self.vram_read(sx + x, sy + y);
+                let pixel = vram[VRAM_WIDTH * (sy + y) + sx + x];
                 let index = y * width + x;
                 vram_frame.rgba[index] = Color::new_5bit(pixel).with_full_alpha();
             }
         }
+        drop(vram);
 
         vram_frame
     }
 
     // Don't reuse the rectangle drawer for this because this isn't affected by masked bit
     pub fn vram_quick_fill(&mut self, r: Vec2, side_x: i32, side_y: i32, color: Color) {
-        let min_x = r.x.max(0) as usize;
-        let min_y = r.y.max(0) as usize;
-        let max_x = (r.x + side_x).min(0x400) as usize;
-        let max_y = (r.y + side_y).min(0x200) as usize;
-
-        for x in min_x..max_x {
-            for y in min_y..max_y {
-                let index = VRAM_WIDTH * y + x;
-                self.vram[index] = color.to_5bit(None);
-            }
-        }
-    }
-
-    fn clip_rect(
-        &self,
-        min_x: i32,
-        min_y: i32,
-        max_x: i32,
-        max_y: i32,
-    ) -> Option<(usize, usize, usize, usize)> {
-        // Don't draw large ass primitives
-        if (max_x - min_x) > 1023 || (max_y - min_y) > 511 {
-            return None;
-        }
-
-        let tl = self.ctx.drawing_area_top_left;
-        let br = self.ctx.drawing_area_bottom_right;
-
-        // Trivial reject
-        if max_x < tl.x || min_x > br.x || max_y < tl.y || min_y > br.y {
-            return None;
-        }
-
-        Some((
-            min_x.max(tl.x) as usize,
-            min_y.max(tl.y) as usize,
-            max_x.min(br.x) as usize,
-            max_y.min(br.y) as usize,
-        ))
+        self.rasterise(move |canvas, _| canvas.fill(r, side_x, side_y, color));
     }
 
     pub fn draw_rectangle<const SEMI_TRANS: bool>(
@@ -284,25 +375,7 @@ impl Renderer {
     ) {
         r += self.ctx.drawing_area_offset;
 
-        let Some((min_x, min_y, max_x, max_y)) =
-            self.clip_rect(r.x, r.y, r.x + side.x - 1, r.y + side.y - 1)
-        else {
-            return;
-        };
-
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                let mut color = color;
-                if SEMI_TRANS {
-                    let old = self.vram_read(x, y);
-                    color.blend_screen(
-                        Color::new_5bit(old),
-                        self.ctx.rect_texture.transparency_weights,
-                    );
-                }
-                self.vram_write(x, y, color.to_5bit(Some(self.ctx.force_set_masked_bit)));
-            }
-        }
+        self.rasterise(move |canvas, ctx| canvas.rectangle::<SEMI_TRANS>(ctx, r, side, color));
     }
 
     pub fn draw_rectangle_textured<const SEMI_TRANS: bool, const BLEND: bool>(
@@ -314,96 +387,19 @@ impl Renderer {
     ) {
         r += self.ctx.drawing_area_offset;
 
-        let Some((min_x, min_y, max_x, max_y)) =
-            self.clip_rect(r.x, r.y, r.x + side.x - 1, r.y + side.y - 1)
-        else {
-            return;
-        };
-
         self.ctx.rect_texture.set_clut(tex.clut);
 
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                let uv = tex.uv + Vec2::new(x as i32, y as i32) - r;
-                let texel = self.ctx.rect_texture.get_texel(self, uv);
-                if texel == 0 {
-                    continue;
-                }
-
-                let mut tex_color = Color::new_5bit(texel);
-                if BLEND {
-                    tex_color.blend(color);
-                }
-
-                if SEMI_TRANS && (texel & 0x8000) != 0 {
-                    let old = self.vram_read(x, y);
-                    tex_color.blend_screen(
-                        Color::new_5bit(old),
-                        self.ctx.rect_texture.transparency_weights,
-                    );
-                }
-                let mask = self.ctx.force_set_masked_bit.then_some(true);
-                self.vram_write(x, y, tex_color.to_5bit(mask));
-            }
-        }
+        let tex = *tex;
+        self.rasterise(move |canvas, ctx| {
+            canvas.rectangle_textured::<SEMI_TRANS, BLEND>(ctx, r, side, color, &tex);
+        });
     }
 
     pub fn draw_line<const SEMI_TRANS: bool>(&mut self, mut l: [Vec2; 2], mono: Color) {
         l[0] += self.ctx.drawing_area_offset;
         l[1] += self.ctx.drawing_area_offset;
 
-        let Some((x0, y0, x1, y1)) = self.clip_rect(l[0].x, l[0].y, l[1].x, l[1].y) else {
-            return;
-        };
-
-        let x0 = x0 as i32;
-        let x1 = x1 as i32;
-        let y0 = y0 as i32;
-        let y1 = y1 as i32;
-
-        let dx = (x1 - x0).abs();
-        let dy = -(y1 - y0).abs();
-
-        let sx = if x0 < x1 { 1 } else { -1 };
-        let sy = if y0 < y1 { 1 } else { -1 };
-
-        let mut err = dx + dy;
-        let mut x = x0;
-        let mut y = y0;
-
-        loop {
-            let mut color = mono;
-
-            if SEMI_TRANS {
-                let old = self.vram_read(x as usize, y as usize);
-                color.blend_screen(
-                    Color::new_5bit(old),
-                    self.ctx.rect_texture.transparency_weights,
-                );
-            }
-
-            self.vram_write(
-                x as usize,
-                y as usize,
-                color.to_5bit(Some(self.ctx.force_set_masked_bit)),
-            );
-
-            let e2 = 2 * err;
-            if e2 >= dy {
-                if x == x1 {
-                    break;
-                }
-                err += dy;
-                x += sx;
-            }
-            if e2 <= dx {
-                if y == y1 {
-                    break;
-                }
-                err += dx;
-                y += sy;
-            }
-        }
+        self.rasterise(move |canvas, ctx| canvas.line::<SEMI_TRANS>(ctx, l, mono));
     }
 
     pub fn draw_line_shaded<const SEMI_TRANS: bool>(
@@ -414,473 +410,272 @@ impl Renderer {
         l[0] += self.ctx.drawing_area_offset;
         l[1] += self.ctx.drawing_area_offset;
 
-        let Some((x0, y0, x1, y1)) = self.clip_rect(l[0].x, l[0].y, l[1].x, l[1].y) else {
-            return;
-        };
-
-        let x0 = x0 as i32;
-        let x1 = x1 as i32;
-        let y0 = y0 as i32;
-        let y1 = y1 as i32;
-
-        let dx = (x1 - x0).abs();
-        let dy = -(y1 - y0).abs();
-
-        let sx = if x0 < x1 { 1 } else { -1 };
-        let sy = if y0 < y1 { 1 } else { -1 };
-
-        let mut err = dx + dy;
-        let mut x = x0;
-        let mut y = y0;
-
-        loop {
-            let mut color = {
-                let (num, denom) = if dx >= -dy {
-                    ((x - x0).abs(), dx)
-                } else {
-                    ((y - y0).abs(), -dy)
-                };
-
-                if denom == 0 {
-                    shaded[0]
-                } else {
-                    let inv = denom - num;
-                    let red = (i32::from(shaded[0].r) * inv + i32::from(shaded[1].r) * num) / denom;
-                    let green =
-                        (i32::from(shaded[0].g) * inv + i32::from(shaded[1].g) * num) / denom;
-                    let blue =
-                        (i32::from(shaded[0].b) * inv + i32::from(shaded[1].b) * num) / denom;
-
-                    Color {
-                        r: red as u8,
-                        g: green as u8,
-                        b: blue as u8,
-                        m: 0,
-                    }
-                }
-            };
-
-            if SEMI_TRANS {
-                let old = self.vram_read(x as usize, y as usize);
-                color.blend_screen(
-                    Color::new_5bit(old),
-                    self.ctx.rect_texture.transparency_weights,
-                );
-            }
-
-            self.vram_write(
-                x as usize,
-                y as usize,
-                color.to_5bit(Some(self.ctx.force_set_masked_bit)),
-            );
-
-            let e2 = 2 * err;
-            if e2 >= dy {
-                if x == x1 {
-                    break;
-                }
-                err += dy;
-                x += sx;
-            }
-            if e2 <= dx {
-                if y == y1 {
-                    break;
-                }
-                err += dx;
-                y += sy;
-            }
-        }
+        self.rasterise(move |canvas, ctx| canvas.line_shaded::<SEMI_TRANS>(ctx, l, shaded));
     }
 
-    pub fn draw_triangle<const SEMI_TRANS: bool>(&mut self, mut t: [Vec2; 3], mono: Color) {
+    pub fn draw_triangle<const SEMI_TRANS: bool>(
+        &mut self,
+        mut t: [Vec2; 3],
+        mut precise: [Option<PreciseVertex>; 3],
+        mono: Color,
+    ) {
         if needs_vertex_reordering(&t) {
             t.swap(0, 1);
+            precise.swap(0, 1);
         }
 
-        t[0] += self.ctx.drawing_area_offset;
-        t[1] += self.ctx.drawing_area_offset;
-        t[2] += self.ctx.drawing_area_offset;
-
-        let min_x = t[0].x.min(t[1].x).min(t[2].x);
-        let min_y = t[0].y.min(t[1].y).min(t[2].y);
-        let max_x = t[0].x.max(t[1].x).max(t[2].x);
-        let max_y = t[0].y.max(t[1].y).max(t[2].y);
-
-        let Some((min_x, min_y, max_x, max_y)) = self.clip_rect(min_x, min_y, max_x, max_y) else {
-            return;
-        };
-
-        let start = Vec2::new(min_x as i32, min_y as i32);
-        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
-        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
-        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
-
-        let bias1 = i32::from(!is_top_left(t[0], t[1]));
-        let bias2 = i32::from(!is_top_left(t[1], t[2]));
-        let bias3 = i32::from(!is_top_left(t[2], t[0]));
-
-        for y in min_y..=max_y {
-            let mut e1 = e1_row;
-            let mut e2 = e2_row;
-            let mut e3 = e3_row;
-
-            for x in min_x..=max_x {
-                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
-                    let mut color = mono;
-
-                    if SEMI_TRANS {
-                        let old = self.vram_read(x, y);
-                        color.blend_screen(
-                            Color::new_5bit(old),
-                            self.ctx.rect_texture.transparency_weights,
-                        );
-                    }
-
-                    if self.ctx.rect_texture.dithering {
-                        color.apply_dithering(x, y);
-                    }
-
-                    self.vram_write(x, y, color.to_5bit(Some(self.ctx.force_set_masked_bit)));
-                }
-
-                e1 += a1;
-                e2 += a2;
-                e3 += a3;
-            }
-
-            e1_row += b1;
-            e2_row += b2;
-            e3_row += b3;
-        }
+        let p = self.precise_triangle(t, precise);
+        let t = self.offset_triangle(t);
+        self.rasterise(move |canvas, ctx| canvas.triangle::<SEMI_TRANS>(ctx, t, p, mono));
     }
 
     pub fn draw_triangle_shaded<const SEMI_TRANS: bool>(
         &mut self,
         mut t: [Vec2; 3],
+        mut precise: [Option<PreciseVertex>; 3],
         mut shaded: [Color; 3],
     ) {
         if needs_vertex_reordering(&t) {
             t.swap(0, 1);
+            precise.swap(0, 1);
             shaded.swap(0, 1);
         }
 
-        t[0] += self.ctx.drawing_area_offset;
-        t[1] += self.ctx.drawing_area_offset;
-        t[2] += self.ctx.drawing_area_offset;
-
-        let min_x = t[0].x.min(t[1].x).min(t[2].x);
-        let min_y = t[0].y.min(t[1].y).min(t[2].y);
-        let max_x = t[0].x.max(t[1].x).max(t[2].x);
-        let max_y = t[0].y.max(t[1].y).max(t[2].y);
-
-        let Some((min_x, min_y, max_x, max_y)) = self.clip_rect(min_x, min_y, max_x, max_y) else {
-            return;
-        };
-
-        let start = Vec2::new(min_x as i32, min_y as i32);
-        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
-        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
-        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
-
-        let bias1 = i32::from(!is_top_left(t[0], t[1]));
-        let bias2 = i32::from(!is_top_left(t[1], t[2]));
-        let bias3 = i32::from(!is_top_left(t[2], t[0]));
-
-        let [c0, c1, c2] = shaded;
-
-        let r_dx = a2 * i32::from(c0.r) + a3 * i32::from(c1.r) + a1 * i32::from(c2.r);
-        let g_dx = a2 * i32::from(c0.g) + a3 * i32::from(c1.g) + a1 * i32::from(c2.g);
-        let b_dx = a2 * i32::from(c0.b) + a3 * i32::from(c1.b) + a1 * i32::from(c2.b);
-
-        let r_dy = b2 * i32::from(c0.r) + b3 * i32::from(c1.r) + b1 * i32::from(c2.r);
-        let g_dy = b2 * i32::from(c0.g) + b3 * i32::from(c1.g) + b1 * i32::from(c2.g);
-        let b_dy = b2 * i32::from(c0.b) + b3 * i32::from(c1.b) + b1 * i32::from(c2.b);
-
-        let mut r_row =
-            e2_row * i32::from(c0.r) + e3_row * i32::from(c1.r) + e1_row * i32::from(c2.r);
-        let mut g_row =
-            e2_row * i32::from(c0.g) + e3_row * i32::from(c1.g) + e1_row * i32::from(c2.g);
-        let mut b_row =
-            e2_row * i32::from(c0.b) + e3_row * i32::from(c1.b) + e1_row * i32::from(c2.b);
-
-        let sum = e1_row + e2_row + e3_row;
-
-        for y in min_y..=max_y {
-            let mut e1 = e1_row;
-            let mut e2 = e2_row;
-            let mut e3 = e3_row;
-            let mut r_num = r_row;
-            let mut g_num = g_row;
-            let mut b_num = b_row;
-
-            for x in min_x..=max_x {
-                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
-                    let mut color = Color {
-                        r: (r_num / sum) as u8,
-                        g: (g_num / sum) as u8,
-                        b: (b_num / sum) as u8,
-                        m: 0,
-                    };
-
-                    if SEMI_TRANS {
-                        let old = self.vram_read(x, y);
-                        color.blend_screen(
-                            Color::new_5bit(old),
-                            self.ctx.rect_texture.transparency_weights,
-                        );
-                    }
-
-                    if self.ctx.rect_texture.dithering {
-                        color.apply_dithering(x, y);
-                    }
-
-                    self.vram_write(x, y, color.to_5bit(Some(self.ctx.force_set_masked_bit)));
-                }
-
-                e1 += a1;
-                e2 += a2;
-                e3 += a3;
-                r_num += r_dx;
-                g_num += g_dx;
-                b_num += b_dx;
-            }
-
-            e1_row += b1;
-            e2_row += b2;
-            e3_row += b3;
-            r_row += r_dy;
-            g_row += g_dy;
-            b_row += b_dy;
-        }
+        let p = self.precise_triangle(t, precise);
+        let t = self.offset_triangle(t);
+        self.rasterise(move |canvas, ctx| {
+            canvas.triangle_shaded::<SEMI_TRANS>(ctx, t, p, shaded);
+        });
     }
 
     pub fn draw_triangle_textured<const SEMI_TRANS: bool, const BLEND: bool>(
         &mut self,
         mut t: [Vec2; 3],
+        mut precise: [Option<PreciseVertex>; 3],
         mono: Color,
         mut tex: TextureOptions,
     ) {
         if needs_vertex_reordering(&t) {
             t.swap(0, 1);
+            precise.swap(0, 1);
             tex.uvs.swap(0, 1);
         }
 
-        t[0] += self.ctx.drawing_area_offset;
-        t[1] += self.ctx.drawing_area_offset;
-        t[2] += self.ctx.drawing_area_offset;
-
-        let min_x = t[0].x.min(t[1].x).min(t[2].x);
-        let min_y = t[0].y.min(t[1].y).min(t[2].y);
-        let max_x = t[0].x.max(t[1].x).max(t[2].x);
-        let max_y = t[0].y.max(t[1].y).max(t[2].y);
-
-        let Some((min_x, min_y, max_x, max_y)) = self.clip_rect(min_x, min_y, max_x, max_y) else {
-            return;
-        };
-
-        let start = Vec2::new(min_x as i32, min_y as i32);
-        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
-        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
-        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
-
-        let bias1 = i32::from(!is_top_left(t[0], t[1]));
-        let bias2 = i32::from(!is_top_left(t[1], t[2]));
-        let bias3 = i32::from(!is_top_left(t[2], t[0]));
-
-        let [uv0, uv1, uv2] = tex.uvs;
-
-        let u_dx = a2 * uv0.x + a3 * uv1.x + a1 * uv2.x;
-        let v_dx = a2 * uv0.y + a3 * uv1.y + a1 * uv2.y;
-        let u_dy = b2 * uv0.x + b3 * uv1.x + b1 * uv2.x;
-        let v_dy = b2 * uv0.y + b3 * uv1.y + b1 * uv2.y;
-
-        let mut u_row = e2_row * uv0.x + e3_row * uv1.x + e1_row * uv2.x;
-        let mut v_row = e2_row * uv0.y + e3_row * uv1.y + e1_row * uv2.y;
-
-        let sum = e1_row + e2_row + e3_row;
-
-        for y in min_y..=max_y {
-            let mut e1 = e1_row;
-            let mut e2 = e2_row;
-            let mut e3 = e3_row;
-            let mut u_num = u_row;
-            let mut v_num = v_row;
-
-            for x in min_x..=max_x {
-                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
-                    let texel = tex
-                        .texture
-                        .get_texel(self, Vec2::new(u_num / sum, v_num / sum));
-
-                    // Fully black texels are ignored
-                    if texel != 0 {
-                        let mut color = Color::new_5bit(texel);
-                        if BLEND {
-                            color.blend(mono);
-                        }
-
-                        if SEMI_TRANS && (texel & 0x8000) != 0 {
-                            let old = self.vram_read(x, y);
-                            color.blend_screen(
-                                Color::new_5bit(old),
-                                tex.texture.transparency_weights,
-                            );
-                        }
-
-                        if tex.texture.dithering {
-                            color.apply_dithering(x, y);
-                        }
-
-                        self.vram_write(
-                            x,
-                            y,
-                            color.to_5bit(self.ctx.force_set_masked_bit.then_some(true)),
-                        );
-                    }
-                }
-
-                e1 += a1;
-                e2 += a2;
-                e3 += a3;
-                u_num += u_dx;
-                v_num += v_dx;
-            }
-
-            e1_row += b1;
-            e2_row += b2;
-            e3_row += b3;
-            u_row += u_dy;
-            v_row += v_dy;
-        }
+        let p = self.precise_triangle(t, precise);
+        let t = self.offset_triangle(t);
+        self.rasterise(move |canvas, ctx| {
+            canvas.triangle_textured::<SEMI_TRANS, BLEND>(ctx, t, p, mono, &tex);
+        });
     }
 
     pub fn draw_triangle_textured_shaded<const SEMI_TRANS: bool, const BLEND: bool>(
         &mut self,
         mut t: [Vec2; 3],
+        mut precise: [Option<PreciseVertex>; 3],
         mut shaded: [Color; 3],
         mut tex: TextureOptions,
     ) {
         if needs_vertex_reordering(&t) {
             t.swap(0, 1);
+            precise.swap(0, 1);
             shaded.swap(0, 1);
             tex.uvs.swap(0, 1);
         }
 
-        t[0] += self.ctx.drawing_area_offset;
-        t[1] += self.ctx.drawing_area_offset;
-        t[2] += self.ctx.drawing_area_offset;
+        let p = self.precise_triangle(t, precise);
+        let t = self.offset_triangle(t);
+        self.rasterise(move |canvas, ctx| {
+            canvas.triangle_textured_shaded::<SEMI_TRANS, BLEND>(ctx, t, p, shaded, &tex);
+        });
+    }
 
-        let min_x = t[0].x.min(t[1].x).min(t[2].x);
-        let min_y = t[0].y.min(t[1].y).min(t[2].y);
-        let max_x = t[0].x.max(t[1].x).max(t[2].x);
-        let max_y = t[0].y.max(t[1].y).max(t[2].y);
+    fn offset_triangle(&self, t: [Vec2; 3]) -> [Vec2; 3] {
+        t.map(|v| v + self.ctx.drawing_area_offset)
+    }
 
-        let Some((min_x, min_y, max_x, max_y)) = self.clip_rect(min_x, min_y, max_x, max_y) else {
-            return;
-        };
+    /// Offset precise vertices, filling in the whole ones for any the GTE didn't project.
+    /// Triangles without any are drawn from whole pixels only.
+    fn precise_triangle(
+        &self,
+        t: [Vec2; 3],
+        precise: [Option<PreciseVertex>; 3],
+    ) -> Option<[PreciseVertex; 3]> {
+        if !self.precise_geometry || precise.iter().all(Option::is_none) {
+            return None;
+        }
 
-        let start = Vec2::new(min_x as i32, min_y as i32);
-        let (mut e1_row, a1, b1) = edge_function(start, t[0], t[1]);
-        let (mut e2_row, a2, b2) = edge_function(start, t[1], t[2]);
-        let (mut e3_row, a3, b3) = edge_function(start, t[2], t[0]);
-
-        let bias1 = i32::from(!is_top_left(t[0], t[1]));
-        let bias2 = i32::from(!is_top_left(t[1], t[2]));
-        let bias3 = i32::from(!is_top_left(t[2], t[0]));
-
-        let [c0, c1, c2] = shaded;
-
-        let r_dx = a2 * i32::from(c0.r) + a3 * i32::from(c1.r) + a1 * i32::from(c2.r);
-        let g_dx = a2 * i32::from(c0.g) + a3 * i32::from(c1.g) + a1 * i32::from(c2.g);
-        let b_dx = a2 * i32::from(c0.b) + a3 * i32::from(c1.b) + a1 * i32::from(c2.b);
-
-        let r_dy = b2 * i32::from(c0.r) + b3 * i32::from(c1.r) + b1 * i32::from(c2.r);
-        let g_dy = b2 * i32::from(c0.g) + b3 * i32::from(c1.g) + b1 * i32::from(c2.g);
-        let b_dy = b2 * i32::from(c0.b) + b3 * i32::from(c1.b) + b1 * i32::from(c2.b);
-
-        let mut r_row =
-            e2_row * i32::from(c0.r) + e3_row * i32::from(c1.r) + e1_row * i32::from(c2.r);
-        let mut g_row =
-            e2_row * i32::from(c0.g) + e3_row * i32::from(c1.g) + e1_row * i32::from(c2.g);
-        let mut b_row =
-            e2_row * i32::from(c0.b) + e3_row * i32::from(c1.b) + e1_row * i32::from(c2.b);
-
-        let [uv0, uv1, uv2] = tex.uvs;
-
-        let u_dx = a2 * uv0.x + a3 * uv1.x + a1 * uv2.x;
-        let v_dx = a2 * uv0.y + a3 * uv1.y + a1 * uv2.y;
-        let u_dy = b2 * uv0.x + b3 * uv1.x + b1 * uv2.x;
-        let v_dy = b2 * uv0.y + b3 * uv1.y + b1 * uv2.y;
-
-        let mut u_row = e2_row * uv0.x + e3_row * uv1.x + e1_row * uv2.x;
-        let mut v_row = e2_row * uv0.y + e3_row * uv1.y + e1_row * uv2.y;
-
-        let sum = e1_row + e2_row + e3_row;
-
-        for y in min_y..=max_y {
-            let mut e1 = e1_row;
-            let mut e2 = e2_row;
-            let mut e3 = e3_row;
-            let mut r_num = r_row;
-            let mut g_num = g_row;
-            let mut b_num = b_row;
-            let mut u_num = u_row;
-            let mut v_num = v_row;
-
-            for x in min_x..=max_x {
-                if e1 >= bias1 && e2 >= bias2 && e3 >= bias3 {
-                    let texel = tex
-                        .texture
-                        .get_texel(self, Vec2::new(u_num / sum, v_num / sum));
-                    // Fully black texels are ignored
-                    if texel != 0 {
-                        let mut color = Color::new_5bit(texel);
-                        if BLEND {
-                            color.blend(Color {
-                                r: (r_num / sum) as u8,
-                                g: (g_num / sum) as u8,
-                                b: (b_num / sum) as u8,
-                                m: 0,
-                            });
-                        }
-
-                        if SEMI_TRANS && (texel >> 15) & 1 == 1 {
-                            let old = self.vram_read(x, y);
-                            color.blend_screen(
-                                Color::new_5bit(old),
-                                tex.texture.transparency_weights,
-                            );
-                        }
-
-                        if tex.texture.dithering {
-                            color.apply_dithering(x, y);
-                        }
-
-                        self.vram_write(
-                            x,
-                            y,
-                            color.to_5bit(self.ctx.force_set_masked_bit.then_some(true)),
-                        );
-                    }
-                }
+        let offset = self.ctx.drawing_area_offset;
+        Some(std::array::from_fn(|i| {
+            let mut v = precise[i].unwrap_or_else(|| PreciseVertex::from_whole(t[i]));
+            v.x += offset.x as f32;
+            v.y += offset.y as f32;
+            if !self.perspective_correct {
+                v.w = 0.0;
+            }
+            v
+        }))
+    }
+}
+
+/// Dot `x` of a 24 bit picture starting at halfword `sx` of a VRAM row, 3 bytes per dot
+fn read_24bit(row: &[u16], sx: usize, x: usize) -> u32 {
+    let byte = |i: usize| {
+        let halfword = row[(sx + i / 2) % VRAM_WIDTH];
+        u32::from(if i.is_multiple_of(2) {
+            halfword & 0xFF
+        } else {
+            halfword >> 8
+        })
+    };
+
+    let start = x * 3;
+    byte(start) | (byte(start + 1) << 8) | (byte(start + 2) << 16)
+}
 
-                e1 += a1;
-                e2 += a2;
-                e3 += a3;
-                r_num += r_dx;
-                g_num += g_dx;
-                b_num += b_dx;
-                u_num += u_dx;
-                v_num += v_dx;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Clut;
+    use crate::utils::Texture;
+
+    /// Texture page attribute for 15 bit direct color at the top left of VRAM
+    const PAGE_15BIT: u16 = 2 << 7;
+
+    fn renderer(scale: usize) -> Renderer {
+        let mut renderer = Renderer::default();
+        renderer.ctx.drawing_area_bottom_right = Vec2::new(VRAM_WIDTH as i32 - 1, 511);
+        renderer.ctx.rect_texture = Texture::new(PAGE_15BIT, None);
+        renderer.set_resolution_scale(scale);
+        renderer
+    }
+
+    /// Every canvas pixel of the upscaled copy that covers native pixel `x`, `y`
+    fn scaled_block(renderer: &Renderer, x: usize, y: usize) -> Vec<u16> {
+        let target = renderer.raster.lock();
+        let scaled = target.scaled.as_ref().expect("upscaled copy");
+        let s = scaled.scale;
+
+        let block = (0..s * s)
+            .map(|i| scaled.pixels[VRAM_WIDTH * s * (y * s + i / s) + x * s + i % s])
+            .collect();
+        drop(target);
+        block
+    }
+
+    /// Upscaled copy of axis aligned drawing, every block has to match its native pixel
+    fn assert_blocks_match(renderer: &Renderer, width: usize, height: usize) {
+        for y in 0..height {
+            for x in 0..width {
+                let native = renderer.vram_read(x, y);
+                let block = scaled_block(renderer, x, y);
+                assert!(
+                    block.iter().all(|&p| p == native),
+                    "({x}, {y}) native {native:04X}, scaled {block:04X?}"
+                );
             }
-            e1_row += b1;
-            e2_row += b2;
-            e3_row += b3;
-            r_row += r_dy;
-            g_row += g_dy;
-            b_row += b_dy;
-            u_row += u_dy;
-            v_row += v_dy;
         }
     }
+
+    #[test]
+    fn upscaled_copy_starts_from_native_vram() {
+        let mut renderer = Renderer::default();
+        renderer.vram_write(3, 2, 0x1234);
+        renderer.set_resolution_scale(3);
+
+        assert_eq!(renderer.resolution_scale(), 3);
+        assert_blocks_match(&renderer, 8, 4);
+
+        renderer.set_resolution_scale(MAX_RESOLUTION_SCALE + 1);
+        assert_eq!(renderer.resolution_scale(), MAX_RESOLUTION_SCALE);
+        assert_blocks_match(&renderer, 8, 4);
+    }
+
+    #[test]
+    fn fills_copies_and_uploads_reach_the_upscaled_copy() {
+        let mut renderer = renderer(2);
+
+        renderer.vram_quick_fill(Vec2::new(0, 0), 4, 2, Color::new_5bit(0x001Fu16));
+        renderer.vram_write(5, 1, 0x7C00);
+        renderer.vram_self_copy(Vec2::new(0, 0), Vec2::new(8, 2), Vec2::new(6, 2));
+        renderer.draw_rectangle::<false>(
+            Vec2::new(2, 4),
+            Vec2::new(3, 2),
+            Color::new_5bit(0x03E0u16),
+        );
+
+        assert_eq!(renderer.vram_read(9, 3), 0x001F);
+        assert_eq!(renderer.vram_read(13, 3), 0x7C00);
+        assert_eq!(renderer.vram_read(4, 5), 0x03E0);
+        assert_blocks_match(&renderer, 16, 8);
+    }
+
+    #[test]
+    fn upscaled_copy_samples_textures_from_before_the_primitive() {
+        let mut renderer = renderer(2);
+        for x in 0..4 {
+            renderer.vram_write(x, 0, 0x7FFF);
+        }
+
+        // Draw the texture over itself at half brightness. Sampling what the native pass just
+        // wrote would darken the upscaled copy twice.
+        let half = Color {
+            r: 64,
+            g: 64,
+            b: 64,
+            m: 0,
+        };
+        let tex = RectTextureOptions {
+            clut: Clut::new(0),
+            uv: Vec2::new(0, 0),
+        };
+        renderer.draw_rectangle_textured::<false, true>(
+            Vec2::new(0, 0),
+            Vec2::new(4, 1),
+            half,
+            &tex,
+        );
+
+        let darkened = renderer.vram_read(0, 0);
+        assert_ne!(darkened, 0x7FFF);
+        assert_blocks_match(&renderer, 4, 1);
+    }
+
+    #[test]
+    fn triangles_cover_the_same_pixels_when_upscaled() {
+        let mut renderer = renderer(4);
+        let red = Color::new_5bit(0x001Fu16);
+        renderer.draw_triangle::<false>(
+            [Vec2::new(0, 0), Vec2::new(16, 0), Vec2::new(0, 16)],
+            [None; 3],
+            red,
+        );
+
+        // Blocks well inside the triangle are filled at every scale, ones outside at none
+        assert_eq!(renderer.vram_read(2, 2), 0x001F);
+        assert!(scaled_block(&renderer, 2, 2).iter().all(|&p| p == 0x001F));
+        assert_eq!(renderer.vram_read(15, 15), 0);
+        assert!(scaled_block(&renderer, 15, 15).iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn deinterlaced_frames_are_progressive() {
+        let mut renderer = Renderer::default();
+        renderer.ctx.display_hor_range = 640 * 4;
+        renderer.ctx.display_ver_range = 240;
+        renderer.change_resolution(640, 480);
+
+        renderer.ctx.interlaced = true;
+        let frame = renderer.produce_frame_buffer();
+        assert!(!frame.is_interlaced);
+        assert!(frame.is_deinterlaced);
+        assert_eq!(frame.resolution[1], 448);
+
+        // Lines outside 480i are doubled instead
+        renderer.ctx.interlaced = false;
+        renderer.change_resolution(640, 240);
+        let frame = renderer.produce_frame_buffer();
+        assert!(!frame.is_interlaced);
+        assert!(!frame.is_deinterlaced);
+        assert_eq!(frame.resolution[1], 448);
+    }
 }
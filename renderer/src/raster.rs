@@ -0,0 +1,375 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::PoisonError;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use tracing::error;
+use tracing::info;
+
+use crate::VRAM_SIZE;
+use crate::VRAM_WIDTH;
+use crate::canvas::Canvas;
+use crate::utils::DrawContext;
+
+/// Draw commands queued ahead of the renderer thread before the emulator blocks
+const QUEUE_DEPTH: usize = 4096;
+
+/// A primitive or VRAM transfer, run once on native VRAM and once on the upscaled copy
+type Job = Box<dyn FnMut(&mut Canvas, &DrawContext) + Send>;
+
+enum Message {
+    Draw(DrawContext, Job),
+    /// Answered once every command queued before it has been drawn
+    Fence(mpsc::SyncSender<()>),
+}
+
+/// Everything primitives are drawn into
+pub struct Target {
+    pub vram: Box<[u16; VRAM_SIZE]>,
+    pub scaled: Option<ScaledVram>,
+}
+
+impl Target {
+    /// Draw into the upscaled copy if there is one, then the same into native VRAM. The copy
+    /// goes first so it samples textures from VRAM as it was before the primitive.
+    pub fn draw(&mut self, ctx: &DrawContext, job: &mut dyn FnMut(&mut Canvas, &DrawContext)) {
+        if let Some(scaled) = self.scaled.as_mut() {
+            let mut canvas = Canvas::scaled(&mut scaled.pixels, self.vram.as_slice(), scaled.scale);
+            job(&mut canvas, ctx);
+        }
+
+        job(&mut Canvas::native(self.vram.as_mut_slice()), ctx);
+    }
+}
+
+/// Copy of VRAM at a higher resolution that primitives are also drawn into. The native copy
+/// stays authoritative for everything the console can read back.
+pub struct ScaledVram {
+    pub pixels: Box<[u16]>,
+    pub scale: usize,
+}
+
+impl ScaledVram {
+    /// Upscale native VRAM by repeating each pixel
+    pub fn from_native(vram: &[u16], scale: usize) -> Self {
+        let width = VRAM_WIDTH * scale;
+        let mut pixels = vec![0; VRAM_SIZE * scale * scale].into_boxed_slice();
+
+        for (y, row) in pixels.chunks_exact_mut(width).enumerate() {
+            let native = &vram[(y / scale) * VRAM_WIDTH..][..VRAM_WIDTH];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = native[x / scale];
+            }
+        }
+
+        Self { pixels, scale }
+    }
+}
+
+/// Halfwords of VRAM transfers, they arrive one at a time so they're drawn together as one
+/// job instead of taking VRAM for each
+struct Uploads {
+    ctx: DrawContext,
+    pixels: Vec<(usize, usize, u16)>,
+}
+
+impl Uploads {
+    /// Uploads only look at the mask settings, any other change to the context can share a job
+    const fn same_mask(&self, ctx: &DrawContext) -> bool {
+        self.ctx.preserve_masked_pixels == ctx.preserve_masked_pixels
+            && self.ctx.force_set_masked_bit == ctx.force_set_masked_bit
+    }
+}
+
+/// Runs draw commands in order, either right away or on a renderer thread. Anything that
+/// looks at VRAM waits for the queue to drain first, so both give the same pixels.
+pub struct Rasteriser {
+    target: Arc<Mutex<Target>>,
+    worker: Option<Worker>,
+    /// Commands were queued since the last time the worker caught up
+    pending: Cell<bool>,
+    /// The renderer thread is gone, everything is drawn on the caller's thread instead
+    disconnected: Cell<bool>,
+    /// VRAM writes not drawn yet, they go out before the next command or look at VRAM
+    uploads: RefCell<Option<Uploads>>,
+}
+
+struct Worker {
+    commands: mpsc::SyncSender<Message>,
+    thread: JoinHandle<()>,
+}
+
+impl Worker {
+    fn spawn(target: Arc<Mutex<Target>>) -> Self {
+        let (commands, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+
+        let thread = std::thread::Builder::new()
+            .name("renderer".into())
+            .spawn(move || {
+                info!("renderer thread started...");
+
+                // Ends once the emulator side hangs up
+                for message in rx {
+                    match message {
+                        Message::Draw(ctx, mut job) => {
+                            lock_target(&target).draw(&ctx, &mut job);
+                        }
+                        Message::Fence(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+
+                info!("renderer thread stopped!");
+            })
+            .expect("spawn renderer thread");
+
+        Self { commands, thread }
+    }
+
+    fn stop(self) {
+        drop(self.commands);
+        if self.thread.join().is_err() {
+            error!("renderer thread panicked");
+        }
+    }
+}
+
+impl Default for Rasteriser {
+    fn default() -> Self {
+        Self {
+            target: Arc::new(Mutex::new(Target {
+                vram: vec![0; VRAM_SIZE].try_into().expect("vram alloc"),
+                scaled: None,
+            })),
+            worker: None,
+            pending: Cell::new(false),
+            disconnected: Cell::new(false),
+            uploads: RefCell::new(None),
+        }
+    }
+}
+
+impl Drop for Rasteriser {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            worker.stop();
+        }
+    }
+}
+
+impl Rasteriser {
+    pub fn is_threaded(&self) -> bool {
+        self.worker().is_some()
+    }
+
+    /// Move drawing to a renderer thread or back onto the caller's, also replaces a renderer
+    /// thread that stopped
+    pub fn set_threaded(&mut self, threaded: bool) {
+        if threaded == self.is_threaded() {
+            return;
+        }
+
+        self.flush_uploads();
+        if let Some(worker) = self.worker.take() {
+            // Joining drains the queue
+            worker.stop();
+            self.pending.set(false);
+        }
+
+        self.disconnected.set(false);
+        if threaded {
+            self.worker = Some(Worker::spawn(Arc::clone(&self.target)));
+        }
+    }
+
+    /// Queue a command, or draw it now without a renderer thread
+    pub fn draw(
+        &self,
+        ctx: &DrawContext,
+        job: impl FnMut(&mut Canvas, &DrawContext) + Send + 'static,
+    ) {
+        self.flush_uploads();
+        self.send(ctx, Box::new(job));
+    }
+
+    /// Write a native pixel, batched with the rest of the transfer it's part of
+    pub fn upload(&self, ctx: &DrawContext, x: usize, y: usize, data: u16) {
+        let mut uploads = self.uploads.borrow_mut();
+        if let Some(batch) = uploads.as_mut().filter(|b| b.same_mask(ctx)) {
+            batch.pixels.push((x, y, data));
+            return;
+        }
+
+        let previous = uploads.replace(Uploads {
+            ctx: ctx.clone(),
+            pixels: vec![(x, y, data)],
+        });
+        drop(uploads);
+
+        if let Some(previous) = previous {
+            self.send_uploads(previous);
+        }
+    }
+
+    fn flush_uploads(&self) {
+        let uploads = self.uploads.borrow_mut().take();
+        if let Some(uploads) = uploads {
+            self.send_uploads(uploads);
+        }
+    }
+
+    fn send_uploads(&self, uploads: Uploads) {
+        let Uploads { ctx, pixels } = uploads;
+        self.send(
+            &ctx,
+            Box::new(move |canvas, ctx| {
+                for &(x, y, data) in &pixels {
+                    canvas.write_block(ctx, x, y, data);
+                }
+            }),
+        );
+    }
+
+    fn send(&self, ctx: &DrawContext, mut job: Job) {
+        if let Some(worker) = self.worker() {
+            match worker.commands.send(Message::Draw(ctx.clone(), job)) {
+                Ok(()) => {
+                    self.pending.set(true);
+                    return;
+                }
+                Err(mpsc::SendError(message)) => {
+                    self.disconnect();
+                    let Message::Draw(_, returned) = message else {
+                        unreachable!("a draw was sent");
+                    };
+                    job = returned;
+                }
+            }
+        }
+
+        lock_target(&self.target).draw(ctx, &mut job);
+    }
+
+    /// The renderer thread, unless it has stopped answering
+    fn worker(&self) -> Option<&Worker> {
+        self.worker.as_ref().filter(|_| !self.disconnected.get())
+    }
+
+    /// Carry on without the renderer thread, it only hangs up if it panicked
+    fn disconnect(&self) {
+        error!("renderer thread stopped, drawing on the emulator thread");
+        self.disconnected.set(true);
+        self.pending.set(false);
+    }
+
+    /// Wait for every queued command and take VRAM
+    pub fn lock(&self) -> MutexGuard<'_, Target> {
+        self.flush_uploads();
+
+        if let Some(worker) = self.worker()
+            && self.pending.replace(false)
+        {
+            let (done, wait) = mpsc::sync_channel(1);
+            let drained = worker.commands.send(Message::Fence(done)).is_ok() && wait.recv().is_ok();
+            if !drained {
+                self.disconnect();
+            }
+        }
+
+        lock_target(&self.target)
+    }
+}
+
+/// A panic on the renderer thread poisons the lock, VRAM is still usable as it was left
+fn lock_target(target: &Mutex<Target>) -> MutexGuard<'_, Target> {
+    target.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Native VRAM, only held while the renderer thread is idle
+pub struct VramGuard<'a>(pub(crate) MutexGuard<'a, Target>);
+
+impl Deref for VramGuard<'_> {
+    type Target = [u16];
+
+    fn deref(&self) -> &[u16] {
+        self.0.vram.as_slice()
+    }
+}
+
+impl DerefMut for VramGuard<'_> {
+    fn deref_mut(&mut self) -> &mut [u16] {
+        self.0.vram.as_mut_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_keep_the_mask_setting_they_were_made_with() {
+        for threaded in [false, true] {
+            let mut raster = Rasteriser::default();
+            raster.set_threaded(threaded);
+
+            let mut ctx = DrawContext::default();
+            raster.upload(&ctx, 0, 0, 0x0001);
+            raster.upload(&ctx, 1, 0, 0x0002);
+
+            ctx.force_set_masked_bit = true;
+            raster.upload(&ctx, 2, 0, 0x0003);
+
+            // Drawing lands after the uploads queued before it
+            raster.draw(&ctx, |canvas, ctx| canvas.write_block(ctx, 3, 0, 0x0004));
+            raster.upload(&ctx, 4, 0, 0x0005);
+
+            let vram = raster.lock().vram[..5].to_vec();
+            assert_eq!(vram, [0x0001, 0x0002, 0x8003, 0x8004, 0x8005]);
+        }
+    }
+
+    #[test]
+    fn switching_threads_keeps_queued_work() {
+        let mut raster = Rasteriser::default();
+        raster.set_threaded(true);
+        assert!(raster.is_threaded());
+
+        let ctx = DrawContext::default();
+        raster.upload(&ctx, 10, 0, 0x1234);
+        raster.draw(&ctx, |canvas, ctx| canvas.write_block(ctx, 11, 0, 0x5678));
+
+        raster.set_threaded(false);
+        assert!(!raster.is_threaded());
+        assert_eq!(raster.lock().vram[10..12], [0x1234, 0x5678]);
+    }
+
+    #[test]
+    fn carries_on_without_a_renderer_thread_that_panicked() {
+        let mut raster = Rasteriser::default();
+        raster.set_threaded(true);
+
+        let ctx = DrawContext::default();
+        raster.draw(&ctx, |_, _| panic!("draw job failed"));
+
+        // Waiting on the queue finds the thread gone
+        drop(raster.lock());
+        assert!(!raster.is_threaded());
+
+        raster.draw(&ctx, |canvas, ctx| canvas.write_block(ctx, 0, 0, 0x1234));
+        raster.upload(&ctx, 1, 0, 0x5678);
+        assert_eq!(raster.lock().vram[..2], [0x1234, 0x5678]);
+
+        // Asking for a thread again starts a new one
+        raster.set_threaded(true);
+        assert!(raster.is_threaded());
+        raster.draw(&ctx, |canvas, ctx| canvas.write_block(ctx, 2, 0, 0x4321));
+        assert_eq!(raster.lock().vram[2], 0x4321);
+    }
+}
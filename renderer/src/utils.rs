@@ -1,7 +1,7 @@
 use num_enum::FromPrimitive;
 use num_enum::IntoPrimitive;
 
-use crate::Renderer;
+use crate::VRAM_WIDTH;
 use crate::vec2::Vec2;
 
 #[repr(C)]
@@ -130,7 +130,7 @@ const fn convert_5bit_to_8bit(color: u16) -> u8 {
 }
 
 // Store the current drawing context
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
 pub struct DrawContext {
     pub drawing_area_top_left: Vec2,
     pub drawing_area_bottom_right: Vec2,
@@ -147,6 +147,10 @@ pub struct DrawContext {
     pub display_hor_range: u16,
     pub display_ver_range: u16,
 
+    /// Where the display area starts, in GPU clock cycles and scanlines from sync
+    pub display_hor_start: u16,
+    pub display_ver_start: u16,
+
     pub rect_texture: Texture,
 
     pub preserve_masked_pixels: bool,
@@ -155,12 +159,52 @@ pub struct DrawContext {
     pub display_depth: DisplayDepth,
 
     pub display_disabled: bool,
+    pub display_pal: bool,
     pub interlaced: bool,
 
     pub frame_counter: u32,
     pub line_counter: u32,
 }
 
+impl Default for DrawContext {
+    fn default() -> Self {
+        Self {
+            drawing_area_top_left: Vec2::default(),
+            drawing_area_bottom_right: Vec2::default(),
+            drawing_area_offset: Vec2::default(),
+
+            texture_window_mask: Vec2::default(),
+            texture_window_offset: Vec2::default(),
+
+            display_vram_start: Vec2::default(),
+
+            display_width: 0,
+            display_height: 0,
+
+            display_hor_range: 0,
+            display_ver_range: 0,
+
+            // GP1(06) and GP1(07) reset values
+            display_hor_start: 0x200,
+            display_ver_start: 0x10,
+
+            rect_texture: Texture::default(),
+
+            preserve_masked_pixels: false,
+            force_set_masked_bit: false,
+
+            display_depth: DisplayDepth::default(),
+
+            display_disabled: false,
+            display_pal: false,
+            interlaced: false,
+
+            frame_counter: 0,
+            line_counter: 0,
+        }
+    }
+}
+
 impl DrawContext {
     pub fn reset(&mut self) {
         *self = Self::default();
@@ -181,9 +225,15 @@ impl Clut {
         Self { base_x, base_y }
     }
 
+    /// Inverse of [`Clut::new`]
+    #[must_use]
+    pub const fn raw(&self) -> u16 {
+        ((self.base_x >> 4) | (self.base_y << 6)) as u16
+    }
+
     #[must_use]
-    pub fn get_color(&self, renderer: &Renderer, index: u8) -> u16 {
-        renderer.vram_read(self.base_x + index as usize, self.base_y)
+    pub fn get_color(&self, vram: &[u16], index: u8) -> u16 {
+        vram[VRAM_WIDTH * self.base_y + self.base_x + index as usize]
     }
 }
 
@@ -239,20 +289,41 @@ impl Texture {
         }
     }
 
+    /// Inverse of [`Texture::new`], returns the texpage attribute and the optional clut
+    #[must_use]
+    pub fn raw(&self) -> (u16, Option<u16>) {
+        let transparency = match self.transparency_weights {
+            (2, 2) => 0,
+            (4, 4) => 1,
+            (4, -4) => 2,
+            _ => 3,
+        };
+
+        let data = (self.page_x >> 6)
+            | ((self.page_y >> 8) << 4)
+            | (transparency << 5)
+            | (u8::from(self.depth) as usize) << 7
+            | usize::from(self.dithering) << 9
+            | usize::from(self.draw_to_display) << 10;
+
+        (data as u16, self.clut.as_ref().map(Clut::raw))
+    }
+
     pub const fn set_clut(&mut self, clut: Clut) {
         self.clut = Some(clut);
     }
 
+    /// Texel at `p` in the texture page, `vram` being the native 1024x512 VRAM
     #[must_use]
-    pub fn get_texel(&self, renderer: &Renderer, p: Vec2) -> u16 {
+    pub fn get_texel(&self, ctx: &DrawContext, vram: &[u16], p: Vec2) -> u16 {
         let Vec2 {
             x: x_mask,
             y: y_mask,
-        } = renderer.ctx.texture_window_mask;
+        } = ctx.texture_window_mask;
         let Vec2 {
             x: x_offset,
             y: y_offset,
-        } = renderer.ctx.texture_window_offset;
+        } = ctx.texture_window_offset;
 
         // Calculate new texcoords based on some offsets and masks
         let p = Vec2::new(
@@ -261,35 +332,35 @@ impl Texture {
         );
 
         match self.depth {
-            PageColor::Bit4 => self.get_texel_4bit(renderer, p),
-            PageColor::Bit8 => self.get_texel_8bit(renderer, p),
-            PageColor::Bit15 => self.get_texel_16bit(renderer, p),
+            PageColor::Bit4 => self.get_texel_4bit(vram, p),
+            PageColor::Bit8 => self.get_texel_8bit(vram, p),
+            PageColor::Bit15 => self.get_texel_16bit(vram, p),
         }
     }
 
-    fn get_texel_16bit(&self, renderer: &Renderer, p: Vec2) -> u16 {
+    fn get_texel_16bit(&self, vram: &[u16], p: Vec2) -> u16 {
         let (u, v) = (p.x as usize, p.y as usize);
-        renderer.vram_read(self.page_x + u, self.page_y + v)
+        vram[VRAM_WIDTH * (self.page_y + v) + self.page_x + u]
     }
 
-    fn get_texel_8bit(&self, renderer: &Renderer, p: Vec2) -> u16 {
+    fn get_texel_8bit(&self, vram: &[u16], p: Vec2) -> u16 {
         let (u, v) = (p.x as usize, p.y as usize);
-        let texel = renderer.vram_read(self.page_x + u / 2, self.page_y + v);
+        let texel = vram[VRAM_WIDTH * (self.page_y + v) + self.page_x + u / 2];
         let clut_index = (texel >> ((u % 2) * 8)) & 0xFF;
 
         self.clut
             .expect("8 bit texture must have color table")
-            .get_color(renderer, clut_index as u8)
+            .get_color(vram, clut_index as u8)
     }
 
-    fn get_texel_4bit(&self, renderer: &Renderer, p: Vec2) -> u16 {
+    fn get_texel_4bit(&self, vram: &[u16], p: Vec2) -> u16 {
         let (u, v) = (p.x as usize, p.y as usize);
-        let texel = renderer.vram_read(self.page_x + u / 4, self.page_y + v);
+        let texel = vram[VRAM_WIDTH * (self.page_y + v) + self.page_x + u / 4];
         let clut_index = (texel >> ((u % 4) * 4)) & 0xF;
 
         self.clut
             .expect("4 bit texture must have color table")
-            .get_color(renderer, clut_index as u8)
+            .get_color(vram, clut_index as u8)
     }
 }
 
@@ -298,6 +369,7 @@ pub struct TextureOptions {
     pub uvs: [Vec2; 3],
 }
 
+#[derive(Clone, Copy)]
 pub struct RectTextureOptions {
     pub clut: Clut,
     pub uv: Vec2,
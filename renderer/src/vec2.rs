@@ -13,6 +13,32 @@ impl Vec2 {
     pub const fn new(x: i32, y: i32) -> Self {
         Self { x, y }
     }
+
+    #[must_use]
+    pub const fn scaled(self, scale: i32) -> Self {
+        Self::new(self.x * scale, self.y * scale)
+    }
+}
+
+/// A vertex as the GTE projected it, before it was rounded down to whole pixels for the GPU
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct PreciseVertex {
+    pub x: f32,
+    pub y: f32,
+    /// Depth in view space, 0 when unknown
+    pub w: f32,
+}
+
+impl PreciseVertex {
+    /// Stand-in for a vertex that didn't come from the GTE
+    #[must_use]
+    pub const fn from_whole(v: Vec2) -> Self {
+        Self {
+            x: v.x as f32,
+            y: v.y as f32,
+            w: 0.0,
+        }
+    }
 }
 
 /// Evaluates the edge function E(x, y) = ax + by + c for point `p` relative to line `p0` -> `p1`.